@@ -1059,7 +1059,7 @@ class HighSpeedFileManager {
         
         try {
             for (const path of this.selectedItems) {
-                await this.apiCall('delete', { path });
+                await this.apiCall('delete', { path, recursive: true });
             }
             
             this.selectedItems.clear();
@@ -0,0 +1,49 @@
+use warp::{Filter, Rejection};
+
+/// API のバージョン。/api/v1/... のようにパスの先頭で指定する (バージョンなしの /api/... は v1)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ApiVersion {
+    V1,
+}
+
+impl ApiVersion {
+    // 対応しているバージョン (互換性のない変更は新しいバージョンとして加え、既存のバージョンの応答は変えない)
+    pub const SUPPORTED: &'static [ApiVersion] = &[ApiVersion::V1];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "v1",
+        }
+    }
+
+    /// バージョンごとのルートの前に付けるフィルター (/api/ の次の v1 などのセグメント)
+    pub fn path(self) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+        warp::path(self.name())
+    }
+}
+
+/// 対応していないバージョンへのリクエストの拒否理由 (recover で 404 のレスポンスにする)
+#[derive(Debug)]
+pub struct Unsupported {
+    pub version: String,
+}
+
+impl warp::reject::Reject for Unsupported {}
+
+/// /api/ の次のセグメントが v2 のようなバージョンで、対応していないものなら拒否する
+pub fn unsupported() -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+    warp::path::param::<String>().and_then(|segment: String| async move {
+        let is_version = segment.len() > 1
+            && segment.starts_with('v')
+            && segment[1..].chars().all(|c| c.is_ascii_digit());
+        if is_version && !ApiVersion::SUPPORTED.iter().any(|version| version.name() == segment) {
+            Err::<warp::reply::Response, _>(warp::reject::custom(Unsupported { version: segment }))
+        } else {
+            Err(warp::reject::not_found())
+        }
+    })
+}
+
+pub fn supported_names() -> Vec<&'static str> {
+    ApiVersion::SUPPORTED.iter().map(|version| version.name()).collect()
+}
@@ -0,0 +1,322 @@
+use crate::signing;
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+// 起動時に直前のエントリを探すために読むログ末尾のバイト数
+const TAIL_LEN: u64 = 64 * 1024;
+
+const ED25519_PUBLIC_KEY_LEN: usize = 32;
+
+/// 監査ログの 1 エントリ。署名が有効な場合は操作のレシートとしてレスポンスにも含める
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    pub action: String,
+    pub path: String,
+    pub detail: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>, // 操作後 (削除は削除前) のファイルの SHA256
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev_hash: Option<String>,    // 直前のログ行の SHA256 (改ざん検出用の連鎖)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,    // signature 以外を JSON にしたものの Ed25519 署名 (16 進)
+}
+
+impl AuditEntry {
+    /// 署名対象のバイト列 (signature を除いた JSON)
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let unsigned = AuditEntry {
+            signature: None,
+            ..self.clone()
+        };
+        serde_json::to_vec(&unsigned).unwrap_or_default()
+    }
+}
+
+pub fn line_hash(line: &str) -> String {
+    format!("{:x}", Sha256::digest(line.as_bytes()))
+}
+
+/// receipt_key から導出するレシートの署名鍵 (Ed25519、シードは receipt_key の SHA256)。
+/// 検証は公開鍵だけでできるため、レシートを確かめる側に receipt_key を渡さなくてよい
+pub fn receipt_key_pair(receipt_key: &str) -> Ed25519KeyPair {
+    let seed = Sha256::digest(receipt_key.as_bytes());
+    Ed25519KeyPair::from_seed_unchecked(&seed).expect("a SHA256 digest is a valid Ed25519 seed")
+}
+
+/// receipt_key に対応する公開鍵 (16 進)
+pub fn receipt_public_key(receipt_key: &str) -> String {
+    signing::to_hex(receipt_key_pair(receipt_key).public_key().as_ref())
+}
+
+/// 監査ログ (JSON Lines 形式で追記)。receipt_key が設定されていれば、receipt_key から導出した Ed25519 の鍵でエントリに署名する
+pub struct AuditLog {
+    path: PathBuf,
+    key: Option<Ed25519KeyPair>,
+    last_hash: Mutex<Option<String>>, // 最後に書き込んだ行の SHA256 (書き込みの排他も兼ねる)
+}
+
+// ログ末尾の行のハッシュ (ログがなければ None)
+fn read_last_hash(path: &Path) -> Option<String> {
+    let mut file = fs::File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(TAIL_LEN))).ok()?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).ok()?;
+    let tail = String::from_utf8_lossy(&tail);
+    tail.lines().rev().find(|line| !line.trim().is_empty()).map(line_hash)
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf, receipt_key: &str) -> Self {
+        let key = if receipt_key.is_empty() {
+            None
+        } else {
+            Some(receipt_key_pair(receipt_key))
+        };
+        let last_hash = if key.is_some() { read_last_hash(&path) } else { None };
+        Self {
+            path,
+            key,
+            last_hash: Mutex::new(last_hash),
+        }
+    }
+
+    pub fn signing_enabled(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// レシートを検証する公開鍵 (16 進、署名が無効なら None)
+    pub fn public_key(&self) -> Option<String> {
+        self.key.as_ref().map(|key| signing::to_hex(key.public_key().as_ref()))
+    }
+
+    pub fn record(&self, action: &str, path: &str, detail: &str) {
+        self.append(action, path, detail, None);
+    }
+
+    /// API 経由の操作を署名付きで記録し、レシートとして返す (署名が無効なら記録しない)
+    pub fn receipt(&self, action: &str, path: &str, detail: &str, content_hash: Option<String>) -> Option<AuditEntry> {
+        if !self.signing_enabled() {
+            return None;
+        }
+        self.append(action, path, detail, content_hash)
+    }
+
+    /// レシートに含めるファイルのハッシュ (署名が無効、またはファイルでなければ None)
+    pub fn file_hash(&self, path: &Path) -> Option<String> {
+        if !self.signing_enabled() || !path.is_file() {
+            return None;
+        }
+        signing::file_sha256_hex(path).ok()
+    }
+
+    fn append(&self, action: &str, path: &str, detail: &str, content_hash: Option<String>) -> Option<AuditEntry> {
+        let mut last_hash = self.last_hash.lock().unwrap();
+        let mut entry = AuditEntry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            action: action.to_string(),
+            path: path.to_string(),
+            detail: detail.to_string(),
+            content_hash,
+            prev_hash: None,
+            signature: None,
+        };
+        if let Some(key) = &self.key {
+            entry.prev_hash = Some(last_hash.clone().unwrap_or_default());
+            entry.signature = Some(signing::to_hex(key.sign(&entry.signing_payload()).as_ref()));
+        }
+        let line = serde_json::to_string(&entry).ok()?;
+
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{}", line));
+        match result {
+            Ok(_) => {
+                *last_hash = Some(line_hash(&line));
+                Some(entry)
+            }
+            Err(e) => {
+                log_error!("⚠️ 監査ログの書き込みに失敗しました: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// 監査ログの検証結果
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub entries: usize,
+    pub signed: usize,
+    pub errors: Vec<String>,
+    pub last_signed: Option<AuditEntry>, // 末尾の削除は連鎖では検出できないため、最新のレシートと照合する
+}
+
+/// 監査ログの署名を公開鍵で、prev_hash の連鎖とともに検証する (ネットワーク不要)
+/// public_key は 16 進の Ed25519 公開鍵 (/api/capabilities の receipts.public_key)
+pub fn verify_log(path: &Path, public_key: &str) -> Result<VerifyReport, String> {
+    let public_key = signing::from_hex(public_key)
+        .filter(|key| key.len() == ED25519_PUBLIC_KEY_LEN)
+        .ok_or_else(|| format!("Invalid public key: {}", public_key))?;
+    let public_key = UnparsedPublicKey::new(&ED25519, public_key);
+    let file = fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut report = VerifyReport::default();
+    let mut prev_hash = String::new();
+
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("{}: {}", path.display(), e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let number = i + 1;
+        report.entries += 1;
+
+        match serde_json::from_str::<AuditEntry>(&line) {
+            Ok(entry) => {
+                if let Some(signature) = &entry.signature {
+                    report.signed += 1;
+                    if entry.prev_hash.as_deref() != Some(prev_hash.as_str()) {
+                        report.errors.push(format!("line {}: prev_hash does not match the previous line", number));
+                    }
+                    let valid = signing::from_hex(signature).is_some_and(|signature| public_key.verify(&entry.signing_payload(), &signature).is_ok());
+                    if !valid {
+                        report.errors.push(format!("line {}: invalid signature", number));
+                    }
+                    report.last_signed = Some(entry);
+                }
+            }
+            Err(e) => report.errors.push(format!("line {}: not an audit entry ({})", number, e)),
+        }
+        prev_hash = line_hash(&line);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECEIPT_KEY: &str = "test-receipt-key";
+
+    // 3 つの署名付きエントリを書いた監査ログ
+    fn signed_log(name: &str) -> (PathBuf, AuditLog) {
+        let path = std::env::temp_dir().join(format!("file_agent_audit_{}_{}.log", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        let log = AuditLog::new(path.clone(), RECEIPT_KEY);
+        for file in ["a.txt", "b.txt", "c.txt"] {
+            assert!(log.receipt("write", file, "", None).is_some());
+        }
+        (path, log)
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        fs::read_to_string(path).unwrap().lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn chain_verifies_with_the_public_key() {
+        let (path, log) = signed_log("valid");
+        let public_key = log.public_key().unwrap();
+        assert_eq!(public_key, receipt_public_key(RECEIPT_KEY));
+        assert_eq!(public_key.len(), 64);
+
+        let report = verify_log(&path, &public_key).unwrap();
+        assert_eq!((report.entries, report.signed), (3, 3));
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(report.last_signed.unwrap().path, "c.txt");
+    }
+
+    #[test]
+    fn other_public_key_fails() {
+        let (path, _) = signed_log("other_key");
+        let report = verify_log(&path, &receipt_public_key("other-key")).unwrap();
+        assert_eq!(report.errors.len(), 3, "{:?}", report.errors);
+        assert!(verify_log(&path, "not hex").is_err());
+        assert!(verify_log(&path, "abcd").is_err());
+    }
+
+    #[test]
+    fn edited_entry_fails() {
+        let (path, log) = signed_log("edited");
+        let mut edited = lines(&path);
+        edited[1] = edited[1].replace("b.txt", "x.txt");
+        fs::write(&path, edited.join("\n") + "\n").unwrap();
+
+        let report = verify_log(&path, &log.public_key().unwrap()).unwrap();
+        assert_eq!(report.errors, ["line 2: invalid signature", "line 3: prev_hash does not match the previous line"]);
+    }
+
+    #[test]
+    fn reordered_entries_fail() {
+        let (path, log) = signed_log("reordered");
+        let mut reordered = lines(&path);
+        reordered.swap(1, 2);
+        fs::write(&path, reordered.join("\n") + "\n").unwrap();
+
+        let report = verify_log(&path, &log.public_key().unwrap()).unwrap();
+        assert_eq!(
+            report.errors,
+            ["line 2: prev_hash does not match the previous line", "line 3: prev_hash does not match the previous line"]
+        );
+    }
+
+    #[test]
+    fn broken_prev_hash_fails() {
+        let (path, log) = signed_log("prev_hash");
+        let mut broken = lines(&path);
+        broken.remove(1);
+        fs::write(&path, broken.join("\n") + "\n").unwrap();
+
+        let report = verify_log(&path, &log.public_key().unwrap()).unwrap();
+        assert_eq!(report.signed, 2);
+        assert_eq!(report.errors, ["line 2: prev_hash does not match the previous line"]);
+    }
+
+    #[test]
+    fn truncated_log_fails() {
+        let (path, log) = signed_log("truncated");
+        let public_key = log.public_key().unwrap();
+
+        // 書き込みの途中で切れた末尾の行
+        let content = fs::read_to_string(&path).unwrap();
+        fs::write(&path, &content[..content.len() - 20]).unwrap();
+        let report = verify_log(&path, &public_key).unwrap();
+        assert_eq!(report.entries, 3);
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        assert!(report.errors[0].starts_with("line 3: not an audit entry"), "{:?}", report.errors);
+
+        // 先頭の行を切り落としたログ
+        let (path, log) = signed_log("truncated_head");
+        let rest = lines(&path)[1..].join("\n") + "\n";
+        fs::write(&path, rest).unwrap();
+        let report = verify_log(&path, &log.public_key().unwrap()).unwrap();
+        assert_eq!(report.errors, ["line 1: prev_hash does not match the previous line"]);
+    }
+
+    #[test]
+    fn missing_log_is_an_error() {
+        let path = std::env::temp_dir().join(format!("file_agent_audit_missing_{}.log", std::process::id()));
+        assert!(verify_log(&path, &receipt_public_key(RECEIPT_KEY)).is_err());
+    }
+
+    #[test]
+    fn unsigned_log_has_no_public_key() {
+        let log = AuditLog::new(std::env::temp_dir().join("file_agent_audit_unsigned.log"), "");
+        assert!(!log.signing_enabled());
+        assert_eq!(log.public_key(), None);
+        assert!(log.receipt("write", "a.txt", "", None).is_none());
+    }
+}
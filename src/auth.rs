@@ -0,0 +1,746 @@
+use crate::audit::AuditLog;
+use crate::{policy, writequota};
+use crate::{generate_token_hash, verify_token};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+// メインのトークン (token=) に割り当てられる制限なしのティア名
+pub const ADMIN_TIER: &str = "admin";
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+// 認証失敗を記録しておくクライアント数がこれを超えたら、期限の過ぎた記録を捨てる
+const MAX_TRACKED_CLIENTS: usize = 1024;
+
+// 接続元のアドレスがないクライアントを監査ログとログに出すときの名前
+const LOCAL_CLIENT: &str = "local";
+
+/// API の操作の種類 (allow= で有効にする操作を制限できる)
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Read,    // read / read_binary / read_chunk / mime
+    Write,   // write / write_binary
+    Delete,
+    List,
+    Search,  // search / grep / index/search / stale
+    Create,
+    Move,
+    Copy,
+    Paste,
+    Clipboard, // clipboard/get / clipboard/set
+    Print,
+    Cleanup,
+    Changes,
+    Clients,
+    Metrics,
+    Tokens,
+    Vault,   // vault/status / unlock / lock / rotate
+    Shutdown,
+    Plugin,  // plugins / plugins/<name>/<endpoint> / plugins/transform
+    Script,
+    Exec,
+    S3,      // s3/upload / s3/download / s3/list
+    Sync,    // sync/status
+    Shares,  // shares
+}
+
+const OPERATIONS: &[Operation] = &[
+    Operation::Read,
+    Operation::Write,
+    Operation::Delete,
+    Operation::List,
+    Operation::Search,
+    Operation::Create,
+    Operation::Move,
+    Operation::Copy,
+    Operation::Paste,
+    Operation::Clipboard,
+    Operation::Print,
+    Operation::Cleanup,
+    Operation::Changes,
+    Operation::Clients,
+    Operation::Metrics,
+    Operation::Tokens,
+    Operation::Vault,
+    Operation::Shutdown,
+    Operation::Plugin,
+    Operation::Script,
+    Operation::Exec,
+    Operation::S3,
+    Operation::Sync,
+    Operation::Shares,
+];
+
+impl Operation {
+    pub fn name(self) -> &'static str {
+        match self {
+            Operation::Read => "read",
+            Operation::Write => "write",
+            Operation::Delete => "delete",
+            Operation::List => "list",
+            Operation::Search => "search",
+            Operation::Create => "create",
+            Operation::Move => "move",
+            Operation::Copy => "copy",
+            Operation::Paste => "paste",
+            Operation::Clipboard => "clipboard",
+            Operation::Print => "print",
+            Operation::Cleanup => "cleanup",
+            Operation::Changes => "changes",
+            Operation::Clients => "clients",
+            Operation::Metrics => "metrics",
+            Operation::Tokens => "tokens",
+            Operation::Vault => "vault",
+            Operation::Shutdown => "shutdown",
+            Operation::Plugin => "plugin",
+            Operation::Script => "script",
+            Operation::Exec => "exec",
+            Operation::S3 => "s3",
+            Operation::Sync => "sync",
+            Operation::Shares => "shares",
+        }
+    }
+
+    // 形式: read,list,search (["read", "list"] のような表記も受け付ける)
+    pub fn parse_list(value: &str) -> Option<Vec<Self>> {
+        value
+            .trim()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .split(',')
+            .map(|name| name.trim().trim_matches('"'))
+            .filter(|name| !name.is_empty())
+            .map(|name| OPERATIONS.iter().copied().find(|op| op.name() == name))
+            .collect()
+    }
+
+    pub fn to_ini_value(ops: &[Self]) -> String {
+        ops.iter().map(|op| op.name()).collect::<Vec<_>>().join(",")
+    }
+}
+
+/// トークンの発行日時・有効期限 (UNIX 秒) と、ローテーション前のトークンのハッシュ
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct TokenMeta {
+    pub created: Option<u64>,
+    pub expires: Option<u64>,
+    pub previous_hash: Option<String>, // previous_until まで引き続き有効
+    pub previous_until: Option<u64>,
+}
+
+impl TokenMeta {
+    // ini のキーと値の組を返す (tier= 行ではそのまま、メインのトークンは token_ を付けて保存)
+    pub fn ini_pairs(&self) -> Vec<(&'static str, String)> {
+        let mut pairs = Vec::new();
+        if let Some(created) = self.created {
+            pairs.push(("created", created.to_string()));
+        }
+        if let Some(expires) = self.expires {
+            pairs.push(("expires", expires.to_string()));
+        }
+        if let (Some(hash), Some(until)) = (&self.previous_hash, self.previous_until) {
+            pairs.push(("previous_hash", hash.clone()));
+            pairs.push(("previous_until", until.to_string()));
+        }
+        pairs
+    }
+
+    /// token_ を除いた ini のキーとして読み込む名前か
+    pub fn is_key(key: &str) -> bool {
+        matches!(key, "created" | "expires" | "previous_hash" | "previous" | "previous_until")
+    }
+
+    /// ini のキーを 1 つ読み込む。対象外のキーや不正な値なら None
+    pub fn parse_pair(&mut self, key: &str, val: &str) -> Option<()> {
+        match key {
+            "created" => self.created = Some(val.parse().ok()?),
+            "expires" => self.expires = Some(val.parse().ok()?),
+            "previous_hash" => self.previous_hash = Some(val.to_string()),
+            "previous" => self.previous_hash = Some(generate_token_hash(val)), // 旧形式 (平文)
+            "previous_until" => self.previous_until = Some(val.parse().ok()?),
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// 追加トークンとその制限 (ティア)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TokenTier {
+    pub name: String,
+    pub token_hash: String, // トークンの SHA256 (平文は保存しない)
+    pub requests_per_minute: Option<u32>,
+    pub max_transfer_bytes: Option<u64>,
+    pub allowed_operations: Vec<Operation>, // 空なら allow= で有効な操作すべて
+    pub allowed_roots: Vec<PathBuf>,        // 空なら allowed_root= の範囲すべて
+    pub meta: TokenMeta,
+}
+
+impl TokenTier {
+    // 形式: bots|token_hash=xxxx|requests_per_minute=60|max_transfer_bytes=1048576|allow=read,list|root=D:\shared|expires=1767225600
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split('|');
+        let name = parts.next()?.trim();
+        if name.is_empty() || name == ADMIN_TIER {
+            return None;
+        }
+
+        let mut tier = TokenTier {
+            name: name.to_string(),
+            token_hash: String::new(),
+            requests_per_minute: None,
+            max_transfer_bytes: None,
+            allowed_operations: Vec::new(),
+            allowed_roots: Vec::new(),
+            meta: TokenMeta::default(),
+        };
+        for part in parts {
+            let (key, val) = part.split_once('=')?;
+            let val = val.trim();
+            match key.trim() {
+                "token_hash" => tier.token_hash = val.to_string(),
+                "token" => tier.token_hash = generate_token_hash(val), // 平文で書かれた場合は読み込み時にハッシュ化する
+                "requests_per_minute" => tier.requests_per_minute = Some(val.parse().ok()?),
+                "max_transfer_bytes" => tier.max_transfer_bytes = Some(val.parse().ok()?),
+                "allow" => tier.allowed_operations = Operation::parse_list(val)?,
+                "root" if !val.is_empty() => tier.allowed_roots.push(PathBuf::from(val)),
+                key => tier.meta.parse_pair(key, val)?,
+            }
+        }
+
+        if tier.token_hash.is_empty() {
+            return None;
+        }
+        Some(tier)
+    }
+
+    pub fn to_ini_value(&self) -> String {
+        let mut value = format!("{}|token_hash={}", self.name, self.token_hash);
+        if let Some(rate) = self.requests_per_minute {
+            value.push_str(&format!("|requests_per_minute={}", rate));
+        }
+        if let Some(max) = self.max_transfer_bytes {
+            value.push_str(&format!("|max_transfer_bytes={}", max));
+        }
+        if !self.allowed_operations.is_empty() {
+            value.push_str(&format!("|allow={}", Operation::to_ini_value(&self.allowed_operations)));
+        }
+        for root in &self.allowed_roots {
+            value.push_str(&format!("|root={}", root.display()));
+        }
+        for (key, val) in self.meta.ini_pairs() {
+            value.push_str(&format!("|{}={}", key, val));
+        }
+        value
+    }
+}
+
+/// 認証済みリクエストに適用される制限
+#[derive(Debug, Clone)]
+pub struct Grant {
+    pub tier: String,
+    pub max_transfer_bytes: Option<u64>,
+    pub allowed_roots: Vec<PathBuf>, // 空でなければ、このトークンでアクセスできるのはこの範囲のみ
+}
+
+/// トークンで使える操作と制限 (/api/capabilities で返す)
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TokenCapabilities {
+    pub tier: String,
+    pub operations: Vec<String>,
+    pub requests_per_minute: Option<u32>,
+    pub max_transfer_bytes: Option<u64>,
+    pub allowed_roots: Vec<String>, // 空ならエージェントの許可ルートすべて
+    pub max_write_bytes: Option<u64>,
+    pub daily_write_bytes: Option<u64>,
+    #[serde(default)]
+    pub written_today: u64, // 今日 (UTC) このトークンで書き込んだバイト数
+}
+
+impl Grant {
+    /// 読み書きするデータ量がティアの上限内か確認する
+    pub fn check_size(&self, size: u64) -> Result<(), String> {
+        match self.max_transfer_bytes {
+            Some(max) if size > max => Err(format!(
+                "Transfer size {} bytes exceeds the limit for tier '{}' ({} bytes)",
+                size, self.tier, max
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+// 検証用のトークンハッシュと有効期限
+struct Credential {
+    hash: String,
+    expires: Option<u64>,
+    previous: Option<(String, u64)>, // (ローテーション前のトークンのハッシュ, 有効期限)
+}
+
+impl Credential {
+    fn new(hash: String, meta: &TokenMeta) -> Self {
+        Self {
+            hash,
+            expires: meta.expires,
+            previous: meta.previous_hash.clone().zip(meta.previous_until),
+        }
+    }
+
+    // 一致しなければ None、一致したが期限切れなら Some(Err)
+    fn check(&self, token: &str, now: u64) -> Option<Result<(), String>> {
+        if verify_token(token, &self.hash) {
+            return Some(match self.expires {
+                Some(expires) if now >= expires => Err("認証エラー: トークンの有効期限が切れています".to_string()),
+                _ => Ok(()),
+            });
+        }
+        match &self.previous {
+            Some((hash, until)) if verify_token(token, hash) => Some(if now < *until {
+                Ok(())
+            } else {
+                Err("認証エラー: トークンの有効期限が切れています".to_string())
+            }),
+            _ => None,
+        }
+    }
+}
+
+// クライアントごとの認証失敗の記録
+struct Failures {
+    window_start: Instant,
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+/// 認証失敗が続いたクライアントを一時的にロックする。max_failures が 0 なら無効。
+/// 接続元のアドレスがないクライアント (ソケット・stdio など) は None としてまとめて数える
+pub struct Lockout {
+    max_failures: u32,
+    window: Duration,
+    duration: Duration,
+    clients: Mutex<HashMap<Option<IpAddr>, Failures>>,
+}
+
+impl Lockout {
+    pub fn new(max_failures: u32, window: Duration, duration: Duration) -> Self {
+        Self {
+            max_failures,
+            window,
+            duration,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// ロック中なら残りの秒数を返す
+    fn remaining(&self, client: Option<IpAddr>) -> Option<u64> {
+        let clients = self.clients.lock().unwrap();
+        let until = clients.get(&client)?.locked_until?;
+        let remaining = until.checked_duration_since(Instant::now())?;
+        Some((remaining.as_secs_f64().ceil() as u64).max(1))
+    }
+
+    /// 失敗を記録する。これでロックされた場合は (失敗回数, ロックの秒数) を返す
+    fn fail(&self, client: Option<IpAddr>) -> Option<(u32, u64)> {
+        if self.max_failures == 0 {
+            return None;
+        }
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= MAX_TRACKED_CLIENTS && !clients.contains_key(&client) {
+            let window = self.window;
+            clients.retain(|_, f| now.duration_since(f.window_start) < window || f.locked_until.map(|u| u > now).unwrap_or(false));
+        }
+
+        let failures = clients.entry(client).or_insert(Failures {
+            window_start: now,
+            count: 0,
+            locked_until: None,
+        });
+        if now.duration_since(failures.window_start) >= self.window {
+            *failures = Failures {
+                window_start: now,
+                count: 0,
+                locked_until: None,
+            };
+        }
+        failures.count += 1;
+        if failures.count < self.max_failures {
+            return None;
+        }
+        let count = failures.count;
+        *failures = Failures {
+            window_start: now,
+            count: 0,
+            locked_until: Some(now + self.duration),
+        };
+        Some((count, self.duration.as_secs()))
+    }
+
+    fn succeed(&self, client: Option<IpAddr>) {
+        if self.max_failures > 0 {
+            self.clients.lock().unwrap().remove(&client);
+        }
+    }
+}
+
+/// トークンの検証とティアごとのレート制限
+pub struct Auth {
+    admin: RwLock<Credential>,
+    tiers: RwLock<Vec<(Credential, TokenTier)>>,
+    allowed_operations: RwLock<Vec<Operation>>, // 空ならすべての操作を許可
+    windows: Mutex<HashMap<String, (Instant, u32)>>, // ティア名 -> (計測開始時刻, リクエスト数)
+    rotation: Mutex<()>, // ローテーション (設定ファイルの更新を含む) を 1 つずつ行う
+    lockout: Lockout,
+    trusted_transport: bool, // クライアント証明書やソケットの権限で認証済み (トークンを確認しない)
+}
+
+impl Auth {
+    /// allowed_roots はエージェント全体の許可ルート。ティアのルートはこの範囲内のものだけ有効にする
+    pub fn new(admin_hash: String, admin_meta: &TokenMeta, tiers: &[TokenTier], allowed_operations: &[Operation], allowed_roots: &[PathBuf], lockout: Lockout) -> Self {
+        Self {
+            admin: RwLock::new(Credential::new(admin_hash, admin_meta)),
+            tiers: RwLock::new(
+                tiers
+                    .iter()
+                    .filter_map(|tier| scope_tier(tier, allowed_roots))
+                    .map(|tier| (Credential::new(tier.token_hash.clone(), &tier.meta), tier))
+                    .collect(),
+            ),
+            allowed_operations: RwLock::new(allowed_operations.to_vec()),
+            windows: Mutex::new(HashMap::new()),
+            rotation: Mutex::new(()),
+            lockout,
+            trusted_transport: false,
+        }
+    }
+
+    /// 設定ファイルの変更を反映する (認証失敗の記録とティアのリクエスト数はそのまま残す)
+    pub fn reload(&self, admin_hash: String, admin_meta: &TokenMeta, tiers: &[TokenTier], allowed_operations: &[Operation], allowed_roots: &[PathBuf]) {
+        *self.admin.write().unwrap() = Credential::new(admin_hash, admin_meta);
+        *self.tiers.write().unwrap() = tiers
+            .iter()
+            .filter_map(|tier| scope_tier(tier, allowed_roots))
+            .map(|tier| (Credential::new(tier.token_hash.clone(), &tier.meta), tier))
+            .collect();
+        *self.allowed_operations.write().unwrap() = allowed_operations.to_vec();
+    }
+
+    /// 接続できるクライアントが経路 (クライアント証明書やソケットの権限) で限られている場合に、トークンなしでメインのトークンと同じ権限を与える
+    pub fn trust_transport(&mut self) {
+        self.trusted_transport = true;
+    }
+
+    // トークンを検証する。メインのトークンは None (ティアの制限なし)
+    fn authenticate(&self, token: &str) -> Result<Option<TokenTier>, String> {
+        if self.trusted_transport {
+            return Ok(None);
+        }
+        let now = unix_now();
+        let admin = self.admin.read().unwrap().check(token, now);
+        match admin {
+            Some(result) => result.map(|_| None),
+            None => {
+                let tiers = self.tiers.read().unwrap();
+                let found = tiers
+                    .iter()
+                    .find_map(|(credential, tier)| credential.check(token, now).map(|result| result.map(|_| tier.clone())));
+                match found {
+                    Some(result) => Ok(Some(result?)),
+                    None => Err("認証エラー: 無効なトークンです".to_string()),
+                }
+            }
+        }
+    }
+
+    // 認証済みのティアに操作を許可する
+    fn grant(&self, tier: Option<TokenTier>, operation: Operation) -> Result<Grant, String> {
+        let allowed_operations = self.allowed_operations.read().unwrap();
+        if !allowed_operations.is_empty() && !allowed_operations.contains(&operation) {
+            return Err(format!("Operation '{}' is disabled on this agent", operation.name()));
+        }
+        if let Some(tier) = &tier {
+            if !tier.allowed_operations.is_empty() && !tier.allowed_operations.contains(&operation) {
+                return Err(format!("Operation '{}' is not allowed for tier '{}'", operation.name(), tier.name));
+            }
+        }
+
+        let tier = match tier {
+            Some(tier) => tier,
+            None => return Ok(Grant {
+                tier: ADMIN_TIER.to_string(),
+                max_transfer_bytes: None,
+                allowed_roots: Vec::new(),
+            }),
+        };
+
+        if let Some(limit) = tier.requests_per_minute {
+            let now = Instant::now();
+            let mut windows = self.windows.lock().unwrap();
+            let window = windows.entry(tier.name.clone()).or_insert((now, 0));
+            if now.duration_since(window.0) >= RATE_WINDOW {
+                *window = (now, 0);
+            }
+            if window.1 >= limit {
+                return Err(format!(
+                    "Rate limit exceeded for tier '{}' ({} requests per minute)",
+                    tier.name, limit
+                ));
+            }
+            window.1 += 1;
+        }
+
+        Ok(Grant {
+            tier: tier.name.clone(),
+            max_transfer_bytes: tier.max_transfer_bytes,
+            allowed_roots: tier.allowed_roots.clone(),
+        })
+    }
+
+    /// 認証済みのティアで使える操作と制限 (レート制限は消費しない)
+    fn capabilities(&self, tier: Option<TokenTier>) -> TokenCapabilities {
+        let allowed_operations = self.allowed_operations.read().unwrap();
+        let operations = OPERATIONS
+            .iter()
+            .copied()
+            .filter(|op| allowed_operations.is_empty() || allowed_operations.contains(op))
+            .filter(|op| tier.as_ref().is_none_or(|tier| tier.allowed_operations.is_empty() || tier.allowed_operations.contains(op)))
+            .map(|op| op.name().to_string())
+            .collect();
+        match tier {
+            Some(tier) => TokenCapabilities {
+                tier: tier.name.clone(),
+                operations,
+                requests_per_minute: tier.requests_per_minute,
+                max_transfer_bytes: tier.max_transfer_bytes,
+                allowed_roots: tier.allowed_roots.iter().map(|root| root.display().to_string()).collect(),
+                max_write_bytes: writequota::max_write_bytes(),
+                daily_write_bytes: writequota::daily_write_bytes(),
+                written_today: writequota::written_today(&tier.name),
+            },
+            None => TokenCapabilities {
+                tier: ADMIN_TIER.to_string(),
+                operations,
+                requests_per_minute: None,
+                max_transfer_bytes: None,
+                allowed_roots: Vec::new(),
+                max_write_bytes: writequota::max_write_bytes(),
+                daily_write_bytes: writequota::daily_write_bytes(),
+                written_today: writequota::written_today(ADMIN_TIER),
+            },
+        }
+    }
+
+    pub fn lock_rotation(&self) -> std::sync::MutexGuard<'_, ()> {
+        self.rotation.lock().unwrap()
+    }
+
+    /// tier (メインのトークンは ADMIN_TIER) のトークンを差し替える。旧トークンは meta.previous_until まで有効
+    pub fn rotate(&self, tier: &str, token_hash: &str, meta: &TokenMeta) {
+        let credential = Credential::new(token_hash.to_string(), meta);
+        if tier == ADMIN_TIER {
+            *self.admin.write().unwrap() = credential;
+            return;
+        }
+        let mut tiers = self.tiers.write().unwrap();
+        if let Some(entry) = tiers.iter_mut().find(|(_, t)| t.name == tier) {
+            entry.1.token_hash = token_hash.to_string();
+            entry.1.meta = meta.clone();
+            entry.0 = credential;
+        }
+    }
+}
+
+// ティアのルートのうち、エージェント全体の許可ルート外のものを除く。
+// すべて除かれた場合は、全体の範囲に広がらないようティアごと無効にする
+fn scope_tier(tier: &TokenTier, allowed_roots: &[PathBuf]) -> Option<TokenTier> {
+    let mut tier = tier.clone();
+    if tier.allowed_roots.is_empty() || allowed_roots.is_empty() {
+        return Some(tier);
+    }
+
+    tier.allowed_roots.retain(|root| {
+        let inside = policy::is_allowed(allowed_roots, root);
+        if !inside {
+            log!("⚠️ ティア '{}' のルートは許可ルートの範囲外のため無視します: {}", tier.name, root.display());
+        }
+        inside
+    });
+    if tier.allowed_roots.is_empty() {
+        log!("⚠️ ティア '{}' に有効なルートがないため無効にしました", tier.name);
+        return None;
+    }
+    Some(tier)
+}
+
+/// リクエストごとの認証。送信元のアドレスごとに認証失敗を数え、続いた場合はロックして監査ログに残す
+#[derive(Clone)]
+pub struct ClientAuth {
+    auth: Arc<Auth>,
+    audit: Arc<AuditLog>,
+    client: Option<IpAddr>,
+}
+
+impl Deref for ClientAuth {
+    type Target = Auth;
+
+    fn deref(&self) -> &Auth {
+        &self.auth
+    }
+}
+
+impl ClientAuth {
+    pub fn new(auth: Arc<Auth>, audit: Arc<AuditLog>, client: Option<IpAddr>) -> Self {
+        Self { auth, audit, client }
+    }
+
+    /// トークンを検証し、操作が有効か確認してからレート制限を適用する
+    pub fn authorize(&self, token: &str, operation: Operation) -> Result<Grant, String> {
+        let tier = self.check_token(token)?;
+        self.auth.grant(tier, operation)
+    }
+
+    /// トークンが有効か確認する (操作は問わない)
+    pub fn verify(&self, token: &str) -> Result<(), String> {
+        self.check_token(token).map(|_| ())
+    }
+
+    /// トークンで使える操作と制限を返す
+    pub fn capabilities(&self, token: &str) -> Result<TokenCapabilities, String> {
+        let tier = self.check_token(token)?;
+        Ok(self.auth.capabilities(tier))
+    }
+
+    // ロック中のクライアントを拒否し、失敗が続いたクライアントをロックする
+    fn check_token(&self, token: &str) -> Result<Option<TokenTier>, String> {
+        let client = self.client;
+        if let Some(remaining) = self.auth.lockout.remaining(client) {
+            return Err(format!("認証エラー: 認証の失敗が続いたため、あと {} 秒間ロックされています", remaining));
+        }
+
+        match self.auth.authenticate(token) {
+            Ok(tier) => {
+                self.auth.lockout.succeed(client);
+                Ok(tier)
+            }
+            Err(e) => {
+                let address = client.map(|client| client.to_string()).unwrap_or_else(|| LOCAL_CLIENT.to_string());
+                self.audit.record("auth_failure", &address, &e);
+                if let Some((count, secs)) = self.auth.lockout.fail(client) {
+                    log!("🔒 認証の失敗が続いたため {} を {} 秒間ロックしました", address, secs);
+                    self.audit.record("auth_lockout", &address, &format!("{} failed attempts, locked for {} seconds", count, secs));
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "admin-token-0123456789abcdef";
+
+    fn client(address: &str) -> Option<IpAddr> {
+        Some(address.parse().unwrap())
+    }
+
+    fn client_auth(name: &str, client: Option<IpAddr>, lockout: Lockout) -> (ClientAuth, PathBuf) {
+        let log = std::env::temp_dir().join(format!("file_agent_auth_{}_{}.log", name, std::process::id()));
+        let _ = std::fs::remove_file(&log);
+        let auth = Auth::new(generate_token_hash(TOKEN), &TokenMeta::default(), &[], &[], &[], lockout);
+        (ClientAuth::new(Arc::new(auth), Arc::new(AuditLog::new(log.clone(), "")), client), log)
+    }
+
+    #[test]
+    fn lockout_starts_at_the_threshold() {
+        let lockout = Lockout::new(3, Duration::from_secs(60), Duration::from_secs(300));
+        assert_eq!(lockout.fail(client("192.0.2.1")), None);
+        assert_eq!(lockout.fail(client("192.0.2.1")), None);
+        assert_eq!(lockout.remaining(client("192.0.2.1")), None);
+        assert_eq!(lockout.fail(client("192.0.2.1")), Some((3, 300)));
+        assert!(lockout.remaining(client("192.0.2.1")).is_some_and(|secs| secs > 0 && secs <= 300));
+        // 他のクライアントとアドレスのないクライアントは別に数える
+        assert_eq!(lockout.remaining(client("192.0.2.2")), None);
+        assert_eq!(lockout.remaining(None), None);
+    }
+
+    #[test]
+    fn success_clears_the_failures() {
+        let lockout = Lockout::new(2, Duration::from_secs(60), Duration::from_secs(300));
+        assert_eq!(lockout.fail(None), None);
+        lockout.succeed(None);
+        assert_eq!(lockout.fail(None), None);
+        assert_eq!(lockout.fail(None), Some((2, 300)));
+    }
+
+    #[test]
+    fn failures_outside_the_window_start_over() {
+        let lockout = Lockout::new(2, Duration::from_millis(50), Duration::from_secs(300));
+        assert_eq!(lockout.fail(None), None);
+        std::thread::sleep(Duration::from_millis(80));
+        assert_eq!(lockout.fail(None), None);
+        assert_eq!(lockout.fail(None), Some((2, 300)));
+    }
+
+    #[test]
+    fn lock_expires_after_the_duration() {
+        let lockout = Lockout::new(1, Duration::from_secs(60), Duration::from_millis(50));
+        assert!(lockout.fail(None).is_some());
+        assert!(lockout.remaining(None).is_some());
+        std::thread::sleep(Duration::from_millis(80));
+        assert_eq!(lockout.remaining(None), None);
+    }
+
+    #[test]
+    fn zero_failures_disables_the_lockout() {
+        let lockout = Lockout::new(0, Duration::from_secs(60), Duration::from_secs(300));
+        for _ in 0..20 {
+            assert_eq!(lockout.fail(None), None);
+        }
+        assert_eq!(lockout.remaining(None), None);
+    }
+
+    #[test]
+    fn clients_without_an_address_are_locked_out_and_audited() {
+        let (auth, log) = client_auth("local", None, Lockout::new(2, Duration::from_secs(60), Duration::from_secs(300)));
+        assert!(auth.verify("wrong-token").unwrap_err().contains("無効なトークン"));
+        assert!(auth.verify("wrong-token").unwrap_err().contains("無効なトークン"));
+        // ロック中は正しいトークンも断る
+        assert!(auth.verify(TOKEN).unwrap_err().contains("ロックされています"));
+
+        let entries: Vec<crate::audit::AuditEntry> = std::fs::read_to_string(&log)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let actions: Vec<(&str, &str)> = entries.iter().map(|e| (e.action.as_str(), e.path.as_str())).collect();
+        assert_eq!(actions, vec![("auth_failure", "local"), ("auth_failure", "local"), ("auth_lockout", "local")]);
+        assert_eq!(entries[2].detail, "2 failed attempts, locked for 300 seconds");
+    }
+
+    #[test]
+    fn addressed_clients_are_audited_by_address() {
+        let (auth, log) = client_auth("addressed", client("192.0.2.7"), Lockout::new(5, Duration::from_secs(60), Duration::from_secs(300)));
+        assert!(auth.verify("wrong-token").is_err());
+        assert!(auth.verify(TOKEN).is_ok());
+        let content = std::fs::read_to_string(&log).unwrap();
+        let entry: crate::audit::AuditEntry = serde_json::from_str(content.lines().next().unwrap()).unwrap();
+        assert_eq!((entry.action.as_str(), entry.path.as_str()), ("auth_failure", "192.0.2.7"));
+        assert_eq!(content.lines().count(), 1);
+    }
+}
@@ -0,0 +1,39 @@
+use auto_launch::{AutoLaunch, AutoLaunchBuilder};
+
+// 自動起動の登録名 (Windows は Run キーの値の名前、macOS は LaunchAgent、Linux は ~/.config/autostart の .desktop の名前)
+const APP_NAME: &str = "file_agent";
+
+// 実行中の実行ファイルを引数なし (トレイで起動) で登録する
+fn launcher() -> Result<AutoLaunch, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Failed to locate the executable: {}", e))?;
+    AutoLaunchBuilder::new()
+        .set_app_name(APP_NAME)
+        .set_app_path(&exe.to_string_lossy())
+        .set_use_launch_agent(true)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+/// ログイン時に自動起動するよう登録されているか
+pub fn is_enabled() -> bool {
+    launcher().and_then(|launcher| launcher.is_enabled().map_err(|e| e.to_string())).unwrap_or(false)
+}
+
+/// ログイン時の自動起動を登録する (enabled が false なら登録を消す)
+pub fn set_enabled(enabled: bool) -> Result<(), String> {
+    let launcher = launcher()?;
+    let result = if enabled { launcher.enable() } else { launcher.disable() };
+    result.map_err(|e| format!("Failed to update login autostart: {}", e))
+}
+
+/// 設定 (autostart=) と登録が違っていれば登録し直す
+pub fn sync(enabled: bool) {
+    if is_enabled() == enabled {
+        return;
+    }
+    match set_enabled(enabled) {
+        Ok(()) if enabled => log!("✅ ログイン時に自動起動するよう登録しました"),
+        Ok(()) => log!("✅ ログイン時の自動起動の登録を解除しました"),
+        Err(e) => log_error!("⚠️ {}", e),
+    }
+}
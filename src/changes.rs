@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use utoipa::ToSchema;
+
+use crate::shutdown;
+
+// 保持するイベントの最大数 (古いものから破棄)
+const MAX_EVENTS: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChangeEvent {
+    pub seq: u64,
+    pub kind: String,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ChangePoll {
+    pub cursor: u64,
+    pub events: Vec<ChangeEvent>,
+    // cursor が古すぎて取りこぼしたイベントがある場合 true
+    pub missed: bool,
+}
+
+struct Inner {
+    next_seq: u64,
+    events: VecDeque<ChangeEvent>,
+}
+
+/// エージェント経由で行われたファイル変更の履歴
+pub struct ChangeLog {
+    inner: Mutex<Inner>,
+    notify: Notify,
+}
+
+impl Default for ChangeLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChangeLog {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                next_seq: 1,
+                events: VecDeque::new(),
+            }),
+            notify: Notify::new(),
+        }
+    }
+
+    pub fn record(&self, kind: &str, path: &str, destination: Option<&str>) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        {
+            let mut inner = self.inner.lock().unwrap();
+            let seq = inner.next_seq;
+            inner.next_seq += 1;
+            inner.events.push_back(ChangeEvent {
+                seq,
+                kind: kind.to_string(),
+                path: path.to_string(),
+                destination: destination.map(|d| d.to_string()),
+                timestamp,
+            });
+            if inner.events.len() > MAX_EVENTS {
+                inner.events.pop_front();
+            }
+        }
+
+        self.notify.notify_waiters();
+    }
+
+    /// 最新のカーソル位置 (最後に記録されたイベントの seq)
+    pub fn latest(&self) -> u64 {
+        self.inner.lock().unwrap().next_seq - 1
+    }
+
+    /// cursor より後のイベント。cursor が最新より先 (エージェントの再起動前のカーソル) なら、
+    /// 保持しているイベントをすべて返して missed にする
+    pub fn since(&self, cursor: u64) -> ChangePoll {
+        let inner = self.inner.lock().unwrap();
+        let latest = inner.next_seq - 1;
+        let oldest = inner.events.front().map(|e| e.seq).unwrap_or(inner.next_seq);
+        let future = cursor > latest;
+        let after = if future { 0 } else { cursor };
+        ChangePoll {
+            cursor: latest,
+            events: inner.events.iter().filter(|e| e.seq > after).cloned().collect(),
+            missed: future || after.saturating_add(1) < oldest,
+        }
+    }
+
+    /// cursor 以降のイベントが届くか wait が経過するまで待機する
+    pub async fn wait_since(&self, cursor: u64, wait: Duration) -> ChangePoll {
+        let deadline = tokio::time::Instant::now() + wait;
+        loop {
+            // 取りこぼし防止のため、確認より先に通知待ちを登録しておく
+            let notified = self.notify.notified();
+            let poll = self.since(cursor);
+            if !poll.events.is_empty() || poll.missed {
+                return poll;
+            }
+            // エージェントの停止を待たせないよう、停止を要求されたら待機をやめる
+            tokio::select! {
+                result = tokio::time::timeout_at(deadline, notified) => {
+                    if result.is_err() {
+                        return self.since(cursor);
+                    }
+                }
+                _ = shutdown::requested() => return self.since(cursor),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_with(count: usize) -> ChangeLog {
+        let log = ChangeLog::new();
+        for i in 0..count {
+            log.record("write", &format!("/data/{}.txt", i), None);
+        }
+        log
+    }
+
+    #[test]
+    fn current_cursor_gets_new_events() {
+        let log = log_with(3);
+        let poll = log.since(1);
+        assert_eq!(poll.cursor, 3);
+        assert_eq!(poll.events.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![2, 3]);
+        assert!(!poll.missed);
+        assert!(log.since(3).events.is_empty());
+        assert!(!log.since(3).missed);
+    }
+
+    #[test]
+    fn cursor_older_than_the_ring_is_missed() {
+        let log = log_with(MAX_EVENTS + 5);
+        let poll = log.since(2);
+        assert!(poll.missed);
+        assert_eq!(poll.events.len(), MAX_EVENTS);
+        assert_eq!(poll.events[0].seq, 6);
+        // 破棄された直後のイベントまで受け取っていれば取りこぼしはない
+        assert!(!log.since(5).missed);
+    }
+
+    #[test]
+    fn cursor_from_before_a_restart_is_missed() {
+        let log = log_with(2);
+        let poll = log.since(50);
+        assert!(poll.missed);
+        assert_eq!(poll.cursor, 2);
+        assert_eq!(poll.events.len(), 2);
+    }
+
+    #[test]
+    fn largest_cursor_does_not_overflow() {
+        let poll = log_with(1).since(u64::MAX);
+        assert!(poll.missed);
+        assert!(!ChangeLog::new().since(0).missed);
+        assert!(ChangeLog::new().since(u64::MAX).missed);
+    }
+}
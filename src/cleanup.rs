@@ -0,0 +1,215 @@
+use crate::audit::AuditLog;
+use crate::changes::ChangeLog;
+use crate::walk;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use utoipa::ToSchema;
+use walkdir::WalkDir;
+
+/// 自動クリーンアップルール
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CleanupRule {
+    pub directory: PathBuf,
+    pub pattern: Option<String>,
+    pub older_than_days: Option<u64>,
+    pub keep_newest: Option<usize>,
+    pub recursive: bool,
+}
+
+impl CleanupRule {
+    // 形式: D:\temp|older_than_days=7|pattern=*.bak|keep_newest=20|recursive=true
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split('|');
+        let directory = parts.next()?.trim();
+        if directory.is_empty() {
+            return None;
+        }
+
+        let mut rule = CleanupRule {
+            directory: PathBuf::from(directory),
+            pattern: None,
+            older_than_days: None,
+            keep_newest: None,
+            recursive: false,
+        };
+        for part in parts {
+            let (key, val) = part.split_once('=')?;
+            let val = val.trim();
+            match key.trim() {
+                "pattern" => rule.pattern = Some(val.to_string()),
+                "older_than_days" => rule.older_than_days = Some(val.parse().ok()?),
+                "keep_newest" => rule.keep_newest = Some(val.parse().ok()?),
+                "recursive" => rule.recursive = val == "true",
+                _ => return None,
+            }
+        }
+
+        // 条件なしのルールはディレクトリ全体を消してしまうため受け付けない
+        if rule.older_than_days.is_none() && rule.keep_newest.is_none() {
+            return None;
+        }
+        Some(rule)
+    }
+
+    pub fn to_ini_value(&self) -> String {
+        let mut value = self.directory.display().to_string();
+        if let Some(pattern) = &self.pattern {
+            value.push_str(&format!("|pattern={}", pattern));
+        }
+        if let Some(days) = self.older_than_days {
+            value.push_str(&format!("|older_than_days={}", days));
+        }
+        if let Some(keep) = self.keep_newest {
+            value.push_str(&format!("|keep_newest={}", keep));
+        }
+        if self.recursive {
+            value.push_str("|recursive=true");
+        }
+        value
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CleanupReport {
+    pub rule: String,
+    pub dry_run: bool,
+    pub deleted: Vec<String>,
+    pub freed_bytes: u64,
+    pub errors: Vec<String>,
+}
+
+/// `*` と `?` のワイルドカード照合 (大文字小文字を区別しない)
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// ルールを適用する。dry_run の場合は削除対象の一覧のみ返す。
+pub fn apply(rule: &CleanupRule, excludes: &[String], dry_run: bool) -> CleanupReport {
+    let mut report = CleanupReport {
+        rule: rule.to_ini_value(),
+        dry_run,
+        deleted: Vec::new(),
+        freed_bytes: 0,
+        errors: Vec::new(),
+    };
+
+    let max_depth = if rule.recursive { usize::MAX } else { 1 };
+    let mut candidates: Vec<(PathBuf, SystemTime, u64)> = Vec::new();
+    let walker = WalkDir::new(&rule.directory)
+        .max_depth(max_depth)
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !walk::is_excluded_name(excludes, e.path()));
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                report.errors.push(e.to_string());
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy();
+        if let Some(pattern) = &rule.pattern {
+            if !wildcard_match(pattern, &name) {
+                continue;
+            }
+        }
+        if let Ok(metadata) = entry.metadata() {
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            candidates.push((entry.into_path(), modified, metadata.len()));
+        }
+    }
+
+    // 新しい順に並べ、keep_newest 件を残す
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.1));
+    let skip = rule.keep_newest.unwrap_or(0);
+    let cutoff = rule
+        .older_than_days
+        .and_then(|days| SystemTime::now().checked_sub(Duration::from_secs(days * 24 * 60 * 60)));
+
+    for (path, modified, size) in candidates.into_iter().skip(skip) {
+        if let Some(cutoff) = cutoff {
+            if modified >= cutoff {
+                continue;
+            }
+        }
+        if !dry_run {
+            if let Err(e) = fs::remove_file(&path) {
+                report.errors.push(format!("{}: {}", path.display(), e));
+                continue;
+            }
+        }
+        report.deleted.push(path.to_string_lossy().to_string());
+        report.freed_bytes += size;
+    }
+    report
+}
+
+/// 全ルールを実行し、削除したファイルを監査ログと変更履歴に記録する
+pub fn run_all(rules: &[CleanupRule], excludes: &[String], dry_run: bool, audit: &AuditLog, changes: &ChangeLog) -> Vec<CleanupReport> {
+    let reports: Vec<CleanupReport> = rules.iter().map(|rule| apply(rule, excludes, dry_run)).collect();
+    if !dry_run {
+        for report in &reports {
+            for path in &report.deleted {
+                audit.record("cleanup_delete", path, &report.rule);
+                changes.record("delete", path, None);
+            }
+            for error in &report.errors {
+                audit.record("cleanup_error", &report.rule, error);
+            }
+        }
+    }
+    reports
+}
+
+/// 一定間隔でクリーンアップルールを実行するスケジューラ
+pub async fn run_scheduler(rules: Vec<CleanupRule>, excludes: Vec<String>, interval: Duration, audit: Arc<AuditLog>, changes: Arc<ChangeLog>) {
+    if rules.is_empty() {
+        return;
+    }
+    let rules = Arc::new(rules);
+    let excludes = Arc::new(excludes);
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    loop {
+        ticker.tick().await;
+        let rules = rules.clone();
+        let excludes = excludes.clone();
+        let audit = audit.clone();
+        let changes = changes.clone();
+        let result = tokio::task::spawn_blocking(move || run_all(&rules, &excludes, false, &audit, &changes)).await;
+        match result {
+            Ok(reports) => {
+                let count: usize = reports.iter().map(|r| r.deleted.len()).sum();
+                if count > 0 {
+                    log!("🧹 クリーンアップ: {} 件のファイルを削除しました", count);
+                }
+            }
+            Err(e) => log_error!("❌ クリーンアップの実行に失敗しました: {}", e),
+        }
+    }
+}
@@ -0,0 +1,648 @@
+//! file_agent の API を呼び出す Rust のクライアント (client フィーチャー)
+//!
+//! リクエストとレスポンスの型はエージェントと同じもの ([`crate::handlers`] など) を使う。
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), file_agent::client::Error> {
+//! let client = file_agent::client::Client::new("http://localhost:8767", "your-token");
+//! let files = client.list("C:\\Users\\me\\Documents", false).await?;
+//! let content = client.read_binary(&files[0].path).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use base64::{Engine as _, engine::general_purpose};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::handlers::*;
+use crate::{changes, cleanup, clients, dirsize, exec, git, index, jobs, mime, plugins, s3, script, shares, sync, trash, vault};
+
+/// クライアントのエラー
+#[derive(Debug)]
+pub enum Error {
+    /// 接続できない、応答を読み込めないなど
+    Http(reqwest::Error),
+    /// リクエストの処理前に拒否された (403 / 411 / 413 / 429 / 503 など)。429 と 503 では retry_after_secs 秒後に再試行できる
+    Status {
+        status: u16,
+        message: String,
+        retry_after_secs: Option<u64>,
+    },
+    /// エージェントが success: false を返した。data は不正なパス・ハッシュの競合などの詳細
+    Api {
+        message: String,
+        data: Option<serde_json::Value>,
+    },
+    /// 応答が期待した形式ではない
+    Decode(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "Request failed: {}", e),
+            Error::Status { status, message, .. } => write!(f, "HTTP {}: {}", status, message),
+            Error::Api { message, .. } => write!(f, "{}", message),
+            Error::Decode(e) => write!(f, "Unexpected response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// success / error と、拒否 (429 など) の retry_after_secs だけを先に読む
+#[derive(Deserialize)]
+struct Envelope {
+    #[serde(default)]
+    success: Option<bool>,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    data: Option<serde_json::Value>,
+    #[serde(default)]
+    retry_after_secs: Option<u64>,
+}
+
+/// エージェントの API クライアント (/api/v1 を呼び出す)
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    token: String,
+}
+
+impl Client {
+    /// base_url は "http://localhost:8767" のようなエージェントのアドレス
+    pub fn new(base_url: &str, token: &str) -> Self {
+        Self::with_http_client(reqwest::Client::new(), base_url, token)
+    }
+
+    /// 証明書やタイムアウトを設定した reqwest::Client を使う (自己署名の証明書の TLS など)
+    pub fn with_http_client(http: reqwest::Client, base_url: &str, token: &str) -> Self {
+        Client {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// 以降のリクエストで使うトークンを変える (rotate_token の後など)
+    pub fn set_token(&mut self, token: &str) {
+        self.token = token.to_string();
+    }
+
+    fn url(&self, endpoint: &str) -> String {
+        format!("{}/api/v1/{}", self.base_url, endpoint)
+    }
+
+    async fn get_envelope<Q: Serialize + ?Sized>(&self, endpoint: &str, query: &Q) -> Result<serde_json::Value> {
+        let response = self.http.get(self.url(endpoint)).query(query).send().await?;
+        envelope(response).await
+    }
+
+    async fn post_envelope<B: Serialize + ?Sized>(&self, endpoint: &str, body: &B) -> Result<serde_json::Value> {
+        let response = self.http.post(self.url(endpoint)).json(body).send().await?;
+        envelope(response).await
+    }
+
+    // ApiResponse<T> の data を返す
+    async fn get<T: DeserializeOwned, Q: Serialize + ?Sized>(&self, endpoint: &str, query: &Q) -> Result<T> {
+        data(self.get_envelope(endpoint, query).await?)
+    }
+
+    async fn post<T: DeserializeOwned, B: Serialize + ?Sized>(&self, endpoint: &str, body: &B) -> Result<T> {
+        data(self.post_envelope(endpoint, body).await?)
+    }
+
+    // ReceiptResponse など、data 以外の項目もあるレスポンスは全体を返す
+    async fn post_full<T: DeserializeOwned, B: Serialize + ?Sized>(&self, endpoint: &str, body: &B) -> Result<T> {
+        decode(self.post_envelope(endpoint, body).await?)
+    }
+
+    pub async fn health(&self) -> Result<HealthInfo> {
+        self.get("health", &[("token", &self.token)]).await
+    }
+
+    pub async fn version(&self) -> Result<VersionInfo> {
+        self.get("version", &()).await
+    }
+
+    pub async fn capabilities(&self) -> Result<Capabilities> {
+        self.get("capabilities", &[("token", &self.token)]).await
+    }
+
+    pub async fn read(&self, path: &str) -> Result<String> {
+        self.post("read", &self.read_request(path, None, false)).await
+    }
+
+    /// 内容とハッシュを読み込む。content_hash が現在のハッシュと違えば Error::Api (data は HashConflict)
+    pub async fn read_with_hash(&self, path: &str, content_hash: Option<&str>) -> Result<ReadWithHash> {
+        self.post("read", &self.read_request(path, content_hash, true)).await
+    }
+
+    pub async fn read_binary(&self, path: &str) -> Result<Vec<u8>> {
+        let content: String = self.post("read_binary", &self.read_request(path, None, false)).await?;
+        general_purpose::STANDARD.decode(content).map_err(|e| Error::Decode(e.to_string()))
+    }
+
+    /// offset バイト目から最大 length バイトを読み込む (length が None ならファイルの終わりまで)
+    pub async fn read_binary_range(&self, path: &str, offset: u64, length: Option<u64>) -> Result<Vec<u8>> {
+        let request = ReadRequest {
+            offset: Some(offset),
+            length,
+            ..self.read_request(path, None, false)
+        };
+        let content: String = self.post("read_binary", &request).await?;
+        general_purpose::STANDARD.decode(content).map_err(|e| Error::Decode(e.to_string()))
+    }
+
+    fn read_request(&self, path: &str, content_hash: Option<&str>, include_hash: bool) -> ReadRequest {
+        ReadRequest {
+            path: path.to_string(),
+            token: self.token.clone(),
+            content_hash: content_hash.map(str::to_string),
+            include_hash,
+            offset: None,
+            length: None,
+        }
+    }
+
+    pub async fn read_chunk(&self, path: &str, seq: u64, chunk_size: Option<u64>) -> Result<ChunkInfo> {
+        let request = ReadChunkRequest {
+            path: path.to_string(),
+            seq,
+            chunk_size,
+            token: self.token.clone(),
+        };
+        self.post("read_chunk", &request).await
+    }
+
+    pub async fn detect_mime(&self, path: &str) -> Result<mime::Detection> {
+        let request = MimeRequest {
+            path: path.to_string(),
+            token: self.token.clone(),
+        };
+        self.post("mime", &request).await
+    }
+
+    /// ディレクトリ配下の合計サイズ (refresh が false なら、変わっていなければエージェントのキャッシュから返る)
+    pub async fn dir_size(&self, directory: &str, refresh: bool) -> Result<dirsize::DirSize> {
+        let request = DirSizeRequest {
+            directory: directory.to_string(),
+            token: self.token.clone(),
+            refresh,
+        };
+        self.post("du", &request).await
+    }
+
+    /// ファイルの SHA256 (変わっていないファイルはエージェントのキャッシュから返る)
+    pub async fn hash(&self, path: &str) -> Result<FileHash> {
+        let request = HashRequest {
+            path: path.to_string(),
+            token: self.token.clone(),
+        };
+        self.post("hash", &request).await
+    }
+
+    pub async fn write(&self, path: &str, content: &str) -> Result<ReceiptResponse> {
+        let request = WriteRequest {
+            path: path.to_string(),
+            content: content.to_string(),
+            token: self.token.clone(),
+        };
+        self.post_full("write", &request).await
+    }
+
+    pub async fn write_binary(&self, path: &str, content: &[u8]) -> Result<ReceiptResponse> {
+        let request = WriteBinaryRequest {
+            path: path.to_string(),
+            content: general_purpose::STANDARD.encode(content),
+            token: self.token.clone(),
+        };
+        self.post_full("write_binary", &request).await
+    }
+
+    /// 内容を Base64 にせずそのまま送る (PUT /api/file)。長さの分からない本文はエージェントが 411 で拒否する
+    pub async fn upload(&self, path: &str, content: impl Into<reqwest::Body>) -> Result<ReceiptResponse> {
+        let response = self.http.put(self.url("file"))
+            .query(&[("path", path), ("token", &self.token)])
+            .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+            .body(content)
+            .send()
+            .await?;
+        decode(envelope(response).await?)
+    }
+
+    /// 内容を Base64 にせずそのまま受け取る (GET /api/file)
+    pub async fn download(&self, path: &str) -> Result<Vec<u8>> {
+        let response = self.http.get(self.url("file"))
+            .query(&[("path", path), ("token", &self.token)])
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(envelope(response).await.err().unwrap_or_else(|| Error::Decode("unexpected download response".to_string())));
+        }
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    /// recursive が false なら空でないフォルダは削除しない
+    pub async fn delete(&self, path: &str, recursive: bool) -> Result<ReceiptResponse> {
+        let request = DeleteRequest {
+            path: path.to_string(),
+            token: self.token.clone(),
+            recursive,
+        };
+        self.post_full("delete", &request).await
+    }
+
+    pub async fn list(&self, path: &str, show_hidden: bool) -> Result<Vec<FileInfo>> {
+        let show_hidden = show_hidden.to_string();
+        self.get("list", &[("path", path), ("token", &self.token), ("show_hidden", &show_hidden)]).await
+    }
+
+    /// request.token は無視してクライアントのトークンを使う (SearchRequest::default() から作れる)
+    pub async fn search(&self, mut request: SearchRequest) -> Result<SearchResponse> {
+        request.token = self.token.clone();
+        self.post_full("search", &request).await
+    }
+
+    /// request.token は無視してクライアントのトークンを使う
+    pub async fn grep(&self, mut request: GrepRequest) -> Result<GrepResult> {
+        request.token = self.token.clone();
+        self.post("grep", &request).await
+    }
+
+    pub async fn index_search(&self, query: &str, limit: Option<usize>) -> Result<index::IndexSearchResult> {
+        let request = IndexSearchRequest {
+            query: query.to_string(),
+            token: self.token.clone(),
+            limit,
+        };
+        self.post("index/search", &request).await
+    }
+
+    /// request.token は無視してクライアントのトークンを使う
+    pub async fn stale(&self, mut request: StaleRequest) -> Result<StaleReport> {
+        request.token = self.token.clone();
+        self.post("stale", &request).await
+    }
+
+    pub async fn create(&self, path: &str, is_directory: bool) -> Result<ReceiptResponse> {
+        let request = CreateRequest {
+            path: path.to_string(),
+            is_directory,
+            template: None,
+            variables: HashMap::new(),
+            token: self.token.clone(),
+        };
+        self.post_full("create", &request).await
+    }
+
+    /// template_dir= のテンプレートからファイルを作成する ({{name}}・{{date}} と variables のキーを置き換える)
+    pub async fn create_from_template(&self, path: &str, template: &str, variables: HashMap<String, String>) -> Result<ReceiptResponse> {
+        let request = CreateRequest {
+            path: path.to_string(),
+            is_directory: false,
+            template: Some(template.to_string()),
+            variables,
+            token: self.token.clone(),
+        };
+        self.post_full("create", &request).await
+    }
+
+    pub async fn move_path(&self, source: &str, destination: &str) -> Result<ReceiptResponse> {
+        let request = MoveRequest {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            token: self.token.clone(),
+        };
+        self.post_full("move", &request).await
+    }
+
+    /// background を指定したフォルダのコピーは、data に開始したジョブ (jobs::CopyJob の JSON) が入る
+    pub async fn copy(&self, source: &str, destination: &str, background: bool) -> Result<ReceiptResponse> {
+        let request = CopyRequest {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            token: self.token.clone(),
+            background,
+        };
+        self.post_full("copy", &request).await
+    }
+
+    pub async fn jobs(&self) -> Result<Vec<jobs::CopyJob>> {
+        self.get("jobs", &[("token", &self.token)]).await
+    }
+
+    pub async fn paste_from_clipboard(&self, destination: &str, overwrite: bool) -> Result<PasteResult> {
+        let request = PasteRequest {
+            destination: destination.to_string(),
+            token: self.token.clone(),
+            overwrite,
+        };
+        self.post("paste_from_clipboard", &request).await
+    }
+
+    pub async fn print(&self, path: &str, printer: Option<&str>) -> Result<String> {
+        let request = PrintRequest {
+            path: path.to_string(),
+            printer: printer.map(str::to_string),
+            token: self.token.clone(),
+        };
+        self.post("print", &request).await
+    }
+
+    pub async fn cleanup(&self, dry_run: bool) -> Result<Vec<cleanup::CleanupReport>> {
+        let request = CleanupRequest {
+            token: self.token.clone(),
+            dry_run,
+        };
+        self.post("cleanup", &request).await
+    }
+
+    /// cursor の後の変更を、最大 wait_secs 秒待って返す (cursor が None なら最新の位置から)
+    pub async fn poll_changes(&self, cursor: Option<u64>, wait_secs: u64) -> Result<changes::ChangePoll> {
+        let mut query = vec![("token", self.token.clone()), ("wait", wait_secs.to_string())];
+        if let Some(cursor) = cursor {
+            query.push(("cursor", cursor.to_string()));
+        }
+        self.get("changes/poll", &query).await
+    }
+
+    pub async fn metrics(&self) -> Result<Metrics> {
+        self.get("metrics", &[("token", &self.token)]).await
+    }
+
+    pub async fn tail_logs(&self, lines: usize) -> Result<LogTail> {
+        self.get("logs/tail", &[("token", self.token.clone()), ("lines", lines.to_string())]).await
+    }
+
+    /// 新しいトークンは一度だけ返される (このクライアントには set_token で設定する)
+    pub async fn rotate_token(&self, grace_secs: Option<u64>, expires_in_secs: Option<u64>) -> Result<RotatedToken> {
+        let request = RotateTokenRequest {
+            token: self.token.clone(),
+            grace_secs,
+            expires_in_secs,
+        };
+        self.post("tokens/rotate", &request).await
+    }
+
+    /// エージェントを停止する (メインのトークンのみ)
+    pub async fn shutdown(&self) -> Result<String> {
+        let request = ShutdownRequest {
+            token: self.token.clone(),
+        };
+        self.post("shutdown", &request).await
+    }
+
+    pub async fn pair(&self, name: &str) -> Result<PairResult> {
+        let request = PairRequest {
+            name: name.to_string(),
+            token: self.token.clone(),
+        };
+        self.post("clients/pair", &request).await
+    }
+
+    pub async fn clients(&self) -> Result<Vec<clients::ClientRecord>> {
+        self.get("clients", &[("token", &self.token)]).await
+    }
+
+    pub async fn remove_client(&self, name: &str) -> Result<String> {
+        let request = RemoveClientRequest {
+            name: name.to_string(),
+            token: self.token.clone(),
+        };
+        self.post("clients/remove", &request).await
+    }
+
+    pub async fn trash(&self) -> Result<Vec<trash::HeldEntry>> {
+        self.get("trash", &[("token", &self.token)]).await
+    }
+
+    /// id を省略するとアクセスできる保管中のフォルダをすべて削除する
+    pub async fn purge_trash(&self, id: Option<&str>) -> Result<trash::PurgeReport> {
+        let request = PurgeTrashRequest {
+            token: self.token.clone(),
+            id: id.map(str::to_string),
+        };
+        self.post("trash/purge", &request).await
+    }
+
+    pub async fn vault_status(&self) -> Result<vault::VaultStatus> {
+        self.get("vault/status", &[("token", &self.token)]).await
+    }
+
+    pub async fn vault_unlock(&self, passphrase: &str) -> Result<vault::VaultStatus> {
+        let request = VaultUnlockRequest {
+            token: self.token.clone(),
+            passphrase: passphrase.to_string(),
+        };
+        self.post("vault/unlock", &request).await
+    }
+
+    pub async fn vault_lock(&self) -> Result<vault::VaultStatus> {
+        let request = VaultLockRequest {
+            token: self.token.clone(),
+        };
+        self.post("vault/lock", &request).await
+    }
+
+    pub async fn vault_rotate(&self, passphrase: &str, new_passphrase: Option<&str>) -> Result<vault::VaultStatus> {
+        let request = VaultRotateRequest {
+            token: self.token.clone(),
+            passphrase: passphrase.to_string(),
+            new_passphrase: new_passphrase.map(str::to_string),
+        };
+        self.post("vault/rotate", &request).await
+    }
+
+    pub async fn plugins(&self) -> Result<Vec<plugins::PluginInfo>> {
+        self.get("plugins", &[("token", &self.token)]).await
+    }
+
+    /// プラグインのエンドポイントを呼び出す (body はオブジェクト。token はクライアントが加える)
+    pub async fn call_plugin(&self, plugin: &str, endpoint: &str, body: serde_json::Value) -> Result<serde_json::Value> {
+        let mut body = match body {
+            serde_json::Value::Object(body) => body,
+            _ => serde_json::Map::new(),
+        };
+        body.insert("token".to_string(), serde_json::Value::String(self.token.clone()));
+        self.post(&format!("plugins/{}/{}", plugin, endpoint), &body).await
+    }
+
+    /// destination を指定すると変換した内容をそのファイルに書き込み、省略すると内容を返す
+    pub async fn transform(&self, plugin: &str, transform: &str, path: &str, destination: Option<&str>) -> Result<TransformResult> {
+        let request = TransformRequest {
+            plugin: plugin.to_string(),
+            transform: transform.to_string(),
+            path: path.to_string(),
+            destination: destination.map(str::to_string),
+            token: self.token.clone(),
+        };
+        self.post("plugins/transform", &request).await
+    }
+
+    /// rhai のスクリプトを実行する (params はスクリプトの変数 params になる)
+    pub async fn run_script(&self, script: &str, params: serde_json::Value) -> Result<script::ScriptOutput> {
+        let request = ScriptRequest {
+            script: script.to_string(),
+            params,
+            token: self.token.clone(),
+        };
+        self.post("script", &request).await
+    }
+
+    /// exec= で宣言したコマンドを実行する (args は allow_arg= のどれかに一致すること)
+    pub async fn exec(&self, name: &str, args: &[&str]) -> Result<exec::ExecResult> {
+        let request = ExecRequest {
+            name: name.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            token: self.token.clone(),
+        };
+        self.post("exec", &request).await
+    }
+
+    pub async fn git_status(&self, path: &str) -> Result<git::GitStatus> {
+        let request = GitStatusRequest {
+            path: path.to_string(),
+            token: self.token.clone(),
+        };
+        self.post("git/status", &request).await
+    }
+
+    /// file を指定するとそのファイルの差分だけ、staged が true ならステージした変更の差分
+    pub async fn git_diff(&self, path: &str, file: Option<&str>, staged: bool) -> Result<git::GitDiff> {
+        let request = GitDiffRequest {
+            path: path.to_string(),
+            file: file.map(str::to_string),
+            staged,
+            token: self.token.clone(),
+        };
+        self.post("git/diff", &request).await
+    }
+
+    pub async fn git_log(&self, path: &str, limit: Option<usize>) -> Result<git::GitLog> {
+        let request = GitLogRequest {
+            path: path.to_string(),
+            limit,
+            token: self.token.clone(),
+        };
+        self.post("git/log", &request).await
+    }
+
+    /// ファイルを設定したバケットに送る (key を省略するとファイル名)
+    pub async fn s3_upload(&self, path: &str, key: Option<&str>) -> Result<S3Uploaded> {
+        let request = S3UploadRequest {
+            path: path.to_string(),
+            key: key.map(str::to_string),
+            token: self.token.clone(),
+        };
+        self.post("s3/upload", &request).await
+    }
+
+    /// バケットの key を path に書き込む (あれば置き換える)
+    pub async fn s3_download(&self, key: &str, path: &str) -> Result<ReceiptResponse> {
+        let request = S3DownloadRequest {
+            key: key.to_string(),
+            path: path.to_string(),
+            token: self.token.clone(),
+        };
+        self.post_full("s3/download", &request).await
+    }
+
+    /// prefix の直下のオブジェクトと「フォルダ」 (続きは next_continuation を continuation に渡す)
+    pub async fn s3_list(&self, prefix: &str, continuation: Option<&str>) -> Result<s3::S3Listing> {
+        let request = S3ListRequest {
+            prefix: prefix.to_string(),
+            continuation: continuation.map(str::to_string),
+            token: self.token.clone(),
+        };
+        self.post("s3/list", &request).await
+    }
+
+    /// sync= の同期ごとの状態と前回の結果
+    pub async fn sync_status(&self) -> Result<Vec<sync::SyncStatus>> {
+        self.get("sync/status", &[("token", &self.token)]).await
+    }
+
+    /// 接続中のネットワーク共有と share= の共有
+    pub async fn shares(&self) -> Result<Vec<shares::ShareInfo>> {
+        self.get("shares", &[("token", &self.token)]).await
+    }
+
+    pub async fn clipboard_text(&self) -> Result<String> {
+        let content: ClipboardContent = self.get("clipboard/get", &[("token", &self.token)]).await?;
+        Ok(content.text)
+    }
+
+    pub async fn set_clipboard_text(&self, text: &str) -> Result<String> {
+        let request = ClipboardSetRequest {
+            text: text.to_string(),
+            token: self.token.clone(),
+        };
+        self.post("clipboard/set", &request).await
+    }
+}
+
+// HTTP のエラーと success: false をエラーにし、成功した応答の JSON を返す
+async fn envelope(response: reqwest::Response) -> Result<serde_json::Value> {
+    let status = response.status();
+    let body = response.bytes().await?;
+    let value: serde_json::Value = match serde_json::from_slice(&body) {
+        Ok(value) => value,
+        Err(_) if !status.is_success() => {
+            return Err(Error::Status {
+                status: status.as_u16(),
+                message: String::from_utf8_lossy(&body).trim().to_string(),
+                retry_after_secs: None,
+            })
+        }
+        Err(e) => return Err(Error::Decode(e.to_string())),
+    };
+    let header = Envelope::deserialize(&value).map_err(|e| Error::Decode(e.to_string()))?;
+    if !status.is_success() {
+        return Err(Error::Status {
+            status: status.as_u16(),
+            message: header.error.unwrap_or_else(|| status.to_string()),
+            retry_after_secs: header.retry_after_secs,
+        });
+    }
+    match header.success {
+        Some(true) => Ok(value),
+        Some(false) => Err(Error::Api {
+            message: header.error.unwrap_or_else(|| "Request failed".to_string()),
+            data: header.data.filter(|data| !data.is_null()),
+        }),
+        None => Err(Error::Decode("missing success".to_string())),
+    }
+}
+
+fn data<T: DeserializeOwned>(mut value: serde_json::Value) -> Result<T> {
+    decode(value.get_mut("data").map(serde_json::Value::take).unwrap_or_default())
+}
+
+fn decode<T: DeserializeOwned>(value: serde_json::Value) -> Result<T> {
+    serde_json::from_value(value).map_err(|e| Error::Decode(e.to_string()))
+}
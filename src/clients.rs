@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+// last_seen の更新をファイルに書き出す最小間隔 (秒)
+const SEEN_SAVE_INTERVAL_SECS: u64 = 60;
+
+/// ペアリング済みクライアントの記録
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct ClientRecord {
+    pub name: String,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub token_fingerprint: String, // ペアリング時のトークンの SHA256 先頭 16 桁
+}
+
+/// クライアント一覧 (JSON ファイルに保存)
+pub struct ClientRegistry {
+    path: PathBuf,
+    clients: Mutex<Vec<ClientRecord>>,
+}
+
+impl ClientRegistry {
+    pub fn load(path: PathBuf) -> Self {
+        let clients = fs::read(&path)
+            .ok()
+            .and_then(|content| serde_json::from_slice(&content).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            clients: Mutex::new(clients),
+        }
+    }
+
+    fn save(&self, clients: &[ClientRecord]) {
+        let result = serde_json::to_vec_pretty(clients)
+            .map_err(|e| e.to_string())
+            .and_then(|content| fs::write(&self.path, content).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            log_error!("⚠️ クライアント一覧の保存に失敗しました: {}", e);
+        }
+    }
+
+    /// クライアントを登録する (登録済みなら last_seen とフィンガープリントを更新)
+    pub fn pair(&self, name: &str, token_fingerprint: &str) -> ClientRecord {
+        let now = now_secs();
+        let mut clients = self.clients.lock().unwrap();
+        let record = match clients.iter_mut().find(|c| c.name == name) {
+            Some(record) => {
+                record.last_seen = now;
+                record.token_fingerprint = token_fingerprint.to_string();
+                record.clone()
+            }
+            None => {
+                let record = ClientRecord {
+                    name: name.to_string(),
+                    first_seen: now,
+                    last_seen: now,
+                    token_fingerprint: token_fingerprint.to_string(),
+                };
+                clients.push(record.clone());
+                record
+            }
+        };
+        self.save(&clients);
+        record
+    }
+
+    /// 登録済みクライアントからのアクセスを記録する
+    pub fn seen(&self, name: &str) {
+        let now = now_secs();
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(record) = clients.iter_mut().find(|c| c.name == name) {
+            let save = now.saturating_sub(record.last_seen) >= SEEN_SAVE_INTERVAL_SECS;
+            record.last_seen = now;
+            if save {
+                self.save(&clients);
+            }
+        }
+    }
+
+    pub fn list(&self) -> Vec<ClientRecord> {
+        self.clients.lock().unwrap().clone()
+    }
+
+    pub fn remove(&self, name: &str) -> bool {
+        let mut clients = self.clients.lock().unwrap();
+        let before = clients.len();
+        clients.retain(|c| c.name != name);
+        let removed = clients.len() != before;
+        if removed {
+            self.save(&clients);
+        }
+        removed
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
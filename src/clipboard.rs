@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+
+// 他のアプリケーションが開いている間は開けないため、少し待って開き直す回数
+#[cfg(target_os = "windows")]
+const OPEN_ATTEMPTS: u32 = 10;
+
+/// クリップボード上のファイル一覧 (エクスプローラーでの「コピー」) を取得する
+#[cfg(target_os = "windows")]
+pub fn file_list() -> Result<Vec<PathBuf>, String> {
+    use std::ffi::OsString;
+    use std::os::windows::ffi::OsStringExt;
+    use winapi::um::shellapi::{DragQueryFileW, HDROP};
+    use winapi::um::winuser::{CloseClipboard, GetClipboardData, IsClipboardFormatAvailable, OpenClipboard, CF_HDROP};
+
+    unsafe {
+        if IsClipboardFormatAvailable(CF_HDROP) == 0 {
+            return Err("Clipboard does not contain files".to_string());
+        }
+        if OpenClipboard(std::ptr::null_mut()) == 0 {
+            return Err("Failed to open clipboard".to_string());
+        }
+
+        let handle = GetClipboardData(CF_HDROP);
+        if handle.is_null() {
+            CloseClipboard();
+            return Err("Failed to read clipboard data".to_string());
+        }
+
+        let hdrop = handle as HDROP;
+        let count = DragQueryFileW(hdrop, 0xFFFF_FFFF, std::ptr::null_mut(), 0);
+        let mut files = Vec::with_capacity(count as usize);
+        for i in 0..count {
+            let len = DragQueryFileW(hdrop, i, std::ptr::null_mut(), 0);
+            let mut buffer = vec![0u16; len as usize + 1];
+            DragQueryFileW(hdrop, i, buffer.as_mut_ptr(), buffer.len() as u32);
+            files.push(PathBuf::from(OsString::from_wide(&buffer[..len as usize])));
+        }
+
+        CloseClipboard();
+        Ok(files)
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn file_list() -> Result<Vec<PathBuf>, String> {
+    Err("Pasting files from the clipboard is only supported on Windows".to_string())
+}
+
+// クリップボードを開く (開けなければ少し待って開き直す)
+#[cfg(target_os = "windows")]
+unsafe fn open() -> Result<(), String> {
+    use winapi::um::winuser::OpenClipboard;
+
+    for _ in 0..OPEN_ATTEMPTS {
+        if OpenClipboard(std::ptr::null_mut()) != 0 {
+            return Ok(());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    Err("Failed to open clipboard".to_string())
+}
+
+/// クリップボードのテキストを取得する
+#[cfg(target_os = "windows")]
+pub fn text() -> Result<String, String> {
+    use winapi::um::winbase::{GlobalLock, GlobalUnlock};
+    use winapi::um::winuser::{CloseClipboard, GetClipboardData, IsClipboardFormatAvailable, CF_UNICODETEXT};
+
+    unsafe {
+        if IsClipboardFormatAvailable(CF_UNICODETEXT) == 0 {
+            return Err("Clipboard does not contain text".to_string());
+        }
+        open()?;
+
+        let handle = GetClipboardData(CF_UNICODETEXT);
+        let data = if handle.is_null() { std::ptr::null() } else { GlobalLock(handle) as *const u16 };
+        if data.is_null() {
+            CloseClipboard();
+            return Err("Failed to read clipboard data".to_string());
+        }
+
+        let mut len = 0;
+        while *data.add(len) != 0 {
+            len += 1;
+        }
+        let text = String::from_utf16_lossy(std::slice::from_raw_parts(data, len));
+
+        GlobalUnlock(handle);
+        CloseClipboard();
+        Ok(text)
+    }
+}
+
+/// クリップボードにテキストを設定する
+#[cfg(target_os = "windows")]
+pub fn set_text(text: &str) -> Result<(), String> {
+    use winapi::um::winbase::{GlobalAlloc, GlobalFree, GlobalLock, GlobalUnlock, GMEM_MOVEABLE};
+    use winapi::um::winuser::{CloseClipboard, EmptyClipboard, SetClipboardData, CF_UNICODETEXT};
+
+    let wide: Vec<u16> = text.encode_utf16().chain(std::iter::once(0)).collect();
+    unsafe {
+        let memory = GlobalAlloc(GMEM_MOVEABLE, wide.len() * 2);
+        if memory.is_null() {
+            return Err("Failed to allocate clipboard memory".to_string());
+        }
+        let data = GlobalLock(memory) as *mut u16;
+        if data.is_null() {
+            GlobalFree(memory);
+            return Err("Failed to allocate clipboard memory".to_string());
+        }
+        std::ptr::copy_nonoverlapping(wide.as_ptr(), data, wide.len());
+        GlobalUnlock(memory);
+
+        if let Err(e) = open() {
+            GlobalFree(memory);
+            return Err(e);
+        }
+        EmptyClipboard();
+        // 設定できたメモリはクリップボードのものになるので、失敗したときだけ解放する
+        if SetClipboardData(CF_UNICODETEXT, memory).is_null() {
+            CloseClipboard();
+            GlobalFree(memory);
+            return Err("Failed to set clipboard data".to_string());
+        }
+        CloseClipboard();
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn text() -> Result<String, String> {
+    Err("Reading the clipboard is only supported on Windows".to_string())
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn set_text(_text: &str) -> Result<(), String> {
+    Err("Setting the clipboard is only supported on Windows".to_string())
+}
@@ -0,0 +1,119 @@
+use std::io::Write;
+use warp::http::header::{self, HeaderValue};
+use warp::http::StatusCode;
+use warp::hyper::Body;
+use warp::reply::Response;
+
+/// 応答の圧縮方式 (Accept-Encoding で両方受け付ける場合は brotli を優先する)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// Accept-Encoding から使う方式を選ぶ (q=0 は受け付けない扱い)
+    pub fn negotiate(accept_encoding: &str) -> Option<Self> {
+        let accepted: Vec<&str> = accept_encoding
+            .split(',')
+            .filter_map(|item| {
+                let mut parts = item.split(';');
+                let name = parts.next()?.trim();
+                let refused = parts.any(|param| {
+                    param.trim().strip_prefix("q=").and_then(|q| q.trim().parse::<f32>().ok()) == Some(0.0)
+                });
+                (!refused).then_some(name)
+            })
+            .collect();
+        let accepts = |name: &str| accepted.iter().any(|item| item.eq_ignore_ascii_case(name) || *item == "*");
+        if accepts("br") {
+            Some(Encoding::Brotli)
+        } else if accepts("gzip") {
+            Some(Encoding::Gzip)
+        } else {
+            None
+        }
+    }
+
+    fn encode(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                // 品質 5 は速度と圧縮率の釣り合いがよい (11 は JSON の応答には遅すぎる)
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 64 * 1024, 5, 22);
+                encoder.write_all(data)?;
+                Ok(encoder.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+// API の JSON の応答だけを圧縮する。Content-Length のある応答は GET /api/file のダウンロードで、
+// .json のファイルでも全体を読み込まないよう対象外にする (NDJSON のストリームは Content-Type で除く)
+fn is_compressible(response: &Response) -> bool {
+    let headers = response.headers();
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    response.status() != StatusCode::NOT_MODIFIED
+        && is_json
+        && !headers.contains_key(header::CONTENT_ENCODING)
+        && !headers.contains_key(header::CONTENT_LENGTH)
+}
+
+/// リクエストごとの圧縮の条件 (encoding が None ならクライアントが圧縮を受け付けない)
+#[derive(Debug, Clone, Copy)]
+pub struct Negotiated {
+    pub encoding: Option<Encoding>,
+    pub min_bytes: u64,
+}
+
+/// min_bytes 以上の JSON の応答を圧縮する (None なら圧縮が無効。圧縮しても小さくならない場合はそのまま返す)
+pub async fn response(negotiated: Option<Negotiated>, mut response: Response) -> Response {
+    let Some(Negotiated { encoding, min_bytes }) = negotiated.filter(|_| is_compressible(&response)) else {
+        return response;
+    };
+    // 同じ URL でも Accept-Encoding によって応答が変わることをキャッシュに伝える
+    response.headers_mut().append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    let Some(encoding) = encoding else {
+        return response;
+    };
+    let (mut parts, body) = response.into_parts();
+    let bytes = match warp::hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(_) => return Response::from_parts(parts, Body::empty()),
+    };
+    if (bytes.len() as u64) < min_bytes {
+        return Response::from_parts(parts, Body::from(bytes));
+    }
+
+    let data = bytes.clone();
+    let compressed = tokio::task::spawn_blocking(move || encoding.encode(&data)).await;
+    match compressed {
+        Ok(Ok(compressed)) if compressed.len() < bytes.len() => {
+            parts.headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+            // 圧縮すると内容のバイト列が変わるため、ETag は弱い比較用にする (If-None-Match はそのまま一致する)
+            if let Some(etag) = parts.headers.get(header::ETAG).and_then(|value| value.to_str().ok()) {
+                if !etag.starts_with("W/") {
+                    if let Ok(weak) = HeaderValue::from_str(&format!("W/{}", etag)) {
+                        parts.headers.insert(header::ETAG, weak);
+                    }
+                }
+            }
+            Response::from_parts(parts, Body::from(compressed))
+        }
+        _ => Response::from_parts(parts, Body::from(bytes)),
+    }
+}
@@ -0,0 +1,131 @@
+//! 同時に処理するリクエストと重い処理 (ディレクトリの走査・ハッシュ・コピージョブ) の数の制限
+//!
+//! 上限に達している場合は busy_wait_secs まで空きを待ち、空かなければ 503 (busy) で拒否する。
+//! バックグラウンドのコピージョブは拒否せず、空くまで待ってから始める。
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// 制限の種類 (エラーのメッセージに使う)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Requests,
+    Heavy,
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Requests => "concurrent requests",
+            Kind::Heavy => "concurrent heavy operations",
+        }
+    }
+}
+
+/// 上限に達して空かなかったリクエストの拒否理由 (recover で 503 のレスポンスにする)
+#[derive(Debug)]
+pub struct Busy {
+    pub kind: Kind,
+    pub limit: usize,
+    pub retry_after_secs: u64,
+}
+
+impl warp::reject::Reject for Busy {}
+
+#[derive(Debug, Serialize)]
+pub struct BusyResponse {
+    pub success: bool,
+    pub data: Option<()>,
+    pub error: Option<String>,
+    pub busy: bool,
+    pub retry_after_secs: u64,
+}
+
+impl From<&Busy> for BusyResponse {
+    fn from(busy: &Busy) -> Self {
+        Self {
+            success: false,
+            data: None,
+            error: Some(format!(
+                "Server busy: too many {} (limit {}); retry after {} seconds",
+                busy.kind.name(),
+                busy.limit,
+                busy.retry_after_secs
+            )),
+            busy: true,
+            retry_after_secs: busy.retry_after_secs,
+        }
+    }
+}
+
+/// 同時に実行する数の上限 (limit が 0 なら制限なし)
+pub struct Limiter {
+    kind: Kind,
+    limit: usize,
+    wait: Duration,
+    semaphore: Option<Arc<Semaphore>>,
+}
+
+impl Limiter {
+    pub fn new(kind: Kind, limit: usize, wait: Duration) -> Self {
+        Self {
+            kind,
+            limit,
+            wait,
+            semaphore: (limit > 0).then(|| Arc::new(Semaphore::new(limit))),
+        }
+    }
+
+    /// 空きを最大 wait まで待つ。返した許可を破棄すると空きに戻る (制限なしなら None)
+    pub async fn acquire(&self) -> Result<Option<OwnedSemaphorePermit>, Busy> {
+        let Some(semaphore) = &self.semaphore else {
+            return Ok(None);
+        };
+        match tokio::time::timeout(self.wait, semaphore.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(Some(permit)),
+            // セマフォは閉じないため、エラーは待ち時間を過ぎた場合だけ
+            _ => Err(Busy {
+                kind: self.kind,
+                limit: self.limit,
+                retry_after_secs: self.wait.as_secs().max(1),
+            }),
+        }
+    }
+
+    /// 空くまで待つ (バックグラウンドの処理用で、拒否しない)
+    pub async fn queue(&self) -> Option<OwnedSemaphorePermit> {
+        let semaphore = self.semaphore.as_ref()?;
+        semaphore.clone().acquire_owned().await.ok()
+    }
+
+    /// 処理中の数
+    pub fn in_use(&self) -> usize {
+        self.semaphore.as_ref().map_or(0, |semaphore| self.limit - semaphore.available_permits())
+    }
+}
+
+// 重い処理の上限 (ハンドラーやジョブのどこからでも使うため、サーバーの起動時に設定する)
+static HEAVY: RwLock<Option<Arc<Limiter>>> = RwLock::new(None);
+
+/// 重い処理の上限を設定する (max_heavy_operations= と busy_wait_secs=)
+pub fn set_heavy_limit(limit: usize, wait: Duration) {
+    *HEAVY.write().unwrap() = Some(Arc::new(Limiter::new(Kind::Heavy, limit, wait)));
+}
+
+/// 重い処理の上限 (設定していなければ制限なし)
+pub fn heavy() -> Arc<Limiter> {
+    HEAVY
+        .read()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| Arc::new(Limiter::new(Kind::Heavy, 0, Duration::ZERO)))
+}
+
+/// 重い処理の空きを待ってから、専用スレッドで task を実行する (コピージョブなどのバックグラウンドの処理用)
+pub fn spawn_heavy(task: impl FnOnce() + Send + 'static) {
+    tokio::spawn(async move {
+        let _permit = heavy().queue().await;
+        let _ = tokio::task::spawn_blocking(task).await;
+    });
+}
@@ -0,0 +1,1263 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::auth::{self, Operation, TokenMeta, TokenTier};
+use crate::cleanup::CleanupRule;
+use crate::exec::ExecCommand;
+use crate::hooks::Hook;
+use crate::{generate_agent_id, generate_token, generate_token_hash};
+use crate::{copy, grep, index, ini, ipfilter, logs, policy, s3, walk, webdav};
+use crate::listener::Listener;
+use crate::policy::RootPolicy;
+use crate::quota::DirQuota;
+use crate::scan::Scanner;
+use crate::shares::Share;
+use crate::sync::SyncTask;
+use crate::vault::VaultKeyInfo;
+
+// 検索結果の既定の上限件数とタイムアウト
+const DEFAULT_SEARCH_MAX_RESULTS: usize = 1000;
+const DEFAULT_SEARCH_TIMEOUT_SECS: u64 = 30;
+
+// ディレクトリ一覧キャッシュの既定の有効期間 (0 で無効)
+const DEFAULT_LIST_CACHE_TTL_SECS: u64 = 5;
+
+// ディレクトリの合計サイズのキャッシュの既定の有効期間 (0 で無効。エージェント外の変更はこの時間まで反映されない)
+const DEFAULT_DIR_SIZE_CACHE_TTL_SECS: u64 = 60;
+
+// 書き込み中の一時ファイルを残しておく既定の時間 (これより古いものは転送が止まったものとして削除する)
+const DEFAULT_TEMP_MAX_AGE_HOURS: u64 = 24;
+
+// 削除したフォルダを保管する既定の時間
+const DEFAULT_SOFT_DELETE_RETENTION_HOURS: u64 = 72;
+
+// リクエスト本文の大きさの上限の既定値 (write / write_binary とそれ以外)
+const DEFAULT_MAX_BODY_BYTES: u64 = 4 * 1024 * 1024;
+const DEFAULT_MAX_UPLOAD_BODY_BYTES: u64 = 128 * 1024 * 1024;
+
+// 同時に処理するリクエストと重い処理 (走査・ハッシュ・コピージョブ) の数の既定の上限と、空きを待つ秒数
+const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 64;
+const DEFAULT_MAX_HEAVY_OPERATIONS: usize = 4;
+const DEFAULT_BUSY_WAIT_SECS: u64 = 5;
+
+// ファイルシステムの処理を待つ既定の上限 (応答のないネットワークドライブでクライアントを待たせ続けない)
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 300;
+
+// これより小さい応答は圧縮しない (圧縮の手間に見合わないため)
+const DEFAULT_COMPRESSION_MIN_BYTES: u64 = 1024;
+
+// クライアントのアドレスごとのレート制限の既定値 (毎秒のリクエスト数と連続で受け付ける数)
+const DEFAULT_RATE_LIMIT_PER_SECOND: u32 = 50;
+const DEFAULT_RATE_LIMIT_BURST: u32 = 100;
+
+// 認証の失敗がこの回数続いたクライアントを一時的にロックする (期間内の回数と、ロックする秒数)
+const DEFAULT_AUTH_LOCKOUT_FAILURES: u32 = 10;
+const DEFAULT_AUTH_LOCKOUT_WINDOW_SECS: u64 = 60;
+const DEFAULT_AUTH_LOCKOUT_SECS: u64 = 300;
+
+// これより短いトークンは validate で警告する (生成するトークンは 64 文字)
+const MIN_TOKEN_LENGTH: usize = 16;
+
+// 設定を置き換える環境変数の名前の先頭 (FILE_AGENT_PORT なら port=)
+const ENV_PREFIX: &str = "FILE_AGENT_";
+
+// 変更を反映するのに再起動が必要な設定 (待ち受け・起動時に始めるバックグラウンドの処理など)
+const RESTART_KEYS: &[&str] = &[
+    "port", "port_fallback", "bind", "listener", "mdns", "socket", "socket_require_token", "stdio_require_token",
+    "tls_cert", "tls_key", "tls_self_signed", "tls_client_ca", "tls_client_cert_only", "allow_insecure_lan",
+    "cors_origin", "api_docs", "web_ui", "max_body_bytes_ws", "receipt_key",
+    "index_dir", "index_interval_minutes", "index_max_file_size", "cleanup", "cleanup_interval_minutes", "walk_exclude",
+    "vault", "vault_key", "soft_delete_retention_hours", "temp_max_age_hours", "list_cache_ttl_secs", "dir_size_cache_ttl_secs",
+    "auth_lockout_failures", "auth_lockout_window_secs", "auth_lockout_secs",
+    "max_concurrent_requests", "max_heavy_operations", "busy_wait_secs", "plugin_dir",
+    "sync", "proxy", "no_proxy",
+];
+
+// s3_region= を省略したときのリージョン (MinIO などの既定値もこれ)
+const DEFAULT_S3_REGION: &str = "us-east-1";
+
+// 保存する設定ファイルの先頭のコメント
+const INI_HEADER: &str = "; File Agent の設定 (エージェントが保存するとコメントは消えます)\n";
+
+// 既定の待ち受けアドレス (ループバックのみ)
+const DEFAULT_BIND_ADDRESS: std::net::IpAddr = std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+/// file_agent.ini の設定 (ライブラリとして使う場合は Default から作って項目を書き換えてもよい)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Config {
+    pub agent_id: String,
+    pub token_hash: String, // トークンの SHA256 (平文は保存しない)
+    pub token_meta: TokenMeta,
+    pub token_tiers: Vec<TokenTier>,
+    pub allowed_operations: Vec<Operation>, // 空ならすべての操作を許可
+    pub port: u16,
+    pub port_fallback: u16, // port が使用中の場合に続けて試すポートの数 (0 なら試さない)
+    pub allowed_roots: Vec<PathBuf>,
+    pub quotas: Vec<DirQuota>,
+    pub policies: Vec<RootPolicy>,
+    pub cleanup_rules: Vec<CleanupRule>,
+    pub cleanup_interval_minutes: u64,
+    pub index_dirs: Vec<PathBuf>,
+    pub index_interval_minutes: u64,
+    pub index_max_file_size: u64,
+    pub allow_print: bool,
+    pub allow_script: bool, // POST /api/script で rhai のスクリプトを実行できる
+    pub search_max_results: usize,
+    pub search_timeout_secs: u64,
+    pub list_cache_ttl_secs: u64,
+    pub dir_size_cache_ttl_secs: u64, // /api/du の合計をキャッシュする秒数 (0 ならキャッシュしない)
+    pub grep_max_file_size: u64,
+    pub copy_parallelism: usize, // ディレクトリのコピーで並行してコピーするファイル数 (1 なら 1 つずつ)
+    pub receipt_key: String, // 空でなければ監査ログに署名し、操作のレシートを返す
+    pub walk_excludes: Vec<String>, // 再帰的な操作 (検索・クリーンアップ・インデックスなど) で飛ばす名前
+    pub protected_paths: Vec<PathBuf>, // 削除を拒否するパス (ドライブのルート・ホーム・エージェントのフォルダに加えて)
+    pub hooks: Vec<Hook>, // 書き込み・削除・移動の前後に実行するフック
+    pub exec_commands: Vec<ExecCommand>, // POST /api/exec で実行できるコマンド (なければ無効)
+    pub sync_tasks: Vec<SyncTask>, // S3 / WebDAV と定期的に同期するフォルダ
+    pub plugin_dir: String, // 空でなければこのフォルダの .wasm をプラグインとして読み込む (相対パスは設定ファイルのフォルダから)
+    pub template_dir: String, // /api/create の template で使うテンプレートのフォルダ (相対パスは設定ファイルのフォルダから)
+    pub soft_delete_retention_hours: u64, // 0 ならフォルダの削除は即時・永続
+    pub temp_max_age_hours: u64, // これより古い一時ファイルを削除する (0 なら起動時のみ)
+    pub rate_limit_per_second: u32, // 0 ならクライアントごとのレート制限なし
+    pub rate_limit_burst: u32,
+    pub auth_lockout_failures: u32, // 0 なら認証失敗によるロックなし
+    pub auth_lockout_window_secs: u64,
+    pub auth_lockout_secs: u64,
+    pub max_concurrent_requests: usize, // 0 なら同時に処理するリクエストの数の制限なし
+    pub max_heavy_operations: usize, // 0 なら重い処理 (走査・ハッシュ・コピージョブ) の数の制限なし
+    pub busy_wait_secs: u64, // 上限に達しているとき、空きを待ってから busy で拒否するまでの秒数
+    pub request_timeout_secs: u64, // ファイルシステムの処理を待つ上限 (0 なら上限なし)
+    pub max_write_bytes: u64, // 1 回に書き込めるファイルの大きさ (0 なら上限なし)
+    pub daily_write_bytes: u64, // トークンごとに 1 日 (UTC) に書き込める合計 (0 なら上限なし)
+    pub vault_roots: Vec<PathBuf>, // 配下に書き込むファイルを暗号化するルート
+    pub vault_key: String, // 旧形式の鍵 (パスフレーズを設定すると削除される)
+    pub vault_keys: VaultKeyInfo,
+    pub bind_address: std::net::IpAddr, // 既定はループバックのみ。それ以外は TLS が必要
+    pub listeners: Vec<Listener>, // 設定すると bind= と port= の代わりにこれらのアドレスで待ち受ける
+    pub tls_cert: String,
+    pub tls_key: String,
+    pub tls_self_signed: bool, // 証明書がなければ自己署名の証明書を生成する
+    pub tls_client_ca: String, // 設定するとこの CA が署名したクライアント証明書を必須にする
+    pub tls_client_cert_only: bool, // クライアント証明書だけで認証する (トークン不要)
+    pub allow_insecure_lan: bool, // ループバック以外でも TLS なしで待ち受ける (トークンが平文で流れる)
+    pub s3_endpoint: String, // S3 互換のサービスの URL (https://s3.ap-northeast-1.amazonaws.com など。空なら /api/s3/ は無効)
+    pub s3_region: String,
+    pub s3_bucket: String,
+    pub s3_access_key: String,
+    pub s3_secret_key: String,
+    pub s3_path_style: bool, // true なら https://<endpoint>/<bucket>/<key>、false なら https://<bucket>.<endpoint>/<key>
+    pub s3_prefix: String, // 空でなければキーをすべてこの下に置く
+    pub webdav_url: String, // 同期に使う WebDAV のフォルダの URL (https://cloud.example.com/remote.php/dav/files/me/ など)
+    pub webdav_user: String,
+    pub webdav_password: String,
+    pub shares: Vec<Share>, // 資格情報を指定して接続するネットワーク共有 (\\server\share)
+    pub proxy: String, // 外へのリクエストに使うプロキシの URL (空なら環境変数と OS の設定に従う)
+    pub no_proxy: String, // proxy= を通さないホスト (カンマ区切り。*.example.com や 10.0.0.0/8 など)
+    pub cors_origins: Vec<String>, // ブラウザからのアクセスを許可するオリジン。"any" ならすべて
+    pub scanner: Scanner, // 書き込む内容を確認するウイルススキャナー
+    pub includes: Vec<String>, // include= で取り込む設定ファイル (このファイルの設定が優先)
+    pub socket: String, // 設定すると TCP の代わりに Unix ドメインソケット / 名前付きパイプで待ち受ける
+    pub socket_require_token: bool, // ソケットでもトークンを確認する (既定。false ならソケットの権限のみ)
+    pub stdio_require_token: bool, // --stdio でもトークンを確認する (既定は起動したプロセスを信頼する)
+    pub allowed_ips: Vec<ipfilter::IpRange>, // 空でなければ、このアドレスからのリクエストのみ受け付ける
+    pub max_body_bytes: u64,
+    pub body_limits: std::collections::BTreeMap<String, u64>, // エンドポイント (write、clients_pair など) ごとの上限
+    pub api_docs: bool, // /api/docs で API ドキュメント (Swagger UI) を表示する
+    pub web_ui: bool, // /ui/ でブラウザー用のファイル管理画面を表示する
+    pub log_file: String, // 空なら設定ファイルと同じフォルダの file_agent.log
+    pub log_max_bytes: u64, // この大きさを超えたら .1 に移して新しく書き始める
+    pub env_overrides: Vec<String>, // 環境変数で置き換えた設定の名前 (設定ファイルには書き込まない)
+    pub autostart: bool, // ログイン時に自動起動する (トレイで起動したときに登録を合わせる)
+    pub mdns: bool, // mDNS (Bonjour) で LAN に公開する
+    pub compression: bool, // Accept-Encoding に応じて JSON の応答を gzip / brotli で圧縮する
+    pub compression_min_bytes: u64, // これより小さい応答は圧縮しない
+    pub weak_token: bool, // token= や FILE_AGENT_TOKEN で指定したトークンが短い (ハッシュからは分からないため読み込み時に記録する)
+}
+
+impl Config {
+    pub fn get_ini_path() -> PathBuf {
+        let exe_path = std::env::current_exe().unwrap_or_else(|_| PathBuf::from("."));
+        let exe_dir = exe_path.parent().unwrap_or_else(|| Path::new("."));
+        exe_dir.join("file_agent.ini")
+    }
+    
+    pub fn get_audit_log_path() -> PathBuf {
+        Self::get_ini_path().with_file_name("file_agent_audit.log")
+    }
+    
+    pub fn get_index_path() -> PathBuf {
+        Self::get_ini_path().with_file_name("file_agent_index.json")
+    }
+    
+    pub fn get_clients_path() -> PathBuf {
+        Self::get_ini_path().with_file_name("file_agent_clients.json")
+    }
+    
+    pub fn get_trash_path() -> PathBuf {
+        Self::get_ini_path().with_file_name("file_agent_trash.json")
+    }
+    
+    pub fn get_hash_cache_path() -> PathBuf {
+        Self::get_ini_path().with_file_name("file_agent_hashes.json")
+    }
+
+    pub fn get_temp_manifest_path() -> PathBuf {
+        Self::get_ini_path().with_file_name("file_agent_temp.json")
+    }
+
+    pub fn get_jobs_dir() -> PathBuf {
+        Self::get_ini_path().with_file_name("file_agent_jobs")
+    }
+
+    pub fn get_sync_dir() -> PathBuf {
+        Self::get_ini_path().with_file_name("file_agent_sync")
+    }
+    
+    /// エンドポイント (/api/ より後の / を _ にした名前) のリクエスト本文の大きさの上限
+    pub fn body_limit(&self, endpoint: &str) -> u64 {
+        match self.body_limits.get(endpoint) {
+            Some(bytes) => *bytes,
+            // ws は WebSocket の 1 メッセージの上限 (write_binary も送れるよう書き込みと同じ既定値)
+            None if endpoint == "write" || endpoint == "write_binary" || endpoint == "file" || endpoint == "ws" => DEFAULT_MAX_UPLOAD_BODY_BYTES.max(self.max_body_bytes),
+            None => self.max_body_bytes,
+        }
+    }
+
+    /// TLS の証明書と秘密鍵のパス (TLS を使わなければ None)
+    pub fn tls_paths(&self) -> Option<(PathBuf, PathBuf)> {
+        if !self.tls_cert.is_empty() && !self.tls_key.is_empty() {
+            Some((PathBuf::from(&self.tls_cert), PathBuf::from(&self.tls_key)))
+        } else if self.tls_self_signed {
+            Some((
+                Self::get_ini_path().with_file_name("file_agent_cert.pem"),
+                Self::get_ini_path().with_file_name("file_agent_key.pem"),
+            ))
+        } else {
+            None
+        }
+    }
+    
+    /// TCP で待ち受けるアドレス。listener= がなければ bind= と port= の 1 つ (証明書があれば TLS)
+    pub fn effective_listeners(&self) -> Vec<Listener> {
+        if !self.listeners.is_empty() {
+            return self.listeners.clone();
+        }
+        vec![Listener {
+            address: std::net::SocketAddr::new(self.bind_address, self.port),
+            tls: self.tls_paths().is_some(),
+            allowed_operations: Vec::new(),
+        }]
+    }
+
+    /// 設定ファイルを読み込む (ないときは既定の設定を保存して使う)。誤りがあれば行番号付きのエラーを返す
+    pub fn load() -> Result<Self, ConfigError> {
+        let ini_path = Self::get_ini_path();
+        
+        let content = match fs::read_to_string(&ini_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                log!("設定ファイルが見つかりません。デフォルト設定を使用します。");
+                let mut default_config = Self::default();
+                let _ = default_config.save(); // デフォルト設定を保存
+                default_config.apply_env()?;
+                return Ok(default_config);
+            }
+            Err(e) => {
+                return Err(ConfigError::from(ini::LineError {
+                    file: ini_path,
+                    line: 0,
+                    message: format!("設定ファイルを読み込めません: {}", e),
+                }))
+            }
+        };
+
+        log!("設定ファイル読み込み: {}", ini_path.display());
+        let includes = content
+            .lines()
+            .filter_map(|line| {
+                let (key, value) = line.split_once('=')?;
+                (key.trim() == "include").then(|| value.trim().to_string())
+            })
+            .collect();
+        let (mut config, save) = Self::parse(&content, &ini_path)?;
+        config.includes = includes;
+        if save {
+            let _ = config.save();
+        }
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    /// ini の内容から設定を作る (ファイルの読み書きも環境変数の反映もしない。組み込みやテストで使う)
+    pub fn from_ini(content: &str) -> Result<Self, ConfigError> {
+        Self::parse(content, Path::new("file_agent.ini")).map(|(config, _)| config)
+    }
+
+    // FILE_AGENT_<設定名> の環境変数で設定ファイルの値を置き換える (コンテナなどで使う)
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        let mut vars: Vec<(String, String)> = std::env::vars()
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .collect();
+        vars.sort();
+
+        let mut errors = Vec::new();
+        for (name, value) in vars {
+            let key = match name[ENV_PREFIX.len()..].to_ascii_lowercase().as_str() {
+                "roots" => "allowed_root".to_string(),
+                key => key.to_string(),
+            };
+            // 何度も書ける設定は、設定ファイルの値を足すのではなく置き換える
+            let mut state = ParseState::default();
+            self.clear_list(&key);
+            let values: Vec<String> = if matches!(key.as_str(), "allowed_root" | "index_dir" | "vault") {
+                std::env::split_paths(&value).map(|path| path.display().to_string()).collect()
+            } else {
+                vec![value.trim().to_string()]
+            };
+            let mut result = Ok(());
+            for value in &values {
+                result = self.apply(&key, value, &mut state);
+                if result.is_err() {
+                    break;
+                }
+            }
+            let message = match result {
+                Ok(()) => {
+                    let saved_key = if key == "token" { "token_hash".to_string() } else { key };
+                    self.env_overrides.push(saved_key);
+                    log!("  環境変数で設定: {}", name);
+                    continue;
+                }
+                Err(SettingError::Unknown) => {
+                    log_error!("⚠️ 不明な環境変数です (無視します): {}", name);
+                    continue;
+                }
+                Err(SettingError::Invalid(message)) => format!("{} ({}={})", message, key, value),
+            };
+            errors.push(ini::LineError {
+                file: PathBuf::from(name),
+                line: 0,
+                message,
+            });
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError { errors })
+        }
+    }
+
+    // 何度も書ける設定の値をすべて消す
+    fn clear_list(&mut self, key: &str) {
+        match key {
+            "allowed_root" => self.allowed_roots.clear(),
+            "index_dir" => self.index_dirs.clear(),
+            "vault" => self.vault_roots.clear(),
+            "quota" => self.quotas.clear(),
+            "policy" => self.policies.clear(),
+            "cleanup" => self.cleanup_rules.clear(),
+            "protected_path" => self.protected_paths.clear(),
+            "hook" => self.hooks.clear(),
+            "exec" => self.exec_commands.clear(),
+            "sync" => self.sync_tasks.clear(),
+            "share" => self.shares.clear(),
+            "tier" => self.token_tiers.clear(),
+            "cors_origin" => self.cors_origins.clear(),
+            "allowed_ips" => self.allowed_ips.clear(),
+            "listener" => self.listeners.clear(),
+            _ => {}
+        }
+    }
+
+    // 設定ファイルの内容を読み込む。保存し直す必要があれば true (初回起動時や平文のトークンの移行)
+    fn parse(content: &str, file: &Path) -> Result<(Self, bool), ConfigError> {
+        let (entries, mut errors) = ini::parse(content, file);
+        let mut config = Config {
+            agent_id: String::new(),
+            ..Config::default()
+        };
+        let mut state = ParseState::default();
+
+        for entry in &entries {
+            // セクション内では、セクション名を前に付けた名前 ([tls] の cert= なら tls_cert=) を優先する
+            let prefix = entry.section.as_deref().map(key_prefix).filter(|prefix| !entry.key.starts_with(&format!("{}_", prefix)));
+            let result = match prefix {
+                Some(prefix) => match config.apply(&format!("{}_{}", prefix, entry.key), &entry.value, &mut state) {
+                    Err(SettingError::Unknown) => config.apply(&entry.key, &entry.value, &mut state),
+                    result => result,
+                },
+                None => config.apply(&entry.key, &entry.value, &mut state),
+            };
+            let message = match result {
+                Ok(()) => continue,
+                Err(SettingError::Unknown) => format!("不明な設定です: {}", entry.key),
+                Err(SettingError::Invalid(message)) => format!("{} ({}={})", message, entry.key, entry.value),
+            };
+            errors.push(ini::LineError {
+                file: entry.file.clone(),
+                line: entry.line,
+                message,
+            });
+        }
+        if !errors.is_empty() {
+            errors.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+            return Err(ConfigError { errors });
+        }
+        
+        // 初回起動時 (agent_id 未設定) は ID を生成して保存する
+        let generated = config.agent_id.is_empty();
+        if generated {
+            config.agent_id = generate_agent_id();
+        }
+        Ok((config, generated || state.migrate))
+    }
+
+    // 1 つの設定を反映する (key はセクション名を含めた名前)
+    fn apply(&mut self, key: &str, value: &str, state: &mut ParseState) -> Result<(), SettingError> {
+        match key {
+            "agent_id" => self.agent_id = value.to_string(),
+            "port" => self.port = parse_number(value)?,
+            "port_fallback" => self.port_fallback = parse_number(value)?,
+            "token" => {
+                // 平文のトークンはハッシュに置き換えて保存し直す
+                self.token_hash = generate_token_hash(value);
+                self.weak_token = value.chars().count() < MIN_TOKEN_LENGTH;
+                state.migrate = true;
+            }
+            "token_hash" => self.token_hash = value.to_string(),
+            "tier" => {
+                state.migrate |= value.contains("|token=") || value.contains("|previous=");
+                self.token_tiers.push(TokenTier::parse(value).ok_or_else(|| invalid("トークンティアの設定が不正です"))?);
+            }
+            "allow" => self.allowed_operations = Operation::parse_list(value).ok_or_else(|| invalid("許可する操作の設定が不正です"))?,
+            "allowed_root" => self.allowed_roots.push(PathBuf::from(value)),
+            "quota" => self.quotas.push(DirQuota::parse(value).ok_or_else(|| invalid("容量制限の設定が不正です"))?),
+            "policy" => self.policies.push(RootPolicy::parse(value).ok_or_else(|| invalid("ポリシーの設定が不正です"))?),
+            "cleanup" => self.cleanup_rules.push(CleanupRule::parse(value).ok_or_else(|| invalid("クリーンアップルールの設定が不正です"))?),
+            "cleanup_interval_minutes" => self.cleanup_interval_minutes = parse_number::<u64>(value)?.max(1),
+            "index_dir" => self.index_dirs.push(PathBuf::from(value)),
+            "index_interval_minutes" => self.index_interval_minutes = parse_number::<u64>(value)?.max(1),
+            "index_max_file_size" => self.index_max_file_size = parse_number(value)?,
+            "allow_print" => self.allow_print = parse_bool(value)?,
+            "allow_script" => self.allow_script = parse_bool(value)?,
+            "search_max_results" => self.search_max_results = parse_number::<usize>(value)?.max(1),
+            "search_timeout_secs" => self.search_timeout_secs = parse_number::<u64>(value)?.max(1),
+            "list_cache_ttl_secs" => self.list_cache_ttl_secs = parse_number(value)?,
+            "dir_size_cache_ttl_secs" => self.dir_size_cache_ttl_secs = parse_number(value)?,
+            "grep_max_file_size" => self.grep_max_file_size = parse_number(value)?,
+            "copy_parallelism" => self.copy_parallelism = parse_number(value)?,
+            "receipt_key" => self.receipt_key = value.to_string(),
+            "walk_exclude" => {
+                // 1 行でも指定すると既定の除外リストを置き換える (値が空の行だけなら除外なし)
+                if !state.walk_excludes_set {
+                    self.walk_excludes.clear();
+                    state.walk_excludes_set = true;
+                }
+                if !value.is_empty() {
+                    self.walk_excludes.push(value.to_string());
+                }
+            }
+            "protected_path" => self.protected_paths.push(PathBuf::from(value)),
+            "hook" => self.hooks.push(Hook::parse(value).ok_or_else(|| invalid("フックの設定が不正です"))?),
+            "exec" => self.exec_commands.push(ExecCommand::parse(value).ok_or_else(|| invalid("実行するコマンドの設定が不正です"))?),
+            "sync" => self.sync_tasks.push(SyncTask::parse(value).ok_or_else(|| invalid("同期の設定が不正です"))?),
+            "plugin_dir" => self.plugin_dir = value.to_string(),
+            "template_dir" => self.template_dir = value.to_string(),
+            "soft_delete_retention_hours" => self.soft_delete_retention_hours = parse_number(value)?,
+            "temp_max_age_hours" => self.temp_max_age_hours = parse_number(value)?,
+            "rate_limit_per_second" => self.rate_limit_per_second = parse_number(value)?,
+            "rate_limit_burst" => self.rate_limit_burst = parse_number::<u32>(value)?.max(1),
+            "auth_lockout_failures" => self.auth_lockout_failures = parse_number(value)?,
+            "auth_lockout_window_secs" => self.auth_lockout_window_secs = parse_number::<u64>(value)?.max(1),
+            "auth_lockout_secs" => self.auth_lockout_secs = parse_number::<u64>(value)?.max(1),
+            "max_concurrent_requests" => self.max_concurrent_requests = parse_number(value)?,
+            "max_heavy_operations" => self.max_heavy_operations = parse_number(value)?,
+            "busy_wait_secs" => self.busy_wait_secs = parse_number(value)?,
+            "request_timeout_secs" => self.request_timeout_secs = parse_number(value)?,
+            "max_write_bytes" => self.max_write_bytes = parse_number(value)?,
+            "daily_write_bytes" => self.daily_write_bytes = parse_number(value)?,
+            "vault" => self.vault_roots.push(PathBuf::from(value)),
+            "vault_key" => self.vault_key = value.to_string(),
+            "vault_salt" => self.vault_keys.salt = value.to_string(),
+            "vault_wrapped_key" => self.vault_keys.wrapped_key = value.to_string(),
+            "vault_previous_wrapped_key" => self.vault_keys.previous_wrapped_key = value.to_string(),
+            "bind" => self.bind_address = value.parse().map_err(|_| invalid("待ち受けアドレスが不正です"))?,
+            "tls_cert" => self.tls_cert = value.to_string(),
+            "tls_key" => self.tls_key = value.to_string(),
+            "tls_self_signed" => self.tls_self_signed = parse_bool(value)?,
+            "tls_client_ca" => self.tls_client_ca = value.to_string(),
+            "tls_client_cert_only" => self.tls_client_cert_only = parse_bool(value)?,
+            "allow_insecure_lan" => self.allow_insecure_lan = parse_bool(value)?,
+            "s3_endpoint" => self.s3_endpoint = value.trim_end_matches('/').to_string(),
+            "s3_region" => self.s3_region = value.to_string(),
+            "s3_bucket" => self.s3_bucket = value.to_string(),
+            "s3_access_key" => self.s3_access_key = value.to_string(),
+            "s3_secret_key" => self.s3_secret_key = value.to_string(),
+            "s3_path_style" => self.s3_path_style = parse_bool(value)?,
+            "s3_prefix" => self.s3_prefix = value.to_string(),
+            "webdav_url" => self.webdav_url = value.to_string(),
+            "webdav_user" => self.webdav_user = value.to_string(),
+            "webdav_password" => self.webdav_password = value.to_string(),
+            "proxy" => self.proxy = value.to_string(),
+            "no_proxy" => self.no_proxy = value.to_string(),
+            "share" => self.shares.push(Share::parse(value).ok_or_else(|| invalid("ネットワーク共有の設定が不正です"))?),
+            "cors_origin" => self.cors_origins.push(parse_cors_origin(value).ok_or_else(|| invalid("CORS のオリジンの設定が不正です"))?),
+            "scan_clamd" => self.scanner = Scanner::Clamd(value.to_string()),
+            "scan_command" => self.scanner = Scanner::Command(value.to_string()),
+            "socket" => self.socket = value.to_string(),
+            "listener" => self.listeners.push(Listener::parse(value).ok_or_else(|| invalid("待ち受けの設定が不正です"))?),
+            "socket_require_token" => self.socket_require_token = parse_bool(value)?,
+            "stdio_require_token" => self.stdio_require_token = parse_bool(value)?,
+            "max_body_bytes" => self.max_body_bytes = parse_number(value)?,
+            "allowed_ips" => {
+                for item in value.split(',').filter(|item| !item.trim().is_empty()) {
+                    let range = ipfilter::IpRange::parse(item)
+                        .ok_or_else(|| invalid(&format!("許可するアドレスの設定が不正です: {}", item.trim())))?;
+                    self.allowed_ips.push(range);
+                }
+            }
+            "api_docs" => self.api_docs = parse_bool(value)?,
+            "web_ui" => self.web_ui = parse_bool(value)?,
+            "autostart" => self.autostart = parse_bool(value)?,
+            "mdns" => self.mdns = parse_bool(value)?,
+            "compression" => self.compression = parse_bool(value)?,
+            "compression_min_bytes" => self.compression_min_bytes = parse_number(value)?,
+            "log_file" => self.log_file = value.to_string(),
+            "log_max_bytes" => self.log_max_bytes = parse_number(value)?,
+            _ => {
+                if let Some(endpoint) = key.strip_prefix("max_body_bytes_") {
+                    self.body_limits.insert(endpoint.to_string(), parse_number(value)?);
+                } else if let Some(name) = key.strip_prefix("token_").filter(|name| TokenMeta::is_key(name)) {
+                    // [tokens] の tier= や token= (token_tier や token_token) は、不明な設定として元の名前で読み直す
+                    state.migrate |= name == "previous";
+                    if self.token_meta.parse_pair(name, value).is_none() {
+                        return Err(invalid("トークンの設定が不正です"));
+                    }
+                } else {
+                    return Err(SettingError::Unknown);
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let ini_path = Self::get_ini_path();
+        let mut content = self.to_ini();
+        if !self.env_overrides.is_empty() {
+            content = self.keep_file_values(&content, &ini_path)?;
+        }
+        if !self.includes.is_empty() {
+            // 取り込んだ設定と同じ行は書かず、違う行だけをこのマシンの設定として残す
+            let include_lines: String = self.includes.iter().map(|include| format!("include={}\n", include)).collect();
+            let (base, _) = Self::parse(&include_lines, &ini_path)?;
+            let mut base_lines: Vec<String> = base.to_ini().lines().filter(|line| is_setting_line(line)).map(str::to_string).collect();
+            let mut overrides = String::new();
+            for line in content.lines().filter(|line| is_setting_line(line)) {
+                match base_lines.iter().position(|base_line| base_line == line) {
+                    Some(i) => {
+                        base_lines.swap_remove(i);
+                    }
+                    None => {
+                        overrides.push_str(line);
+                        overrides.push('\n');
+                    }
+                }
+            }
+            content = format!("{}[Settings]\n{}{}", INI_HEADER, include_lines, overrides);
+        }
+        
+        fs::write(&ini_path, content)?;
+        log!("設定ファイルを保存しました: {}", ini_path.display());
+        Ok(())
+    }
+
+    // 環境変数で置き換えた設定は書き込まず、設定ファイルにある値をそのまま残す
+    fn keep_file_values(&self, content: &str, ini_path: &Path) -> Result<String, ConfigError> {
+        let file_lines = match fs::read_to_string(ini_path) {
+            Ok(file_content) => Self::parse(&file_content, ini_path)?.0.to_ini(),
+            Err(_) => String::new(),
+        };
+        let overridden = |line: &str| {
+            let key = line.split_once('=').map_or("", |(key, _)| key);
+            self.env_overrides.iter().any(|override_key| override_key == key)
+        };
+        let mut kept = String::new();
+        for line in content.lines().filter(|line| !overridden(line)) {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+        for line in file_lines.lines().filter(|line| is_setting_line(line) && overridden(line)) {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+        Ok(kept)
+    }
+
+    /// 読み込んだ設定の問題を確認する (起動は止めず、ログとトレイの通知で知らせる)
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.port == 0 && self.socket.is_empty() {
+            problems.push("port=0 では待ち受けられません (1〜65535 を指定してください)".to_string());
+        }
+
+        if !is_sha256_hex(&self.token_hash) {
+            problems.push("token_hash= が SHA256 (16 進数 64 文字) ではないため、メインのトークンで認証できません".to_string());
+        }
+        if self.weak_token {
+            problems.push(format!(
+                "トークンが短すぎます ({} 文字以上にしてください。file_agent regenerate-token で作り直せます)",
+                MIN_TOKEN_LENGTH
+            ));
+        }
+        for tier in &self.token_tiers {
+            if !is_sha256_hex(&tier.token_hash) {
+                problems.push(format!("ティア '{}' のトークンハッシュが SHA256 ではないため、このティアでは認証できません", tier.name));
+            }
+        }
+
+        if self.allowed_roots.is_empty() {
+            problems.push("allowed_root= がないため、すべてのフォルダーにアクセスできます".to_string());
+        }
+        for (label, dirs) in [("許可ルート", &self.allowed_roots), ("インデックスのフォルダー", &self.index_dirs), ("保管庫のフォルダー", &self.vault_roots)] {
+            for dir in dirs {
+                if !dir.exists() {
+                    problems.push(format!("{}が見つかりません: {}", label, dir.display()));
+                } else if !dir.is_dir() {
+                    problems.push(format!("{}がフォルダーではありません: {}", label, dir.display()));
+                }
+            }
+        }
+
+        if let Some(dir) = self.plugin_path() {
+            if !dir.is_dir() {
+                problems.push(format!("plugin_dir= のフォルダーが見つかりません: {}", dir.display()));
+            } else if self.allowed_roots.is_empty() {
+                problems.push("allowed_root= がないため、プラグインはファイルにアクセスできません".to_string());
+            }
+        }
+
+        if let Some(dir) = self.template_path() {
+            if !dir.is_dir() {
+                problems.push(format!("template_dir= のフォルダーが見つかりません: {}", dir.display()));
+            }
+        }
+
+        for (i, command) in self.exec_commands.iter().enumerate() {
+            if self.exec_commands[..i].iter().any(|other| other.name == command.name) {
+                problems.push(format!("exec= のコマンド名が重複しています (後の行は使われません): {}", command.name));
+            }
+            let program = Path::new(&command.program);
+            if program.is_absolute() && !program.is_file() {
+                problems.push(format!("exec= のプログラムが見つかりません: {}", command.program));
+            }
+            if let Some(cwd) = command.cwd.as_ref().filter(|cwd| !cwd.is_dir()) {
+                problems.push(format!("exec= の作業フォルダーが見つかりません: {}", cwd.display()));
+            }
+        }
+
+        if self.tls_cert.is_empty() != self.tls_key.is_empty() {
+            problems.push("tls_cert= と tls_key= は両方指定してください (TLS を使いません)".to_string());
+        } else if !self.tls_cert.is_empty() && !self.tls_self_signed {
+            // 自己署名の証明書は、ないときに起動時に生成する
+            for (key, path) in [("tls_cert", &self.tls_cert), ("tls_key", &self.tls_key)] {
+                if let Err(e) = fs::File::open(path) {
+                    problems.push(format!("{}= のファイルを読み込めません: {} ({})", key, path, e));
+                }
+            }
+        }
+        if !self.tls_client_ca.is_empty() {
+            if let Err(e) = fs::File::open(&self.tls_client_ca) {
+                problems.push(format!("tls_client_ca= のファイルを読み込めません: {} ({})", self.tls_client_ca, e));
+            }
+        }
+        if !self.s3_endpoint.is_empty() || !self.s3_bucket.is_empty() {
+            if self.s3_endpoint.is_empty() || self.s3_bucket.is_empty() {
+                problems.push("s3_endpoint= と s3_bucket= は両方指定してください (S3 を使いません)".to_string());
+            } else if let Some(Err(e)) = s3::Bucket::from_config(self) {
+                problems.push(format!("S3 の設定が不正です: {}", e));
+            }
+        }
+        if let Some(Err(e)) = webdav::WebDav::from_config(self) {
+            problems.push(format!("WebDAV の設定が不正です: {}", e));
+        }
+        if !self.proxy.is_empty() && reqwest::Proxy::all(&self.proxy).is_err() {
+            problems.push("proxy= の URL が不正です (環境変数と OS のプロキシ設定を使います)".to_string());
+        }
+        if !self.no_proxy.is_empty() && self.proxy.is_empty() {
+            problems.push("no_proxy= は proxy= と一緒に使います (環境変数のプロキシには NO_PROXY を設定してください)".to_string());
+        }
+        for (i, share) in self.shares.iter().enumerate() {
+            if self.shares[..i].iter().any(|other| other.path.eq_ignore_ascii_case(&share.path)) {
+                problems.push(format!("share= の共有が重複しています (後の行は使われません): {}", share.path));
+            } else if !share.drive.is_empty() && self.shares[..i].iter().any(|other| other.drive == share.drive) {
+                problems.push(format!("share={} のドライブ {} はほかの share= でも使われています", share.path, share.drive));
+            }
+        }
+        if !self.shares.is_empty() && !cfg!(target_os = "windows") {
+            problems.push("share= は Windows でのみ接続します (共有をマウントしてください)".to_string());
+        }
+        for (i, task) in self.sync_tasks.iter().enumerate() {
+            if self.sync_tasks[..i].iter().any(|other| other.name == task.name) {
+                problems.push(format!("sync= の名前が重複しています (後の行は使われません): {}", task.name));
+            }
+            if !task.local.is_dir() {
+                problems.push(format!("sync={} のフォルダーが見つかりません: {}", task.name, task.local.display()));
+            }
+            if policy::is_allowed(&self.vault_roots, &task.local) {
+                problems.push(format!("sync={} のフォルダーは vault= の中にあるため同期しません", task.name));
+            }
+            if task.remote.starts_with("s3:") && (self.s3_endpoint.is_empty() || self.s3_bucket.is_empty()) {
+                problems.push(format!("sync={} には [s3] の s3_endpoint= と s3_bucket= が必要です", task.name));
+            }
+            if task.remote.starts_with("webdav:") && self.webdav_url.is_empty() {
+                problems.push(format!("sync={} には [webdav] の webdav_url= が必要です", task.name));
+            }
+        }
+        if !self.socket.is_empty() && !self.listeners.is_empty() {
+            problems.push("socket= を設定しているため listener= は使いません".to_string());
+        }
+        for listener in &self.listeners {
+            if listener.tls && self.tls_paths().is_none() {
+                problems.push(format!("listener={} の TLS には tls_cert= と tls_key=、または tls_self_signed=true が必要です", listener.address));
+            } else if !listener.tls && !listener.address.ip().is_loopback() && !self.allow_insecure_lan {
+                problems.push(format!("listener={} はループバック以外のため tls=true が必要です (起動しません)", listener.address));
+            }
+        }
+        if self.tls_client_cert_only && self.listeners.iter().any(|listener| !listener.tls) {
+            problems.push("tls_client_cert_only=true では、すべての listener= に tls=true が必要です (起動しません)".to_string());
+        }
+        if self.mdns && self.socket.is_empty() && self.effective_listeners().iter().all(|listener| listener.address.ip().is_loopback()) {
+            problems.push("mdns=true ですが、ループバックで待ち受けているため LAN に公開しません (bind= または listener= を設定してください)".to_string());
+        }
+        problems
+    }
+
+    /// other との違いのうち、反映するのに再起動が必要な設定の名前
+    pub(crate) fn restart_required(&self, other: &Config) -> Vec<&'static str> {
+        let (ours, theirs) = (self.to_ini(), other.to_ini());
+        let values = |content: &str, key: &str| -> Vec<String> {
+            content
+                .lines()
+                .filter(|line| line.split_once('=').is_some_and(|(k, _)| k == key))
+                .map(str::to_string)
+                .collect()
+        };
+        RESTART_KEYS.iter().copied().filter(|key| values(&ours, key) != values(&theirs, key)).collect()
+    }
+
+    // 設定ファイルの内容 (セクションごとにまとめ、名前はどのセクションでも使える完全な名前で書く)
+    fn to_ini(&self) -> String {
+        let mut server = vec![
+            format!("agent_id={}", self.agent_id),
+            format!("port={}", self.port),
+        ];
+        if self.port_fallback > 0 {
+            server.push(format!("port_fallback={}", self.port_fallback));
+        }
+        if self.bind_address != DEFAULT_BIND_ADDRESS {
+            server.push(format!("bind={}", self.bind_address));
+        }
+        for listener in &self.listeners {
+            server.push(format!("listener={}", listener.to_ini_value()));
+        }
+        if !self.socket.is_empty() {
+            server.push(format!("socket={}", self.socket));
+        }
+        if !self.socket_require_token {
+            server.push("socket_require_token=false".to_string());
+        }
+        if self.stdio_require_token {
+            server.push("stdio_require_token=true".to_string());
+        }
+        if !self.allowed_ips.is_empty() {
+            let ranges: Vec<String> = self.allowed_ips.iter().map(|range| range.to_ini_value()).collect();
+            server.push(format!("allowed_ips={}", ranges.join(",")));
+        }
+        for origin in &self.cors_origins {
+            server.push(format!("cors_origin={}", origin));
+        }
+        if self.allow_print {
+            server.push("allow_print=true".to_string());
+        }
+        if self.allow_script {
+            server.push("allow_script=true".to_string());
+        }
+        if self.api_docs {
+            server.push("api_docs=true".to_string());
+        }
+        if !self.web_ui {
+            server.push("web_ui=false".to_string());
+        }
+        if self.autostart {
+            server.push("autostart=true".to_string());
+        }
+        if self.mdns {
+            server.push("mdns=true".to_string());
+        }
+        if !self.compression {
+            server.push("compression=false".to_string());
+        }
+
+        let mut tokens = vec![format!("token_hash={}", self.token_hash)];
+        for (key, val) in self.token_meta.ini_pairs() {
+            tokens.push(format!("token_{}={}", key, val));
+        }
+        for tier in &self.token_tiers {
+            tokens.push(format!("tier={}", tier.to_ini_value()));
+        }
+        if !self.allowed_operations.is_empty() {
+            tokens.push(format!("allow={}", Operation::to_ini_value(&self.allowed_operations)));
+        }
+        if !self.receipt_key.is_empty() {
+            tokens.push(format!("receipt_key={}", self.receipt_key));
+        }
+
+        let mut roots = Vec::new();
+        for root in &self.allowed_roots {
+            roots.push(format!("allowed_root={}", root.display()));
+        }
+        for quota in &self.quotas {
+            roots.push(format!("quota={}", quota.to_ini_value()));
+        }
+        for policy in &self.policies {
+            roots.push(format!("policy={}", policy.to_ini_value()));
+        }
+        if self.walk_excludes.is_empty() {
+            roots.push("walk_exclude=".to_string());
+        }
+        for pattern in &self.walk_excludes {
+            roots.push(format!("walk_exclude={}", pattern));
+        }
+        for path in &self.protected_paths {
+            roots.push(format!("protected_path={}", path.display()));
+        }
+        for hook in &self.hooks {
+            roots.push(format!("hook={}", hook.to_ini_value()));
+        }
+        for command in &self.exec_commands {
+            roots.push(format!("exec={}", command.to_ini_value()));
+        }
+        for task in &self.sync_tasks {
+            roots.push(format!("sync={}", task.to_ini_value()));
+        }
+        if !self.plugin_dir.is_empty() {
+            roots.push(format!("plugin_dir={}", self.plugin_dir));
+        }
+        if !self.template_dir.is_empty() {
+            roots.push(format!("template_dir={}", self.template_dir));
+        }
+        roots.push(format!("soft_delete_retention_hours={}", self.soft_delete_retention_hours));
+        if self.temp_max_age_hours != DEFAULT_TEMP_MAX_AGE_HOURS {
+            roots.push(format!("temp_max_age_hours={}", self.temp_max_age_hours));
+        }
+        for rule in &self.cleanup_rules {
+            roots.push(format!("cleanup={}", rule.to_ini_value()));
+        }
+        if !self.cleanup_rules.is_empty() {
+            roots.push(format!("cleanup_interval_minutes={}", self.cleanup_interval_minutes));
+        }
+        for dir in &self.index_dirs {
+            roots.push(format!("index_dir={}", dir.display()));
+        }
+        if !self.index_dirs.is_empty() {
+            roots.push(format!("index_interval_minutes={}", self.index_interval_minutes));
+            roots.push(format!("index_max_file_size={}", self.index_max_file_size));
+        }
+        match &self.scanner {
+            Scanner::None => {}
+            Scanner::Clamd(address) => roots.push(format!("scan_clamd={}", address)),
+            Scanner::Command(command) => roots.push(format!("scan_command={}", command)),
+        }
+
+        let mut vault = Vec::new();
+        for root in &self.vault_roots {
+            vault.push(format!("vault={}", root.display()));
+        }
+        if !self.vault_key.is_empty() {
+            vault.push(format!("vault_key={}", self.vault_key));
+        }
+        if self.vault_keys.initialized() {
+            vault.push(format!("vault_salt={}", self.vault_keys.salt));
+            vault.push(format!("vault_wrapped_key={}", self.vault_keys.wrapped_key));
+        }
+        if !self.vault_keys.previous_wrapped_key.is_empty() {
+            vault.push(format!("vault_previous_wrapped_key={}", self.vault_keys.previous_wrapped_key));
+        }
+
+        let mut tls = Vec::new();
+        if !self.tls_cert.is_empty() {
+            tls.push(format!("tls_cert={}", self.tls_cert));
+        }
+        if !self.tls_key.is_empty() {
+            tls.push(format!("tls_key={}", self.tls_key));
+        }
+        if self.tls_self_signed {
+            tls.push("tls_self_signed=true".to_string());
+        }
+        if !self.tls_client_ca.is_empty() {
+            tls.push(format!("tls_client_ca={}", self.tls_client_ca));
+        }
+        if self.tls_client_cert_only {
+            tls.push("tls_client_cert_only=true".to_string());
+        }
+        if self.allow_insecure_lan {
+            tls.push("allow_insecure_lan=true".to_string());
+        }
+
+        let mut s3 = Vec::new();
+        if !self.s3_endpoint.is_empty() {
+            s3.push(format!("s3_endpoint={}", self.s3_endpoint));
+            s3.push(format!("s3_region={}", self.s3_region));
+        }
+        if !self.s3_bucket.is_empty() {
+            s3.push(format!("s3_bucket={}", self.s3_bucket));
+        }
+        if !self.s3_access_key.is_empty() {
+            s3.push(format!("s3_access_key={}", self.s3_access_key));
+        }
+        if !self.s3_secret_key.is_empty() {
+            s3.push(format!("s3_secret_key={}", self.s3_secret_key));
+        }
+        if !self.s3_path_style {
+            s3.push("s3_path_style=false".to_string());
+        }
+        if !self.s3_prefix.is_empty() {
+            s3.push(format!("s3_prefix={}", self.s3_prefix));
+        }
+
+        let mut webdav = Vec::new();
+        if !self.webdav_url.is_empty() {
+            webdav.push(format!("webdav_url={}", self.webdav_url));
+        }
+        if !self.webdav_user.is_empty() {
+            webdav.push(format!("webdav_user={}", self.webdav_user));
+        }
+        if !self.webdav_password.is_empty() {
+            webdav.push(format!("webdav_password={}", self.webdav_password));
+        }
+
+        let shares: Vec<String> = self.shares.iter().map(|share| format!("share={}", share.to_ini_value())).collect();
+
+        let mut network = Vec::new();
+        if !self.proxy.is_empty() {
+            network.push(format!("proxy={}", self.proxy));
+        }
+        if !self.no_proxy.is_empty() {
+            network.push(format!("no_proxy={}", self.no_proxy));
+        }
+
+        let mut limits = vec![
+            format!("search_max_results={}", self.search_max_results),
+            format!("search_timeout_secs={}", self.search_timeout_secs),
+            format!("list_cache_ttl_secs={}", self.list_cache_ttl_secs),
+            format!("dir_size_cache_ttl_secs={}", self.dir_size_cache_ttl_secs),
+            format!("grep_max_file_size={}", self.grep_max_file_size),
+            format!("copy_parallelism={}", self.copy_parallelism),
+            format!("rate_limit_per_second={}", self.rate_limit_per_second),
+            format!("rate_limit_burst={}", self.rate_limit_burst),
+            format!("auth_lockout_failures={}", self.auth_lockout_failures),
+            format!("auth_lockout_window_secs={}", self.auth_lockout_window_secs),
+            format!("auth_lockout_secs={}", self.auth_lockout_secs),
+            format!("max_concurrent_requests={}", self.max_concurrent_requests),
+            format!("max_heavy_operations={}", self.max_heavy_operations),
+            format!("busy_wait_secs={}", self.busy_wait_secs),
+            format!("request_timeout_secs={}", self.request_timeout_secs),
+            format!("max_body_bytes={}", self.max_body_bytes),
+        ];
+        for (endpoint, bytes) in &self.body_limits {
+            limits.push(format!("max_body_bytes_{}={}", endpoint, bytes));
+        }
+        if self.compression_min_bytes != DEFAULT_COMPRESSION_MIN_BYTES {
+            limits.push(format!("compression_min_bytes={}", self.compression_min_bytes));
+        }
+        if self.max_write_bytes > 0 {
+            limits.push(format!("max_write_bytes={}", self.max_write_bytes));
+        }
+        if self.daily_write_bytes > 0 {
+            limits.push(format!("daily_write_bytes={}", self.daily_write_bytes));
+        }
+
+        let mut logging = Vec::new();
+        if !self.log_file.is_empty() {
+            logging.push(format!("log_file={}", self.log_file));
+        }
+        if self.log_max_bytes != logs::DEFAULT_MAX_BYTES {
+            logging.push(format!("log_max_bytes={}", self.log_max_bytes));
+        }
+
+        let mut content = INI_HEADER.to_string();
+        for (section, lines) in [("server", server), ("tokens", tokens), ("roots", roots), ("vault", vault), ("tls", tls), ("s3", s3), ("webdav", webdav), ("shares", shares), ("network", network), ("limits", limits), ("logging", logging)] {
+            if lines.is_empty() {
+                continue;
+            }
+            if content.len() > INI_HEADER.len() {
+                content.push('\n');
+            }
+            content.push_str(&format!("[{}]\n", section));
+            for line in lines {
+                content.push_str(&line);
+                content.push('\n');
+            }
+        }
+        content
+    }
+
+    /// エージェントのログファイル (log_file= の相対パスは設定ファイルのフォルダから)
+    pub fn log_path(&self) -> PathBuf {
+        if self.log_file.is_empty() {
+            Self::get_ini_path().with_file_name("file_agent.log")
+        } else {
+            Self::get_ini_path().with_file_name("").join(&self.log_file)
+        }
+    }
+
+    /// プラグインを読み込むフォルダ (plugin_dir= がなければ None)
+    pub fn plugin_path(&self) -> Option<PathBuf> {
+        (!self.plugin_dir.is_empty()).then(|| Self::get_ini_path().with_file_name("").join(&self.plugin_dir))
+    }
+
+    /// テンプレートのフォルダ (template_dir= がなければ None)
+    pub fn template_path(&self) -> Option<PathBuf> {
+        (!self.template_dir.is_empty()).then(|| Self::get_ini_path().with_file_name("").join(&self.template_dir))
+    }
+
+    // メインのトークンを OS の乱数から新しく生成し、平文を一度だけ表示する (設定ファイルにはハッシュのみ保存)
+    pub fn regenerate_token(&mut self) {
+        let token = generate_token();
+        self.token_hash = generate_token_hash(&token);
+        self.token_meta = TokenMeta {
+            created: Some(auth::unix_now()),
+            ..TokenMeta::default()
+        };
+        // トークンはログファイルには書き込まない
+        logs::console("🔑 新しいトークン (この表示は一度だけです。安全な場所に控えてください):");
+        logs::console(&format!("  {}", token));
+    }
+
+    // トークンが未設定なら生成する。生成した場合は true
+    pub fn ensure_token(&mut self) -> bool {
+        if !self.token_hash.is_empty() {
+            return false;
+        }
+        self.regenerate_token();
+        true
+    }
+}
+
+fn default_walk_excludes() -> Vec<String> {
+    walk::DEFAULT_EXCLUDES.iter().map(|name| name.to_string()).collect()
+}
+
+// "https://example.com:8080" の形式のオリジン (または "any") を小文字にそろえて返す
+fn parse_cors_origin(value: &str) -> Option<String> {
+    let origin = value.trim().trim_end_matches('/').to_ascii_lowercase();
+    if origin == "any" {
+        return Some(origin);
+    }
+    let (scheme, host) = origin.split_once("://")?;
+    let valid_host = !host.is_empty() && host.chars().all(|c| c.is_ascii_alphanumeric() || "-.:[]".contains(c));
+    ((scheme == "http" || scheme == "https") && valid_host).then_some(origin)
+}
+
+/// 設定ファイルの誤り (誤りのある行をすべて含む)
+#[derive(Debug)]
+pub struct ConfigError {
+    pub errors: Vec<ini::LineError>,
+}
+
+impl From<ini::LineError> for ConfigError {
+    fn from(error: ini::LineError) -> Self {
+        ConfigError { errors: vec![error] }
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lines: Vec<String> = self.errors.iter().map(|error| error.to_string()).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Default)]
+struct ParseState {
+    migrate: bool,           // 平文のトークンなど、保存し直す必要がある
+    walk_excludes_set: bool, // walk_exclude= で既定の除外リストを置き換えた
+}
+
+enum SettingError {
+    Unknown,
+    Invalid(String),
+}
+
+fn invalid(message: &str) -> SettingError {
+    SettingError::Invalid(message.to_string())
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, SettingError> {
+    value.parse().map_err(|_| invalid("数値が不正です"))
+}
+
+fn parse_bool(value: &str) -> Result<bool, SettingError> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(invalid("true または false を指定してください")),
+    }
+}
+
+fn is_sha256_hex(value: &str) -> bool {
+    value.len() == 64 && value.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+// セクションの中の設定の名前に前に付ける文字 ([logging] の file= は log_file=)
+fn key_prefix(section: &str) -> &str {
+    match section {
+        "logging" => "log",
+        "tokens" => "token",
+        section => section,
+    }
+}
+
+// 保存した設定のうち、セクションの見出しとコメント以外の行
+fn is_setting_line(line: &str) -> bool {
+    !line.is_empty() && !line.starts_with('[') && !line.starts_with(';')
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            agent_id: generate_agent_id(),
+            token_hash: String::new(),
+            token_meta: TokenMeta::default(),
+            token_tiers: Vec::new(),
+            allowed_operations: Vec::new(),
+            port: 8767,
+            port_fallback: 0,
+            allowed_roots: Vec::new(),
+            quotas: Vec::new(),
+            policies: Vec::new(),
+            cleanup_rules: Vec::new(),
+            cleanup_interval_minutes: 60,
+            index_dirs: Vec::new(),
+            index_interval_minutes: 30,
+            index_max_file_size: index::DEFAULT_MAX_CONTENT_SIZE,
+            allow_print: false,
+            allow_script: false,
+            search_max_results: DEFAULT_SEARCH_MAX_RESULTS,
+            search_timeout_secs: DEFAULT_SEARCH_TIMEOUT_SECS,
+            list_cache_ttl_secs: DEFAULT_LIST_CACHE_TTL_SECS,
+            dir_size_cache_ttl_secs: DEFAULT_DIR_SIZE_CACHE_TTL_SECS,
+            grep_max_file_size: grep::DEFAULT_MAX_FILE_SIZE,
+            copy_parallelism: copy::DEFAULT_PARALLELISM,
+            receipt_key: String::new(),
+            walk_excludes: default_walk_excludes(),
+            protected_paths: Vec::new(),
+            hooks: Vec::new(),
+            exec_commands: Vec::new(),
+            sync_tasks: Vec::new(),
+            plugin_dir: String::new(),
+            template_dir: String::new(),
+            soft_delete_retention_hours: DEFAULT_SOFT_DELETE_RETENTION_HOURS,
+            temp_max_age_hours: DEFAULT_TEMP_MAX_AGE_HOURS,
+            rate_limit_per_second: DEFAULT_RATE_LIMIT_PER_SECOND,
+            rate_limit_burst: DEFAULT_RATE_LIMIT_BURST,
+            auth_lockout_failures: DEFAULT_AUTH_LOCKOUT_FAILURES,
+            auth_lockout_window_secs: DEFAULT_AUTH_LOCKOUT_WINDOW_SECS,
+            auth_lockout_secs: DEFAULT_AUTH_LOCKOUT_SECS,
+            max_concurrent_requests: DEFAULT_MAX_CONCURRENT_REQUESTS,
+            max_heavy_operations: DEFAULT_MAX_HEAVY_OPERATIONS,
+            busy_wait_secs: DEFAULT_BUSY_WAIT_SECS,
+            request_timeout_secs: DEFAULT_REQUEST_TIMEOUT_SECS,
+            max_write_bytes: 0,
+            daily_write_bytes: 0,
+            vault_roots: Vec::new(),
+            vault_key: String::new(),
+            vault_keys: VaultKeyInfo::default(),
+            bind_address: DEFAULT_BIND_ADDRESS,
+            listeners: Vec::new(),
+            tls_cert: String::new(),
+            tls_key: String::new(),
+            tls_self_signed: false,
+            tls_client_ca: String::new(),
+            tls_client_cert_only: false,
+            allow_insecure_lan: false,
+            s3_endpoint: String::new(),
+            s3_region: DEFAULT_S3_REGION.to_string(),
+            s3_bucket: String::new(),
+            s3_access_key: String::new(),
+            s3_secret_key: String::new(),
+            s3_path_style: true,
+            s3_prefix: String::new(),
+            webdav_url: String::new(),
+            webdav_user: String::new(),
+            webdav_password: String::new(),
+            shares: Vec::new(),
+            proxy: String::new(),
+            no_proxy: String::new(),
+            cors_origins: Vec::new(),
+            scanner: Scanner::None,
+            includes: Vec::new(),
+            socket: String::new(),
+            socket_require_token: true,
+            stdio_require_token: false,
+            allowed_ips: Vec::new(),
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            body_limits: std::collections::BTreeMap::new(),
+            api_docs: false,
+            web_ui: true,
+            log_file: String::new(),
+            log_max_bytes: logs::DEFAULT_MAX_BYTES,
+            env_overrides: Vec::new(),
+            autostart: false,
+            mdns: false,
+            compression: true,
+            compression_min_bytes: DEFAULT_COMPRESSION_MIN_BYTES,
+            weak_token: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_section_accepts_plain_names() {
+        let config = Config::from_ini("[tokens]\ntoken=admin-token-0123456789abcdef\ntier=reader|token=reader-token-0123456789abcdef|allow=read\ntoken_expires=1767225600\n").unwrap();
+        assert_eq!(config.token_hash, generate_token_hash("admin-token-0123456789abcdef"));
+        assert_eq!(config.token_tiers.len(), 1);
+        assert_eq!(config.token_tiers[0].name, "reader");
+        assert_eq!(config.token_meta.expires, Some(1767225600));
+    }
+
+    #[test]
+    fn section_names_can_be_left_out() {
+        let config = Config::from_ini("[tls]\ncert=a.pem\ntls_key=a.key\n[logging]\nfile=agent.log\n[roots]\nallowed_root=/data\n").unwrap();
+        assert_eq!((config.tls_cert.as_str(), config.tls_key.as_str()), ("a.pem", "a.key"));
+        assert_eq!(config.log_file, "agent.log");
+        assert_eq!(config.allowed_roots, vec![PathBuf::from("/data")]);
+    }
+
+    #[test]
+    fn saved_settings_load_again() {
+        let config = Config::from_ini("[tokens]\ntoken=admin-token-0123456789abcdef\ntier=reader|token=reader-token-0123456789abcdef|allow=read,list\n[roots]\nallowed_root=/data\npolicy=/data/locked|write=false\n").unwrap();
+        let reloaded = Config::from_ini(&config.to_ini()).unwrap();
+        assert_eq!(reloaded.token_hash, config.token_hash);
+        assert_eq!(reloaded.token_tiers[0].token_hash, config.token_tiers[0].token_hash);
+        assert_eq!(reloaded.token_tiers[0].allowed_operations, config.token_tiers[0].allowed_operations);
+        assert_eq!(reloaded.allowed_roots, vec![PathBuf::from("/data")]);
+        assert!(!reloaded.policies[0].write);
+    }
+
+    #[test]
+    fn unknown_token_setting_is_reported() {
+        let errors = Config::from_ini("[tokens]\ntoken_colour=blue\n").unwrap_err().errors;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+        assert!(errors[0].message.contains("token_colour"), "{}", errors[0].message);
+    }
+}
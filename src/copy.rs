@@ -0,0 +1,82 @@
+//! ファイルとディレクトリのコピー
+//!
+//! ディレクトリは先にサブディレクトリを作ってから、ファイルを copy_parallelism= 個のスレッドで
+//! 並行してコピーする。小さなファイルが多い場合やネットワークドライブへのコピーでは、
+//! ファイルを開く・閉じる待ち時間が重なるため、1 つずつコピーするより大幅に速くなる。
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// 並行してコピーするファイル数の既定値
+pub const DEFAULT_PARALLELISM: usize = 8;
+
+// 読み書きのバッファの大きさ (ネットワークドライブへの書き込み回数を減らす)
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+/// ファイルをコピーする (権限もコピーする)。コピーしたバイト数を返す
+pub fn file(src: &Path, dst: &Path) -> io::Result<u64> {
+    let source = File::open(src)?;
+    let permissions = source.metadata()?.permissions();
+    let mut reader = BufReader::with_capacity(BUFFER_SIZE, source);
+    let mut writer = BufWriter::with_capacity(BUFFER_SIZE, File::create(dst)?);
+    let bytes = io::copy(&mut reader, &mut writer)?;
+    writer.flush()?;
+    let target = writer.into_inner().map_err(|e| e.into_error())?;
+    target.set_permissions(permissions)?;
+    Ok(bytes)
+}
+
+/// ディレクトリを再帰的にコピーする。parallelism が 1 以下なら 1 つずつコピーする。
+/// どれかのファイルが失敗すると残りのコピーをやめ、最初のエラーを返す
+pub fn dir_recursive(src: &Path, dst: &Path, parallelism: usize) -> io::Result<()> {
+    // サブディレクトリを先に作り、コピーするファイルを集める
+    let mut files: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut pending = vec![(src.to_path_buf(), dst.to_path_buf())];
+    while let Some((src, dst)) = pending.pop() {
+        if !dst.exists() {
+            fs::create_dir_all(&dst)?;
+        }
+        for entry in fs::read_dir(&src)? {
+            let entry = entry?;
+            let src_path = entry.path();
+            let dst_path = dst.join(entry.file_name());
+            if src_path.is_dir() {
+                pending.push((src_path, dst_path));
+            } else {
+                files.push((src_path, dst_path));
+            }
+        }
+    }
+
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let error: Mutex<Option<io::Error>> = Mutex::new(None);
+    let workers = parallelism.clamp(1, files.len().max(1));
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while !stop.load(Ordering::Relaxed) {
+                    let Some((src, dst)) = files.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+                    if let Err(e) = file(src, dst) {
+                        stop.store(true, Ordering::Relaxed);
+                        // 並行しているとどのファイルか分からないため、パスを付ける
+                        error
+                            .lock()
+                            .unwrap()
+                            .get_or_insert_with(|| io::Error::new(e.kind(), format!("{}: {}", src.display(), e)));
+                    }
+                }
+            });
+        }
+    });
+
+    match error.into_inner().unwrap() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
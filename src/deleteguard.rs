@@ -0,0 +1,50 @@
+//! 削除の安全確認
+//!
+//! パスの指定を 1 つ誤っただけでフォルダごと消えないよう、ドライブのルート・ホームフォルダ・
+//! エージェント自身のフォルダ・protected_path= で指定したパス (とそれらを含むフォルダ) の削除を拒否する。
+use crate::config::Config;
+use crate::quota;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+// 常に保護するパス (ホームフォルダとエージェント自身のフォルダ)
+fn builtin_protected() -> Vec<PathBuf> {
+    let mut protected = Vec::new();
+    for var in ["HOME", "USERPROFILE"] {
+        if let Some(home) = std::env::var_os(var).filter(|home| !home.is_empty()) {
+            protected.push(PathBuf::from(home));
+        }
+    }
+    if let Some(dir) = Config::get_ini_path().parent() {
+        protected.push(dir.to_path_buf());
+    }
+    protected
+}
+
+/// path を削除してよいか確認する (保護されたパスかそれを含むフォルダならエラー)
+pub fn check(path: &Path, configured: &[PathBuf]) -> Result<(), String> {
+    let target = quota::resolve(path);
+    // ドライブのルート ("C:\" や "/")
+    if target.parent().is_none() {
+        return Err(format!("Refusing to delete protected path: {} is a drive root", path.display()));
+    }
+    for protected in builtin_protected().iter().chain(configured) {
+        let protected = quota::resolve(protected);
+        if protected == target {
+            return Err(format!("Refusing to delete protected path: {}", path.display()));
+        }
+        if protected.starts_with(&target) {
+            return Err(format!(
+                "Refusing to delete {}: it contains the protected path {}",
+                path.display(),
+                protected.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// フォルダを削除すると消えるエントリ数 (フォルダ自身は含まない)
+pub fn count_entries(dir: &Path) -> usize {
+    WalkDir::new(dir).min_depth(1).into_iter().filter_map(|e| e.ok()).count()
+}
@@ -0,0 +1,152 @@
+use crate::changes::ChangeLog;
+use crate::listcache::CacheStats;
+use crate::walk;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+// キャッシュするディレクトリ数の上限 (超えたら最も古いものを破棄)
+const MAX_CACHED_DIRS: usize = 256;
+
+/// ディレクトリ配下の合計 (ディレクトリ自身は dirs に含めない)
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct DirSize {
+    pub path: String,
+    pub bytes: u64,
+    pub files: u64,
+    pub dirs: u64,
+    pub computed_at: u64, // 集計した時刻 (UNIX 時刻、秒)
+    pub truncated: bool, // search_timeout_secs で打ち切った (途中までの合計。キャッシュしない)
+    pub cached: bool,
+}
+
+struct CachedSize {
+    size: DirSize,
+    cached_at: Instant,
+    change_cursor: u64, // キャッシュ時点の ChangeLog の位置
+}
+
+/// ディレクトリの合計サイズのキャッシュ。
+/// 配下へのエージェント経由の変更、または TTL 経過で無効になる (エージェント外の変更は TTL まで反映されない)
+pub struct DirSizeCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<PathBuf, CachedSize>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+// イベントのパスが dir の合計に影響するか (dir 配下の変更、または dir 自身・祖先の変更)
+fn affects(dir: &Path, path: &str) -> bool {
+    let path = Path::new(path);
+    path.starts_with(dir) || dir.starts_with(path)
+}
+
+impl DirSizeCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    pub fn get(&self, dir: &Path, changes: &ChangeLog) -> Option<DirSize> {
+        if !self.enabled() {
+            return None;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let valid = match entries.get_mut(dir) {
+            Some(cached) => {
+                let mut valid = cached.cached_at.elapsed() < self.ttl;
+                let latest = changes.latest();
+                if valid && latest != cached.change_cursor {
+                    let poll = changes.since(cached.change_cursor);
+                    valid = !poll.missed
+                        && !poll.events.iter().any(|e| {
+                            affects(dir, &e.path) || e.destination.as_deref().map(|d| affects(dir, d)).unwrap_or(false)
+                        });
+                    cached.change_cursor = latest;
+                }
+                valid
+            }
+            None => false,
+        };
+
+        if valid {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            entries.get(dir).map(|cached| DirSize { cached: true, ..cached.size.clone() })
+        } else {
+            entries.remove(dir);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+
+    /// 合計を保存する。change_cursor は走査を始める前に取得したものを渡す (打ち切った合計は保存しない)
+    pub fn put(&self, dir: &Path, size: &DirSize, change_cursor: u64) {
+        if !self.enabled() || size.truncated {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_CACHED_DIRS && !entries.contains_key(dir) {
+            let oldest = entries.iter().min_by_key(|(_, c)| c.cached_at).map(|(p, _)| p.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(dir.to_path_buf(), CachedSize {
+            size: size.clone(),
+            cached_at: Instant::now(),
+            change_cursor,
+        });
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries.lock().unwrap().len(),
+            ttl_secs: self.ttl.as_secs(),
+        }
+    }
+}
+
+/// dir 配下を走査して合計を求める (シンボリックリンクは辿らず、リンク自体も数えない)
+pub fn compute(dir: &Path, options: &walk::WalkOptions, deadline: Instant) -> DirSize {
+    let mut size = DirSize {
+        path: dir.to_string_lossy().to_string(),
+        bytes: 0,
+        files: 0,
+        dirs: 0,
+        computed_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        truncated: false,
+        cached: false,
+    };
+    for entry in walk::entries(dir, options) {
+        if Instant::now() >= deadline {
+            size.truncated = true;
+            break;
+        }
+        let Some(metadata) = entry.metadata else {
+            continue;
+        };
+        if metadata.is_file() {
+            size.files += 1;
+            size.bytes += metadata.len();
+        } else if metadata.is_dir() && entry.path != dir {
+            size.dirs += 1;
+        }
+    }
+    size
+}
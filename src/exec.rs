@@ -0,0 +1,246 @@
+//! 許可したコマンドの実行 (exec=)
+//!
+//! POST /api/exec で実行できるのは設定の exec= で宣言したコマンドだけで、プログラムと先頭の引数は設定で固定する。
+//! クライアントが加える引数は、それぞれ allow_arg= のどれかのパターンに一致しなければならない。
+//! シェルを通さずに直接起動するので、引数がコマンドとして解釈されることはない。
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+use crate::cleanup::wildcard_match;
+use crate::longpath;
+
+// timeout_secs を指定しないコマンドの待ち時間
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+
+// 応答に含める標準出力・標準エラー出力の上限 (超えた分は読み捨てる)
+const MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+
+// プログラムの終了を確認する間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// 許可ルートの中のパスであることを求める allow_arg= のパターン
+pub const PATH_PATTERN: &str = "{path}";
+
+/// 実行を許可したコマンド
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExecCommand {
+    pub name: String,
+    pub program: String,
+    pub args: Vec<String>,        // 常に先頭に付ける引数
+    pub allow_args: Vec<String>,  // クライアントの引数に許すパターン (* と ?、または {path})
+    pub cwd: Option<PathBuf>,
+    pub timeout_secs: u64,
+}
+
+impl ExecCommand {
+    // 形式: prettier|program=C:\tools\prettier.cmd|args=--write|allow_arg={path}|cwd=D:\site|timeout_secs=30
+    //       (args= は空白区切り、allow_arg= は繰り返せる)
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split('|');
+        let name = parts.next()?.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return None;
+        }
+
+        let mut command = ExecCommand {
+            name: name.to_string(),
+            program: String::new(),
+            args: Vec::new(),
+            allow_args: Vec::new(),
+            cwd: None,
+            timeout_secs: DEFAULT_TIMEOUT_SECS,
+        };
+        for part in parts {
+            let (key, val) = part.split_once('=')?;
+            let val = val.trim();
+            match key.trim() {
+                "program" if !val.is_empty() => command.program = val.to_string(),
+                "args" => command.args = val.split_whitespace().map(str::to_string).collect(),
+                "allow_arg" if !val.is_empty() => command.allow_args.push(val.to_string()),
+                "cwd" if !val.is_empty() => command.cwd = Some(PathBuf::from(val)),
+                "timeout_secs" => command.timeout_secs = val.parse::<u64>().ok()?.max(1),
+                _ => return None,
+            }
+        }
+        if command.program.is_empty() {
+            return None;
+        }
+        Some(command)
+    }
+
+    pub fn to_ini_value(&self) -> String {
+        let mut value = format!("{}|program={}", self.name, self.program);
+        if !self.args.is_empty() {
+            value.push_str(&format!("|args={}", self.args.join(" ")));
+        }
+        for pattern in &self.allow_args {
+            value.push_str(&format!("|allow_arg={}", pattern));
+        }
+        if let Some(cwd) = &self.cwd {
+            value.push_str(&format!("|cwd={}", cwd.display()));
+        }
+        if self.timeout_secs != DEFAULT_TIMEOUT_SECS {
+            value.push_str(&format!("|timeout_secs={}", self.timeout_secs));
+        }
+        value
+    }
+
+    /// クライアントの引数を確認する。{path} を許すコマンドでは、絶対パスの引数は文字列のパターンに
+    /// 一致しても check_path で許可ルートの中か確認する (* などのパターンで許可ルートの外を渡せないように)
+    pub fn check_args(&self, args: &[String], check_path: impl Fn(&Path) -> Result<(), String>) -> Result<(), String> {
+        let takes_paths = self.allow_args.iter().any(|pattern| pattern == PATH_PATTERN);
+        for arg in args {
+            if takes_paths && Path::new(arg).is_absolute() {
+                check_path(Path::new(arg))?;
+                continue;
+            }
+            if self.allow_args.iter().any(|pattern| pattern != PATH_PATTERN && wildcard_match(pattern, arg)) {
+                continue;
+            }
+            return Err(format!("Argument not allowed for {}: {}", self.name, arg));
+        }
+        Ok(())
+    }
+}
+
+/// コマンドの実行結果
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ExecResult {
+    pub name: String,
+    pub exit_code: Option<i32>, // シグナルで終了した場合や時間切れの場合は None
+    pub stdout: String,
+    pub stderr: String,
+    pub timed_out: bool,
+    pub truncated: bool, // 出力が 1 MB を超えて切り詰めた
+    pub duration_ms: u64,
+}
+
+/// コマンドを実行し、終了を待って結果を返す (専用スレッドから呼ぶ)
+pub fn run(command: &ExecCommand, args: &[String]) -> Result<ExecResult, String> {
+    let started = Instant::now();
+    let mut process = Command::new(&command.program);
+    process.args(&command.args).args(args).stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    if let Some(cwd) = &command.cwd {
+        // 作業フォルダには \\?\ 付きのパスも MAX_PATH を超えるパスも使えない
+        process.current_dir(longpath::legacy(cwd)?);
+    }
+    let mut child = process.spawn().map_err(|e| format!("Failed to run {}: {}", command.program, e))?;
+
+    // 出力を読みながら待たないと、パイプが詰まって止まることがある
+    let stdout = read_in_background(child.stdout.take().expect("stdout is piped"));
+    let stderr = read_in_background(child.stderr.take().expect("stderr is piped"));
+
+    let deadline = started + Duration::from_secs(command.timeout_secs);
+    let (status, timed_out) = loop {
+        match child.try_wait().map_err(|e| format!("Failed to run {}: {}", command.program, e))? {
+            Some(status) => break (Some(status), false),
+            None if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                break (None, true);
+            }
+            None => std::thread::sleep(POLL_INTERVAL),
+        }
+    };
+
+    let (stdout, stdout_truncated) = stdout.join().unwrap_or_default();
+    let (stderr, stderr_truncated) = stderr.join().unwrap_or_default();
+    Ok(ExecResult {
+        name: command.name.clone(),
+        exit_code: status.and_then(|status| status.code()),
+        stdout,
+        stderr,
+        timed_out,
+        truncated: stdout_truncated || stderr_truncated,
+        duration_ms: started.elapsed().as_millis() as u64,
+    })
+}
+
+// 上限までを残し、残りは読み捨てる (切り詰めたら true)
+fn read_in_background(mut pipe: impl Read + Send + 'static) -> std::thread::JoinHandle<(String, bool)> {
+    std::thread::spawn(move || {
+        let mut output = Vec::new();
+        let mut buffer = [0u8; 8192];
+        let mut truncated = false;
+        loop {
+            match pipe.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    let room = MAX_OUTPUT_BYTES.saturating_sub(output.len());
+                    output.extend_from_slice(&buffer[..n.min(room)]);
+                    truncated |= n > room;
+                }
+            }
+        }
+        (String::from_utf8_lossy(&output).to_string(), truncated)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root() -> PathBuf {
+        std::env::temp_dir().join("file_agent_exec_root")
+    }
+
+    // 許可ルートの中だけを許す check_path
+    fn inside_root(path: &Path) -> Result<(), String> {
+        if path.starts_with(root()) {
+            Ok(())
+        } else {
+            Err(format!("Access denied: {} is outside the allowed roots", path.display()))
+        }
+    }
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_the_ini_value() {
+        let command = ExecCommand::parse("prettier|program=/usr/bin/prettier|args=--write --no-color|allow_arg={path}|allow_arg=--check|timeout_secs=30").unwrap();
+        assert_eq!(command.args, args(&["--write", "--no-color"]));
+        assert_eq!(command.allow_args, args(&[PATH_PATTERN, "--check"]));
+        assert_eq!(command.timeout_secs, 30);
+        assert_eq!(ExecCommand::parse(&command.to_ini_value()).unwrap().allow_args, command.allow_args);
+        assert!(ExecCommand::parse("bad name|program=x").is_none());
+        assert!(ExecCommand::parse("tool|args=x").is_none());
+    }
+
+    #[test]
+    fn text_patterns_match_arguments() {
+        let command = ExecCommand::parse("tool|program=tool|allow_arg=--check|allow_arg=*.txt").unwrap();
+        assert!(command.check_args(&args(&["--check", "notes.txt"]), inside_root).is_ok());
+        assert!(command.check_args(&args(&["--delete"]), inside_root).unwrap_err().contains("Argument not allowed"));
+    }
+
+    #[test]
+    fn paths_are_checked_against_the_roots() {
+        let command = ExecCommand::parse("tool|program=tool|allow_arg={path}").unwrap();
+        let inside = root().join("a.txt").display().to_string();
+        assert!(command.check_args(&args(&[&inside]), inside_root).is_ok());
+        assert!(command.check_args(&args(&["/etc/shadow"]), inside_root).unwrap_err().contains("outside the allowed roots"));
+        // 相対パスは作業フォルダ次第で場所が変わるため {path} には一致しない
+        assert!(command.check_args(&args(&["a.txt"]), inside_root).unwrap_err().contains("Argument not allowed"));
+    }
+
+    #[test]
+    fn text_patterns_do_not_let_paths_skip_the_root_check() {
+        let command = ExecCommand::parse("tool|program=tool|allow_arg={path}|allow_arg=*|allow_arg=*.txt").unwrap();
+        for arg in ["/etc/shadow", "/etc/notes.txt"] {
+            assert!(command.check_args(&args(&[arg]), inside_root).unwrap_err().contains("outside the allowed roots"), "{}", arg);
+        }
+        assert!(command.check_args(&args(&["--verbose", &root().join("b.txt").display().to_string()]), inside_root).is_ok());
+    }
+
+    #[test]
+    fn commands_without_path_arguments_keep_text_patterns() {
+        let command = ExecCommand::parse("echo|program=echo|allow_arg=*").unwrap();
+        assert!(command.check_args(&args(&["/etc/shadow"]), |_| Err("checked".to_string())).is_ok());
+    }
+}
@@ -0,0 +1,79 @@
+// fzf 風のあいまい一致スコアリング
+// パターンの各文字が順番どおりに現れれば一致とし、連続一致や単語境界での一致を高く評価する
+
+const SCORE_MATCH: i64 = 16;
+const BONUS_BOUNDARY: i64 = 8;
+const BONUS_CAMEL: i64 = 7;
+const BONUS_FIRST_CHAR: i64 = 8;
+const BONUS_CONSECUTIVE: i64 = 4;
+const PENALTY_GAP_START: i64 = -3;
+const PENALTY_GAP_EXTENSION: i64 = -1;
+
+const NONE: i64 = i64::MIN / 4;
+
+fn is_separator(c: char) -> bool {
+    matches!(c, ' ' | '_' | '-' | '.' | '/' | '\\')
+}
+
+// candidate[j] で一致した場合の位置ボーナス
+fn position_bonus(candidate: &[char], j: usize) -> i64 {
+    if j == 0 {
+        return BONUS_FIRST_CHAR;
+    }
+    let prev = candidate[j - 1];
+    let cur = candidate[j];
+    if is_separator(prev) {
+        BONUS_BOUNDARY
+    } else if (prev.is_lowercase() && cur.is_uppercase()) || (!prev.is_ascii_digit() && cur.is_ascii_digit()) {
+        BONUS_CAMEL
+    } else {
+        0
+    }
+}
+
+/// pattern が candidate にあいまい一致する場合はスコアを返す (大文字小文字を区別しない)
+pub fn score(pattern: &str, candidate: &str) -> Option<i64> {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().filter(|c| !c.is_whitespace()).collect();
+    if pattern.is_empty() {
+        return Some(0);
+    }
+    let original: Vec<char> = candidate.chars().collect();
+    let lower: Vec<char> = original.iter().map(|c| c.to_lowercase().next().unwrap_or(*c)).collect();
+    let n = lower.len();
+    if pattern.len() > n {
+        return None;
+    }
+
+    // prev[j]: パターンの直前の文字までを一致させ、その最後が candidate[j] である場合の最高スコア
+    let mut prev = vec![NONE; n];
+    let mut cur = vec![NONE; n];
+
+    for (i, &pc) in pattern.iter().enumerate() {
+        // gap_best: j-2 以前で前の文字が一致した場合の (スコア + ギャップペナルティ) の最大値
+        let mut gap_best = NONE;
+        for j in 0..n {
+            cur[j] = NONE;
+            if lower[j] == pc {
+                let base = SCORE_MATCH + position_bonus(&original, j);
+                if i == 0 {
+                    cur[j] = base;
+                } else if j > 0 {
+                    let consecutive = if prev[j - 1] > NONE { prev[j - 1] + BONUS_CONSECUTIVE } else { NONE };
+                    let gapped = gap_best;
+                    let best = consecutive.max(gapped);
+                    if best > NONE {
+                        cur[j] = best + base;
+                    }
+                }
+            }
+            if i > 0 && j > 0 {
+                let from_prev = if prev[j - 1] > NONE { prev[j - 1] + PENALTY_GAP_START } else { NONE };
+                let extended = if gap_best > NONE { gap_best + PENALTY_GAP_EXTENSION } else { NONE };
+                gap_best = from_prev.max(extended);
+            }
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+
+    prev.into_iter().filter(|s| *s > NONE).max()
+}
@@ -0,0 +1,216 @@
+//! Git リポジトリの状態・差分・履歴 (読み取りのみ)
+//!
+//! 指定したパスを含むリポジトリを探して開く。作業フォルダーが許可ルートの中にあるかは呼び出し側で確認する。
+use git2::{Branch, DiffFormat, DiffOptions, Repository, Sort, Status, StatusOptions};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use utoipa::ToSchema;
+
+// 差分の本文の上限 (超えた分は返さない)
+const MAX_PATCH_BYTES: usize = 4 * 1024 * 1024;
+
+// /api/git/log で limit を省略したときの件数と、指定できる上限
+pub const DEFAULT_LOG_LIMIT: usize = 50;
+pub const MAX_LOG_LIMIT: usize = 1000;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GitStatus {
+    pub root: String,             // リポジトリの作業フォルダー
+    pub branch: Option<String>,   // HEAD が切り離されているか、まだコミットがない場合は None
+    pub upstream: Option<String>, // 追跡しているブランチ (origin/main など)
+    pub ahead: usize,
+    pub behind: usize,
+    pub files: Vec<GitFileStatus>, // 変更のあるファイル (無視したファイルは含まない)
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GitFileStatus {
+    pub path: String,             // 絶対パス
+    pub index: Option<String>,    // ステージした変更: new / modified / deleted / renamed / typechange
+    pub worktree: Option<String>, // ステージしていない変更: new (追跡していない) / modified / deleted / renamed / typechange
+    pub conflicted: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GitDiff {
+    pub root: String,
+    pub staged: bool, // true ならステージした変更 (HEAD とインデックスの差分)
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+    pub patch: String,   // unified diff
+    pub truncated: bool, // patch が 4 MB を超えて切り詰めた
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GitLog {
+    pub root: String,
+    pub commits: Vec<GitCommit>, // 新しい順
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GitCommit {
+    pub id: String,
+    pub summary: String,
+    pub author: String,
+    pub email: String,
+    pub time: i64, // UNIX 秒
+}
+
+/// path を含むリポジトリを開き、作業フォルダーと一緒に返す
+pub fn open(path: &Path) -> Result<(Repository, PathBuf), String> {
+    let repo = Repository::discover(path).map_err(|_| format!("Not inside a git repository: {}", path.display()))?;
+    let root = repo
+        .workdir()
+        .map(Path::to_path_buf)
+        .ok_or_else(|| format!("Bare repositories are not supported: {}", repo.path().display()))?;
+    Ok((repo, root))
+}
+
+pub fn status(repo: &Repository, root: &Path) -> Result<GitStatus, String> {
+    let mut options = StatusOptions::new();
+    options.include_untracked(true).recurse_untracked_dirs(true).renames_head_to_index(true);
+    let statuses = repo.statuses(Some(&mut options)).map_err(|e| e.message().to_string())?;
+    let files = statuses
+        .iter()
+        .filter_map(|entry| {
+            let status = entry.status();
+            Some(GitFileStatus {
+                path: root.join(entry.path()?).display().to_string(),
+                index: index_change(status).map(str::to_string),
+                worktree: worktree_change(status).map(str::to_string),
+                conflicted: status.contains(Status::CONFLICTED),
+            })
+        })
+        .collect();
+
+    let mut result = GitStatus {
+        root: root.display().to_string(),
+        branch: None,
+        upstream: None,
+        ahead: 0,
+        behind: 0,
+        files,
+    };
+    // コミットのないリポジトリでは HEAD を読めない
+    let Ok(head) = repo.head() else {
+        return Ok(result);
+    };
+    if !head.is_branch() {
+        return Ok(result);
+    }
+    result.branch = head.shorthand().map(str::to_string);
+    let local = head.target();
+    if let Ok(upstream) = Branch::wrap(head).upstream() {
+        result.upstream = upstream.name().ok().flatten().map(str::to_string);
+        if let (Some(local), Some(remote)) = (local, upstream.get().target()) {
+            if let Ok((ahead, behind)) = repo.graph_ahead_behind(local, remote) {
+                result.ahead = ahead;
+                result.behind = behind;
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn index_change(status: Status) -> Option<&'static str> {
+    match status {
+        s if s.contains(Status::INDEX_NEW) => Some("new"),
+        s if s.contains(Status::INDEX_MODIFIED) => Some("modified"),
+        s if s.contains(Status::INDEX_DELETED) => Some("deleted"),
+        s if s.contains(Status::INDEX_RENAMED) => Some("renamed"),
+        s if s.contains(Status::INDEX_TYPECHANGE) => Some("typechange"),
+        _ => None,
+    }
+}
+
+fn worktree_change(status: Status) -> Option<&'static str> {
+    match status {
+        s if s.contains(Status::WT_NEW) => Some("new"),
+        s if s.contains(Status::WT_MODIFIED) => Some("modified"),
+        s if s.contains(Status::WT_DELETED) => Some("deleted"),
+        s if s.contains(Status::WT_RENAMED) => Some("renamed"),
+        s if s.contains(Status::WT_TYPECHANGE) => Some("typechange"),
+        _ => None,
+    }
+}
+
+/// file を指定するとそのファイル (かフォルダー) の差分だけを返す
+pub fn diff(repo: &Repository, root: &Path, file: Option<&Path>, staged: bool) -> Result<GitDiff, String> {
+    let mut options = DiffOptions::new();
+    options.include_untracked(true).recurse_untracked_dirs(true).show_untracked_content(true);
+    if let Some(file) = file {
+        let relative = file
+            .strip_prefix(root)
+            .map_err(|_| format!("{} is not inside the repository {}", file.display(), root.display()))?;
+        // git のパスは / 区切り
+        options.pathspec(relative.to_string_lossy().replace('\\', "/"));
+    }
+
+    let diff = if staged {
+        let head = repo.head().ok().and_then(|head| head.peel_to_tree().ok());
+        repo.diff_tree_to_index(head.as_ref(), None, Some(&mut options))
+    } else {
+        repo.diff_index_to_workdir(None, Some(&mut options))
+    }
+    .map_err(|e| e.message().to_string())?;
+    let stats = diff.stats().map_err(|e| e.message().to_string())?;
+
+    let mut patch = Vec::new();
+    let mut truncated = false;
+    let printed = diff.print(DiffFormat::Patch, |_, _, line| {
+        let origin = line.origin();
+        let prefix = matches!(origin, '+' | '-' | ' ').then_some(origin as u8);
+        let length = prefix.map_or(0, |_| 1) + line.content().len();
+        if patch.len() + length > MAX_PATCH_BYTES {
+            truncated = true;
+            return false;
+        }
+        patch.extend(prefix);
+        patch.extend_from_slice(line.content());
+        true
+    });
+    // 上限で打ち切った場合は print がエラーを返す
+    if let Err(e) = printed {
+        if !truncated {
+            return Err(e.message().to_string());
+        }
+    }
+
+    Ok(GitDiff {
+        root: root.display().to_string(),
+        staged,
+        files_changed: stats.files_changed(),
+        insertions: stats.insertions(),
+        deletions: stats.deletions(),
+        patch: String::from_utf8_lossy(&patch).to_string(),
+        truncated,
+    })
+}
+
+/// HEAD から新しい順に limit 件のコミット
+pub fn log(repo: &Repository, root: &Path, limit: usize) -> Result<GitLog, String> {
+    let mut result = GitLog {
+        root: root.display().to_string(),
+        commits: Vec::new(),
+    };
+    let mut walk = repo.revwalk().map_err(|e| e.message().to_string())?;
+    // コミットのないリポジトリは空の履歴
+    if walk.push_head().is_err() {
+        return Ok(result);
+    }
+    walk.set_sorting(Sort::TIME).map_err(|e| e.message().to_string())?;
+    for id in walk.take(limit) {
+        let id = id.map_err(|e| e.message().to_string())?;
+        let commit = repo.find_commit(id).map_err(|e| e.message().to_string())?;
+        let author = commit.author();
+        result.commits.push(GitCommit {
+            id: id.to_string(),
+            summary: commit.summary().unwrap_or_default().to_string(),
+            author: author.name().unwrap_or_default().to_string(),
+            email: author.email().unwrap_or_default().to_string(),
+            time: commit.time().seconds(),
+        });
+    }
+    Ok(result)
+}
@@ -0,0 +1,121 @@
+use crate::mime;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use utoipa::ToSchema;
+
+// 1 行として保持する最大バイト数 (これを超える部分は検索しない)
+pub const MAX_LINE_LEN: usize = 64 * 1024;
+
+// 内容検索の対象にするファイルの既定の最大サイズ
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 100 * 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GrepMatch {
+    pub path: String,
+    pub line_number: u64,
+    pub line: String,
+}
+
+/// 検索しなかった (または一部のみ検索した) ファイルとその理由
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: String,
+}
+
+pub enum Outcome {
+    Searched,
+    Stopped,            // on_match が false を返した
+    Partial(String),    // 一部を検索しなかった理由
+    Skipped(String),
+}
+
+/// ファイルを 1 行ずつ読み、query を含む行ごとに on_match を呼ぶ (false を返すと中断)。
+/// ファイル全体や長すぎる行をメモリに読み込まないため、巨大なファイルでもメモリ使用量は一定
+pub fn grep_file(
+    path: &Path,
+    query: &str,
+    case_sensitive: bool,
+    max_file_size: u64,
+    mut on_match: impl FnMut(u64, String) -> bool,
+) -> Outcome {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => return Outcome::Skipped(format!("open failed: {}", e)),
+    };
+    let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+    if size > max_file_size {
+        return Outcome::Skipped(format!("file is larger than {} bytes", max_file_size));
+    }
+
+    let mut reader = BufReader::new(file);
+    let head = match reader.fill_buf() {
+        Ok(head) => &head[..head.len().min(mime::SNIFF_LEN)],
+        Err(e) => return Outcome::Skipped(format!("read failed: {}", e)),
+    };
+    if mime::looks_binary(head) {
+        return Outcome::Skipped("binary file".to_string());
+    }
+
+    let needle = if case_sensitive { query.to_string() } else { query.to_lowercase() };
+    let mut line = Vec::new();
+    let mut line_number = 0u64;
+    let mut long_lines = false;
+
+    loop {
+        line.clear();
+        // 改行までを最大 MAX_LINE_LEN バイト読み込み、残りは読み捨てる
+        let read = match (&mut reader).take(MAX_LINE_LEN as u64).read_until(b'\n', &mut line) {
+            Ok(read) => read,
+            Err(e) => return Outcome::Partial(format!("read failed at line {}: {}", line_number + 1, e)),
+        };
+        if read == 0 {
+            break;
+        }
+        line_number += 1;
+        if !line.ends_with(b"\n") && read == MAX_LINE_LEN {
+            long_lines = true;
+            if let Err(e) = skip_to_newline(&mut reader) {
+                return Outcome::Partial(format!("read failed at line {}: {}", line_number, e));
+            }
+        }
+
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim_end_matches(['\r', '\n']);
+        let found = if case_sensitive {
+            text.contains(&needle)
+        } else {
+            text.to_lowercase().contains(&needle)
+        };
+        if found && !on_match(line_number, text.to_string()) {
+            return Outcome::Stopped;
+        }
+    }
+
+    if long_lines {
+        Outcome::Partial(format!("lines longer than {} bytes were only searched in their first {} bytes", MAX_LINE_LEN, MAX_LINE_LEN))
+    } else {
+        Outcome::Searched
+    }
+}
+
+fn skip_to_newline(reader: &mut impl BufRead) -> std::io::Result<()> {
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        match buffer.iter().position(|&b| b == b'\n') {
+            Some(i) => {
+                reader.consume(i + 1);
+                return Ok(());
+            }
+            None => {
+                let len = buffer.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
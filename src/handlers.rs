@@ -0,0 +1,4936 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use warp::{Rejection, Reply};
+use base64::{Engine as _, engine::general_purpose};
+
+use crate::audit::AuditLog;
+use crate::auth::{self, ClientAuth, Operation, TokenMeta};
+use crate::changes::ChangeLog;
+use crate::clients::ClientRegistry;
+use crate::config::Config;
+use crate::dirsize::DirSizeCache;
+use crate::hashcache::HashCache;
+use crate::index::SearchIndex;
+use crate::jobs::JobStore;
+use crate::listcache::ListCache;
+use crate::plugins::PluginHost;
+use crate::sandbox::Sandbox;
+use crate::sync::SyncManager;
+use crate::shutdown;
+use crate::trash::Trash;
+use crate::vault::{self, Vault, VaultKeyInfo};
+use crate::{is_hidden, os_random_hex, sha256_hex, verify_token, generate_token, generate_token_hash};
+use crate::{apiversion, audit, changes, cleanup, clients, clipboard, concurrency, deleteguard, dirsize, exec, fuzzy, git, grep, hashcache, hooks, index, jobs, listcache, logs, mime, paths, plugins, policy, print, quota, remote, s3, scan, script, shares, signing, sync, tempfiles, templates, timeout, trash, walk, writequota};
+
+// ロングポーリングの最大待機秒数
+pub(crate) const MAX_POLL_WAIT_SECS: u64 = 60;
+
+// チャンク読み込みのデフォルト/最大サイズ
+pub(crate) const DEFAULT_CHUNK_SIZE: u64 = 64 * 1024;
+pub(crate) const MAX_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+// read_binary でこれより大きな内容は、全体を読み込まずに Base64 へ少しずつ符号化して送る
+const STREAM_BASE64_THRESHOLD: u64 = 1024 * 1024;
+// 少しずつ符号化するときに一度に読み込む大きさ (Base64 をつなげられるよう 3 の倍数)
+const BASE64_STREAM_CHUNK: usize = 3 * 64 * 1024;
+
+// トークンのローテーション後、旧トークンを使える既定の秒数
+pub(crate) const DEFAULT_ROTATION_GRACE_SECS: u64 = 300;
+
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct FileInfo {
+    pub path: String,
+    pub name: String,
+    pub is_file: bool,
+    pub size: Option<u64>,
+    pub hidden: bool,
+    pub modified: Option<u64>, // UNIX 時刻 (秒)
+    pub readonly: bool,
+    pub is_symlink: bool,
+    pub mime_type: String,     // 拡張子から推定
+}
+
+impl FileInfo {
+    // metadata はシンボリックリンクを辿らないもの (DirEntry::metadata) を渡す
+    fn from_path(path: &Path, metadata: Option<&fs::Metadata>) -> Self {
+        let is_file = path.is_file();
+        FileInfo {
+            path: path.to_string_lossy().to_string(),
+            name: path.file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("")
+                .to_string(),
+            is_file,
+            size: metadata.map(|m| m.len()),
+            hidden: is_hidden(path, metadata),
+            modified: metadata.and_then(modified_secs),
+            readonly: metadata.map(|m| m.permissions().readonly()).unwrap_or(false),
+            is_symlink: metadata.map(|m| m.file_type().is_symlink()).unwrap_or(false),
+            mime_type: if is_file { mime::from_extension(path) } else { "inode/directory" }.to_string(),
+        }
+    }
+}
+
+fn modified_secs(metadata: &fs::Metadata) -> Option<u64> {
+    metadata.modified().ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+}
+
+// ファイルの ETag。内容を読まずに求められるよう、大きさと更新時刻 (ナノ秒) から作る
+fn file_etag(metadata: &fs::Metadata) -> String {
+    let modified = metadata.modified().ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!("\"{:x}-{:x}\"", metadata.len(), modified)
+}
+
+// 読み込めるファイルの現在の ETag (パスが不正・許可されていない・存在しない場合は None)
+async fn current_etag(config: &Arc<Config>, path: &str) -> Option<String> {
+    let config = config.clone();
+    let path = PathBuf::from(path);
+    blocking(move || {
+        paths::validate(&path.to_string_lossy()).ok()?;
+        check_access(&config, &path, policy::Action::Read).ok()?;
+        fs::metadata(&path).ok().filter(|m| m.is_file()).map(|m| file_etag(&m))
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+// If-None-Match が現在の ETag に一致すれば 304 を返す (カンマ区切りの複数指定と * を受け付ける)
+fn not_modified(if_none_match: Option<&str>, etag: Option<&str>) -> Option<warp::reply::Response> {
+    let (if_none_match, etag) = (if_none_match?, etag?);
+    let matched = if_none_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag);
+    if !matched {
+        return None;
+    }
+    let mut response = warp::reply::Response::new(warp::hyper::Body::empty());
+    *response.status_mut() = warp::http::StatusCode::NOT_MODIFIED;
+    response.headers_mut().insert(warp::http::header::ETAG, warp::http::HeaderValue::from_str(etag).ok()?);
+    Some(response)
+}
+
+fn with_etag(reply: impl Reply, etag: Option<String>) -> warp::reply::Response {
+    let mut response = reply.into_response();
+    if let Some(etag) = etag.and_then(|etag| warp::http::HeaderValue::from_str(&etag).ok()) {
+        response.headers_mut().insert(warp::http::header::ETAG, etag);
+    }
+    response
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ApiResponse<T> {
+    pub success: bool,
+    pub data: Option<T>,
+    pub error: Option<String>,
+}
+
+// 変更操作のレスポンス (receipt_key 設定時は署名付きのレシートを追加したもの)
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ReceiptResponse {
+    pub success: bool,
+    pub data: Option<String>,
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<audit::AuditEntry>,
+}
+
+// 検索用レスポンス (ApiResponse に打ち切りの有無を追加したもの)
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SearchResponse {
+    pub success: bool,
+    pub data: Option<Vec<FileInfo>>,
+    pub error: Option<String>,
+    pub truncated: bool, // 件数上限またはタイムアウトで打ち切った場合 true
+    pub cursor: Option<String>, // 打ち切った場合の続きの位置 (部分一致検索のみ)
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ReadRequest {
+    pub path: String,
+    pub token: String,
+    #[serde(default)]
+    pub content_hash: Option<String>, // 以前取得したハッシュ (一致しない場合は競合エラー)
+    #[serde(default)]
+    pub include_hash: bool,
+    #[serde(default)]
+    pub offset: Option<u64>, // read_binary のみ: 読み込みを始める位置 (バイト)
+    #[serde(default)]
+    pub length: Option<u64>, // read_binary のみ: 読み込むバイト数 (省略時はファイルの終わりまで)
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ReadWithHash {
+    pub content: String,
+    sha256: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct HashConflict {
+    pub conflict: bool,
+    pub current_hash: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ReadChunkRequest {
+    pub path: String,
+    pub seq: u64,
+    #[serde(default)]
+    pub chunk_size: Option<u64>,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ChunkInfo {
+    pub seq: u64,
+    pub offset: u64,
+    pub length: u64,
+    pub total_size: u64,
+    pub total_chunks: u64,
+    sha256: String,     // このチャンクのデータのハッシュ
+    pub data: String,       // Base64エンコードされたチャンクデータ
+    pub modified: Option<u64>, // 転送中のファイル変更検出用
+    pub last: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct WriteRequest {
+    pub path: String,
+    pub content: String,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct WriteBinaryRequest {
+    pub path: String,
+    pub content: String, // Base64エンコードされたバイナリデータ
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DeleteRequest {
+    pub path: String,
+    pub token: String,
+    #[serde(default)]
+    pub recursive: bool, // 空でないフォルダを中身ごと削除する (false なら削除せずにエントリ数を返す)
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct SearchRequest {
+    pub directory: String,
+    pub pattern: String,
+    pub token: String,
+    #[serde(default = "default_true")]
+    pub show_hidden: bool, // 省略時は隠しファイルも含める (以前の動作)
+    #[serde(default)]
+    pub mode: SearchMode,
+    #[serde(default)]
+    pub respect_gitignore: bool,
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    #[serde(default)]
+    pub limit: Option<usize>, // 設定の search_max_results が上限
+    #[serde(default)]
+    pub cursor: Option<String>, // 前回のレスポンスの cursor (続きから検索する)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    #[default]
+    Substring, // 部分一致
+    Fuzzy,     // あいまい一致 (スコア順)
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct GrepRequest {
+    pub directory: String,
+    pub query: String,
+    pub token: String,
+    #[serde(default)]
+    pub case_sensitive: bool,
+    #[serde(default = "default_true")]
+    pub show_hidden: bool, // 省略時は隠しファイルも含める (以前の動作)
+    #[serde(default)]
+    pub respect_gitignore: bool,
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    #[serde(default)]
+    pub limit: Option<usize>, // 設定の search_max_results が上限
+    #[serde(default)]
+    pub max_file_size: Option<u64>, // 設定の grep_max_file_size より大きくはできない
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GrepResult {
+    pub matches: Vec<grep::GrepMatch>,
+    pub skipped: Vec<grep::SkippedFile>, // サイズ超過・バイナリなどで検索しなかった (一部のみ検索した) ファイル
+    pub files_searched: u64,
+    pub truncated: bool, // 件数上限またはタイムアウトで打ち切った場合 true
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IndexSearchRequest {
+    pub query: String,
+    pub token: String,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct StaleRequest {
+    pub directory: String,
+    pub days: u64,
+    pub token: String,
+    #[serde(default)]
+    pub include_files: bool,
+    #[serde(default)]
+    pub follow_symlinks: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct StaleGroup {
+    pub directory: String,
+    pub file_count: u64,
+    pub bytes: u64,
+    pub oldest_modified: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct StaleReport {
+    pub total_files: u64,
+    pub total_bytes: u64,
+    pub groups: Vec<StaleGroup>, // 回収可能サイズの大きい順
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MimeRequest {
+    pub path: String,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DirSizeRequest {
+    pub directory: String,
+    pub token: String,
+    #[serde(default)]
+    pub refresh: bool, // キャッシュを使わずに集計し直す
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct HashRequest {
+    pub path: String,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FileHash {
+    pub path: String,
+    pub sha256: String, // 暗号化されたファイルは復号後の内容のハッシュ
+    pub size: u64,
+    pub cached: bool, // 前回求めたハッシュを返した (ファイルは変わっていない)
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CleanupRequest {
+    pub token: String,
+    #[serde(default = "default_true")]
+    pub dry_run: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PrintRequest {
+    pub path: String,
+    #[serde(default)]
+    pub printer: Option<String>, // 未指定時は既定のプリンター
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateRequest {
+    pub path: String,
+    pub is_directory: bool,
+    #[serde(default)]
+    pub template: Option<String>, // template_dir= のテンプレートの名前 (ファイルの作成時のみ)
+    #[serde(default)]
+    pub variables: HashMap<String, String>, // テンプレートで {{キー}} を置き換える値 (name と date より優先)
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MoveRequest {
+    pub source: String,
+    pub destination: String,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CopyRequest {
+    pub source: String,
+    pub destination: String,
+    pub token: String,
+    #[serde(default)]
+    pub background: bool, // フォルダをバックグラウンドのジョブとしてコピーする (再起動後も再開)
+}
+
+// 許可ルート (allowed_root) の範囲内か、ルートごとのポリシーで許可されているかを確認する
+pub(crate) fn check_access(config: &Config, path: &Path, action: policy::Action) -> Result<(), String> {
+    shares::ensure(&config.shares, path)?;
+    policy::check_allowed(&config.allowed_roots, path)?;
+    policy::check(&config.policies, path, action)
+}
+
+// 書き込み先が許可ルートの範囲内か確認し、ポリシー適用後の実際の書き込み先を返す
+pub(crate) fn write_target(config: &Config, path: &Path) -> Result<PathBuf, String> {
+    shares::ensure(&config.shares, path)?;
+    policy::check_allowed(&config.allowed_roots, path)?;
+    policy::write_target(&config.policies, path)
+}
+
+// トークンに許可ルートがある場合は、それを許可ルートとした設定を返す
+fn scoped_config(config: Arc<Config>, grant: &auth::Grant) -> Arc<Config> {
+    if grant.allowed_roots.is_empty() {
+        return config;
+    }
+    let mut scoped = (*config).clone();
+    scoped.allowed_roots = grant.allowed_roots.clone();
+    Arc::new(scoped)
+}
+
+// トークンを検証し、操作が有効か確認してティアのレート制限を適用する
+async fn check_auth(token: &str, auth: &ClientAuth, operation: Operation) -> Result<auth::Grant, String> {
+    auth.authorize(token, operation)
+}
+
+// ファイルシステムを使う処理 (ブロッキング I/O) を専用スレッドで行う。
+// 大きなファイルや遅いネットワークドライブの処理中も、他のリクエストを止めないようにする。
+// request_timeout_secs= を過ぎたら処理を待たずに 504 で拒否する (処理は裏で続く)
+async fn blocking<T: Send + 'static>(task: impl FnOnce() -> T + Send + 'static) -> Result<T, Rejection> {
+    match timeout::run(tokio::task::spawn_blocking(task)).await {
+        Ok(Ok(result)) => Ok(result),
+        // 処理中のパニックは、これまでどおりそのリクエストのパニックとして扱う
+        Ok(Err(e)) => std::panic::resume_unwind(e.into_panic()),
+        Err(timed_out) => Err(warp::reject::custom(timed_out)),
+    }
+}
+
+// ディレクトリの走査やハッシュなどの重い処理は、同時に実行する数を制限する
+// (max_heavy_operations= に達していて空かなければ busy で拒否する)
+async fn heavy<T: Send + 'static>(task: impl FnOnce() -> T + Send + 'static) -> Result<T, Rejection> {
+    let _permit = concurrency::heavy().acquire().await.map_err(warp::reject::custom)?;
+    blocking(task).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/read",
+    request_body = ReadRequest,
+    params(("If-None-Match" = Option<String>, Header, description = "ETag from an earlier response; returns 304 when the file is unchanged")),
+    responses(
+        (status = 200, description = "File content. With include_hash the data is a ReadWithHash object; a content_hash mismatch returns a HashConflict", body = ApiResponse<String>, headers(("ETag" = String, description = "Changes when the file's size or modification time changes"))),
+        (status = 304, description = "The file has not changed since the If-None-Match ETag"),
+    ),
+)]
+pub async fn read_file(request: ReadRequest, if_none_match: Option<String>, auth: ClientAuth, config: Arc<Config>, vault: Arc<Vault>, changes: Arc<ChangeLog>, hashes: Arc<HashCache>) -> Result<warp::reply::Response, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Read).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }).into_response()),
+    };
+    let config = scoped_config(config, &grant);
+
+    let etag = current_etag(&config, &request.path).await;
+    if let Some(reply) = not_modified(if_none_match.as_deref(), etag.as_deref()) {
+        return Ok(reply);
+    }
+
+    let hashing = request.content_hash.is_some() || request.include_hash;
+    let task = move || -> Result<warp::reply::Json, Rejection> {
+        if let Err(e) = paths::validate(&request.path) {
+            return Ok(invalid_path_reply(e));
+        }
+
+        if let Err(e) = check_access(&config, Path::new(&request.path), policy::Action::Read) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+    
+        if let Ok(metadata) = fs::metadata(&request.path) {
+            if let Err(e) = grant.check_size(metadata.len()) {
+                return Ok(warp::reply::json(&ApiResponse::<String> {
+                    success: false,
+                    data: None,
+                    error: Some(e),
+                }));
+            }
+        }
+
+        if request.content_hash.is_none() && !request.include_hash {
+            let content = vault.read(Path::new(&request.path)).and_then(|bytes| {
+                String::from_utf8(bytes).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "stream did not contain valid UTF-8"))
+            });
+            return match content {
+                Ok(content) => Ok(warp::reply::json(&ApiResponse {
+                    success: true,
+                    data: Some(content),
+                    error: None,
+                })),
+                Err(e) => Ok(warp::reply::json(&ApiResponse::<String> {
+                    success: false,
+                    data: None,
+                    error: Some(e.to_string()),
+                })),
+            };
+        }
+
+        // 暗号化されていないファイルは、読み込む前に少しずつハッシュを求めて比較する
+        // (競合する場合に大きなファイルをメモリに読み込まない。変わっていなければキャッシュを使う)
+        if let Some(content_hash) = &request.content_hash {
+            let path = Path::new(&request.path);
+            if !vault.is_encrypted_file(path) {
+                if let Ok((current_hash, _)) = hashes.get_or_compute(path, &changes, || signing::file_sha256_hex(path)) {
+                    if !content_hash.eq_ignore_ascii_case(&current_hash) {
+                        return Ok(hash_conflict(current_hash));
+                    }
+                }
+            }
+        }
+
+        // ハッシュ指定あり: 内容のハッシュを計算して比較する
+        let bytes = match vault.read(Path::new(&request.path)) {
+            Ok(bytes) => bytes,
+            Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e.to_string()),
+            })),
+        };
+        let current_hash = sha256_hex(&bytes);
+
+        if let Some(content_hash) = &request.content_hash {
+            if !content_hash.eq_ignore_ascii_case(&current_hash) {
+                return Ok(hash_conflict(current_hash));
+            }
+        }
+
+        let content = match String::from_utf8(bytes) {
+            Ok(content) => content,
+            Err(_) => return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some("stream did not contain valid UTF-8".to_string()),
+            })),
+        };
+
+        if request.include_hash {
+            Ok(warp::reply::json(&ApiResponse {
+                success: true,
+                data: Some(ReadWithHash {
+                    content,
+                    sha256: current_hash,
+                }),
+                error: None,
+            }))
+        } else {
+            Ok(warp::reply::json(&ApiResponse {
+                success: true,
+                data: Some(content),
+                error: None,
+            }))
+        }
+    };
+    // ハッシュを求める場合は重い処理として数える
+    let reply = if hashing { heavy(task).await?? } else { blocking(task).await?? };
+    Ok(with_etag(reply, etag))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/read_binary",
+    request_body = ReadRequest,
+    params(("If-None-Match" = Option<String>, Header, description = "ETag from an earlier response; returns 304 when the file is unchanged")),
+    responses(
+        (status = 200, description = "Base64-encoded file content", body = ApiResponse<String>, headers(("ETag" = String, description = "Changes when the file's size or modification time changes"))),
+        (status = 304, description = "The file has not changed since the If-None-Match ETag"),
+    ),
+)]
+pub async fn read_binary_file(request: ReadRequest, if_none_match: Option<String>, auth: ClientAuth, config: Arc<Config>, vault: Arc<Vault>) -> Result<warp::reply::Response, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Read).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }).into_response()),
+    };
+    let config = scoped_config(config, &grant);
+
+    let etag = current_etag(&config, &request.path).await;
+    if let Some(reply) = not_modified(if_none_match.as_deref(), etag.as_deref()) {
+        return Ok(reply);
+    }
+
+    let content = blocking(move || -> Result<BinaryContent, Rejection> {
+        if let Err(e) = paths::validate(&request.path) {
+            return Ok(BinaryContent::Reply(invalid_path_reply(e)));
+        }
+
+        if let Err(e) = check_access(&config, Path::new(&request.path), policy::Action::Read) {
+            return Ok(BinaryContent::Reply(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            })));
+        }
+    
+        // 範囲を指定した場合は、その範囲の大きさで転送量の上限を確認する
+        let ranged = request.offset.is_some() || request.length.is_some();
+        let offset = request.offset.unwrap_or(0);
+        if let Ok(metadata) = fs::metadata(&request.path) {
+            let size = metadata.len().saturating_sub(offset).min(request.length.unwrap_or(u64::MAX));
+            if let Err(e) = grant.check_size(size) {
+                return Ok(BinaryContent::Reply(warp::reply::json(&ApiResponse::<String> {
+                    success: false,
+                    data: None,
+                    error: Some(e),
+                })));
+            }
+            // 大きな内容は読み込まずに開いたファイルを返し、送りながら符号化する (暗号化されたファイルは復号が必要なため除く)
+            if size >= STREAM_BASE64_THRESHOLD && metadata.len() >= offset && !vault.is_encrypted_file(Path::new(&request.path)) {
+                use std::io::{Seek, SeekFrom};
+                let opened = fs::File::open(&request.path).and_then(|mut file| file.seek(SeekFrom::Start(offset)).map(|_| file));
+                return Ok(match opened {
+                    Ok(file) => BinaryContent::Stream(file, size),
+                    Err(e) => BinaryContent::Reply(warp::reply::json(&ApiResponse::<String> {
+                        success: false,
+                        data: None,
+                        error: Some(e.to_string()),
+                    })),
+                });
+            }
+        }
+
+        let content = if ranged {
+            read_range(Path::new(&request.path), offset, request.length, &vault)
+        } else {
+            vault.read(Path::new(&request.path))
+        };
+        match content {
+            Ok(content) => {
+                let base64_content = general_purpose::STANDARD.encode(&content);
+                Ok(BinaryContent::Reply(warp::reply::json(&ApiResponse {
+                    success: true,
+                    data: Some(base64_content),
+                    error: None,
+                })))
+            },
+            Err(e) => Ok(BinaryContent::Reply(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e.to_string()),
+            }))),
+        }
+    })
+    .await??;
+    Ok(match content {
+        BinaryContent::Reply(reply) => with_etag(reply, etag),
+        BinaryContent::Stream(file, length) => with_etag(base64_response(file, length), etag),
+    })
+}
+
+// read_binary の内容 (小さな内容は符号化済みの応答、大きな内容は開いたファイルと送る大きさ)
+enum BinaryContent {
+    Reply(warp::reply::Json),
+    Stream(fs::File, u64),
+}
+
+// file の現在位置から length バイトを、Base64 の JSON の応答 ({"success":true,"data":"...","error":null}) として逐次送る。
+// 大きさが先に決まるため Content-Length を付ける (圧縮の対象外になり、全体をメモリに溜めない)
+fn base64_response(file: fs::File, length: u64) -> warp::reply::Response {
+    use std::io::Read;
+
+    const PREFIX: &str = r#"{"success":true,"data":""#;
+    const SUFFIX: &str = r#"","error":null}"#;
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<std::io::Result<String>>(8);
+    tokio::task::spawn_blocking(move || {
+        let mut reader = file.take(length);
+        let mut buffer = vec![0u8; BASE64_STREAM_CHUNK];
+        let mut sent = 0u64;
+        loop {
+            // 最後以外のチャンクが 3 の倍数になるよう、バッファが埋まるまで読み込む
+            let mut filled = 0;
+            while filled < buffer.len() {
+                match reader.read(&mut buffer[filled..]) {
+                    Ok(0) => break,
+                    Ok(n) => filled += n,
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        let _ = tx.blocking_send(Err(e));
+                        return;
+                    }
+                }
+            }
+            if filled == 0 {
+                break;
+            }
+            sent += filled as u64;
+            // 送信できない場合はクライアントが切断しているので読み込みを中断する
+            if tx.blocking_send(Ok(general_purpose::STANDARD.encode(&buffer[..filled]))).is_err() {
+                return;
+            }
+        }
+        // 読み込み中にファイルが短くなった場合は Content-Length と合わないため、応答を中断する
+        if sent < length {
+            let _ = tx.blocking_send(Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)));
+        }
+    });
+
+    let (mut sender, body) = warp::hyper::Body::channel();
+    tokio::spawn(async move {
+        if sender.send_data(PREFIX.into()).await.is_err() {
+            return;
+        }
+        while let Some(chunk) = rx.recv().await {
+            match chunk {
+                Ok(chunk) => {
+                    if sender.send_data(chunk.into()).await.is_err() {
+                        return;
+                    }
+                }
+                Err(_) => {
+                    sender.abort();
+                    return;
+                }
+            }
+        }
+        let _ = sender.send_data(SUFFIX.into()).await;
+    });
+
+    let content_length = PREFIX.len() as u64 + length.div_ceil(3) * 4 + SUFFIX.len() as u64;
+    let mut response = warp::reply::Response::new(body);
+    let headers = response.headers_mut();
+    headers.insert(warp::http::header::CONTENT_TYPE, warp::http::HeaderValue::from_static("application/json"));
+    headers.insert(warp::http::header::CONTENT_LENGTH, warp::http::HeaderValue::from(content_length));
+    response
+}
+
+fn hash_conflict(current_hash: String) -> warp::reply::Json {
+    warp::reply::json(&ApiResponse {
+        success: false,
+        data: Some(HashConflict {
+            conflict: true,
+            current_hash,
+        }),
+        error: Some("Conflict: file content has changed since the given hash".to_string()),
+    })
+}
+
+// ファイルの offset バイト目から最大 length バイトを読み込む (length が None ならファイルの終わりまで)
+fn read_range(path: &Path, offset: u64, length: Option<u64>, vault: &Vault) -> std::io::Result<Vec<u8>> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let out_of_range = |size: u64| std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("Offset {} is beyond the end of the file ({} bytes)", offset, size),
+    );
+    // 暗号化されたファイルは全体を復号してから、復号後の内容の範囲を返す
+    if vault.is_encrypted_file(path) {
+        let content = vault.read(path)?;
+        let size = content.len() as u64;
+        if offset > size {
+            return Err(out_of_range(size));
+        }
+        let end = length.map(|length| offset.saturating_add(length).min(size)).unwrap_or(size);
+        return Ok(content[offset as usize..end as usize].to_vec());
+    }
+
+    let mut file = fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    if offset > size {
+        return Err(out_of_range(size));
+    }
+    // 大きなファイルはメモリマップして必要な範囲だけをコピーする
+    if let Some(map) = signing::map_large(&file)? {
+        let size = map.len() as u64;
+        let start = offset.min(size);
+        let end = length.map(|length| start.saturating_add(length).min(size)).unwrap_or(size);
+        return Ok(map[start as usize..end as usize].to_vec());
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buffer = Vec::new();
+    file.take(length.unwrap_or(u64::MAX)).read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/read_chunk",
+    request_body = ReadChunkRequest,
+    responses((status = 200, description = "One chunk of the file", body = ApiResponse<ChunkInfo>)),
+)]
+pub async fn read_file_chunk(request: ReadChunkRequest, auth: ClientAuth, config: Arc<Config>, vault: Arc<Vault>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Read).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<ChunkInfo> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    blocking(move || {
+        if let Err(e) = paths::validate(&request.path) {
+            return Ok(invalid_path_reply(e));
+        }
+
+        if let Err(e) = check_access(&config, Path::new(&request.path), policy::Action::Read) {
+            return Ok(warp::reply::json(&ApiResponse::<ChunkInfo> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+
+        // ティアの転送上限を超えるチャンクサイズは上限に合わせる
+        let chunk_size = request.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE)
+            .min(grant.max_transfer_bytes.unwrap_or(MAX_CHUNK_SIZE))
+            .clamp(1, MAX_CHUNK_SIZE);
+
+        match read_chunk(Path::new(&request.path), request.seq, chunk_size, &vault) {
+            Ok(chunk) => Ok(warp::reply::json(&ApiResponse {
+                success: true,
+                data: Some(chunk),
+                error: None,
+            })),
+            Err(e) => Ok(warp::reply::json(&ApiResponse::<ChunkInfo> {
+                success: false,
+                data: None,
+                error: Some(e.to_string()),
+            })),
+        }
+    })
+    .await?
+}
+
+fn read_chunk(path: &Path, seq: u64, chunk_size: u64, vault: &Vault) -> std::io::Result<ChunkInfo> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file = fs::File::open(path)?;
+    let metadata = file.metadata()?;
+    // 暗号化されたファイルは全体を復号してから、復号後の内容をチャンクに分ける
+    let decrypted = if vault.is_encrypted_file(path) {
+        Some(vault.read(path)?)
+    } else {
+        None
+    };
+    let total_size = decrypted.as_ref().map(|d| d.len() as u64).unwrap_or(metadata.len());
+    let total_chunks = total_size.div_ceil(chunk_size).max(1);
+
+    if seq >= total_chunks {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Chunk {} is out of range (total chunks: {})", seq, total_chunks),
+        ));
+    }
+
+    let offset = seq * chunk_size;
+    let length = chunk_size.min(total_size - offset);
+    let buffer = match &decrypted {
+        Some(decrypted) => decrypted[offset as usize..(offset + length) as usize].to_vec(),
+        None => {
+            let mut buffer = vec![0u8; length as usize];
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut buffer)?;
+            buffer
+        }
+    };
+
+    Ok(ChunkInfo {
+        seq,
+        offset,
+        length,
+        total_size,
+        total_chunks,
+        sha256: sha256_hex(&buffer),
+        data: general_purpose::STANDARD.encode(&buffer),
+        modified: modified_secs(&metadata),
+        last: seq + 1 == total_chunks,
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/write",
+    request_body = WriteRequest,
+    responses((status = 200, description = "Written; includes a signed receipt when receipt_key is set", body = ReceiptResponse)),
+)]
+pub async fn write_file(request: WriteRequest, auth: ClientAuth, changes: Arc<ChangeLog>, config: Arc<Config>, audit: Arc<AuditLog>, vault: Arc<Vault>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Write).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    if let Err(e) = paths::validate(&request.path) {
+        return Ok(invalid_path_reply(e));
+    }
+
+    if let Err(e) = grant.check_size(request.content.len() as u64).and_then(|_| writequota::check_file(request.content.len() as u64)) {
+        return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    let target = match write_target_within_quota(&config, &request.path, request.content.len() as u64).await? {
+        Ok(target) => target,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+
+    if let Err(reply) = scan_content(&config, &audit, &target, request.content.as_bytes().to_vec()).await {
+        return Ok(reply);
+    }
+
+    blocking(move || {
+        if let Err(e) = hooks::before(&config.hooks, "write", &target, None) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+        if let Err(e) = policy::save_version(&config.policies, &target) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+
+        let length = request.content.len() as u64;
+        if let Err(e) = writequota::reserve(&grant.tier, length) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+
+        match vault.write(&target, request.content.as_bytes()) {
+            Ok(_) => {
+                changes.record("write", &target.to_string_lossy(), None);
+                hooks::after(&config.hooks, "write", &target, None);
+                let receipt = audit.receipt("write", &target.to_string_lossy(), "", audit.file_hash(&target));
+                Ok(warp::reply::json(&ReceiptResponse {
+                    success: true,
+                    data: Some(written_message("File written successfully", Path::new(&request.path), &target)),
+                    error: None,
+                    receipt,
+                }))
+            },
+            Err(e) => {
+                writequota::refund(&grant.tier, length);
+                Ok(warp::reply::json(&ApiResponse::<String> {
+                    success: false,
+                    data: None,
+                    error: Some(e.to_string()),
+                }))
+            },
+        }
+    })
+    .await?
+}
+
+// 書き込み先を決めてクォータと空き容量を確認する (どれもファイルシステムを調べるため専用スレッドで行う)
+async fn write_target_within_quota(config: &Arc<Config>, path: &str, bytes: u64) -> Result<Result<PathBuf, String>, Rejection> {
+    let config = config.clone();
+    let path = PathBuf::from(path);
+    blocking(move || {
+        let target = write_target(&config, &path)?;
+        quota::check(&config.quotas, &target, None, quota::Usage { bytes, files: 1 })?;
+        quota::check_free_space(&target, bytes)?;
+        Ok(target)
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/write_binary",
+    request_body = WriteBinaryRequest,
+    responses((status = 200, description = "Written; includes a signed receipt when receipt_key is set", body = ReceiptResponse)),
+)]
+pub async fn write_binary_file(request: WriteBinaryRequest, auth: ClientAuth, changes: Arc<ChangeLog>, config: Arc<Config>, audit: Arc<AuditLog>, vault: Arc<Vault>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Write).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    if let Err(e) = paths::validate(&request.path) {
+        return Ok(invalid_path_reply(e));
+    }
+    
+    // 復号した内容を保持しないよう、まず Base64 を検証しながら復号後の大きさを求める
+    let length = match base64_decoded_len(&request.content) {
+        Ok(length) => length,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(format!("Base64 decode error: {}", e)),
+        })),
+    };
+
+    if let Err(e) = grant.check_size(length).and_then(|_| writequota::check_file(length)) {
+        return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    let target = match write_target_within_quota(&config, &request.path, length).await? {
+        Ok(target) => target,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+
+    // 保管庫への書き込み (暗号化) とウイルススキャンは内容の全体が必要なため、その場合だけメモリに復号する
+    let binary_data = if vault.is_vault_path(&target) || config.scanner.enabled() {
+        match general_purpose::STANDARD.decode(&request.content) {
+            Ok(binary_data) => Some(binary_data),
+            Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(format!("Base64 decode error: {}", e)),
+            })),
+        }
+    } else {
+        None
+    };
+    if let Some(binary_data) = &binary_data {
+        if let Err(reply) = scan_content(&config, &audit, &target, binary_data.clone()).await {
+            return Ok(reply);
+        }
+    }
+
+    blocking(move || {
+        if let Err(e) = hooks::before(&config.hooks, "write", &target, None) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+        if let Err(e) = policy::save_version(&config.policies, &target) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+
+        if let Err(e) = writequota::reserve(&grant.tier, length) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+
+        // バイナリデータをファイルに書き込み
+        let written = match &binary_data {
+            Some(binary_data) => vault.write(&target, binary_data),
+            None => write_base64_file(&request.content, &target),
+        };
+        match written {
+            Ok(_) => {
+                changes.record("write", &target.to_string_lossy(), None);
+                hooks::after(&config.hooks, "write", &target, None);
+                let receipt = audit.receipt("write", &target.to_string_lossy(), "", audit.file_hash(&target));
+                Ok(warp::reply::json(&ReceiptResponse {
+                    success: true,
+                    data: Some(written_message("Binary file written successfully", Path::new(&request.path), &target)),
+                    error: None,
+                    receipt,
+                }))
+            },
+            Err(e) => {
+                writequota::refund(&grant.tier, length);
+                Ok(warp::reply::json(&ApiResponse::<String> {
+                    success: false,
+                    data: None,
+                    error: Some(format!("File write error: {}", e)),
+                }))
+            },
+        }
+    })
+    .await?
+}
+
+// Base64 を復号した大きさ (内容は捨てながら復号するため、不正な Base64 もここで分かる)
+fn base64_decoded_len(content: &str) -> Result<u64, std::io::Error> {
+    let mut decoder = base64::read::DecoderReader::new(content.as_bytes(), &general_purpose::STANDARD);
+    std::io::copy(&mut decoder, &mut std::io::sink())
+}
+
+// Base64 を少しずつ復号して同じフォルダの一時ファイルに書き込み、置き換える (復号した内容の全体をメモリに持たない)
+fn write_base64_file(content: &str, target: &Path) -> std::io::Result<()> {
+    let temp = tempfiles::create(target, "upload");
+    let result = (|| {
+        let mut decoder = base64::read::DecoderReader::new(content.as_bytes(), &general_purpose::STANDARD);
+        let mut file = std::io::BufWriter::with_capacity(BASE64_STREAM_CHUNK, fs::File::create(&temp)?);
+        std::io::copy(&mut decoder, &mut file)?;
+        file.into_inner().map_err(|e| e.into_error())?;
+        fs::rename(&temp, target)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    tempfiles::release(&temp);
+    result
+}
+
+// ダウンロードのエラー。ファイルとして保存されないよう、本文は JSON のまま 200 以外の状態コードで返す
+fn download_error(status: warp::http::StatusCode, error: String) -> warp::reply::Response {
+    let body = warp::reply::json(&ApiResponse::<String> {
+        success: false,
+        data: None,
+        error: Some(error),
+    });
+    warp::reply::with_status(body, status).into_response()
+}
+
+// ファイルの内容をそのまま返す。HEAD の場合は本文を返さず、大きさ・更新時刻・ETag・Content-Type だけを返す
+#[utoipa::path(
+    get,
+    path = "/api/file",
+    params(
+        ("path" = String, Query, description = "File to download"),
+        ("token" = String, Query, description = "API token"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag from an earlier response; returns 304 when the file is unchanged"),
+    ),
+    responses(
+        (status = 200, description = "The file content as is (no body for HEAD)", content_type = "application/octet-stream", headers(
+            ("ETag" = String, description = "Changes when the file's size or modification time changes"),
+            ("Last-Modified" = String, description = "Modification time of the file"),
+            ("Content-Length" = u64, description = "Size of the content (decrypted size for vault files)"),
+        )),
+        (status = 304, description = "The file has not changed since the If-None-Match ETag"),
+        (status = 400, description = "Invalid path", body = ApiResponse<String>),
+        (status = 403, description = "Invalid token or access denied", body = ApiResponse<String>),
+        (status = 404, description = "The file does not exist", body = ApiResponse<String>),
+    ),
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn download_file(path: String, token: String, head: bool, if_none_match: Option<String>, auth: ClientAuth, config: Arc<Config>, vault: Arc<Vault>) -> Result<warp::reply::Response, Rejection> {
+    use warp::http::{header, HeaderValue, StatusCode};
+
+    let grant = match check_auth(&token, &auth, Operation::Read).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(download_error(StatusCode::FORBIDDEN, e)),
+    };
+    let config = scoped_config(config, &grant);
+
+    if let Err(e) = paths::validate(&path) {
+        return Ok(warp::reply::with_status(invalid_path_reply(e), StatusCode::BAD_REQUEST).into_response());
+    }
+
+    let file = {
+        let (path, vault) = (PathBuf::from(&path), vault.clone());
+        blocking(move || {
+            if let Err(e) = check_access(&config, &path, policy::Action::Read) {
+                return Err((StatusCode::FORBIDDEN, e));
+            }
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => return Err((StatusCode::NOT_FOUND, "File does not exist".to_string())),
+            };
+            if let Err(e) = grant.check_size(metadata.len()) {
+                return Err((StatusCode::FORBIDDEN, e));
+            }
+            Ok((vault.content_len(&path, metadata.len()), vault.is_encrypted_file(&path), metadata))
+        })
+        .await?
+    };
+    let (length, encrypted, metadata) = match file {
+        Ok(file) => file,
+        Err((status, e)) => return Ok(download_error(status, e)),
+    };
+
+    let etag = file_etag(&metadata);
+    if let Some(response) = not_modified(if_none_match.as_deref(), Some(&etag)) {
+        return Ok(response);
+    }
+
+    let body = if head {
+        warp::hyper::Body::empty()
+    } else if encrypted {
+        // 暗号化されたファイルは全体を復号してから返す
+        let (path, vault) = (PathBuf::from(&path), vault.clone());
+        match blocking(move || vault.read(&path)).await? {
+            Ok(content) => warp::hyper::Body::from(content),
+            Err(e) => return Ok(download_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        }
+    } else {
+        match tokio::fs::File::open(&path).await {
+            Ok(file) => warp::hyper::Body::wrap_stream(tokio_util::io::ReaderStream::with_capacity(file, DEFAULT_CHUNK_SIZE as usize)),
+            Err(e) => return Ok(download_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        }
+    };
+
+    let mut response = warp::reply::Response::new(body);
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(mime::from_extension(Path::new(&path))));
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+    if let Ok(etag) = HeaderValue::from_str(&etag) {
+        headers.insert(header::ETAG, etag);
+    }
+    if let Some(modified) = metadata.modified().ok().and_then(|t| HeaderValue::from_str(&httpdate::fmt_http_date(t)).ok()) {
+        headers.insert(header::LAST_MODIFIED, modified);
+    }
+    Ok(response)
+}
+
+/// PUT /api/file の本文 (ファイルの内容をそのまま受け取る)
+pub type UploadBody = futures_util::stream::BoxStream<'static, Result<warp::hyper::body::Bytes, warp::Error>>;
+
+// 本文を Base64 にせずそのまま受け取り、ディスクへ逐次書き込む。
+// 保管庫への書き込み (暗号化) とウイルススキャンは内容の全体が必要なため、その場合はメモリに読み込んでから書き込む
+#[utoipa::path(
+    put,
+    path = "/api/file",
+    params(
+        ("path" = String, Query, description = "File to write"),
+        ("token" = String, Query, description = "API token"),
+    ),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream", description = "The file content as is"),
+    responses((status = 200, description = "Written; includes a signed receipt when receipt_key is set", body = ReceiptResponse)),
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_file(path: String, token: String, length: u64, mut body: UploadBody, auth: ClientAuth, changes: Arc<ChangeLog>, config: Arc<Config>, audit: Arc<AuditLog>, vault: Arc<Vault>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&token, &auth, Operation::Write).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    if let Err(e) = paths::validate(&path) {
+        return Ok(invalid_path_reply(e));
+    }
+
+    if let Err(e) = grant.check_size(length).and_then(|_| writequota::check_file(length)) {
+        return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    let target = match write_target_within_quota(&config, &path, length).await? {
+        Ok(target) => target,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+
+    let buffered = vault.is_vault_path(&target) || config.scanner.enabled();
+    let data = if buffered {
+        match read_body(&mut body).await {
+            Ok(data) => Some(data),
+            Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(format!("Upload error: {}", e)),
+            })),
+        }
+    } else {
+        None
+    };
+    if let Some(data) = &data {
+        if let Err(reply) = scan_content(&config, &audit, &target, data.clone()).await {
+            return Ok(reply);
+        }
+    }
+
+    let saved = {
+        let (config, target) = (config.clone(), target.clone());
+        blocking(move || hooks::before(&config.hooks, "write", &target, None).and_then(|_| policy::save_version(&config.policies, &target))).await?
+    };
+    if let Err(e) = saved {
+        return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    if let Err(e) = writequota::reserve(&grant.tier, length) {
+        return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    let written = match data {
+        Some(data) => {
+            let (vault, target) = (vault.clone(), target.clone());
+            blocking(move || vault.write(&target, &data)).await?
+        }
+        None => stream_to_file(body, &target).await,
+    };
+    if let Err(e) = written {
+        writequota::refund(&grant.tier, length);
+        return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(format!("File write error: {}", e)),
+        }));
+    }
+
+    blocking(move || {
+        changes.record("write", &target.to_string_lossy(), None);
+        hooks::after(&config.hooks, "write", &target, None);
+        let receipt = audit.receipt("write", &target.to_string_lossy(), "", audit.file_hash(&target));
+        Ok(warp::reply::json(&ReceiptResponse {
+            success: true,
+            data: Some(written_message("File written successfully", Path::new(&path), &target)),
+            error: None,
+            receipt,
+        }))
+    })
+    .await?
+}
+
+async fn read_body(body: &mut UploadBody) -> Result<Vec<u8>, warp::Error> {
+    use futures_util::StreamExt;
+
+    let mut data = Vec::new();
+    while let Some(chunk) = body.next().await {
+        data.extend_from_slice(&chunk?);
+    }
+    Ok(data)
+}
+
+// 同じフォルダの一時ファイルに書き込んでから置き換える (途中で切断されても元のファイルを壊さない)
+async fn stream_to_file(mut body: UploadBody, target: &Path) -> std::io::Result<()> {
+    use futures_util::StreamExt;
+    use tokio::io::AsyncWriteExt;
+
+    let temp = tempfiles::create(target, "upload");
+    let result = async {
+        let mut file = tokio::fs::File::create(&temp).await?;
+        while let Some(chunk) = body.next().await {
+            file.write_all(&chunk.map_err(std::io::Error::other)?).await?;
+        }
+        file.flush().await?;
+        drop(file);
+        tokio::fs::rename(&temp, target).await
+    }
+    .await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&temp).await;
+    }
+    tempfiles::release(&temp);
+    result
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/delete",
+    request_body = DeleteRequest,
+    responses((status = 200, description = "Deleted (folders are held in the trash while soft delete is enabled; non-empty folders need recursive: true)", body = ReceiptResponse)),
+)]
+pub async fn delete_file(request: DeleteRequest, auth: ClientAuth, changes: Arc<ChangeLog>, config: Arc<Config>, audit: Arc<AuditLog>, trash: Arc<Trash>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Delete).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    blocking(move || {
+        if let Err(e) = paths::validate(&request.path) {
+            return Ok(invalid_path_reply(e));
+        }
+    
+        let path = Path::new(&request.path);
+        if let Err(e) = check_access(&config, path, policy::Action::Write) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+        if let Err(e) = deleteguard::check(path, &config.protected_paths) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+        // 空でないフォルダは recursive の指定がなければ削除しない (消えるエントリ数を返す)
+        let entries = if path.is_dir() { deleteguard::count_entries(path) } else { 0 };
+        if entries > 0 && !request.recursive {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(format!(
+                    "Directory is not empty: {} entries would be removed. Set recursive to true to delete it",
+                    entries
+                )),
+            }));
+        }
+        if let Err(e) = hooks::before(&config.hooks, "delete", path, None) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+        if let Err(e) = policy::save_version(&config.policies, path) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+
+        // 削除前の内容のハッシュをレシートに含める
+        let content_hash = audit.file_hash(path);
+        // フォルダは保管期間が設定されていれば保管領域へ移動し、期限が過ぎてから削除する
+        let result = if path.is_file() {
+            fs::remove_file(path)
+                .map(|_| ("Deleted successfully".to_string(), String::new()))
+                .map_err(|e| e.to_string())
+        } else if path.is_dir() && trash.enabled() {
+            trash.hold(path).map(|held| {
+                (
+                    format!(
+                        "Directory moved to holding area (id: {}, {} entries, purged after {} hours)",
+                        held.id,
+                        entries,
+                        trash.retention_hours()
+                    ),
+                    format!("held {}", held.held_path),
+                )
+            })
+        } else if path.is_dir() {
+            fs::remove_dir_all(path)
+                .map(|_| (format!("Deleted successfully ({} entries removed)", entries), String::new()))
+                .map_err(|e| e.to_string())
+        } else {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some("Path does not exist".to_string()),
+            }));
+        };
+
+        match result {
+            Ok((message, detail)) => {
+                changes.record("delete", &request.path, None);
+                hooks::after(&config.hooks, "delete", path, None);
+                let receipt = audit.receipt("delete", &request.path, &detail, content_hash);
+                Ok(warp::reply::json(&ReceiptResponse {
+                    success: true,
+                    data: Some(message),
+                    error: None,
+                    receipt,
+                }))
+            },
+            Err(e) => Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            })),
+        }
+    })
+    .await?
+}
+
+// 検索条件に一致したエントリごとに on_match を呼ぶ (false を返すと中断)
+// あいまい検索の場合はスコアも渡す。limit または deadline で打ち切った場合は true を返す
+fn walk_search(request: &SearchRequest, config: &Config, limit: Option<usize>, deadline: std::time::Instant, mut on_match: impl FnMut(FileInfo, Option<i64>) -> bool) -> bool {
+    let pattern = request.pattern.to_lowercase();
+    let options = walk::WalkOptions {
+        show_hidden: request.show_hidden,
+        respect_gitignore: request.respect_gitignore,
+        follow_symlinks: request.follow_symlinks,
+        allowed_roots: config.allowed_roots.clone(),
+        excluded: policy::denied_under(&config.policies, Path::new(&request.directory), policy::Action::Search),
+        exclude_names: config.walk_excludes.clone(),
+        // あいまい検索はスコア順に並べ替えるため、ページングは部分一致検索のみ
+        resume_after: match request.mode {
+            SearchMode::Substring => request.cursor.as_ref().map(PathBuf::from),
+            SearchMode::Fuzzy => None,
+        },
+    };
+
+    let mut count = 0;
+    for entry in walk::entries(Path::new(&request.directory), &options) {
+        if std::time::Instant::now() >= deadline {
+            return true;
+        }
+
+        let path = entry.path.as_path();
+        let file_name = path.file_name()
+            .map(|n| n.to_string_lossy())
+            .unwrap_or_default();
+
+        let score = if request.mode == SearchMode::Fuzzy {
+            match fuzzy::score(&pattern, &file_name) {
+                Some(score) => Some(score),
+                None => continue,
+            }
+        } else if file_name.to_lowercase().contains(&pattern) {
+            None
+        } else {
+            continue;
+        };
+
+        if limit.map(|limit| count >= limit).unwrap_or(false) {
+            return true;
+        }
+        count += 1;
+        if !on_match(FileInfo::from_path(path, entry.metadata.as_ref()), score) {
+            break;
+        }
+    }
+    false
+}
+
+// リクエストの limit を設定の上限で制限し、タイムアウト時刻を求める
+fn search_bounds(request: &SearchRequest, config: &Config) -> (usize, std::time::Instant) {
+    let limit = request.limit.unwrap_or(config.search_max_results).min(config.search_max_results);
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(config.search_timeout_secs);
+    (limit, deadline)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/search",
+    request_body = SearchRequest,
+    responses((status = 200, description = "Matching entries", body = SearchResponse)),
+)]
+pub async fn search_files(request: SearchRequest, auth: ClientAuth, config: Arc<Config>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Search).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<Vec<FileInfo>> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    heavy(move || {
+        if let Err(e) = paths::validate(&request.directory) {
+            return Ok(invalid_path_reply(e));
+        }
+    
+        if let Err(e) = check_access(&config, Path::new(&request.directory), policy::Action::Search) {
+            return Ok(warp::reply::json(&SearchResponse {
+                success: false,
+                data: None,
+                error: Some(e),
+                truncated: false,
+                cursor: None,
+            }));
+        }
+
+        let (limit, deadline) = search_bounds(&request, &config);
+        let fuzzy = request.mode == SearchMode::Fuzzy;
+        let mut files = Vec::new();
+        let mut scored = Vec::new();
+        // あいまい検索はスコア順に並べてから件数を制限する
+        let mut truncated = walk_search(&request, &config, if fuzzy { None } else { Some(limit) }, deadline, |info, score| {
+            match score {
+                Some(score) => scored.push((score, info)),
+                None => files.push(info),
+            }
+            true
+        });
+
+        if fuzzy {
+            // スコアの高い順、同点なら短い名前を優先
+            scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.name.len().cmp(&b.1.name.len())));
+            if scored.len() > limit {
+                scored.truncate(limit);
+                truncated = true;
+            }
+            files = scored.into_iter().map(|(_, info)| info).collect();
+        }
+
+        // 打ち切った場合は最後に返したエントリ (なければ今回の開始位置) を続きの位置とする
+        let cursor = if truncated && !fuzzy {
+            files.last().map(|f| f.path.clone()).or(request.cursor.clone())
+        } else {
+            None
+        };
+
+        Ok(warp::reply::json(&SearchResponse {
+            success: true,
+            data: Some(files),
+            error: None,
+            truncated,
+            cursor,
+        }))
+    })
+    .await?
+}
+
+// 一致したエントリを 1 行 1 JSON (NDJSON) で逐次返す。最終行は {"done":true,"count":N,"truncated":bool,"cursor":...}
+#[utoipa::path(
+    post,
+    path = "/api/search/stream",
+    request_body = SearchRequest,
+    responses((status = 200, description = "Matching entries as NDJSON, one FileInfo per line; the last line is {\"done\":true,\"count\":N,\"truncated\":bool,\"cursor\":...}", content_type = "application/x-ndjson")),
+)]
+pub async fn search_files_stream(request: SearchRequest, auth: ClientAuth, config: Arc<Config>) -> Result<warp::reply::Response, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Search).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<Vec<FileInfo>> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }).into_response()),
+    };
+    let config = scoped_config(config, &grant);
+
+    if let Err(e) = paths::validate(&request.directory) {
+        return Ok(invalid_path_reply(e).into_response());
+    }
+
+    let access = {
+        let config = config.clone();
+        let directory = PathBuf::from(&request.directory);
+        blocking(move || check_access(&config, &directory, policy::Action::Search)).await?
+    };
+    if let Err(e) = access {
+        return Ok(warp::reply::json(&ApiResponse::<Vec<FileInfo>> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }).into_response());
+    }
+
+    // 走査は重い処理として数え、走査が終わるまで枠を使う
+    let permit = concurrency::heavy().acquire().await.map_err(warp::reject::custom)?;
+    let (limit, deadline) = search_bounds(&request, &config);
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(256);
+    tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        let mut count = 0u64;
+        let mut last_path = None;
+        let truncated = walk_search(&request, &config, Some(limit), deadline, |info, _| {
+            count += 1;
+            last_path = Some(info.path.clone());
+            match serde_json::to_string(&info) {
+                // 送信できない場合はクライアントが切断しているので走査を中断する
+                Ok(line) => tx.blocking_send(line + "\n").is_ok(),
+                Err(_) => true,
+            }
+        });
+        let cursor = if truncated && request.mode == SearchMode::Substring {
+            last_path.or(request.cursor.clone())
+        } else {
+            None
+        };
+        let done = serde_json::json!({ "done": true, "count": count, "truncated": truncated, "cursor": cursor });
+        let _ = tx.blocking_send(format!("{}\n", done));
+    });
+
+    let (mut sender, body) = warp::hyper::Body::channel();
+    tokio::spawn(async move {
+        while let Some(line) = rx.recv().await {
+            if sender.send_data(line.into()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut response = warp::reply::Response::new(body);
+    response.headers_mut().insert(
+        warp::http::header::CONTENT_TYPE,
+        warp::http::HeaderValue::from_static("application/x-ndjson"),
+    );
+    Ok(response)
+}
+
+// ディレクトリ配下のファイルの内容を 1 行ずつ検索する (search の部分一致と同じ走査順)
+fn grep_walk(request: &GrepRequest, config: &Config, limit: usize, deadline: std::time::Instant, max_file_size: u64) -> GrepResult {
+    let directory = Path::new(&request.directory);
+    // 内容を返すため、検索と読み込みの両方を許可しないルートは走査しない
+    let mut excluded = policy::denied_under(&config.policies, directory, policy::Action::Search);
+    excluded.extend(policy::denied_under(&config.policies, directory, policy::Action::Read));
+    let options = walk::WalkOptions {
+        show_hidden: request.show_hidden,
+        respect_gitignore: request.respect_gitignore,
+        follow_symlinks: request.follow_symlinks,
+        allowed_roots: config.allowed_roots.clone(),
+        excluded,
+        exclude_names: config.walk_excludes.clone(),
+        resume_after: None,
+    };
+
+    let mut result = GrepResult {
+        matches: Vec::new(),
+        skipped: Vec::new(),
+        files_searched: 0,
+        truncated: false,
+    };
+    for entry in walk::entries(directory, &options) {
+        if std::time::Instant::now() >= deadline {
+            result.truncated = true;
+            break;
+        }
+
+        // リンクを辿らない場合、ファイルへのシンボリックリンクは読まない (許可ルート外を指す可能性がある)
+        let is_file = if request.follow_symlinks {
+            entry.path.is_file()
+        } else {
+            entry.metadata.as_ref().map(|m| m.is_file()).unwrap_or(false)
+        };
+        if !is_file {
+            continue;
+        }
+
+        let path = entry.path.to_string_lossy().to_string();
+        let matches = &mut result.matches;
+        let outcome = grep::grep_file(&entry.path, &request.query, request.case_sensitive, max_file_size, |line_number, line| {
+            if matches.len() >= limit {
+                return false;
+            }
+            matches.push(grep::GrepMatch {
+                path: path.clone(),
+                line_number,
+                line,
+            });
+            true
+        });
+
+        match outcome {
+            grep::Outcome::Searched => result.files_searched += 1,
+            grep::Outcome::Stopped => {
+                result.files_searched += 1;
+                result.truncated = true;
+                break;
+            }
+            grep::Outcome::Partial(reason) => {
+                result.files_searched += 1;
+                result.skipped.push(grep::SkippedFile { path, reason });
+            }
+            grep::Outcome::Skipped(reason) => result.skipped.push(grep::SkippedFile { path, reason }),
+        }
+    }
+    result
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/grep",
+    request_body = GrepRequest,
+    responses((status = 200, description = "Matching lines", body = ApiResponse<GrepResult>)),
+)]
+pub async fn grep_files(request: GrepRequest, auth: ClientAuth, config: Arc<Config>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Search).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<GrepResult> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    if let Err(e) = paths::validate(&request.directory) {
+        return Ok(invalid_path_reply(e));
+    }
+
+    if request.query.is_empty() {
+        return Ok(warp::reply::json(&ApiResponse::<GrepResult> {
+            success: false,
+            data: None,
+            error: Some("Query is empty".to_string()),
+        }));
+    }
+
+    let access = {
+        let config = config.clone();
+        let directory = PathBuf::from(&request.directory);
+        blocking(move || {
+            check_access(&config, &directory, policy::Action::Search)
+                .and_then(|_| check_access(&config, &directory, policy::Action::Read))
+        })
+        .await?
+    };
+    if let Err(e) = access {
+        return Ok(warp::reply::json(&ApiResponse::<GrepResult> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    let limit = request.limit.unwrap_or(config.search_max_results).min(config.search_max_results);
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(config.search_timeout_secs);
+    let max_file_size = request.max_file_size.unwrap_or(config.grep_max_file_size).min(config.grep_max_file_size);
+
+    // ファイルの読み込みはブロッキング I/O のため専用スレッドで行う (重い処理として数える)
+    let _permit = concurrency::heavy().acquire().await.map_err(warp::reject::custom)?;
+    let result = tokio::task::spawn_blocking(move || grep_walk(&request, &config, limit, deadline, max_file_size)).await;
+    match result {
+        Ok(result) => Ok(warp::reply::json(&ApiResponse {
+            success: true,
+            data: Some(result),
+            error: None,
+        })),
+        Err(e) => Ok(warp::reply::json(&ApiResponse::<GrepResult> {
+            success: false,
+            data: None,
+            error: Some(format!("Grep failed: {}", e)),
+        })),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/index/search",
+    request_body = IndexSearchRequest,
+    responses((status = 200, description = "Hits from the search index", body = ApiResponse<index::IndexSearchResult>)),
+)]
+pub async fn index_search(request: IndexSearchRequest, auth: ClientAuth, config: Arc<Config>, index: Arc<RwLock<Option<SearchIndex>>>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Search).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<index::IndexSearchResult> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    blocking(move || {
+        if config.index_dirs.is_empty() {
+            return Ok(warp::reply::json(&ApiResponse::<index::IndexSearchResult> {
+                success: false,
+                data: None,
+                error: Some("Search index is not enabled (configure index_dir)".to_string()),
+            }));
+        }
+
+        let limit = request.limit.unwrap_or(100).min(1000);
+        let result = index.read().unwrap().as_ref().map(|index| index.search(&request.query, limit));
+        match result {
+            Some(mut result) => {
+                // 検索を許可しないルート内のファイルは除く
+                result.results.retain(|hit| check_access(&config, Path::new(&hit.path), policy::Action::Search).is_ok());
+                Ok(warp::reply::json(&ApiResponse {
+                    success: true,
+                    data: Some(result),
+                    error: None,
+                }))
+            },
+            None => Ok(warp::reply::json(&ApiResponse::<index::IndexSearchResult> {
+                success: false,
+                data: None,
+                error: Some("Search index is still being built".to_string()),
+            })),
+        }
+    })
+    .await?
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/stale",
+    request_body = StaleRequest,
+    responses((status = 200, description = "Files not modified for the given number of days, grouped by folder", body = ApiResponse<StaleReport>)),
+)]
+pub async fn stale_report(request: StaleRequest, auth: ClientAuth, config: Arc<Config>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Search).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<StaleReport> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    heavy(move || {
+        if let Err(e) = paths::validate(&request.directory) {
+            return Ok(invalid_path_reply(e));
+        }
+
+        if let Err(e) = check_access(&config, Path::new(&request.directory), policy::Action::Search) {
+            return Ok(warp::reply::json(&ApiResponse::<StaleReport> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+
+        if !Path::new(&request.directory).is_dir() {
+            return Ok(warp::reply::json(&ApiResponse::<StaleReport> {
+                success: false,
+                data: None,
+                error: Some("Directory does not exist".to_string()),
+            }));
+        }
+
+        let cutoff = std::time::SystemTime::now()
+            .checked_sub(std::time::Duration::from_secs(request.days * 24 * 60 * 60))
+            .unwrap_or(std::time::UNIX_EPOCH);
+        let mut groups: std::collections::BTreeMap<String, StaleGroup> = std::collections::BTreeMap::new();
+        let options = walk::WalkOptions {
+            show_hidden: true,
+            respect_gitignore: false,
+            follow_symlinks: request.follow_symlinks,
+            allowed_roots: config.allowed_roots.clone(),
+            excluded: policy::denied_under(&config.policies, Path::new(&request.directory), policy::Action::Search),
+            exclude_names: config.walk_excludes.clone(),
+            resume_after: None,
+        };
+
+        for entry in walk::entries(Path::new(&request.directory), &options) {
+            // リンクを辿る場合はリンク先のファイルとして数える (WalkEntry のメタデータはリンク自体のもの)
+            let metadata = if request.follow_symlinks { fs::metadata(&entry.path).ok() } else { entry.metadata };
+            let Some(metadata) = metadata.filter(|metadata| metadata.is_file()) else {
+                continue;
+            };
+            let modified = metadata.modified().unwrap_or(std::time::UNIX_EPOCH);
+            if modified >= cutoff {
+                continue;
+            }
+
+            let directory = entry.path.parent()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let group = groups.entry(directory.clone()).or_insert_with(|| StaleGroup {
+                directory,
+                file_count: 0,
+                bytes: 0,
+                oldest_modified: None,
+                files: if request.include_files { Some(Vec::new()) } else { None },
+            });
+            group.file_count += 1;
+            group.bytes += metadata.len();
+            let modified = modified_secs(&metadata);
+            if group.oldest_modified.is_none() || modified < group.oldest_modified {
+                group.oldest_modified = modified;
+            }
+            if let Some(files) = group.files.as_mut() {
+                files.push(entry.path.to_string_lossy().to_string());
+            }
+        }
+
+        let mut groups: Vec<StaleGroup> = groups.into_values().collect();
+        groups.sort_by_key(|g| std::cmp::Reverse(g.bytes));
+
+        Ok(warp::reply::json(&ApiResponse {
+            success: true,
+            data: Some(StaleReport {
+                total_files: groups.iter().map(|g| g.file_count).sum(),
+                total_bytes: groups.iter().map(|g| g.bytes).sum(),
+                groups,
+            }),
+            error: None,
+        }))
+    })
+    .await?
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/du",
+    request_body = DirSizeRequest,
+    responses((status = 200, description = "Total size, file count and folder count under the directory, from the cache when nothing changed", body = ApiResponse<dirsize::DirSize>)),
+)]
+pub async fn dir_size(request: DirSizeRequest, auth: ClientAuth, config: Arc<Config>, changes: Arc<ChangeLog>, cache: Arc<DirSizeCache>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Search).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<dirsize::DirSize> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    let checked = {
+        let (config, directory) = (config.clone(), request.directory.clone());
+        blocking(move || -> Result<(), warp::reply::Json> {
+            paths::validate(&directory).map_err(invalid_path_reply)?;
+            let directory = Path::new(&directory);
+            let error = |e: String| warp::reply::json(&ApiResponse::<dirsize::DirSize> {
+                success: false,
+                data: None,
+                error: Some(e),
+            });
+            check_access(&config, directory, policy::Action::Search).map_err(error)?;
+            if !directory.is_dir() {
+                return Err(error(format!("Not a directory: {}", directory.display())));
+            }
+            Ok(())
+        })
+        .await?
+    };
+    if let Err(reply) = checked {
+        return Ok(reply);
+    }
+
+    let directory = PathBuf::from(&request.directory);
+    if !request.refresh {
+        if let Some(size) = cache.get(&directory, &changes) {
+            return Ok(warp::reply::json(&ApiResponse {
+                success: true,
+                data: Some(size),
+                error: None,
+            }));
+        }
+    }
+
+    // 走査中の変更で無効になるよう、走査の前の位置を保存する
+    let cursor = changes.latest();
+    let size = {
+        let directory = directory.clone();
+        heavy(move || {
+            let options = walk::WalkOptions {
+                show_hidden: true,
+                respect_gitignore: false,
+                follow_symlinks: false,
+                allowed_roots: config.allowed_roots.clone(),
+                excluded: policy::denied_under(&config.policies, &directory, policy::Action::Search),
+                exclude_names: config.walk_excludes.clone(),
+                resume_after: None,
+            };
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(config.search_timeout_secs);
+            dirsize::compute(&directory, &options, deadline)
+        })
+        .await?
+    };
+    cache.put(&directory, &size, cursor);
+
+    Ok(warp::reply::json(&ApiResponse {
+        success: true,
+        data: Some(size),
+        error: None,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/mime",
+    request_body = MimeRequest,
+    responses((status = 200, description = "Detected MIME type", body = ApiResponse<mime::Detection>)),
+)]
+pub async fn detect_mime(request: MimeRequest, auth: ClientAuth, config: Arc<Config>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Read).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<mime::Detection> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    blocking(move || {
+        if let Err(e) = paths::validate(&request.path) {
+            return Ok(invalid_path_reply(e));
+        }
+
+        let path = Path::new(&request.path);
+        if let Err(e) = check_access(&config, path, policy::Action::Read) {
+            return Ok(warp::reply::json(&ApiResponse::<mime::Detection> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+
+        let head = match read_head(path, mime::SNIFF_LEN) {
+            Ok(head) => head,
+            Err(e) => return Ok(warp::reply::json(&ApiResponse::<mime::Detection> {
+                success: false,
+                data: None,
+                error: Some(e.to_string()),
+            })),
+        };
+
+        Ok(warp::reply::json(&ApiResponse {
+            success: true,
+            data: Some(mime::detect(path, &head)),
+            error: None,
+        }))
+    })
+    .await?
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/hash",
+    request_body = HashRequest,
+    responses((status = 200, description = "SHA256 of the file, from the hash cache when the file is unchanged", body = ApiResponse<FileHash>)),
+)]
+pub async fn hash_file(request: HashRequest, auth: ClientAuth, config: Arc<Config>, vault: Arc<Vault>, changes: Arc<ChangeLog>, hashes: Arc<HashCache>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Read).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<FileHash> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    // 確認とキャッシュの参照は軽いので、ハッシュを求める場合だけ重い処理として数える
+    let checked = {
+        let (path, vault, hashes, changes) = (request.path.clone(), vault.clone(), hashes.clone(), changes.clone());
+        blocking(move || -> Result<(u64, Option<String>), warp::reply::Json> {
+            paths::validate(&path).map_err(invalid_path_reply)?;
+            let path = Path::new(&path);
+            let error = |e: String| warp::reply::json(&ApiResponse::<FileHash> {
+                success: false,
+                data: None,
+                error: Some(e),
+            });
+            check_access(&config, path, policy::Action::Read).map_err(error)?;
+            let metadata = fs::metadata(path).map_err(|e| error(e.to_string()))?;
+            if !metadata.is_file() {
+                return Err(error(format!("Not a file: {}", path.display())));
+            }
+            let size = vault.content_len(path, metadata.len());
+            Ok((size, hashes.get(path, &changes)))
+        })
+        .await?
+    };
+    let (size, cached) = match checked {
+        Ok(checked) => checked,
+        Err(reply) => return Ok(reply),
+    };
+
+    let (sha256, cached) = match cached {
+        Some(sha256) => (sha256, true),
+        None => {
+            let path = PathBuf::from(&request.path);
+            let computed = heavy(move || {
+                hashes.compute(&path, || {
+                    if vault.is_encrypted_file(&path) {
+                        vault.read(&path).map(|content| sha256_hex(&content))
+                    } else {
+                        signing::file_sha256_hex(&path)
+                    }
+                })
+            })
+            .await?;
+            match computed {
+                Ok(sha256) => (sha256, false),
+                Err(e) => return Ok(warp::reply::json(&ApiResponse::<FileHash> {
+                    success: false,
+                    data: None,
+                    error: Some(e.to_string()),
+                })),
+            }
+        }
+    };
+
+    Ok(warp::reply::json(&ApiResponse {
+        success: true,
+        data: Some(FileHash {
+            path: request.path,
+            sha256,
+            size,
+            cached,
+        }),
+        error: None,
+    }))
+}
+
+// ファイルの先頭 max バイトを読み込む
+fn read_head(path: &Path, max: usize) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let file = fs::File::open(path)?;
+    let mut head = Vec::with_capacity(max);
+    file.take(max as u64).read_to_end(&mut head)?;
+    Ok(head)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/list",
+    params(
+        ("path" = String, Query, description = "Directory to list"),
+        ("token" = String, Query, description = "API token"),
+        ("show_hidden" = Option<bool>, Query, description = "Include hidden entries (default true)"),
+    ),
+    responses((status = 200, description = "Entries of the directory", body = ApiResponse<Vec<FileInfo>>)),
+)]
+pub async fn list_directory(path: String, token: String, show_hidden: bool, auth: ClientAuth, config: Arc<Config>, changes: Arc<ChangeLog>, cache: Arc<ListCache>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&token, &auth, Operation::List).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<Vec<FileInfo>> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    blocking(move || {
+        if let Err(e) = paths::validate(&path) {
+            return Ok(invalid_path_reply(e));
+        }
+
+        if let Err(e) = check_access(&config, Path::new(&path), policy::Action::Read) {
+            return Ok(warp::reply::json(&ApiResponse::<Vec<FileInfo>> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+
+        let dir = Path::new(&path);
+        let files = match cache.get(dir, &changes) {
+            Some(files) => files,
+            None => {
+                // 読み込み中の変更を見逃さないよう、検証用の値は先に取得する
+                let modified = listcache::dir_modified(dir);
+                let cursor = changes.latest();
+                let mut files = Vec::new();
+                match fs::read_dir(dir) {
+                    Ok(entries) => {
+                        for entry in entries.flatten() {
+                            let metadata = entry.metadata().ok();
+                            files.push(FileInfo::from_path(&entry.path(), metadata.as_ref()));
+                        }
+                    }
+                    Err(e) => return Ok(warp::reply::json(&ApiResponse::<Vec<FileInfo>> {
+                        success: false,
+                        data: None,
+                        error: Some(e.to_string()),
+                    })),
+                }
+                cache.put(dir, files.clone(), modified, cursor);
+                files
+            }
+        };
+
+        let files: Vec<FileInfo> = files.into_iter().filter(|info| show_hidden || !info.hidden).collect();
+        Ok(warp::reply::json(&ApiResponse {
+            success: true,
+            data: Some(files),
+            error: None,
+        }))
+    })
+    .await?
+}
+
+// エントリを読んだ順に 1 行 1 JSON (NDJSON) で逐次返す。最終行は {"done":true,"count":N,"error":null}
+#[utoipa::path(
+    get,
+    path = "/api/list/stream",
+    params(
+        ("path" = String, Query, description = "Directory to list"),
+        ("token" = String, Query, description = "API token"),
+        ("show_hidden" = Option<bool>, Query, description = "Include hidden entries (default true)"),
+    ),
+    responses((status = 200, description = "Entries as NDJSON, one FileInfo per line; the last line is {\"done\":true,\"count\":N,\"error\":null}", content_type = "application/x-ndjson")),
+)]
+pub async fn list_directory_stream(path: String, token: String, show_hidden: bool, auth: ClientAuth, config: Arc<Config>) -> Result<warp::reply::Response, Rejection> {
+    let grant = match check_auth(&token, &auth, Operation::List).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<Vec<FileInfo>> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }).into_response()),
+    };
+    let config = scoped_config(config, &grant);
+
+    if let Err(e) = paths::validate(&path) {
+        return Ok(invalid_path_reply(e).into_response());
+    }
+
+    // 開けないディレクトリは NDJSON を始める前に通常のエラーで返す
+    let opened = blocking(move || {
+        check_access(&config, Path::new(&path), policy::Action::Read)?;
+        fs::read_dir(&path).map_err(|e| e.to_string())
+    })
+    .await?;
+    let entries = match opened {
+        Ok(entries) => entries,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<Vec<FileInfo>> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }).into_response()),
+    };
+
+    // 一覧をメモリに溜めないよう、キャッシュは使わず読んだエントリから送る
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(256);
+    tokio::task::spawn_blocking(move || {
+        let mut count = 0u64;
+        let mut error = None;
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    error = Some(e.to_string());
+                    break;
+                }
+            };
+            let metadata = entry.metadata().ok();
+            let info = FileInfo::from_path(&entry.path(), metadata.as_ref());
+            if !show_hidden && info.hidden {
+                continue;
+            }
+            let Ok(line) = serde_json::to_string(&info) else {
+                continue;
+            };
+            // 送信できない場合はクライアントが切断しているので読み込みを中断する
+            if tx.blocking_send(line + "\n").is_err() {
+                return;
+            }
+            count += 1;
+        }
+        let done = serde_json::json!({ "done": true, "count": count, "error": error });
+        let _ = tx.blocking_send(format!("{}\n", done));
+    });
+
+    let (mut sender, body) = warp::hyper::Body::channel();
+    tokio::spawn(async move {
+        while let Some(line) = rx.recv().await {
+            if sender.send_data(line.into()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut response = warp::reply::Response::new(body);
+    response.headers_mut().insert(
+        warp::http::header::CONTENT_TYPE,
+        warp::http::HeaderValue::from_static("application/x-ndjson"),
+    );
+    Ok(response)
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Metrics {
+    pub list_cache: listcache::CacheStats,
+    pub heavy_operations: usize, // 実行中の重い処理 (走査・ハッシュ・コピージョブ) の数
+    pub hash_cache: hashcache::HashCacheStats,
+    pub dir_size_cache: listcache::CacheStats,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/metrics",
+    params(
+        ("token" = String, Query, description = "API token"),
+    ),
+    responses((status = 200, description = "Agent metrics", body = ApiResponse<Metrics>)),
+)]
+pub async fn get_metrics(token: String, auth: ClientAuth, cache: Arc<ListCache>, hashes: Arc<HashCache>, dir_sizes: Arc<DirSizeCache>) -> Result<impl Reply, Rejection> {
+    if let Err(e) = check_auth(&token, &auth, Operation::Metrics).await {
+        return Ok(warp::reply::json(&ApiResponse::<Metrics> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    Ok(warp::reply::json(&ApiResponse {
+        success: true,
+        data: Some(Metrics {
+            list_cache: cache.stats(),
+            heavy_operations: concurrency::heavy().in_use(),
+            hash_cache: hashes.stats(),
+            dir_size_cache: dir_sizes.stats(),
+        }),
+        error: None,
+    }))
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LogTail {
+    pub path: String,
+    pub lines: Vec<String>, // 古い順。各行の先頭は [UNIX 時刻 (秒)]
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/logs/tail",
+    params(
+        ("token" = String, Query, description = "API token"),
+        ("lines" = Option<usize>, Query, description = "Number of lines from the end (default 200, max 2000)"),
+    ),
+    responses((status = 200, description = "The most recent lines of the agent log", body = ApiResponse<LogTail>)),
+)]
+pub async fn tail_logs(token: String, lines: usize, auth: ClientAuth) -> Result<impl Reply, Rejection> {
+    if let Err(e) = check_auth(&token, &auth, Operation::Metrics).await {
+        return Ok(warp::reply::json(&ApiResponse::<LogTail> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    blocking(move || {
+        let path = logs::path().map(|path| path.to_string_lossy().to_string()).unwrap_or_default();
+        match logs::tail(lines.min(logs::MAX_TAIL_LINES)) {
+            Ok(lines) => Ok(warp::reply::json(&ApiResponse {
+                success: true,
+                data: Some(LogTail { path, lines }),
+                error: None,
+            })),
+            Err(e) => Ok(warp::reply::json(&ApiResponse::<LogTail> {
+                success: false,
+                data: None,
+                error: Some(format!("Failed to read the log: {}", e)),
+            })),
+        }
+    })
+    .await?
+}
+
+// API のエンドポイント (メソッド, パス, 必要な操作)。ルートを追加したらここにも追加する
+pub(crate) const ENDPOINTS: &[(&str, &str, Option<Operation>)] = &[
+    ("GET", "/api/health", None),
+    ("GET", "/api/version", None),
+    ("GET", "/api/openapi.json", None),
+    ("GET", "/api/capabilities", None),
+    ("POST", "/api/read", Some(Operation::Read)),
+    ("POST", "/api/read_binary", Some(Operation::Read)),
+    ("POST", "/api/read_chunk", Some(Operation::Read)),
+    ("POST", "/api/mime", Some(Operation::Read)),
+    ("POST", "/api/hash", Some(Operation::Read)),
+    ("POST", "/api/write", Some(Operation::Write)),
+    ("POST", "/api/write_binary", Some(Operation::Write)),
+    ("GET", "/api/file", Some(Operation::Read)),
+    ("PUT", "/api/file", Some(Operation::Write)),
+    ("POST", "/api/delete", Some(Operation::Delete)),
+    ("GET", "/api/list", Some(Operation::List)),
+    ("GET", "/api/list/stream", Some(Operation::List)),
+    ("POST", "/api/search", Some(Operation::Search)),
+    ("POST", "/api/search/stream", Some(Operation::Search)),
+    ("POST", "/api/grep", Some(Operation::Search)),
+    ("POST", "/api/index/search", Some(Operation::Search)),
+    ("POST", "/api/stale", Some(Operation::Search)),
+    ("POST", "/api/du", Some(Operation::Search)),
+    ("POST", "/api/create", Some(Operation::Create)),
+    ("POST", "/api/move", Some(Operation::Move)),
+    ("POST", "/api/copy", Some(Operation::Copy)),
+    ("GET", "/api/jobs", Some(Operation::Copy)),
+    ("POST", "/api/paste_from_clipboard", Some(Operation::Paste)),
+    ("GET", "/api/clipboard/get", Some(Operation::Clipboard)),
+    ("POST", "/api/clipboard/set", Some(Operation::Clipboard)),
+    ("POST", "/api/print", Some(Operation::Print)),
+    ("POST", "/api/cleanup", Some(Operation::Cleanup)),
+    ("GET", "/api/changes/poll", Some(Operation::Changes)),
+    ("GET", "/api/clients", Some(Operation::Clients)),
+    ("POST", "/api/clients/pair", Some(Operation::Clients)),
+    ("POST", "/api/clients/remove", Some(Operation::Clients)),
+    ("GET", "/api/metrics", Some(Operation::Metrics)),
+    ("GET", "/api/logs/tail", Some(Operation::Metrics)),
+    ("GET", "/api/ws", None),
+    ("POST", "/api/tokens/rotate", Some(Operation::Tokens)),
+    ("POST", "/api/shutdown", Some(Operation::Shutdown)),
+    ("GET", "/api/vault/status", Some(Operation::Vault)),
+    ("POST", "/api/vault/unlock", Some(Operation::Vault)),
+    ("POST", "/api/vault/lock", Some(Operation::Vault)),
+    ("POST", "/api/vault/rotate", Some(Operation::Vault)),
+    ("GET", "/api/trash", Some(Operation::List)),
+    ("POST", "/api/trash/purge", Some(Operation::Delete)),
+    ("GET", "/api/plugins", Some(Operation::Plugin)),
+    ("POST", "/api/plugins/transform", Some(Operation::Plugin)),
+    ("POST", "/api/plugins/{plugin}/{endpoint}", Some(Operation::Plugin)),
+    ("POST", "/api/script", Some(Operation::Script)),
+    ("POST", "/api/exec", Some(Operation::Exec)),
+    ("POST", "/api/git/status", Some(Operation::Read)),
+    ("POST", "/api/git/diff", Some(Operation::Read)),
+    ("POST", "/api/git/log", Some(Operation::Read)),
+    ("POST", "/api/s3/upload", Some(Operation::S3)),
+    ("POST", "/api/s3/download", Some(Operation::S3)),
+    ("POST", "/api/s3/list", Some(Operation::S3)),
+    ("GET", "/api/sync/status", Some(Operation::Sync)),
+    ("GET", "/api/shares", Some(Operation::Shares)),
+];
+
+// ENDPOINTS のパスと一致するか ({plugin} のような部分は任意の 1 段と一致する)
+pub(crate) fn endpoint_matches(pattern: &str, path: &str) -> bool {
+    let mut path_segments = path.split('/');
+    pattern.split('/').all(|segment| match path_segments.next() {
+        Some(part) if segment.starts_with('{') && segment.ends_with('}') => !part.is_empty(),
+        Some(part) => part == segment,
+        None => false,
+    }) && path_segments.next().is_none()
+}
+
+// このバージョンが対応している機能 (このエージェントで有効かは /api/capabilities で確認する)
+pub(crate) const FEATURES: &[&str] = &[
+    "trash",
+    "vault",
+    "jobs",
+    "index",
+    "watch",
+    "print",
+    "virus_scan",
+    "chunked_read",
+    "search_stream",
+    "list_stream",
+    "receipts",
+    "token_tiers",
+    "tls",
+    "client_certificates",
+    "socket",
+    "openapi",
+    "web_ui",
+    "websocket_rpc",
+    "stdio",
+    "mdns",
+    "raw_upload",
+    "download",
+    "compression",
+    "hash_cache",
+    "dir_size",
+    "plugins",
+    "script",
+    "clipboard",
+    "exec",
+    "git",
+    "s3",
+    "sync",
+    "shares",
+];
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct VersionInfo {
+    pub version: String,
+    pub api_versions: Vec<String>, // /api/v1/... のように指定できるバージョン (バージョンなしは v1)
+    pub endpoints: Vec<EndpointInfo>,
+    pub features: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EndpointInfo {
+    pub method: String,
+    pub path: String,
+    pub operation: Option<String>, // None ならトークンのみ、またはトークン不要
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/version",
+    responses((status = 200, description = "Agent version, endpoints and features", body = ApiResponse<VersionInfo>)),
+)]
+pub fn version_info() -> VersionInfo {
+    VersionInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        api_versions: apiversion::supported_names().into_iter().map(String::from).collect(),
+        endpoints: ENDPOINTS
+            .iter()
+            .map(|&(method, path, operation)| EndpointInfo {
+                method: method.to_string(),
+                path: path.to_string(),
+                operation: operation.map(|operation| operation.name().to_string()),
+            })
+            .collect(),
+        features: FEATURES.iter().map(|feature| feature.to_string()).collect(),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Capabilities {
+    pub agent_id: String,
+    pub version: String,
+    pub token: auth::TokenCapabilities,
+    pub features: CapabilityFeatures,
+    pub limits: CapabilityLimits,
+}
+
+// このエージェントで有効な機能 (トークンで使えるかは token.operations で確認する)
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CapabilityFeatures {
+    pub trash: TrashCapability,
+    pub vault: VaultCapability,
+    pub receipts: ReceiptsCapability,
+    pub index: IndexCapability,
+    pub watch: WatchCapability,
+    pub jobs: Feature,
+    pub print: Feature,
+    pub virus_scan: Feature,
+    pub plugins: Feature,
+    pub script: Feature,
+    pub exec: Feature,
+    pub s3: Feature,
+    pub sync: Feature,
+    pub shares: Feature,
+    pub thumbnails: Feature, // このバージョンにはない機能
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Feature {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TrashCapability {
+    pub enabled: bool,
+    pub retention_hours: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct VaultCapability {
+    pub enabled: bool,
+    pub locked: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ReceiptsCapability {
+    pub enabled: bool,
+    pub public_key: Option<String>, // レシートの署名を検証する Ed25519 の公開鍵 (16 進)
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IndexCapability {
+    pub enabled: bool,
+    pub max_file_size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct WatchCapability {
+    pub enabled: bool,
+    pub max_wait_secs: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CapabilityLimits {
+    pub search_max_results: usize,
+    pub search_timeout_secs: u64,
+    pub grep_max_file_size: u64,
+    pub max_chunk_size: u64,
+    pub rate_limit_per_second: u32,
+    pub rate_limit_burst: u32,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/capabilities",
+    params(
+        ("token" = String, Query, description = "API token"),
+    ),
+    responses((status = 200, description = "Operations and limits of the token and the features enabled on this agent", body = ApiResponse<Capabilities>)),
+)]
+pub async fn get_capabilities(token: String, auth: ClientAuth, config: Arc<Config>, audit: Arc<AuditLog>, trash: Arc<Trash>, vault: Arc<Vault>, plugins: Arc<PluginHost>) -> Result<impl Reply, Rejection> {
+    let token = match auth.capabilities(&token) {
+        Ok(token) => token,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<Capabilities> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let vault_status = vault.status();
+    let agent_allows = |op: Operation| config.allowed_operations.is_empty() || config.allowed_operations.contains(&op);
+
+    Ok(warp::reply::json(&ApiResponse {
+        success: true,
+        data: Some(Capabilities {
+            agent_id: config.agent_id.clone(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            token,
+            features: CapabilityFeatures {
+                trash: TrashCapability {
+                    enabled: trash.enabled(),
+                    retention_hours: trash.retention_hours(),
+                },
+                vault: VaultCapability {
+                    enabled: !vault_status.roots.is_empty(),
+                    locked: !vault_status.roots.is_empty() && vault_status.locked,
+                },
+                receipts: ReceiptsCapability {
+                    enabled: audit.signing_enabled(),
+                    public_key: audit.public_key(),
+                },
+                index: IndexCapability {
+                    enabled: !config.index_dirs.is_empty(),
+                    max_file_size: config.index_max_file_size,
+                },
+                watch: WatchCapability {
+                    enabled: agent_allows(Operation::Changes),
+                    max_wait_secs: MAX_POLL_WAIT_SECS,
+                },
+                jobs: Feature { enabled: agent_allows(Operation::Copy) },
+                print: Feature { enabled: config.allow_print && agent_allows(Operation::Print) },
+                virus_scan: Feature { enabled: config.scanner.enabled() },
+                plugins: Feature { enabled: plugins.enabled() && agent_allows(Operation::Plugin) },
+                script: Feature { enabled: config.allow_script && agent_allows(Operation::Script) },
+                exec: Feature { enabled: !config.exec_commands.is_empty() && agent_allows(Operation::Exec) },
+                s3: Feature { enabled: matches!(s3::Bucket::from_config(&config), Some(Ok(_))) && agent_allows(Operation::S3) },
+                sync: Feature { enabled: !config.sync_tasks.is_empty() && agent_allows(Operation::Sync) },
+                shares: Feature { enabled: agent_allows(Operation::Shares) },
+                thumbnails: Feature { enabled: false },
+            },
+            limits: CapabilityLimits {
+                search_max_results: config.search_max_results,
+                search_timeout_secs: config.search_timeout_secs,
+                grep_max_file_size: config.grep_max_file_size,
+                max_chunk_size: MAX_CHUNK_SIZE,
+                rate_limit_per_second: config.rate_limit_per_second,
+                rate_limit_burst: config.rate_limit_burst,
+            },
+        }),
+        error: None,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/create",
+    request_body = CreateRequest,
+    responses((status = 200, description = "Created", body = ReceiptResponse)),
+)]
+pub async fn create_file_or_directory(request: CreateRequest, auth: ClientAuth, changes: Arc<ChangeLog>, config: Arc<Config>, audit: Arc<AuditLog>, vault: Arc<Vault>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Create).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    blocking(move || {
+        if let Err(e) = paths::validate(&request.path) {
+            return Ok(invalid_path_reply(e));
+        }
+    
+        let target = match write_target(&config, Path::new(&request.path)) {
+            Ok(target) => target,
+            Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            })),
+        };
+        let path = target.as_path();
+
+        let content = match (&request.template, config.template_path()) {
+            (None, _) => String::new(),
+            (Some(_), _) if request.is_directory => return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some("A template cannot be used when creating a directory".to_string()),
+            })),
+            (Some(_), None) => return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some("Templates are not configured (set template_dir=)".to_string()),
+            })),
+            (Some(template), Some(dir)) => match templates::render(&dir, template, path, &request.variables) {
+                Ok(content) => content,
+                Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+                    success: false,
+                    data: None,
+                    error: Some(e),
+                })),
+            },
+        };
+        let length = content.len() as u64;
+
+        if !request.is_directory {
+            let added = quota::Usage { bytes: length, files: 1 };
+            if let Err(e) = quota::check(&config.quotas, path, None, added).and_then(|_| quota::check_free_space(path, length)) {
+                return Ok(warp::reply::json(&ApiResponse::<String> {
+                    success: false,
+                    data: None,
+                    error: Some(e),
+                }));
+            }
+            // テンプレートから作る内容も書き込みと同じくスキャンする
+            if !content.is_empty() && config.scanner.enabled() {
+                if let Err(e) = scan::check(&config.scanner, &audit, path, content.as_bytes()) {
+                    return Ok(scan_rejected_reply(path, e));
+                }
+            }
+        }
+    
+        let result = if request.is_directory {
+            fs::create_dir_all(path)
+        } else {
+            if let Some(parent) = path.parent() {
+                if !parent.exists() {
+                    if let Err(e) = fs::create_dir_all(parent) {
+                        return Ok(warp::reply::json(&ApiResponse::<String> {
+                            success: false,
+                            data: None,
+                            error: Some(format!("Failed to create parent directory: {}", e)),
+                        }));
+                    }
+                }
+            }
+            if let Err(e) = policy::save_version(&config.policies, path) {
+                return Ok(warp::reply::json(&ApiResponse::<String> {
+                    success: false,
+                    data: None,
+                    error: Some(e),
+                }));
+            }
+            if let Err(e) = writequota::check_file(length).and_then(|_| writequota::reserve(&grant.tier, length)) {
+                return Ok(warp::reply::json(&ApiResponse::<String> {
+                    success: false,
+                    data: None,
+                    error: Some(e),
+                }));
+            }
+            // vault ルート配下ではテンプレートの内容も暗号化する
+            vault.write(path, content.as_bytes()).inspect_err(|_| writequota::refund(&grant.tier, length))
+        };
+
+        match result {
+            Ok(_) => {
+                changes.record("create", &path.to_string_lossy(), None);
+                let receipt = audit.receipt("create", &path.to_string_lossy(), "", audit.file_hash(path));
+                let message = format!("{} created successfully", if request.is_directory { "Directory" } else { "File" });
+                Ok(warp::reply::json(&ReceiptResponse {
+                    success: true,
+                    data: Some(written_message(&message, Path::new(&request.path), path)),
+                    error: None,
+                    receipt,
+                }))
+            },
+            Err(e) => Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e.to_string()),
+            })),
+        }
+    })
+    .await?
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/move",
+    request_body = MoveRequest,
+    responses((status = 200, description = "Moved", body = ReceiptResponse)),
+)]
+pub async fn move_file(request: MoveRequest, auth: ClientAuth, changes: Arc<ChangeLog>, config: Arc<Config>, audit: Arc<AuditLog>, vault: Arc<Vault>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Move).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    blocking(move || {
+        if let Err(e) = paths::validate(&request.source) {
+            return Ok(invalid_path_reply(e));
+        }
+        if let Err(e) = paths::validate(&request.destination) {
+            return Ok(invalid_path_reply(e));
+        }
+    
+        let source = Path::new(&request.source);
+        if let Err(e) = check_access(&config, source, policy::Action::Write) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+        let destination = match write_target(&config, Path::new(&request.destination)) {
+            Ok(destination) => destination,
+            Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            })),
+        };
+        let destination = destination.as_path();
+    
+        if !source.exists() {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some("Source file does not exist".to_string()),
+            }));
+        }
+    
+        if let Some(parent) = destination.parent() {
+            if !parent.exists() {
+                if let Err(e) = fs::create_dir_all(parent) {
+                    return Ok(warp::reply::json(&ApiResponse::<String> {
+                        success: false,
+                        data: None,
+                        error: Some(format!("Failed to create destination directory: {}", e)),
+                    }));
+                }
+            }
+        }
+
+        if let Err(e) = quota::check(&config.quotas, destination, Some(source), quota::usage_of(source)) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+
+        if let Err(e) = hooks::before(&config.hooks, "move", source, Some(destination)) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+
+        if let Err(e) = policy::save_version(&config.policies, destination) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+
+        // vault ルートの内外をまたぐ移動は、暗号化・復号してから元のファイルを消す
+        match vault.rename(source, destination) {
+            Ok(_) => {
+                changes.record("move", &request.source, Some(&destination.to_string_lossy()));
+                hooks::after(&config.hooks, "move", source, Some(destination));
+                let receipt = audit.receipt("move", &destination.to_string_lossy(), &format!("from {}", request.source), audit.file_hash(destination));
+                Ok(warp::reply::json(&ReceiptResponse {
+                    success: true,
+                    data: Some(written_message("File moved successfully", Path::new(&request.destination), destination)),
+                    error: None,
+                    receipt,
+                }))
+            },
+            Err(e) => Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e.to_string()),
+            })),
+        }
+    })
+    .await?
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/copy",
+    request_body = CopyRequest,
+    responses((status = 200, description = "Copied. With background the data is the started jobs::CopyJob", body = ReceiptResponse)),
+)]
+pub async fn copy_file(request: CopyRequest, auth: ClientAuth, changes: Arc<ChangeLog>, config: Arc<Config>, audit: Arc<AuditLog>, vault: Arc<Vault>, jobs: Arc<JobStore>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Copy).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    blocking(move || {
+        if let Err(e) = paths::validate(&request.source) {
+            return Ok(invalid_path_reply(e));
+        }
+        if let Err(e) = paths::validate(&request.destination) {
+            return Ok(invalid_path_reply(e));
+        }
+    
+        let source = Path::new(&request.source);
+        if let Err(e) = check_access(&config, source, policy::Action::Read) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+        let destination = match write_target(&config, Path::new(&request.destination)) {
+            Ok(destination) => destination,
+            Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            })),
+        };
+        let destination = destination.as_path();
+    
+        if !source.exists() {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some("Source file does not exist".to_string()),
+            }));
+        }
+    
+        if let Some(parent) = destination.parent() {
+            if !parent.exists() {
+                if let Err(e) = fs::create_dir_all(parent) {
+                    return Ok(warp::reply::json(&ApiResponse::<String> {
+                        success: false,
+                        data: None,
+                        error: Some(format!("Failed to create destination directory: {}", e)),
+                    }));
+                }
+            }
+        }
+
+        let added = quota::usage_of(source);
+        if let Err(e) = quota::check(&config.quotas, destination, None, added).and_then(|_| quota::check_free_space(destination, added.bytes)) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+
+        // コピーする内容も書き込みと同じくスキャンする (バックグラウンドのコピーもジョブを作る前に確認する)
+        if config.scanner.enabled() {
+            if let Err(e) = scan::check_tree(&config.scanner, &audit, source, destination) {
+                return Ok(scan_rejected_reply(destination, e));
+            }
+        }
+
+        // フォルダのコピーは 1 ファイルの上限を確認せず、合計だけを今日の書き込みに数える
+        let file_limit = if source.is_dir() { Ok(()) } else { writequota::check_file(added.bytes) };
+        if let Err(e) = file_limit.and_then(|_| writequota::reserve(&grant.tier, added.bytes)) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+
+        if let Err(e) = policy::save_version(&config.policies, destination) {
+            writequota::refund(&grant.tier, added.bytes);
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+
+        // バックグラウンドのジョブはそのままコピーするため、vault ルートが関わるコピーはその場で暗号化・復号する
+        if request.background && source.is_dir() && !vault.is_vault_path(source) && !vault.is_vault_path(destination) {
+            let job = match jobs.create_copy(os_random_hex(8), source, destination) {
+                Ok(job) => job,
+                Err(e) => {
+                    writequota::refund(&grant.tier, added.bytes);
+                    return Ok(warp::reply::json(&ApiResponse::<String> {
+                        success: false,
+                        data: None,
+                        error: Some(e),
+                    }));
+                },
+            };
+            let job_for_task = job.clone();
+            concurrency::spawn_heavy(move || jobs::run_copy(&jobs, job_for_task, &changes, &audit));
+            return Ok(warp::reply::json(&ApiResponse {
+                success: true,
+                data: Some(job),
+                error: None,
+            }));
+        }
+
+        let result = if source.is_dir() {
+            vault.copy_dir(source, destination, config.copy_parallelism)
+        } else {
+            vault.copy_file(source, destination).map(|_| ())
+        };
+
+        match result {
+            Ok(_) => {
+                changes.record("copy", &request.source, Some(&destination.to_string_lossy()));
+                let receipt = audit.receipt("copy", &destination.to_string_lossy(), &format!("from {}", request.source), audit.file_hash(destination));
+                Ok(warp::reply::json(&ReceiptResponse {
+                    success: true,
+                    data: Some(written_message("File copied successfully", Path::new(&request.destination), destination)),
+                    error: None,
+                    receipt,
+                }))
+            },
+            Err(e) => {
+                writequota::refund(&grant.tier, added.bytes);
+                Ok(warp::reply::json(&ApiResponse::<String> {
+                    success: false,
+                    data: None,
+                    error: Some(e.to_string()),
+                }))
+            },
+        }
+    })
+    .await?
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PasteRequest {
+    pub destination: String,
+    pub token: String,
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PasteResult {
+    pub copied: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/paste_from_clipboard",
+    request_body = PasteRequest,
+    responses((status = 200, description = "Files pasted from the clipboard", body = ApiResponse<PasteResult>)),
+)]
+pub async fn paste_from_clipboard(request: PasteRequest, auth: ClientAuth, changes: Arc<ChangeLog>, config: Arc<Config>, audit: Arc<AuditLog>, vault: Arc<Vault>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Paste).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<PasteResult> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    blocking(move || {
+        if let Err(e) = paths::validate(&request.destination) {
+            return Ok(invalid_path_reply(e));
+        }
+
+        let destination = Path::new(&request.destination);
+        if !destination.is_dir() {
+            return Ok(warp::reply::json(&ApiResponse::<PasteResult> {
+                success: false,
+                data: None,
+                error: Some("Destination directory does not exist".to_string()),
+            }));
+        }
+
+        let sources = match clipboard::file_list() {
+            Ok(sources) => sources,
+            Err(e) => return Ok(warp::reply::json(&ApiResponse::<PasteResult> {
+                success: false,
+                data: None,
+                error: Some(e),
+            })),
+        };
+
+        let mut result = PasteResult {
+            copied: Vec::new(),
+            errors: Vec::new(),
+        };
+        for source in sources {
+            let name = match source.file_name() {
+                Some(name) => name,
+                None => continue,
+            };
+            if let Err(e) = check_access(&config, &source, policy::Action::Read) {
+                result.errors.push(e);
+                continue;
+            }
+            let target = match write_target(&config, &destination.join(name)) {
+                Ok(target) => target,
+                Err(e) => {
+                    result.errors.push(e);
+                    continue;
+                }
+            };
+            if target.exists() && !request.overwrite {
+                result.errors.push(format!("{}: destination already exists", target.display()));
+                continue;
+            }
+            let added = quota::usage_of(&source);
+            if let Err(e) = quota::check(&config.quotas, &target, None, added).and_then(|_| quota::check_free_space(&target, added.bytes)) {
+                result.errors.push(e);
+                continue;
+            }
+            if config.scanner.enabled() {
+                if let Err(e) = scan::check_tree(&config.scanner, &audit, &source, &target) {
+                    result.errors.push(format!("{}: {}", source.display(), e.message()));
+                    continue;
+                }
+            }
+            let file_limit = if source.is_dir() { Ok(()) } else { writequota::check_file(added.bytes) };
+            if let Err(e) = file_limit.and_then(|_| writequota::reserve(&grant.tier, added.bytes)) {
+                result.errors.push(e);
+                continue;
+            }
+            if let Err(e) = policy::save_version(&config.policies, &target) {
+                writequota::refund(&grant.tier, added.bytes);
+                result.errors.push(e);
+                continue;
+            }
+
+            let copied = if source.is_dir() {
+                vault.copy_dir(&source, &target, config.copy_parallelism)
+            } else {
+                vault.copy_file(&source, &target).map(|_| ())
+            };
+            match copied {
+                Ok(_) => {
+                    let source = source.to_string_lossy();
+                    let target = target.to_string_lossy();
+                    changes.record("copy", &source, Some(&target));
+                    result.copied.push(target.to_string());
+                }
+                Err(e) => {
+                    writequota::refund(&grant.tier, added.bytes);
+                    result.errors.push(format!("{}: {}", source.display(), e));
+                }
+            }
+        }
+
+        Ok(warp::reply::json(&ApiResponse {
+            success: true,
+            data: Some(result),
+            error: None,
+        }))
+    })
+    .await?
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/changes/poll",
+    params(
+        ("cursor" = Option<u64>, Query, description = "cursor from the previous response (omit to start from the latest change)"),
+        ("wait" = Option<u64>, Query, description = "Seconds to wait for a change (default 30)"),
+        ("token" = String, Query, description = "API token"),
+    ),
+    responses((status = 200, description = "Changes after the cursor", body = ApiResponse<changes::ChangePoll>)),
+)]
+pub async fn poll_changes(cursor: Option<u64>, wait: u64, token: String, auth: ClientAuth, changes: Arc<ChangeLog>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&token, &auth, Operation::Changes).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<changes::ChangePoll> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+
+    // cursor 未指定の場合は現在位置から待機する
+    let cursor = cursor.unwrap_or_else(|| changes.latest());
+    let wait = std::time::Duration::from_secs(wait.min(MAX_POLL_WAIT_SECS));
+    let mut poll = changes.wait_since(cursor, wait).await;
+    // 許可ルートのあるトークンには、その範囲のイベントのみ返す
+    if !grant.allowed_roots.is_empty() {
+        let allowed = |path: &str| policy::is_allowed(&grant.allowed_roots, Path::new(path));
+        poll.events.retain(|e| allowed(&e.path) || e.destination.as_deref().map(allowed).unwrap_or(false));
+    }
+
+    Ok(warp::reply::json(&ApiResponse {
+        success: true,
+        data: Some(poll),
+        error: None,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/cleanup",
+    request_body = CleanupRequest,
+    responses((status = 200, description = "Result of each cleanup rule", body = ApiResponse<Vec<cleanup::CleanupReport>>)),
+)]
+pub async fn run_cleanup(request: CleanupRequest, auth: ClientAuth, changes: Arc<ChangeLog>, config: Arc<Config>, audit: Arc<AuditLog>) -> Result<impl Reply, Rejection> {
+    if let Err(e) = check_auth(&request.token, &auth, Operation::Cleanup).await {
+        return Ok(warp::reply::json(&ApiResponse::<Vec<cleanup::CleanupReport>> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    heavy(move || {
+        let reports = cleanup::run_all(&config.cleanup_rules, &config.walk_excludes, request.dry_run, &audit, &changes);
+        Ok(warp::reply::json(&ApiResponse {
+            success: true,
+            data: Some(reports),
+            error: None,
+        }))
+    })
+    .await?
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ClipboardContent {
+    pub format: String, // 現在は text のみ
+    pub text: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ClipboardSetRequest {
+    pub text: String,
+    pub token: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/clipboard/get",
+    params(
+        ("token" = String, Query, description = "API token"),
+    ),
+    responses((status = 200, description = "Text on the desktop clipboard", body = ApiResponse<ClipboardContent>)),
+)]
+pub async fn get_clipboard(token: String, auth: ClientAuth, audit: Arc<AuditLog>) -> Result<impl Reply, Rejection> {
+    if let Err(e) = check_auth(&token, &auth, Operation::Clipboard).await {
+        return Ok(warp::reply::json(&ApiResponse::<ClipboardContent> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    blocking(move || match clipboard::text() {
+        Ok(text) => {
+            audit.record("clipboard_get", "", &format!("{} characters", text.chars().count()));
+            warp::reply::json(&ApiResponse {
+                success: true,
+                data: Some(ClipboardContent { format: "text".to_string(), text }),
+                error: None,
+            })
+        },
+        Err(e) => warp::reply::json(&ApiResponse::<ClipboardContent> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/clipboard/set",
+    request_body = ClipboardSetRequest,
+    responses((status = 200, description = "Text copied to the desktop clipboard", body = ApiResponse<String>)),
+)]
+pub async fn set_clipboard(request: ClipboardSetRequest, auth: ClientAuth, audit: Arc<AuditLog>) -> Result<impl Reply, Rejection> {
+    if let Err(e) = check_auth(&request.token, &auth, Operation::Clipboard).await {
+        return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    blocking(move || match clipboard::set_text(&request.text) {
+        Ok(()) => {
+            audit.record("clipboard_set", "", &format!("{} characters", request.text.chars().count()));
+            warp::reply::json(&ApiResponse {
+                success: true,
+                data: Some("Text copied to the clipboard".to_string()),
+                error: None,
+            })
+        },
+        Err(e) => warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/print",
+    request_body = PrintRequest,
+    responses((status = 200, description = "Sent to the printer", body = ApiResponse<String>)),
+)]
+pub async fn print_document(request: PrintRequest, auth: ClientAuth, config: Arc<Config>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Print).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    blocking(move || {
+        if let Err(e) = paths::validate(&request.path) {
+            return Ok(invalid_path_reply(e));
+        }
+
+        if !config.allow_print {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some("Printing is disabled (set allow_print=true)".to_string()),
+            }));
+        }
+
+        let path = Path::new(&request.path);
+        if let Err(e) = check_access(&config, path, policy::Action::Read) {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+
+        if !path.is_file() {
+            return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some("File does not exist".to_string()),
+            }));
+        }
+
+        match print::print_file(path, request.printer.as_deref()) {
+            Ok(_) => Ok(warp::reply::json(&ApiResponse {
+                success: true,
+                data: Some("Print job sent successfully".to_string()),
+                error: None,
+            })),
+            Err(e) => Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            })),
+        }
+    })
+    .await?
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TransformRequest {
+    pub plugin: String,
+    pub transform: String,
+    pub path: String,
+    #[serde(default)]
+    pub destination: Option<String>, // 指定すると結果をこのファイルに書き込む (省略時は結果を返す)
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TransformResult {
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>, // 書き込んだ実際のパス
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>, // destination がなく、結果が UTF-8 の場合
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_base64: Option<String>, // destination がなく、結果が UTF-8 でない場合
+}
+
+// プラグインとスクリプトがファイルにアクセスできる範囲 (トークンの許可ルートと、許可されているファイル操作)
+fn file_sandbox(token: &str, auth: &ClientAuth, grant: &auth::Grant, config: Arc<Config>, changes: Arc<ChangeLog>, audit: Arc<AuditLog>) -> Sandbox {
+    let operations = auth.capabilities(token).map(|token| token.operations).unwrap_or_default();
+    let allows = |operation: Operation| operations.iter().any(|name| name == operation.name());
+    Sandbox {
+        config: scoped_config(config, grant),
+        tier: grant.tier.clone(),
+        read: allows(Operation::Read),
+        write: allows(Operation::Write),
+        moves: allows(Operation::Move),
+        copies: allows(Operation::Copy),
+        changes,
+        audit,
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/plugins",
+    params(
+        ("token" = String, Query, description = "API token"),
+    ),
+    responses((status = 200, description = "Plugins loaded from plugin_dir with their endpoints and transforms", body = ApiResponse<Vec<plugins::PluginInfo>>)),
+)]
+pub async fn list_plugins(token: String, auth: ClientAuth, plugins: Arc<PluginHost>) -> Result<impl Reply, Rejection> {
+    if let Err(e) = check_auth(&token, &auth, Operation::Plugin).await {
+        return Ok(warp::reply::json(&ApiResponse::<Vec<plugins::PluginInfo>> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    Ok(warp::reply::json(&ApiResponse {
+        success: true,
+        data: Some(plugins.list()),
+        error: None,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/plugins/{plugin}/{endpoint}",
+    params(
+        ("plugin" = String, Path, description = "Plugin name (the .wasm file name without the extension)"),
+        ("endpoint" = String, Path, description = "Endpoint declared by the plugin"),
+    ),
+    request_body(content = Object, description = "JSON object with the token; the other fields are passed to the plugin"),
+    responses((status = 200, description = "ApiResponse whose data is the JSON returned by the plugin", body = Object)),
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn call_plugin(plugin: String, endpoint: String, mut body: serde_json::Value, auth: ClientAuth, changes: Arc<ChangeLog>, config: Arc<Config>, audit: Arc<AuditLog>, plugins: Arc<PluginHost>) -> Result<impl Reply, Rejection> {
+    let token = body
+        .as_object_mut()
+        .and_then(|body| body.remove("token"))
+        .and_then(|token| token.as_str().map(str::to_string))
+        .unwrap_or_default();
+    let grant = match check_auth(&token, &auth, Operation::Plugin).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<serde_json::Value> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let sandbox = file_sandbox(&token, &auth, &grant, config, changes, audit);
+
+    let result = heavy(move || plugins.call_endpoint(&plugin, &endpoint, &body, sandbox)).await?;
+    Ok(match result {
+        Ok(data) => warp::reply::json(&ApiResponse {
+            success: true,
+            data: Some(data),
+            error: None,
+        }),
+        Err(e) => warp::reply::json(&ApiResponse::<serde_json::Value> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/plugins/transform",
+    request_body = TransformRequest,
+    responses((status = 200, description = "The transformed content, or where it was written", body = ApiResponse<TransformResult>)),
+)]
+pub async fn transform_file(request: TransformRequest, auth: ClientAuth, changes: Arc<ChangeLog>, config: Arc<Config>, audit: Arc<AuditLog>, plugins: Arc<PluginHost>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Plugin).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<TransformResult> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let sandbox = file_sandbox(&request.token, &auth, &grant, config, changes, audit);
+
+    // 元のファイルの読み込みと結果の書き込みも、プラグインと同じ範囲に限る
+    let result = heavy(move || -> Result<TransformResult, String> {
+        let content = sandbox.read(&request.path)?;
+        let output = plugins.transform(&request.plugin, &request.transform, &content, sandbox.clone())?;
+        let size = output.len() as u64;
+        if let Some(destination) = &request.destination {
+            let target = sandbox.write(&format!("plugin {}", request.plugin), destination, &output)?;
+            return Ok(TransformResult {
+                path: request.path,
+                destination: Some(target.to_string_lossy().to_string()),
+                size,
+                content: None,
+                content_base64: None,
+            });
+        }
+        let (content, content_base64) = match String::from_utf8(output) {
+            Ok(text) => (Some(text), None),
+            Err(e) => (None, Some(general_purpose::STANDARD.encode(e.as_bytes()))),
+        };
+        Ok(TransformResult {
+            path: request.path,
+            destination: None,
+            size,
+            content,
+            content_base64,
+        })
+    })
+    .await?;
+
+    Ok(match result {
+        Ok(data) => warp::reply::json(&ApiResponse {
+            success: true,
+            data: Some(data),
+            error: None,
+        }),
+        Err(e) => warp::reply::json(&ApiResponse::<TransformResult> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ScriptRequest {
+    pub script: String, // rhai のスクリプト
+    #[serde(default)]
+    pub params: serde_json::Value, // スクリプトの変数 params になる
+    pub token: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/script",
+    request_body = ScriptRequest,
+    responses((status = 200, description = "The script's result, its print output and the paths it changed", body = ApiResponse<script::ScriptOutput>)),
+)]
+pub async fn run_script(request: ScriptRequest, auth: ClientAuth, changes: Arc<ChangeLog>, config: Arc<Config>, audit: Arc<AuditLog>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Script).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<script::ScriptOutput> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+
+    if !config.allow_script {
+        return Ok(warp::reply::json(&ApiResponse::<script::ScriptOutput> {
+            success: false,
+            data: None,
+            error: Some("Scripts are disabled (set allow_script=true)".to_string()),
+        }));
+    }
+    let sandbox = file_sandbox(&request.token, &auth, &grant, config, changes, audit);
+
+    let result = heavy(move || script::run(&request.script, &request.params, sandbox)).await?;
+    Ok(match result {
+        Ok(data) => warp::reply::json(&ApiResponse {
+            success: true,
+            data: Some(data),
+            error: None,
+        }),
+        Err(e) => warp::reply::json(&ApiResponse::<script::ScriptOutput> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ExecRequest {
+    pub name: String, // exec= で宣言したコマンドの名前
+    #[serde(default)]
+    pub args: Vec<String>, // 設定の args= の後に加える引数 (allow_arg= のどれかに一致すること)
+    pub token: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/exec",
+    request_body = ExecRequest,
+    responses((status = 200, description = "Exit code and output of the command", body = ApiResponse<exec::ExecResult>)),
+)]
+pub async fn run_exec(request: ExecRequest, auth: ClientAuth, config: Arc<Config>, audit: Arc<AuditLog>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Exec).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<exec::ExecResult> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    let Some(command) = config.exec_commands.iter().find(|command| command.name == request.name).cloned() else {
+        return Ok(warp::reply::json(&ApiResponse::<exec::ExecResult> {
+            success: false,
+            data: None,
+            error: Some(format!("Command not declared in exec=: {}", request.name)),
+        }));
+    };
+
+    let args = request.args.join(" ");
+    let result = heavy(move || {
+        // {path} の引数は許可ルートの中で、保管庫の外に限る。コマンドは書き換えることがあるため書き込みとして確認する
+        command.check_args(&request.args, |path| {
+            paths::validate(&path.to_string_lossy()).map_err(|e| format!("Invalid path {}: {}", e.path, e.reason))?;
+            if config.allowed_roots.is_empty() {
+                return Err("Commands can only take {path} arguments under allowed_root= folders".to_string());
+            }
+            if policy::is_allowed(&config.vault_roots, path) {
+                return Err(format!("Commands cannot access vault folders: {}", path.display()));
+            }
+            // 隔離ポリシーの書き込み先にはコマンドが従えないので、隔離されるフォルダも断る
+            if write_target(&config, path)? != path {
+                return Err(format!("Commands cannot write to quarantined folders: {}", path.display()));
+            }
+            Ok(())
+        })?;
+        exec::run(&command, &request.args)
+    })
+    .await?;
+    Ok(match result {
+        Ok(data) => {
+            let exit = match (data.exit_code, data.timed_out) {
+                (_, true) => "timed out".to_string(),
+                (Some(code), _) => format!("exit {}", code),
+                (None, _) => "terminated".to_string(),
+            };
+            audit.record("exec", &data.name, &format!("{} ({})", args, exit));
+            warp::reply::json(&ApiResponse {
+                success: true,
+                data: Some(data),
+                error: None,
+            })
+        },
+        Err(e) => warp::reply::json(&ApiResponse::<exec::ExecResult> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GitStatusRequest {
+    pub path: String, // リポジトリの中の任意のパス
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GitDiffRequest {
+    pub path: String, // リポジトリの中の任意のパス
+    #[serde(default)]
+    pub file: Option<String>, // 指定するとこのファイル (かフォルダー) の差分だけを返す
+    #[serde(default)]
+    pub staged: bool, // true ならステージした変更、false ならステージしていない変更
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GitLogRequest {
+    pub path: String, // リポジトリの中の任意のパス
+    #[serde(default)]
+    pub limit: Option<usize>, // 既定 50、最大 1000
+    pub token: String,
+}
+
+// path を含むリポジトリを開いて f を実行する (path とリポジトリの作業フォルダーの両方が許可ルートの中にあること)
+async fn with_repository<T: Serialize + Send + 'static>(
+    token: &str,
+    path: String,
+    auth: &ClientAuth,
+    config: Arc<Config>,
+    f: impl FnOnce(&Config, &git2::Repository, &Path) -> Result<T, String> + Send + 'static,
+) -> Result<warp::reply::Json, Rejection> {
+    let grant = match check_auth(token, auth, Operation::Read).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<T> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    heavy(move || {
+        if let Err(e) = paths::validate(&path) {
+            return invalid_path_reply(e);
+        }
+
+        let result = check_access(&config, Path::new(&path), policy::Action::Read)
+            .and_then(|_| git::open(Path::new(&path)))
+            .and_then(|(repo, root)| {
+                check_access(&config, &root, policy::Action::Read)
+                    .map_err(|_| format!("The repository {} is outside the allowed roots", root.display()))?;
+                if policy::is_allowed(&config.vault_roots, &root) {
+                    return Err(format!("Repositories in vault folders are not supported: {}", root.display()));
+                }
+                f(&config, &repo, &root)
+            });
+        match result {
+            Ok(data) => warp::reply::json(&ApiResponse {
+                success: true,
+                data: Some(data),
+                error: None,
+            }),
+            Err(e) => warp::reply::json(&ApiResponse::<T> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }),
+        }
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/git/status",
+    request_body = GitStatusRequest,
+    responses((status = 200, description = "Branch and changed files of the repository containing the path", body = ApiResponse<git::GitStatus>)),
+)]
+pub async fn git_status(request: GitStatusRequest, auth: ClientAuth, config: Arc<Config>) -> Result<impl Reply, Rejection> {
+    with_repository(&request.token, request.path, &auth, config, |_, repo, root| git::status(repo, root)).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/git/diff",
+    request_body = GitDiffRequest,
+    responses((status = 200, description = "Unified diff of the unstaged (or staged) changes", body = ApiResponse<git::GitDiff>)),
+)]
+pub async fn git_diff(request: GitDiffRequest, auth: ClientAuth, config: Arc<Config>) -> Result<impl Reply, Rejection> {
+    let file = request.file;
+    let staged = request.staged;
+    with_repository(&request.token, request.path, &auth, config, move |config, repo, root| {
+        if let Some(file) = &file {
+            paths::validate(file).map_err(|e| format!("Invalid path: {}", e.reason))?;
+            check_access(config, Path::new(file), policy::Action::Read)?;
+        }
+        git::diff(repo, root, file.as_deref().map(Path::new), staged)
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/git/log",
+    request_body = GitLogRequest,
+    responses((status = 200, description = "Commits from HEAD, newest first", body = ApiResponse<git::GitLog>)),
+)]
+pub async fn git_log(request: GitLogRequest, auth: ClientAuth, config: Arc<Config>) -> Result<impl Reply, Rejection> {
+    let limit = request.limit.unwrap_or(git::DEFAULT_LOG_LIMIT).clamp(1, git::MAX_LOG_LIMIT);
+    with_repository(&request.token, request.path, &auth, config, move |_, repo, root| git::log(repo, root, limit)).await
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct S3UploadRequest {
+    pub path: String, // 送るファイル
+    #[serde(default)]
+    pub key: Option<String>, // 省略するとファイル名 (s3_prefix= より後の部分)
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct S3DownloadRequest {
+    pub key: String,  // s3_prefix= より後の部分
+    pub path: String, // 書き込むファイル (あれば置き換える)
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct S3ListRequest {
+    #[serde(default)]
+    pub prefix: String, // "backups/2024/" のように / で終えると、その「フォルダ」の中
+    #[serde(default)]
+    pub continuation: Option<String>, // 前の応答の next_continuation
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct S3Uploaded {
+    pub bucket: String,
+    pub key: String,
+    pub size: u64,
+    pub etag: String,
+}
+
+// s3 の操作と、ファイルの側で必要な操作 (送るなら read、受け取るなら write) をトークンが持っているか確かめ、設定したバケットを返す
+async fn s3_bucket(token: &str, auth: &ClientAuth, config: &Config, file_operation: Option<Operation>) -> Result<(auth::Grant, s3::Bucket), String> {
+    let grant = check_auth(token, auth, Operation::S3).await?;
+    if let Some(operation) = file_operation {
+        let operations = auth.capabilities(token).map(|token| token.operations).unwrap_or_default();
+        if !operations.iter().any(|name| name == operation.name()) {
+            return Err(format!("Operation not allowed for this token: {}", operation.name()));
+        }
+    }
+    let bucket = s3::Bucket::from_config(config).ok_or("S3 is not configured (set s3_endpoint= and s3_bucket=)")??;
+    Ok((grant, bucket))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/s3/upload",
+    request_body = S3UploadRequest,
+    responses((status = 200, description = "Uploaded to the configured bucket", body = ApiResponse<S3Uploaded>)),
+)]
+pub async fn s3_upload(request: S3UploadRequest, auth: ClientAuth, config: Arc<Config>, audit: Arc<AuditLog>, vault: Arc<Vault>) -> Result<impl Reply, Rejection> {
+    let (grant, bucket) = match s3_bucket(&request.token, &auth, &config, Some(Operation::Read)).await {
+        Ok(found) => found,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<S3Uploaded> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    if let Err(e) = paths::validate(&request.path) {
+        return Ok(invalid_path_reply(e));
+    }
+    let key = match &request.key {
+        Some(key) => key.clone(),
+        None => Path::new(&request.path).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default(),
+    };
+    if let Err(e) = s3::validate_key(&key) {
+        return Ok(warp::reply::json(&ApiResponse::<S3Uploaded> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    let path = PathBuf::from(&request.path);
+    let opened = blocking(move || -> Result<(remote::Upload, u64), String> {
+        check_access(&config, &path, policy::Action::Read)?;
+        let metadata = fs::metadata(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if !metadata.is_file() {
+            return Err(format!("Not a file: {}", path.display()));
+        }
+        let size = vault.content_len(&path, metadata.len());
+        grant.check_size(size)?;
+        // 保管庫のファイルは復号した内容を送る
+        if vault.is_encrypted_file(&path) {
+            let data = vault.read(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            return Ok((remote::Upload::Bytes(data), size));
+        }
+        let file = fs::File::open(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Ok((remote::Upload::File(tokio::fs::File::from_std(file), size), size))
+    })
+    .await?;
+
+    let uploaded = match opened {
+        Ok((content, size)) => bucket.upload(&key, content).await.map(|etag| (etag, size)),
+        Err(e) => Err(e),
+    };
+    Ok(match uploaded {
+        Ok((etag, size)) => {
+            audit.record("s3_upload", &request.path, &format!("s3://{}/{}", bucket.name(), key));
+            warp::reply::json(&ApiResponse {
+                success: true,
+                data: Some(S3Uploaded {
+                    bucket: bucket.name().to_string(),
+                    key,
+                    size,
+                    etag,
+                }),
+                error: None,
+            })
+        }
+        Err(e) => warp::reply::json(&ApiResponse::<S3Uploaded> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/s3/download",
+    request_body = S3DownloadRequest,
+    responses((status = 200, description = "Downloaded from the configured bucket; includes a signed receipt when receipt_key is set", body = ReceiptResponse)),
+)]
+pub async fn s3_download(request: S3DownloadRequest, auth: ClientAuth, changes: Arc<ChangeLog>, config: Arc<Config>, audit: Arc<AuditLog>, vault: Arc<Vault>) -> Result<impl Reply, Rejection> {
+    let (grant, bucket) = match s3_bucket(&request.token, &auth, &config, Some(Operation::Write)).await {
+        Ok(found) => found,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    if let Err(e) = paths::validate(&request.path) {
+        return Ok(invalid_path_reply(e));
+    }
+    if let Err(e) = s3::validate_key(&request.key) {
+        return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    let download = match bucket.download(&request.key).await {
+        Ok(download) => download,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let length = download.size;
+    if let Err(e) = grant.check_size(length).and_then(|_| writequota::check_file(length)) {
+        return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    let target = match write_target_within_quota(&config, &request.path, length).await? {
+        Ok(target) => target,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+
+    // 保管庫に書き込む場合とスキャンする場合は、先にすべて受け取る
+    let (download, data) = if vault.is_vault_path(&target) || config.scanner.enabled() {
+        match download.bytes().await {
+            Ok(data) => (None, Some(data)),
+            Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+                success: false,
+                data: None,
+                error: Some(e),
+            })),
+        }
+    } else {
+        (Some(download), None)
+    };
+    if let Some(data) = &data {
+        if let Err(reply) = scan_content(&config, &audit, &target, data.clone()).await {
+            return Ok(reply);
+        }
+    }
+
+    let saved = {
+        let (config, target) = (config.clone(), target.clone());
+        blocking(move || hooks::before(&config.hooks, "write", &target, None).and_then(|_| policy::save_version(&config.policies, &target))).await?
+    };
+    if let Err(e) = saved {
+        return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    if let Err(e) = writequota::reserve(&grant.tier, length) {
+        return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    let written = match download {
+        Some(download) => download.save(&target).await,
+        None => {
+            let (vault, target, data) = (vault.clone(), target.clone(), data.unwrap_or_default());
+            blocking(move || vault.write(&target, &data)).await?.map_err(|e| e.to_string())
+        }
+    };
+    if let Err(e) = written {
+        writequota::refund(&grant.tier, length);
+        return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(format!("File write error: {}", e)),
+        }));
+    }
+
+    let source = format!("s3://{}/{}", bucket.name(), request.key);
+    blocking(move || {
+        changes.record("write", &target.to_string_lossy(), None);
+        hooks::after(&config.hooks, "write", &target, None);
+        let receipt = audit.receipt("write", &target.to_string_lossy(), &format!("from {}", source), audit.file_hash(&target));
+        Ok(warp::reply::json(&ReceiptResponse {
+            success: true,
+            data: Some(written_message("File downloaded successfully", Path::new(&request.path), &target)),
+            error: None,
+            receipt,
+        }))
+    })
+    .await?
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/s3/list",
+    request_body = S3ListRequest,
+    responses((status = 200, description = "Objects and sub-prefixes directly under the prefix (up to 1000 per page)", body = ApiResponse<s3::S3Listing>)),
+)]
+pub async fn s3_list(request: S3ListRequest, auth: ClientAuth, config: Arc<Config>) -> Result<impl Reply, Rejection> {
+    let listed = match s3_bucket(&request.token, &auth, &config, None).await {
+        Ok((_, bucket)) => bucket.list(&request.prefix, request.continuation.as_deref()).await,
+        Err(e) => Err(e),
+    };
+    Ok(match listed {
+        Ok(data) => warp::reply::json(&ApiResponse {
+            success: true,
+            data: Some(data),
+            error: None,
+        }),
+        Err(e) => warp::reply::json(&ApiResponse::<s3::S3Listing> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }),
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/sync/status",
+    params(
+        ("token" = String, Query, description = "API token"),
+    ),
+    responses((status = 200, description = "State and last result of each sync= folder", body = ApiResponse<Vec<sync::SyncStatus>>)),
+)]
+pub async fn get_sync_status(token: String, auth: ClientAuth, syncs: Arc<SyncManager>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&token, &auth, Operation::Sync).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<Vec<sync::SyncStatus>> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+
+    let mut statuses = syncs.statuses();
+    // 許可ルートのあるトークンには、その範囲のフォルダの同期のみ返す
+    if !grant.allowed_roots.is_empty() {
+        statuses.retain(|status| policy::is_allowed(&grant.allowed_roots, Path::new(&status.local)));
+    }
+    Ok(warp::reply::json(&ApiResponse {
+        success: true,
+        data: Some(statuses),
+        error: None,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/shares",
+    params(
+        ("token" = String, Query, description = "API token"),
+    ),
+    responses((status = 200, description = "Connected network shares and the share= shares", body = ApiResponse<Vec<shares::ShareInfo>>)),
+)]
+pub async fn get_shares(token: String, auth: ClientAuth, config: Arc<Config>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&token, &auth, Operation::Shares).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<Vec<shares::ShareInfo>> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+
+    let roots = grant.allowed_roots.clone();
+    let result = blocking(move || shares::list(&config.shares)).await?;
+    match result {
+        Ok(mut infos) => {
+            // 許可ルートのあるトークンには、その範囲と重なる共有のみ返す
+            if !roots.is_empty() {
+                infos.retain(|info| shares::overlaps(&roots, info));
+            }
+            Ok(warp::reply::json(&ApiResponse {
+                success: true,
+                data: Some(infos),
+                error: None,
+            }))
+        }
+        Err(e) => Ok(warp::reply::json(&ApiResponse::<Vec<shares::ShareInfo>> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct HealthInfo {
+    pub message: String,
+    pub agent_id: String,
+    pub version: String,
+    pub status: String, // "ok" または "degraded" (自己診断に失敗)
+    pub uptime_secs: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<HealthDetails>, // 有効なトークンを付けた場合のみ
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct HealthDetails {
+    pub listen: String, // 待ち受けアドレス (複数ある場合はカンマ区切り、ソケットの場合はそのパス)
+    pub tls: bool,
+    pub read_only: bool, // 書き込み系の操作がすべて無効
+    pub roots: Vec<RootHealth>,
+    pub disk_write: DiskCheck,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RootHealth {
+    pub path: String,
+    pub accessible: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DiskCheck {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// 設定ファイルのフォルダに小さなファイルを書き、読み戻してから削除する
+fn check_disk_write() -> DiskCheck {
+    let probe = Config::get_ini_path().with_file_name(".file_agent_probe");
+    let result = fs::write(&probe, b"file_agent")
+        .and_then(|_| fs::read(&probe))
+        .and_then(|content| {
+            if content == b"file_agent" {
+                Ok(())
+            } else {
+                Err(std::io::Error::other("read back different content"))
+            }
+        });
+    let _ = fs::remove_file(&probe);
+    match result {
+        Ok(_) => DiskCheck { ok: true, error: None },
+        Err(e) => DiskCheck { ok: false, error: Some(format!("{}: {}", probe.display(), e)) },
+    }
+}
+
+// トークンを付けた場合の自己診断の結果
+fn health_details(config: &Config) -> HealthDetails {
+    let agent_allows = |op: Operation| config.allowed_operations.is_empty() || config.allowed_operations.contains(&op);
+    let writable = [Operation::Write, Operation::Delete, Operation::Create, Operation::Move, Operation::Copy, Operation::Paste]
+        .into_iter()
+        .any(agent_allows);
+    HealthDetails {
+        listen: if config.socket.is_empty() {
+            config.effective_listeners().iter().map(|listener| listener.address.to_string()).collect::<Vec<_>>().join(", ")
+        } else {
+            config.socket.clone()
+        },
+        tls: config.socket.is_empty() && config.effective_listeners().iter().any(|listener| listener.tls),
+        read_only: !writable,
+        roots: config
+            .allowed_roots
+            .iter()
+            .map(|root| RootHealth {
+                path: root.display().to_string(),
+                accessible: fs::read_dir(root).is_ok(),
+            })
+            .collect(),
+        disk_write: check_disk_write(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/health",
+    params(
+        ("token" = Option<String>, Query, description = "API token (adds self-check details)"),
+    ),
+    responses((status = 200, description = "Agent status; details are included only with a valid token", body = ApiResponse<HealthInfo>)),
+)]
+pub async fn health_check(token: Option<String>, auth: ClientAuth, config: Arc<Config>, started: std::time::Instant) -> Result<impl Reply, Rejection> {
+    // トークンがなければ生存確認のみ。トークンが無効ならエラーを返す (監視の設定ミスに気付けるように)
+    if let Some(token) = &token {
+        if let Err(e) = auth.verify(token) {
+            return Ok(warp::reply::json(&ApiResponse::<HealthInfo> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }));
+        }
+    }
+
+    // ルートの確認とディスクへの書き込みの確認は遅いドライブで時間がかかるため専用スレッドで行う
+    let details = match token {
+        Some(_) => Some(blocking({
+            let config = config.clone();
+            move || health_details(&config)
+        }).await?),
+        None => None,
+    };
+    let healthy = details
+        .as_ref()
+        .is_none_or(|details| details.disk_write.ok && details.roots.iter().all(|root| root.accessible));
+
+    Ok(warp::reply::json(&ApiResponse {
+        success: true,
+        data: Some(HealthInfo {
+            message: "File Agent is running (token required for operations)".to_string(),
+            agent_id: config.agent_id.clone(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            status: if healthy { "ok" } else { "degraded" }.to_string(),
+            uptime_secs: started.elapsed().as_secs(),
+            details,
+        }),
+        error: None,
+    }))
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PairRequest {
+    pub name: String,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PairResult {
+    pub agent_id: String,
+    pub client: clients::ClientRecord,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RemoveClientRequest {
+    pub name: String,
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct VaultUnlockRequest {
+    pub token: String,
+    pub passphrase: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct VaultLockRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct VaultRotateRequest {
+    pub token: String,
+    pub passphrase: String,
+    #[serde(default)]
+    pub new_passphrase: Option<String>, // 指定するとパスフレーズも変える
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PurgeTrashRequest {
+    pub token: String,
+    #[serde(default)]
+    pub id: Option<String>, // 省略時はアクセスできる保管中のフォルダをすべて削除する
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RotateTokenRequest {
+    pub token: String,
+    #[serde(default)]
+    pub grace_secs: Option<u64>, // 旧トークンを使える秒数 (既定 300)
+    #[serde(default)]
+    pub expires_in_secs: Option<u64>, // 新トークンの有効期間 (省略時は旧トークンと同じ期間)
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RotatedToken {
+    pub tier: String,
+    pub token: String,
+    pub created: u64,
+    pub expires: Option<u64>,
+    pub previous_valid_until: u64,
+}
+
+// 呼び出したトークンを新しいトークンに差し替え、設定ファイルに保存する
+#[utoipa::path(
+    post,
+    path = "/api/tokens/rotate",
+    request_body = RotateTokenRequest,
+    responses((status = 200, description = "The new token (shown only once)", body = ApiResponse<RotatedToken>)),
+)]
+pub async fn rotate_token(request: RotateTokenRequest, auth: ClientAuth) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Tokens).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<RotatedToken> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+
+    let _rotation = auth.lock_rotation();
+    let mut config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<RotatedToken> {
+            success: false,
+            data: None,
+            error: Some(format!("Failed to load configuration: {}", e)),
+        })),
+    };
+    let (current, meta) = if grant.tier == auth::ADMIN_TIER {
+        (&mut config.token_hash, &mut config.token_meta)
+    } else {
+        match config.token_tiers.iter_mut().find(|tier| tier.name == grant.tier) {
+            Some(tier) => (&mut tier.token_hash, &mut tier.meta),
+            None => return Ok(warp::reply::json(&ApiResponse::<RotatedToken> {
+                success: false,
+                data: None,
+                error: Some(format!("Tier '{}' is not in the configuration", grant.tier)),
+            })),
+        }
+    };
+    // 猶予期間中の旧トークンでローテーションすると、現在のトークンが失われるため拒否する
+    if !verify_token(&request.token, current) {
+        return Ok(warp::reply::json(&ApiResponse::<RotatedToken> {
+            success: false,
+            data: None,
+            error: Some("Rotate with the current token, not a previous one".to_string()),
+        }));
+    }
+
+    let now = auth::unix_now();
+    let previous_until = now + request.grace_secs.unwrap_or(DEFAULT_ROTATION_GRACE_SECS);
+    let lifetime = request.expires_in_secs.or_else(|| {
+        meta.expires.zip(meta.created).map(|(expires, created)| expires.saturating_sub(created))
+    });
+    let new_meta = TokenMeta {
+        created: Some(now),
+        expires: lifetime.map(|lifetime| now + lifetime),
+        previous_hash: Some(generate_token_hash(&request.token)),
+        previous_until: Some(previous_until),
+    };
+    let new_token = generate_token();
+    let new_hash = generate_token_hash(&new_token);
+    *current = new_hash.clone();
+    *meta = new_meta.clone();
+
+    if let Err(e) = config.save() {
+        return Ok(warp::reply::json(&ApiResponse::<RotatedToken> {
+            success: false,
+            data: None,
+            error: Some(format!("Failed to save configuration: {}", e)),
+        }));
+    }
+    auth.rotate(&grant.tier, &new_hash, &new_meta);
+    log!("🔑 トークンをローテーションしました: {}", grant.tier);
+
+    Ok(warp::reply::json(&ApiResponse {
+        success: true,
+        data: Some(RotatedToken {
+            tier: grant.tier,
+            token: new_token,
+            created: now,
+            expires: new_meta.expires,
+            previous_valid_until: previous_until,
+        }),
+        error: None,
+    }))
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ShutdownRequest {
+    pub token: String,
+}
+
+// エージェントを停止する (処理中のリクエストが終わってからサーバーが止まり、プロセスが終了する)
+#[utoipa::path(
+    post,
+    path = "/api/shutdown",
+    request_body = ShutdownRequest,
+    responses((status = 200, description = "Shutdown started", body = ApiResponse<String>)),
+)]
+pub async fn shutdown_agent(request: ShutdownRequest, auth: ClientAuth, audit: Arc<AuditLog>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Shutdown).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    // tier のトークンでは停止できない (メインのトークンのみ)
+    if grant.tier != auth::ADMIN_TIER {
+        return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some("Only the main token can shut down the agent".to_string()),
+        }));
+    }
+
+    audit.record("shutdown", "", "");
+    log!("🛑 /api/shutdown で停止を要求されました");
+    shutdown::request();
+    Ok(warp::reply::json(&ApiResponse {
+        success: true,
+        data: Some("Shutting down".to_string()),
+        error: None,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/clients/pair",
+    request_body = PairRequest,
+    responses((status = 200, description = "Paired client", body = ApiResponse<PairResult>)),
+)]
+pub async fn pair_client(request: PairRequest, auth: ClientAuth, config: Arc<Config>, clients: Arc<ClientRegistry>) -> Result<impl Reply, Rejection> {
+    if let Err(e) = check_auth(&request.token, &auth, Operation::Clients).await {
+        return Ok(warp::reply::json(&ApiResponse::<PairResult> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Ok(warp::reply::json(&ApiResponse::<PairResult> {
+            success: false,
+            data: None,
+            error: Some("Client name is required".to_string()),
+        }));
+    }
+
+    let fingerprint = sha256_hex(request.token.as_bytes())[..16].to_string();
+    let client = clients.pair(name, &fingerprint);
+    log!("🤝 クライアントをペアリングしました: {}", name);
+    Ok(warp::reply::json(&ApiResponse {
+        success: true,
+        data: Some(PairResult {
+            agent_id: config.agent_id.clone(),
+            client,
+        }),
+        error: None,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/clients",
+    params(
+        ("token" = String, Query, description = "API token"),
+    ),
+    responses((status = 200, description = "Paired clients", body = ApiResponse<Vec<clients::ClientRecord>>)),
+)]
+pub async fn list_clients(token: String, auth: ClientAuth, clients: Arc<ClientRegistry>) -> Result<impl Reply, Rejection> {
+    if let Err(e) = check_auth(&token, &auth, Operation::Clients).await {
+        return Ok(warp::reply::json(&ApiResponse::<Vec<clients::ClientRecord>> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    Ok(warp::reply::json(&ApiResponse {
+        success: true,
+        data: Some(clients.list()),
+        error: None,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/trash",
+    params(
+        ("token" = String, Query, description = "API token"),
+    ),
+    responses((status = 200, description = "Folders held in the trash", body = ApiResponse<Vec<trash::HeldEntry>>)),
+)]
+pub async fn list_trash(token: String, auth: ClientAuth, config: Arc<Config>, trash: Arc<Trash>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&token, &auth, Operation::List).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<Vec<trash::HeldEntry>> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    let entries: Vec<trash::HeldEntry> = trash
+        .list()
+        .into_iter()
+        .filter(|entry| check_access(&config, Path::new(&entry.original_path), policy::Action::Read).is_ok())
+        .collect();
+    Ok(warp::reply::json(&ApiResponse {
+        success: true,
+        data: Some(entries),
+        error: None,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/trash/purge",
+    request_body = PurgeTrashRequest,
+    responses((status = 200, description = "Purged folders", body = ApiResponse<trash::PurgeReport>)),
+)]
+pub async fn purge_trash(request: PurgeTrashRequest, auth: ClientAuth, config: Arc<Config>, audit: Arc<AuditLog>, trash: Arc<Trash>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&request.token, &auth, Operation::Delete).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<trash::PurgeReport> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    if let Some(id) = &request.id {
+        if !trash.list().iter().any(|entry| &entry.id == id) {
+            return Ok(warp::reply::json(&ApiResponse::<trash::PurgeReport> {
+                success: false,
+                data: None,
+                error: Some("Held directory not found".to_string()),
+            }));
+        }
+    }
+
+    let id = request.id.clone();
+    let report = tokio::task::spawn_blocking(move || {
+        trash.purge(|entry| {
+            id.as_ref().map(|id| &entry.id == id).unwrap_or(true)
+                && check_access(&config, Path::new(&entry.original_path), policy::Action::Write).is_ok()
+        })
+    })
+    .await
+    .unwrap_or_default();
+
+    for path in &report.purged {
+        audit.record("purge", path, "");
+    }
+    if request.id.is_some() && report.purged.is_empty() && report.errors.is_empty() {
+        return Ok(warp::reply::json(&ApiResponse::<trash::PurgeReport> {
+            success: false,
+            data: None,
+            error: Some("Access denied".to_string()),
+        }));
+    }
+    Ok(warp::reply::json(&ApiResponse {
+        success: report.errors.is_empty(),
+        data: Some(report),
+        error: None,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/jobs",
+    params(
+        ("token" = String, Query, description = "API token"),
+    ),
+    responses((status = 200, description = "Background copy jobs", body = ApiResponse<Vec<jobs::CopyJob>>)),
+)]
+pub async fn list_jobs(token: String, auth: ClientAuth, config: Arc<Config>, jobs: Arc<JobStore>) -> Result<impl Reply, Rejection> {
+    let grant = match check_auth(&token, &auth, Operation::Copy).await {
+        Ok(grant) => grant,
+        Err(e) => return Ok(warp::reply::json(&ApiResponse::<Vec<jobs::CopyJob>> {
+            success: false,
+            data: None,
+            error: Some(e),
+        })),
+    };
+    let config = scoped_config(config, &grant);
+
+    let jobs: Vec<jobs::CopyJob> = jobs
+        .list()
+        .into_iter()
+        .filter(|job| check_access(&config, Path::new(&job.source), policy::Action::Read).is_ok())
+        .collect();
+    Ok(warp::reply::json(&ApiResponse {
+        success: true,
+        data: Some(jobs),
+        error: None,
+    }))
+}
+// 保管庫の鍵の情報を設定ファイルに保存する (旧形式の vault_key= は削除する)
+fn save_vault_keys(info: &VaultKeyInfo) -> Result<(), String> {
+    let mut config = Config::load().map_err(|e| format!("Failed to load configuration: {}", e))?;
+    config.vault_keys = info.clone();
+    config.vault_key.clear();
+    config.save().map_err(|e| format!("Failed to save configuration: {}", e))
+}
+
+// 古い鍵で暗号化されたファイルをバックグラウンドで暗号化し直す
+fn spawn_vault_rotation(vault: Arc<Vault>, audit: Arc<AuditLog>) {
+    tokio::task::spawn_blocking(move || {
+        if let Some(info) = vault.run_rotation() {
+            match save_vault_keys(&info) {
+                Ok(_) => audit.record("vault_rotate", "", "re-encryption completed"),
+                Err(e) => log_error!("⚠️ {}", e),
+            }
+        }
+    });
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/vault/status",
+    params(
+        ("token" = String, Query, description = "API token"),
+    ),
+    responses((status = 200, description = "Vault status", body = ApiResponse<vault::VaultStatus>)),
+)]
+pub async fn vault_status(token: String, auth: ClientAuth, vault: Arc<Vault>) -> Result<impl Reply, Rejection> {
+    if let Err(e) = check_auth(&token, &auth, Operation::Vault).await {
+        return Ok(warp::reply::json(&ApiResponse::<vault::VaultStatus> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    Ok(warp::reply::json(&ApiResponse {
+        success: true,
+        data: Some(vault.status()),
+        error: None,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/vault/unlock",
+    request_body = VaultUnlockRequest,
+    responses((status = 200, description = "Vault status after unlocking", body = ApiResponse<vault::VaultStatus>)),
+)]
+pub async fn vault_unlock(request: VaultUnlockRequest, auth: ClientAuth, audit: Arc<AuditLog>, vault: Arc<Vault>) -> Result<impl Reply, Rejection> {
+    if let Err(e) = check_auth(&request.token, &auth, Operation::Vault).await {
+        return Ok(warp::reply::json(&ApiResponse::<vault::VaultStatus> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    // 鍵の導出は時間がかかるため別スレッドで行う
+    let vault_for_task = vault.clone();
+    let result = tokio::task::spawn_blocking(move || vault_for_task.unlock(&request.passphrase))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()))
+        .and_then(|initialized| match initialized {
+            Some(info) => save_vault_keys(&info).map(|_| "passphrase set"),
+            None => Ok("unlocked"),
+        });
+
+    match result {
+        Ok(detail) => {
+            audit.record("vault_unlock", "", detail);
+            log!("🔓 保管庫を解錠しました ({})", detail);
+            // ローテーションの途中で停止していた場合は再開する
+            if vault.status().rotation_pending {
+                spawn_vault_rotation(vault.clone(), audit);
+            }
+            Ok(warp::reply::json(&ApiResponse {
+                success: true,
+                data: Some(vault.status()),
+                error: None,
+            }))
+        }
+        Err(e) => {
+            audit.record("vault_unlock_failed", "", &e);
+            Ok(warp::reply::json(&ApiResponse::<vault::VaultStatus> {
+                success: false,
+                data: None,
+                error: Some(e),
+            }))
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/vault/lock",
+    request_body = VaultLockRequest,
+    responses((status = 200, description = "Vault status after locking", body = ApiResponse<vault::VaultStatus>)),
+)]
+pub async fn vault_lock(request: VaultLockRequest, auth: ClientAuth, audit: Arc<AuditLog>, vault: Arc<Vault>) -> Result<impl Reply, Rejection> {
+    if let Err(e) = check_auth(&request.token, &auth, Operation::Vault).await {
+        return Ok(warp::reply::json(&ApiResponse::<vault::VaultStatus> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    if let Err(e) = vault.lock() {
+        return Ok(warp::reply::json(&ApiResponse::<vault::VaultStatus> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+    audit.record("vault_lock", "", "");
+    log!("🔒 保管庫を施錠しました");
+    Ok(warp::reply::json(&ApiResponse {
+        success: true,
+        data: Some(vault.status()),
+        error: None,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/vault/rotate",
+    request_body = VaultRotateRequest,
+    responses((status = 200, description = "Vault status; files are re-encrypted in the background", body = ApiResponse<vault::VaultStatus>)),
+)]
+pub async fn vault_rotate(request: VaultRotateRequest, auth: ClientAuth, audit: Arc<AuditLog>, vault: Arc<Vault>) -> Result<impl Reply, Rejection> {
+    if let Err(e) = check_auth(&request.token, &auth, Operation::Vault).await {
+        return Ok(warp::reply::json(&ApiResponse::<vault::VaultStatus> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    let vault_for_task = vault.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        vault_for_task.start_rotation(&request.passphrase, request.new_passphrase.as_deref())
+    })
+    .await
+    .unwrap_or_else(|e| Err(e.to_string()))
+    .and_then(|info| save_vault_keys(&info));
+
+    if let Err(e) = result {
+        return Ok(warp::reply::json(&ApiResponse::<vault::VaultStatus> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+    audit.record("vault_rotate", "", "re-encryption started");
+    spawn_vault_rotation(vault.clone(), audit);
+    Ok(warp::reply::json(&ApiResponse {
+        success: true,
+        data: Some(vault.status()),
+        error: None,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/clients/remove",
+    request_body = RemoveClientRequest,
+    responses((status = 200, description = "Removed", body = ApiResponse<String>)),
+)]
+pub async fn remove_client(request: RemoveClientRequest, auth: ClientAuth, clients: Arc<ClientRegistry>) -> Result<impl Reply, Rejection> {
+    if let Err(e) = check_auth(&request.token, &auth, Operation::Clients).await {
+        return Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some(e),
+        }));
+    }
+
+    if clients.remove(&request.name) {
+        Ok(warp::reply::json(&ApiResponse {
+            success: true,
+            data: Some("Client removed successfully".to_string()),
+            error: None,
+        }))
+    } else {
+        Ok(warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error: Some("Client not found".to_string()),
+        }))
+    }
+}
+
+// 不正なパスへのレスポンス (data に不正と判定した理由を含める)
+fn invalid_path_reply(invalid: paths::InvalidPath) -> warp::reply::Json {
+    let error = format!("Invalid path: {}", invalid.reason);
+    warp::reply::json(&ApiResponse {
+        success: false,
+        data: Some(invalid),
+        error: Some(error),
+    })
+}
+
+// 書き込む内容をスキャンする。拒否する場合は返すレスポンス
+async fn scan_content(config: &Config, audit: &AuditLog, path: &Path, data: Vec<u8>) -> Result<(), warp::reply::Json> {
+    if !config.scanner.enabled() {
+        return Ok(());
+    }
+    let scanner = config.scanner.clone();
+    let result = tokio::task::spawn_blocking(move || scanner.scan(&data))
+        .await
+        .unwrap_or_else(|e| Err(scan::ScanError::Failed(e.to_string())));
+    scan::record(audit, path, result).map_err(|e| scan_rejected_reply(path, e))
+}
+
+// スキャンで書き込みを拒否したときのレスポンス (検出した場合は data にシグネチャを含める)
+fn scan_rejected_reply(path: &Path, e: scan::ScanError) -> warp::reply::Json {
+    let error = Some(e.message());
+    match e {
+        scan::ScanError::Rejected(signature) => warp::reply::json(&ApiResponse {
+            success: false,
+            error,
+            data: Some(scan::ContentRejected {
+                content_rejected: true,
+                path: path.to_string_lossy().to_string(),
+                signature,
+            }),
+        }),
+        scan::ScanError::Failed(_) => warp::reply::json(&ApiResponse::<String> {
+            success: false,
+            data: None,
+            error,
+        }),
+    }
+}
+
+// 隔離ポリシーで書き込み先が変わった場合はその旨をメッセージに加える
+fn written_message(message: &str, requested: &Path, actual: &Path) -> String {
+    if requested == actual {
+        message.to_string()
+    } else {
+        format!("{} (quarantined to {})", message, actual.display())
+    }
+}
+
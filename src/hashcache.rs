@@ -0,0 +1,167 @@
+use crate::changes::ChangeLog;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use utoipa::ToSchema;
+
+// 保存するファイル数の上限 (超えたら最も古く使ったものを破棄)
+const MAX_CACHED_FILES: usize = 4096;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct CachedHash {
+    size: u64,
+    modified_nanos: u128,
+    sha256: String,
+    used: u64, // 最後に使った順番 (破棄する順番に使う)
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct HashCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+struct Inner {
+    entries: HashMap<PathBuf, CachedHash>,
+    change_cursor: u64, // 反映済みの ChangeLog の位置
+    next_use: u64,
+}
+
+/// ファイルのハッシュのキャッシュ (JSON ファイルに保存)。
+/// 大きさか更新日時が変わったとき、またはエージェント経由の変更があったときに無効になる
+pub struct HashCache {
+    path: PathBuf,
+    inner: Mutex<Inner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+// キャッシュと比べるファイルの大きさと更新日時
+fn file_key(path: &Path) -> Option<(u64, u128)> {
+    let metadata = fs::metadata(path).ok().filter(|m| m.is_file())?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos();
+    Some((metadata.len(), modified))
+}
+
+impl HashCache {
+    pub fn load(path: PathBuf, changes: &ChangeLog) -> Self {
+        let entries: HashMap<PathBuf, CachedHash> = fs::read(&path)
+            .ok()
+            .and_then(|content| serde_json::from_slice(&content).ok())
+            .unwrap_or_default();
+        let next_use = entries.values().map(|cached| cached.used).max().unwrap_or(0);
+        Self {
+            path,
+            inner: Mutex::new(Inner {
+                entries,
+                change_cursor: changes.latest(),
+                next_use,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn save(&self, entries: &HashMap<PathBuf, CachedHash>) {
+        let result = serde_json::to_vec(entries)
+            .map_err(|e| e.to_string())
+            .and_then(|content| fs::write(&self.path, content).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            log_error!("⚠️ ハッシュのキャッシュの保存に失敗しました: {}", e);
+        }
+    }
+
+    // 前回から記録された変更のパス (と移動先・その配下) のハッシュを破棄する
+    fn apply_changes(inner: &mut Inner, changes: &ChangeLog) {
+        let latest = changes.latest();
+        if latest == inner.change_cursor {
+            return;
+        }
+        let poll = changes.since(inner.change_cursor);
+        if poll.missed {
+            inner.entries.clear();
+        } else {
+            for event in &poll.events {
+                for changed in std::iter::once(&event.path).chain(event.destination.as_ref()) {
+                    let changed = Path::new(changed);
+                    inner.entries.retain(|path, _| !path.starts_with(changed));
+                }
+            }
+        }
+        inner.change_cursor = latest;
+    }
+
+    /// キャッシュしたハッシュを返す (ファイルが変わっていれば None)
+    pub fn get(&self, path: &Path, changes: &ChangeLog) -> Option<String> {
+        let key = file_key(path);
+        let mut inner = self.inner.lock().unwrap();
+        Self::apply_changes(&mut inner, changes);
+        inner.next_use += 1;
+        let used = inner.next_use;
+        let hit = match (inner.entries.get_mut(path), key) {
+            (Some(cached), Some((size, modified))) if cached.size == size && cached.modified_nanos == modified => {
+                cached.used = used;
+                Some(cached.sha256.clone())
+            }
+            _ => None,
+        };
+        if hit.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        hit
+    }
+
+    /// 求めたハッシュを保存する。hash を求める前と後で大きさか更新日時が変わっていれば保存しない
+    pub fn put(&self, path: &Path, before: Option<(u64, u128)>, sha256: &str) {
+        let Some((size, modified_nanos)) = before.filter(|&before| file_key(path) == Some(before)) else {
+            return;
+        };
+        let mut inner = self.inner.lock().unwrap();
+        if inner.entries.len() >= MAX_CACHED_FILES && !inner.entries.contains_key(path) {
+            let oldest = inner.entries.iter().min_by_key(|(_, cached)| cached.used).map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                inner.entries.remove(&oldest);
+            }
+        }
+        inner.next_use += 1;
+        let used = inner.next_use;
+        inner.entries.insert(path.to_path_buf(), CachedHash {
+            size,
+            modified_nanos,
+            sha256: sha256.to_string(),
+            used,
+        });
+        self.save(&inner.entries);
+    }
+
+    /// キャッシュになければ hash で求めて保存する (戻り値の bool はキャッシュから返したか)
+    pub fn get_or_compute(&self, path: &Path, changes: &ChangeLog, hash: impl FnOnce() -> std::io::Result<String>) -> std::io::Result<(String, bool)> {
+        if let Some(sha256) = self.get(path, changes) {
+            return Ok((sha256, true));
+        }
+        self.compute(path, hash).map(|sha256| (sha256, false))
+    }
+
+    /// hash でハッシュを求めて保存する (キャッシュは見ない)
+    pub fn compute(&self, path: &Path, hash: impl FnOnce() -> std::io::Result<String>) -> std::io::Result<String> {
+        let before = file_key(path);
+        let sha256 = hash()?;
+        self.put(path, before, &sha256);
+        Ok(sha256)
+    }
+
+    pub fn stats(&self) -> HashCacheStats {
+        HashCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.inner.lock().unwrap().entries.len(),
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! 操作のフック (hook=)
+//!
+//! 書き込み・削除・移動の前後に外部のプログラムを実行するか Webhook に POST する。
+//! 操作の内容は JSON で渡す (プログラムには標準入力と環境変数、Webhook にはリクエスト本文)。
+//! pre_ のフックが失敗すると操作を拒否し、post_ のフックは操作の後にバックグラウンドで実行する。
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// timeout_secs を指定しないフックの待ち時間
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+// プログラムの終了を確認する間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// フックを実行する操作
+const OPERATIONS: &[&str] = &["write", "delete", "move"];
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Pre,  // 操作の前 (失敗すると操作を拒否する)
+    Post, // 操作が成功した後
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum HookAction {
+    Command(String), // 操作の内容を標準入力に渡すコマンド。終了コード 0 以外は失敗
+    Webhook(String), // 操作の内容を POST する URL。2xx 以外は失敗
+}
+
+/// 操作の前後に実行するフック
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Hook {
+    pub stage: Stage,
+    pub operation: String, // write / delete / move
+    pub action: HookAction,
+    pub timeout_secs: u64,
+}
+
+impl Hook {
+    // 形式: post_write|command=C:\scripts\build.bat|timeout_secs=60
+    //       post_delete|webhook=https://hooks.example.com/notify
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split('|');
+        let event = parts.next()?.trim();
+        let (stage, operation) = if let Some(operation) = event.strip_prefix("pre_") {
+            (Stage::Pre, operation)
+        } else {
+            (Stage::Post, event.strip_prefix("post_")?)
+        };
+        if !OPERATIONS.contains(&operation) {
+            return None;
+        }
+
+        let mut action = None;
+        let mut timeout_secs = DEFAULT_TIMEOUT_SECS;
+        for part in parts {
+            let (key, val) = part.split_once('=')?;
+            let val = val.trim();
+            match key.trim() {
+                "command" if !val.is_empty() => action = Some(HookAction::Command(val.to_string())),
+                "webhook" if val.starts_with("http://") || val.starts_with("https://") => {
+                    action = Some(HookAction::Webhook(val.to_string()))
+                }
+                "timeout_secs" => timeout_secs = val.parse::<u64>().ok()?.max(1),
+                _ => return None,
+            }
+        }
+        Some(Hook { stage, operation: operation.to_string(), action: action?, timeout_secs })
+    }
+
+    pub fn to_ini_value(&self) -> String {
+        let mut value = self.event();
+        match &self.action {
+            HookAction::Command(command) => value.push_str(&format!("|command={}", command)),
+            HookAction::Webhook(url) => value.push_str(&format!("|webhook={}", url)),
+        }
+        if self.timeout_secs != DEFAULT_TIMEOUT_SECS {
+            value.push_str(&format!("|timeout_secs={}", self.timeout_secs));
+        }
+        value
+    }
+
+    // "pre_write" などのイベント名
+    fn event(&self) -> String {
+        match self.stage {
+            Stage::Pre => format!("pre_{}", self.operation),
+            Stage::Post => format!("post_{}", self.operation),
+        }
+    }
+}
+
+/// フックに渡す操作の内容
+#[derive(Debug, Serialize)]
+struct HookEvent {
+    event: String,
+    operation: String,
+    path: String,
+    destination: Option<String>,
+    timestamp: u64,
+    text: String, // Slack などの Webhook でそのまま表示できる要約
+}
+
+impl HookEvent {
+    fn new(hook: &Hook, path: &Path, destination: Option<&Path>) -> Self {
+        let event = hook.event();
+        let path = path.to_string_lossy().to_string();
+        let destination = destination.map(|d| d.to_string_lossy().to_string());
+        let text = match &destination {
+            Some(destination) => format!("file_agent {}: {} -> {}", event, path, destination),
+            None => format!("file_agent {}: {}", event, path),
+        };
+        HookEvent {
+            event,
+            operation: hook.operation.clone(),
+            path,
+            destination,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            text,
+        }
+    }
+}
+
+fn matching<'a>(hooks: &'a [Hook], stage: Stage, operation: &'a str) -> impl Iterator<Item = &'a Hook> {
+    hooks.iter().filter(move |hook| hook.stage == stage && hook.operation == operation)
+}
+
+/// 操作の前のフックを順に実行する。どれかが失敗したら操作を拒否する理由を返す
+/// (専用スレッドから呼ぶ。プログラムの終了や Webhook の応答を待つため)
+pub fn before(hooks: &[Hook], operation: &str, path: &Path, destination: Option<&Path>) -> Result<(), String> {
+    for hook in matching(hooks, Stage::Pre, operation) {
+        let event = HookEvent::new(hook, path, destination);
+        run(hook, &event).map_err(|e| format!("Rejected by {} hook: {}", event.event, e))?;
+    }
+    Ok(())
+}
+
+/// 操作が成功した後のフックをバックグラウンドで実行する (失敗はログに出す)
+pub fn after(hooks: &[Hook], operation: &str, path: &Path, destination: Option<&Path>) {
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        return;
+    };
+    for hook in matching(hooks, Stage::Post, operation) {
+        let hook = hook.clone();
+        let event = HookEvent::new(&hook, path, destination);
+        runtime.spawn_blocking(move || {
+            if let Err(e) = run(&hook, &event) {
+                log_error!("⚠️ {} フックに失敗しました ({}): {}", event.event, event.path, e);
+            }
+        });
+    }
+}
+
+fn run(hook: &Hook, event: &HookEvent) -> Result<(), String> {
+    let payload = serde_json::to_vec(event).map_err(|e| e.to_string())?;
+    let timeout = Duration::from_secs(hook.timeout_secs);
+    match &hook.action {
+        HookAction::Command(command) => run_command(command, event, &payload, timeout),
+        HookAction::Webhook(url) => post_webhook(url, payload, timeout),
+    }
+}
+
+fn run_command(command_line: &str, event: &HookEvent, payload: &[u8], timeout: Duration) -> Result<(), String> {
+    let mut parts = command_line.split_whitespace();
+    let program = parts.next().ok_or_else(|| "hook command is empty".to_string())?;
+    let mut child = Command::new(program)
+        .args(parts)
+        .env("FILE_AGENT_EVENT", &event.event)
+        .env("FILE_AGENT_PATH", &event.path)
+        .env("FILE_AGENT_DESTINATION", event.destination.as_deref().unwrap_or_default())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+
+    // 出力を読みながら待たないと、パイプが詰まって止まることがある
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = payload.to_vec();
+    std::thread::spawn(move || {
+        let _ = stdin.write_all(&input);
+    });
+    let stdout = read_in_background(child.stdout.take().expect("stdout is piped"));
+    let stderr = read_in_background(child.stderr.take().expect("stderr is piped"));
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait().map_err(|e| format!("Failed to run {}: {}", program, e))? {
+            Some(status) => break status,
+            None if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("{} did not finish within {} seconds", program, timeout.as_secs()));
+            }
+            None => std::thread::sleep(POLL_INTERVAL),
+        }
+    };
+    if status.success() {
+        return Ok(());
+    }
+    // 理由は標準出力の最後の行 (なければ標準エラー出力)
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    let message = last_line(&stdout).or_else(|| last_line(&stderr));
+    Err(match message {
+        Some(message) => message,
+        None => format!("{} exited with {}", program, status),
+    })
+}
+
+fn read_in_background(mut pipe: impl Read + Send + 'static) -> std::thread::JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = pipe.read_to_end(&mut output);
+        String::from_utf8_lossy(&output).to_string()
+    })
+}
+
+fn last_line(output: &str) -> Option<String> {
+    output.lines().map(str::trim).rfind(|line| !line.is_empty()).map(str::to_string)
+}
+
+fn post_webhook(url: &str, payload: Vec<u8>, timeout: Duration) -> Result<(), String> {
+    let client = crate::outbound::client();
+    let runtime = tokio::runtime::Handle::try_current().map_err(|e| e.to_string())?;
+    runtime.block_on(async move {
+        let response = client
+            .post(url)
+            .header("content-type", "application/json")
+            .body(payload)
+            .timeout(timeout)
+            .send()
+            .await
+            .map_err(|e| format!("{}: {}", url, e))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let body = response.text().await.unwrap_or_default();
+        Err(match last_line(&body) {
+            Some(message) => format!("{} ({})", message, status),
+            None => format!("{} returned {}", url, status),
+        })
+    })
+}
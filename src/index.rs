@@ -0,0 +1,243 @@
+use crate::mime;
+use crate::walk;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+use walkdir::WalkDir;
+
+// 内容をインデックスするファイルの最大サイズ
+pub const DEFAULT_MAX_CONTENT_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IndexedDoc {
+    pub path: String,
+    pub name: String,
+    pub size: u64,
+    pub modified: Option<u64>,
+}
+
+/// ファイル名 + 内容の転置インデックス
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct SearchIndex {
+    pub built_at: u64,
+    pub docs: Vec<IndexedDoc>,
+    postings: HashMap<String, Vec<u32>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IndexHit {
+    pub path: String,
+    pub name: String,
+    pub size: u64,
+    pub modified: Option<u64>,
+    pub matched_in: String, // "name" | "content"
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IndexSearchResult {
+    pub results: Vec<IndexHit>,
+    pub built_at: u64,
+    pub document_count: usize,
+}
+
+/// 英数字の連続は単語として、それ以外 (日本語など) は 2 文字単位で分割する
+pub fn tokenize(text: &str) -> HashSet<String> {
+    let mut tokens = HashSet::new();
+    let lower = text.to_lowercase();
+    for run in lower.split(|c: char| !c.is_alphanumeric()) {
+        if run.is_empty() {
+            continue;
+        }
+        if run.is_ascii() {
+            if run.len() >= 2 && run.len() <= 64 {
+                tokens.insert(run.to_string());
+            }
+            continue;
+        }
+        let chars: Vec<char> = run.chars().collect();
+        if chars.len() == 1 {
+            tokens.insert(run.to_string());
+        }
+        for pair in chars.windows(2) {
+            tokens.insert(pair.iter().collect());
+        }
+    }
+    tokens
+}
+
+impl SearchIndex {
+    pub fn build(dirs: &[PathBuf], max_content_size: u64, excludes: &[String]) -> Self {
+        let mut index = SearchIndex {
+            built_at: now_secs(),
+            ..Default::default()
+        };
+
+        for dir in dirs {
+            let walker = WalkDir::new(dir)
+                .into_iter()
+                .filter_entry(|e| e.depth() == 0 || !walk::is_excluded_name(excludes, e.path()));
+            for entry in walker.filter_map(|e| e.ok()) {
+                if !entry.file_type().is_file() {
+                    continue;
+                }
+                let metadata = match entry.metadata() {
+                    Ok(metadata) => metadata,
+                    Err(_) => continue,
+                };
+                let path = entry.path();
+                let name = entry.file_name().to_string_lossy().to_string();
+
+                let mut tokens = tokenize(&name);
+                if metadata.len() <= max_content_size {
+                    if let Some(text) = read_text(path) {
+                        tokens.extend(tokenize(&text));
+                    }
+                }
+
+                let id = index.docs.len() as u32;
+                index.docs.push(IndexedDoc {
+                    path: path.to_string_lossy().to_string(),
+                    name,
+                    size: metadata.len(),
+                    modified: metadata
+                        .modified()
+                        .ok()
+                        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                        .map(|d| d.as_secs()),
+                });
+                for token in tokens {
+                    index.postings.entry(token).or_default().push(id);
+                }
+            }
+        }
+        index
+    }
+
+    pub fn load(path: &Path) -> Option<Self> {
+        let content = fs::read(path).ok()?;
+        serde_json::from_slice(&content).ok()
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let content = serde_json::to_vec(self)?;
+        fs::write(path, content)
+    }
+
+    pub fn search(&self, query: &str, limit: usize) -> IndexSearchResult {
+        let needle = query.trim().to_lowercase();
+        let mut results = Vec::new();
+        let mut seen = HashSet::new();
+
+        // ファイル名の部分一致を優先
+        for (id, doc) in self.docs.iter().enumerate() {
+            if results.len() >= limit {
+                break;
+            }
+            if !needle.is_empty() && doc.name.to_lowercase().contains(&needle) {
+                seen.insert(id as u32);
+                results.push(self.hit(id as u32, "name"));
+            }
+        }
+
+        // 全トークンを含む文書 (AND 検索)
+        let tokens = tokenize(&needle);
+        if !tokens.is_empty() && results.len() < limit {
+            let mut lists: Vec<&Vec<u32>> = Vec::new();
+            for token in &tokens {
+                match self.postings.get(token) {
+                    Some(list) => lists.push(list),
+                    None => {
+                        lists.clear();
+                        break;
+                    }
+                }
+            }
+            lists.sort_by_key(|l| l.len());
+            if let Some((first, rest)) = lists.split_first() {
+                let rest: Vec<HashSet<u32>> = rest.iter().map(|l| l.iter().copied().collect()).collect();
+                for id in first.iter() {
+                    if results.len() >= limit {
+                        break;
+                    }
+                    if seen.contains(id) || !rest.iter().all(|set| set.contains(id)) {
+                        continue;
+                    }
+                    results.push(self.hit(*id, "content"));
+                }
+            }
+        }
+
+        IndexSearchResult {
+            results,
+            built_at: self.built_at,
+            document_count: self.docs.len(),
+        }
+    }
+
+    fn hit(&self, id: u32, matched_in: &'static str) -> IndexHit {
+        let doc = &self.docs[id as usize];
+        IndexHit {
+            path: doc.path.clone(),
+            name: doc.name.clone(),
+            size: doc.size,
+            modified: doc.modified,
+            matched_in: matched_in.to_string(),
+        }
+    }
+}
+
+// テキストファイルのみ内容を読み込む (バイナリは None)
+fn read_text(path: &Path) -> Option<String> {
+    let mut content = Vec::new();
+    fs::File::open(path).ok()?.read_to_end(&mut content).ok()?;
+    let head = &content[..content.len().min(mime::SNIFF_LEN)];
+    if mime::sniff(head).is_some() || mime::looks_binary(head) {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&content).into_owned())
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// バックグラウンドでインデックスを構築・定期更新するスレッドを起動する
+pub fn spawn_indexer(
+    dirs: Vec<PathBuf>,
+    max_content_size: u64,
+    excludes: Vec<String>,
+    interval: Duration,
+    index_path: PathBuf,
+    shared: Arc<RwLock<Option<SearchIndex>>>,
+) {
+    std::thread::spawn(move || {
+        // 前回保存したインデックスがあれば再構築が終わるまでそれを使う
+        if let Some(saved) = SearchIndex::load(&index_path) {
+            log!("📇 保存済みインデックスを読み込みました ({} 件)", saved.docs.len());
+            *shared.write().unwrap() = Some(saved);
+        }
+
+        loop {
+            let started = std::time::Instant::now();
+            let index = SearchIndex::build(&dirs, max_content_size, &excludes);
+            log!(
+                "📇 インデックスを構築しました ({} 件, {:.1} 秒)",
+                index.docs.len(),
+                started.elapsed().as_secs_f32()
+            );
+            if let Err(e) = index.save(&index_path) {
+                log_error!("⚠️ インデックスの保存に失敗しました: {}", e);
+            }
+            *shared.write().unwrap() = Some(index);
+            std::thread::sleep(interval);
+        }
+    });
+}
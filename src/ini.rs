@@ -0,0 +1,178 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// include= の入れ子の上限 (循環した include を止める)
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// 設定ファイルの key=value の 1 行と、その位置
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub file: PathBuf,
+    pub line: usize,
+    pub section: Option<String>, // 小文字のセクション名 ([Settings] とセクションの前は None)
+    pub key: String,
+    pub value: String,
+}
+
+/// 設定ファイルの誤り (line が 0 ならファイル全体)
+#[derive(Debug, Clone)]
+pub struct LineError {
+    pub file: PathBuf,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file.display(), self.message)
+        } else {
+            write!(f, "{}:{}: {}", self.file.display(), self.line, self.message)
+        }
+    }
+}
+
+/// ini の内容を key=value の一覧にする。include= は取り込むファイルの行に展開する
+/// (相対パスは include= を書いたファイルのフォルダから)
+pub fn parse(content: &str, file: &Path) -> (Vec<Entry>, Vec<LineError>) {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    parse_into(content, file, 0, &mut entries, &mut errors);
+    (entries, errors)
+}
+
+fn parse_into(content: &str, file: &Path, depth: usize, entries: &mut Vec<Entry>, errors: &mut Vec<LineError>) {
+    let mut section = None;
+    for (index, line) in content.lines().enumerate() {
+        let line_number = index + 1;
+        let error = |message: String| LineError {
+            file: file.to_path_buf(),
+            line: line_number,
+            message,
+        };
+        // 先頭の BOM (メモ帳で保存したファイル) は無視する
+        let line = line.trim_start_matches('\u{feff}').trim();
+        // コメントは行頭の ; と # だけ。行の途中の ; と # は値の一部 (パスワードや URL、| 区切りの設定に使われるため)
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[') {
+            match name.strip_suffix(']').map(str::trim) {
+                Some(name) if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {
+                    let name = name.to_ascii_lowercase();
+                    section = (name != "settings").then_some(name);
+                }
+                _ => errors.push(error(format!("セクションの見出しが不正です: {}", line))),
+            }
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            errors.push(error(format!("key=value の形式ではありません: {}", line)));
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty() {
+            errors.push(error(format!("設定の名前がありません: {}", line)));
+            continue;
+        }
+
+        if key == "include" {
+            let path = file.parent().unwrap_or_else(|| Path::new(".")).join(value);
+            if depth >= MAX_INCLUDE_DEPTH {
+                errors.push(error(format!("include= の入れ子が深すぎます: {}", path.display())));
+                continue;
+            }
+            match fs::read_to_string(&path) {
+                Ok(included) => parse_into(&included, &path, depth + 1, entries, errors),
+                Err(e) => errors.push(error(format!("取り込む設定ファイルを読み込めません: {} ({})", path.display(), e))),
+            }
+            continue;
+        }
+
+        entries.push(Entry {
+            file: file.to_path_buf(),
+            line: line_number,
+            section: section.clone(),
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("file_agent_ini_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn sections_are_recorded_in_lower_case() {
+        let (entries, errors) = parse("port=1\n[TLS]\ncert=a.pem\n[Settings]\nmdns=true\n", Path::new("file_agent.ini"));
+        assert!(errors.is_empty());
+        let sections: Vec<_> = entries.iter().map(|e| (e.section.as_deref(), e.key.as_str())).collect();
+        assert_eq!(sections, vec![(None, "port"), (Some("tls"), "cert"), (None, "mdns")]);
+    }
+
+    #[test]
+    fn comments_are_whole_lines_only() {
+        let content = "; comment\n  # indented comment\npassword=a;b#c\nurl=http://host/#top ; not a comment\n";
+        let (entries, errors) = parse(content, Path::new("file_agent.ini"));
+        assert!(errors.is_empty());
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].value, "a;b#c");
+        assert_eq!(entries[1].value, "http://host/#top ; not a comment");
+        assert_eq!(entries[1].line, 4);
+    }
+
+    #[test]
+    fn bom_and_spaces_around_the_value_are_trimmed() {
+        let (entries, _) = parse("\u{feff}port = 8767 \n", Path::new("file_agent.ini"));
+        assert_eq!((entries[0].key.as_str(), entries[0].value.as_str()), ("port", "8767"));
+    }
+
+    #[test]
+    fn errors_report_the_line() {
+        let (entries, errors) = parse("port=1\nnot a setting\n=value\n[bad name]\n", Path::new("file_agent.ini"));
+        assert_eq!(entries.len(), 1);
+        let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![2, 3, 4]);
+        assert_eq!(errors[0].to_string(), "file_agent.ini:2: key=value の形式ではありません: not a setting");
+    }
+
+    #[test]
+    fn include_expands_relative_to_the_including_file() {
+        let dir = test_dir("include");
+        fs::create_dir_all(dir.join("conf")).unwrap();
+        fs::write(dir.join("conf").join("roots.ini"), "[roots]\nallowed_root=/data\nbroken\n").unwrap();
+        let main = dir.join("file_agent.ini");
+        let (entries, errors) = parse("port=1\ninclude=conf/roots.ini\nmdns=true\n", &main);
+
+        let keys: Vec<_> = entries.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(keys, vec!["port", "allowed_root", "mdns"]);
+        assert_eq!(entries[1].file, dir.join("conf").join("roots.ini"));
+        assert_eq!((entries[1].line, entries[1].section.as_deref()), (2, Some("roots")));
+        // 取り込んだファイルのセクションは、取り込んだ側に引き継がない
+        assert_eq!(entries[2].section, None);
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].file.clone(), errors[0].line), (dir.join("conf").join("roots.ini"), 3));
+    }
+
+    #[test]
+    fn missing_and_circular_includes_are_errors() {
+        let dir = test_dir("include_loop");
+        fs::write(dir.join("loop.ini"), "include=loop.ini\n").unwrap();
+        let (_, errors) = parse("include=missing.ini\ninclude=loop.ini\n", &dir.join("file_agent.ini"));
+        assert_eq!(errors[0].line, 1);
+        assert!(errors[0].message.contains("missing.ini"), "{}", errors[0].message);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[1].message.contains("入れ子が深すぎます"), "{}", errors[1].message);
+    }
+}
@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+/// 許可するアドレスの範囲 (prefix はビット数。単一のアドレスは 32 / 128)
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct IpRange {
+    pub address: IpAddr,
+    pub prefix: u8,
+}
+
+impl IpRange {
+    // 形式: 192.168.1.20 / 192.168.1.0/24 / fd00::/8
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address.trim().parse::<IpAddr>().ok()?, Some(prefix.trim().parse::<u8>().ok()?)),
+            None => (value.parse::<IpAddr>().ok()?, None),
+        };
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        (prefix <= max).then_some(Self { address, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(range) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(range) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+
+    pub fn to_ini_value(&self) -> String {
+        let max = if self.address.is_ipv4() { 32 } else { 128 };
+        if self.prefix == max {
+            self.address.to_string()
+        } else {
+            format!("{}/{}", self.address, self.prefix)
+        }
+    }
+}
+
+/// 許可リストにないアドレスからのリクエストの拒否理由 (recover で 403 のレスポンスにする)
+#[derive(Debug)]
+pub struct Forbidden {
+    pub client: IpAddr,
+}
+
+impl warp::reject::Reject for Forbidden {}
+
+/// 接続元のアドレスが許可リストにあるか確認する。リストが空なら、またループバックは常に許可する
+pub fn check(allowed: &[IpRange], ip: IpAddr) -> Result<(), Forbidden> {
+    // 0.0.0.0 や :: で待ち受けると IPv4 のクライアントが ::ffff:a.b.c.d になるため戻す
+    let ip = ip.to_canonical();
+    if allowed.is_empty() || ip.is_loopback() || allowed.iter().any(|range| range.contains(ip)) {
+        Ok(())
+    } else {
+        Err(Forbidden { client: ip })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(value: &str) -> IpRange {
+        IpRange::parse(value).unwrap_or_else(|| panic!("valid range: {}", value))
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn single_addresses_use_the_full_prefix() {
+        assert_eq!(range("192.168.1.20").prefix, 32);
+        assert_eq!(range("fd00::1").prefix, 128);
+        assert!(range("192.168.1.20").contains(ip("192.168.1.20")));
+        assert!(!range("192.168.1.20/32").contains(ip("192.168.1.21")));
+        assert!(range("fd00::1/128").contains(ip("fd00::1")));
+        assert!(!range("fd00::1/128").contains(ip("fd00::2")));
+        assert_eq!(range("192.168.1.20").to_ini_value(), "192.168.1.20");
+        assert_eq!(range("192.168.1.0/24").to_ini_value(), "192.168.1.0/24");
+    }
+
+    #[test]
+    fn prefixes_match_networks() {
+        let lan = range("192.168.1.0/24");
+        assert!(lan.contains(ip("192.168.1.255")));
+        assert!(!lan.contains(ip("192.168.2.1")));
+        // ネットワーク部以外のビットが立っていても範囲として扱う
+        assert!(range("10.1.2.3/8").contains(ip("10.200.0.1")));
+        let ula = range("fd00::/8");
+        assert!(ula.contains(ip("fdff:1::1")));
+        assert!(!ula.contains(ip("fe80::1")));
+    }
+
+    #[test]
+    fn zero_prefix_matches_every_address_of_the_family() {
+        assert!(range("0.0.0.0/0").contains(ip("203.0.113.9")));
+        assert!(range("::/0").contains(ip("2001:db8::1")));
+        assert!(!range("0.0.0.0/0").contains(ip("2001:db8::1")));
+        assert!(!range("::/0").contains(ip("203.0.113.9")));
+    }
+
+    #[test]
+    fn ipv4_and_ipv6_do_not_match_each_other() {
+        assert!(!range("192.168.1.0/24").contains(ip("::ffff:192.168.1.5")));
+        assert!(!range("::ffff:192.168.1.0/120").contains(ip("192.168.1.5")));
+    }
+
+    #[test]
+    fn malformed_ranges_are_rejected() {
+        for value in ["", "192.168.1.0/33", "fd00::/129", "192.168.1.0/", "192.168.1.0/abc", "192.168.1.0/-1", "192.168.1/24", "host.example/24", "/24"] {
+            assert!(IpRange::parse(value).is_none(), "{}", value);
+        }
+        assert_eq!(range(" 192.168.1.0 / 24 ").prefix, 24);
+    }
+
+    #[test]
+    fn check_allows_listed_and_loopback_clients() {
+        let allowed = vec![range("192.168.1.0/24"), range("fd00::/8")];
+        assert!(check(&allowed, ip("192.168.1.7")).is_ok());
+        assert!(check(&allowed, ip("fd00::7")).is_ok());
+        assert!(check(&allowed, ip("127.0.0.1")).is_ok());
+        assert!(check(&allowed, ip("::1")).is_ok());
+        assert_eq!(check(&allowed, ip("192.168.2.7")).unwrap_err().client, ip("192.168.2.7"));
+        assert!(check(&[], ip("203.0.113.9")).is_ok());
+    }
+
+    #[test]
+    fn check_matches_ipv4_mapped_clients_against_ipv4_ranges() {
+        let allowed = vec![range("192.168.1.0/24")];
+        assert!(check(&allowed, ip("::ffff:192.168.1.7")).is_ok());
+        assert_eq!(check(&allowed, ip("::ffff:10.0.0.1")).unwrap_err().client, ip("10.0.0.1"));
+        assert!(check(&allowed, ip("::ffff:127.0.0.1")).is_ok());
+    }
+}
@@ -0,0 +1,263 @@
+use crate::audit::AuditLog;
+use crate::changes::ChangeLog;
+use crate::{concurrency, copy};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+use walkdir::WalkDir;
+
+// 進捗を保存する最短の間隔 (ファイルごとに保存すると小さなファイルが多い場合に遅くなる)
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+// 残しておく終了済みジョブの数
+const MAX_FINISHED_JOBS: usize = 50;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// バックグラウンドのコピージョブの状態。エージェントを再起動しても position から再開する
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct CopyJob {
+    pub id: String,
+    pub source: String,
+    pub destination: String,
+    pub status: JobStatus,
+    pub position: usize,    // コピーが終わったエントリ数 (ファイル一覧の位置)
+    pub total_entries: usize,
+    pub bytes_done: u64,
+    pub total_bytes: u64,
+    pub created: u64,
+    pub updated: u64,
+    pub resumed: u32, // 再起動後に再開した回数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// ジョブの状態を保存するストア。ジョブごとに状態 (<id>.json) とファイル一覧 (<id>.list.json) を置く
+pub struct JobStore {
+    dir: PathBuf,
+    jobs: Mutex<HashMap<String, CopyJob>>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl JobStore {
+    pub fn load(dir: PathBuf) -> Self {
+        let mut jobs = HashMap::new();
+        if let Ok(entries) = fs::read_dir(&dir) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !name.ends_with(".json") || name.ends_with(".list.json") {
+                    continue;
+                }
+                let job = fs::read(entry.path())
+                    .ok()
+                    .and_then(|content| serde_json::from_slice::<CopyJob>(&content).ok());
+                match job {
+                    Some(job) => {
+                        jobs.insert(job.id.clone(), job);
+                    }
+                    None => log_error!("⚠️ ジョブの状態を読み込めませんでした: {}", entry.path().display()),
+                }
+            }
+        }
+        Self {
+            dir,
+            jobs: Mutex::new(jobs),
+        }
+    }
+
+    fn state_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    fn list_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.list.json", id))
+    }
+
+    fn save(&self, job: &CopyJob) {
+        let result = fs::create_dir_all(&self.dir)
+            .map_err(|e| e.to_string())
+            .and_then(|_| serde_json::to_vec_pretty(job).map_err(|e| e.to_string()))
+            .and_then(|content| fs::write(self.state_path(&job.id), content).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            log_error!("⚠️ ジョブの状態の保存に失敗しました: {}", e);
+        }
+    }
+
+    fn update(&self, job: &CopyJob) {
+        self.jobs.lock().unwrap().insert(job.id.clone(), job.clone());
+        self.save(job);
+    }
+
+    pub fn list(&self) -> Vec<CopyJob> {
+        let mut jobs: Vec<CopyJob> = self.jobs.lock().unwrap().values().cloned().collect();
+        jobs.sort_by_key(|job| job.created);
+        jobs
+    }
+
+    /// 再起動前に終わらなかったジョブ
+    pub fn unfinished(&self) -> Vec<CopyJob> {
+        self.list().into_iter().filter(|job| job.status == JobStatus::Running).collect()
+    }
+
+    /// コピーするエントリの一覧を作り、ジョブとして保存する (コピーは run_copy で行う)
+    pub fn create_copy(&self, id: String, source: &Path, destination: &Path) -> Result<CopyJob, String> {
+        let mut entries = Vec::new();
+        let mut total_bytes = 0;
+        for entry in WalkDir::new(source).min_depth(1).sort_by_file_name() {
+            let entry = entry.map_err(|e| format!("Failed to list source directory: {}", e))?;
+            let relative = entry.path().strip_prefix(source).map_err(|e| e.to_string())?;
+            let mut relative = relative.to_string_lossy().to_string();
+            if entry.file_type().is_dir() {
+                relative.push('/'); // ディレクトリは末尾の / で区別する
+            } else {
+                total_bytes += entry.metadata().map(|m| m.len()).unwrap_or(0);
+            }
+            entries.push(relative);
+        }
+
+        fs::create_dir_all(&self.dir).map_err(|e| format!("Failed to create job directory: {}", e))?;
+        let list = serde_json::to_vec(&entries).map_err(|e| e.to_string())?;
+        fs::write(self.list_path(&id), list).map_err(|e| format!("Failed to save job file list: {}", e))?;
+
+        let now = now_secs();
+        let job = CopyJob {
+            id,
+            source: source.to_string_lossy().to_string(),
+            destination: destination.to_string_lossy().to_string(),
+            status: JobStatus::Running,
+            position: 0,
+            total_entries: entries.len(),
+            bytes_done: 0,
+            total_bytes,
+            created: now,
+            updated: now,
+            resumed: 0,
+            error: None,
+        };
+        self.update(&job);
+        self.prune();
+        Ok(job)
+    }
+
+    /// ジョブを失敗として終了する
+    pub fn fail(&self, job: &mut CopyJob, error: String) {
+        job.status = JobStatus::Failed;
+        job.error = Some(error);
+        job.updated = now_secs();
+        self.update(job);
+        let _ = fs::remove_file(self.list_path(&job.id));
+    }
+
+    // 古い終了済みジョブを削除する
+    fn prune(&self) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let mut finished: Vec<(u64, String)> = jobs
+            .values()
+            .filter(|job| job.status != JobStatus::Running)
+            .map(|job| (job.updated, job.id.clone()))
+            .collect();
+        if finished.len() <= MAX_FINISHED_JOBS {
+            return;
+        }
+        finished.sort();
+        for (_, id) in &finished[..finished.len() - MAX_FINISHED_JOBS] {
+            jobs.remove(id);
+            let _ = fs::remove_file(self.state_path(id));
+        }
+    }
+}
+
+/// ジョブのコピーを position から進める。終了したエントリごとに進捗を記録する
+pub fn run_copy(store: &JobStore, mut job: CopyJob, changes: &ChangeLog, audit: &AuditLog) {
+    let entries: Vec<String> = match fs::read(store.list_path(&job.id))
+        .map_err(|e| e.to_string())
+        .and_then(|content| serde_json::from_slice(&content).map_err(|e| e.to_string()))
+    {
+        Ok(entries) => entries,
+        Err(e) => {
+            store.fail(&mut job, format!("Failed to read job file list: {}", e));
+            return;
+        }
+    };
+
+    let source = PathBuf::from(&job.source);
+    let destination = PathBuf::from(&job.destination);
+    if let Err(e) = fs::create_dir_all(&destination) {
+        store.fail(&mut job, format!("Failed to create destination directory: {}", e));
+        return;
+    }
+
+    let mut last_save = Instant::now();
+    while job.position < entries.len() {
+        let entry = &entries[job.position];
+        let result = match entry.strip_suffix('/') {
+            Some(dir) => fs::create_dir_all(destination.join(dir)).map(|_| 0),
+            None => {
+                let target = destination.join(entry);
+                // 途中まで書かれたファイルは最初からコピーし直す
+                target
+                    .parent()
+                    .map(fs::create_dir_all)
+                    .unwrap_or(Ok(()))
+                    .and_then(|_| copy::file(&source.join(entry), &target))
+            }
+        };
+        match result {
+            Ok(bytes) => {
+                job.position += 1;
+                job.bytes_done += bytes;
+            }
+            Err(e) => {
+                store.fail(&mut job, format!("{}: {}", entry, e));
+                log_error!("❌ コピージョブが失敗しました ({}): {}", job.id, job.error.as_deref().unwrap_or_default());
+                return;
+            }
+        }
+        if last_save.elapsed() >= SAVE_INTERVAL {
+            job.updated = now_secs();
+            store.update(&job);
+            last_save = Instant::now();
+        }
+    }
+
+    job.status = JobStatus::Completed;
+    job.updated = now_secs();
+    store.update(&job);
+    let _ = fs::remove_file(store.list_path(&job.id));
+    changes.record("copy", &job.source, Some(&job.destination));
+    audit.record("copy", &job.destination, &format!("from {} (job {})", job.source, job.id));
+    log!("✅ コピージョブが完了しました ({}): {} -> {}", job.id, job.source, job.destination);
+}
+
+/// 再起動前に終わらなかったジョブを再開する
+pub fn resume_all(store: Arc<JobStore>, changes: Arc<ChangeLog>, audit: Arc<AuditLog>, check: impl Fn(&CopyJob) -> Result<(), String>) {
+    for mut job in store.unfinished() {
+        if let Err(e) = check(&job) {
+            store.fail(&mut job, e);
+            continue;
+        }
+        job.resumed += 1;
+        store.update(&job);
+        log!("🔁 コピージョブを再開します ({}): {}/{} 件完了", job.id, job.position, job.total_entries);
+        let store = store.clone();
+        let changes = changes.clone();
+        let audit = audit.clone();
+        concurrency::spawn_heavy(move || run_copy(&store, job, &changes, &audit));
+    }
+}
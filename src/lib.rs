@@ -0,0 +1,181 @@
+//! File Agent のライブラリ部分 (設定・認証・ハンドラー・ルート)
+//!
+//! 実行ファイル (トレイ常駐のエージェント) はこのライブラリの薄いラッパーで、
+//! 他の Rust アプリケーションは [`routes`] で作ったフィルターを自分のサーバーに組み込める。
+// warp のルートを .or() でつなぐ数が多く、型の再帰が既定の上限を超えるため
+#![recursion_limit = "256"]
+
+use sha2::{Sha256, Digest};
+use std::fs;
+use std::path::Path;
+
+// log! / log_error! を他のモジュールで使うため最初に宣言する
+#[macro_use]
+pub mod logs;
+mod apiversion;
+pub mod audit;
+pub mod auth;
+pub mod autostart;
+pub mod changes;
+pub mod cleanup;
+pub mod clients;
+mod clipboard;
+mod compress;
+mod concurrency;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod config;
+mod copy;
+mod deleteguard;
+pub mod dirsize;
+pub mod exec;
+mod fuzzy;
+pub mod git;
+mod hashcache;
+pub mod grep;
+pub mod handlers;
+pub mod hooks;
+pub mod index;
+pub mod ini;
+mod ipfilter;
+pub mod jobs;
+pub mod listcache;
+pub mod listener;
+mod longpath;
+mod mdns;
+pub mod mime;
+mod openapi;
+mod outbound;
+mod paths;
+pub mod plugins;
+mod policy;
+mod print;
+mod quota;
+mod ratelimit;
+mod reload;
+pub mod remote;
+mod rpc;
+pub mod s3;
+pub mod sandbox;
+mod scan;
+pub mod script;
+pub mod server;
+pub mod shares;
+pub mod shutdown;
+mod signing;
+mod socket;
+pub mod sync;
+mod tempfiles;
+mod templates;
+mod timeout;
+mod tls;
+pub mod trash;
+pub mod vault;
+mod walk;
+pub mod webdav;
+mod webui;
+mod writequota;
+mod xml;
+
+pub use config::Config;
+pub use server::{routes, start_api_server, start_stdio_server};
+
+pub(crate) fn verify_token(token: &str, expected_hash: &str) -> bool {
+    let hash = generate_token_hash(token);
+    signing::constant_time_eq(hash.as_bytes(), expected_hash.as_bytes())
+}
+
+// 隠しファイル判定 (Windows: Hidden/System 属性, それ以外: ドットファイル)
+#[cfg(target_os = "windows")]
+pub(crate) fn is_hidden(_path: &Path, metadata: Option<&fs::Metadata>) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    const FILE_ATTRIBUTE_SYSTEM: u32 = 0x4;
+    metadata
+        .map(|m| m.file_attributes() & (FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM) != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(target_os = "windows"))]
+pub(crate) fn is_hidden(path: &Path, _metadata: Option<&fs::Metadata>) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .map(|n| n.starts_with('.'))
+        .unwrap_or(false)
+}
+
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    format!("{:x}", hasher.finalize())
+}
+
+// 名前がぶつかりにくい乱数 (SHA256 の 16 進文字列)。一時ファイルの名前用で、推測されて困る値には os_random_hex を使う
+pub(crate) fn random_hex() -> String {
+    use std::hash::{BuildHasher, Hasher};
+
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(nanos);
+    hasher.write_u32(std::process::id());
+
+    let mut seed = nanos.to_le_bytes().to_vec();
+    seed.extend_from_slice(&hasher.finish().to_le_bytes());
+    sha256_hex(&seed)
+}
+
+// OS の乱数生成器による bytes バイトの乱数 (16 進文字列)
+pub(crate) fn os_random_hex(bytes: usize) -> String {
+    use aes_gcm::aead::rand_core::RngCore;
+
+    let mut buffer = vec![0u8; bytes];
+    aes_gcm::aead::OsRng.fill_bytes(&mut buffer);
+    buffer.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// 1970-01-01 からの日数を年月日にする (グレゴリオ暦)
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// 年月日を 1970-01-01 からの日数にする (civil_from_days の逆)
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// エージェントを識別する永続 ID (UUID 形式の乱数)
+pub(crate) fn generate_agent_id() -> String {
+    let hash = os_random_hex(16);
+    format!("{}-{}-{}-{}-{}", &hash[0..8], &hash[8..12], &hash[12..16], &hash[16..20], &hash[20..32])
+}
+
+// 新しく発行するトークン (OS の乱数 32 バイト)
+pub(crate) fn generate_token() -> String {
+    os_random_hex(32)
+}
+
+/// トークンの SHA256 (file_agent.ini の token_hash= に保存する値)
+pub fn generate_token_hash(token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(token.as_bytes());
+    let result = hasher.finalize();
+    format!("{:x}", result)
+}
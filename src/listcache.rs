@@ -0,0 +1,125 @@
+use crate::changes::ChangeLog;
+use crate::handlers::FileInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use utoipa::ToSchema;
+
+// キャッシュするディレクトリ数の上限 (超えたら最も古いものを破棄)
+const MAX_CACHED_DIRS: usize = 256;
+
+struct CachedListing {
+    files: Vec<FileInfo>,
+    dir_modified: Option<SystemTime>,
+    cached_at: Instant,
+    change_cursor: u64, // キャッシュ時点の ChangeLog の位置
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub ttl_secs: u64,
+}
+
+/// ディレクトリ一覧のキャッシュ。
+/// ディレクトリの更新日時の変化、エージェント経由の変更、TTL 経過のいずれかで無効になる
+pub struct ListCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<PathBuf, CachedListing>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// キャッシュ検証用のディレクトリ更新日時 (一覧を読む前に取得しておく)
+pub fn dir_modified(dir: &Path) -> Option<SystemTime> {
+    std::fs::metadata(dir).and_then(|m| m.modified()).ok()
+}
+
+// イベントのパスが dir の一覧に影響するか (dir 直下の変更、または dir 自身・祖先の変更)
+fn affects(dir: &Path, path: &str) -> bool {
+    let path = Path::new(path);
+    path.parent() == Some(dir) || dir.starts_with(path)
+}
+
+impl ListCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    pub fn get(&self, dir: &Path, changes: &ChangeLog) -> Option<Vec<FileInfo>> {
+        if !self.enabled() {
+            return None;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let valid = match entries.get_mut(dir) {
+            Some(cached) => {
+                let mut valid = cached.cached_at.elapsed() < self.ttl && cached.dir_modified == dir_modified(dir);
+                let latest = changes.latest();
+                if valid && latest != cached.change_cursor {
+                    let poll = changes.since(cached.change_cursor);
+                    valid = !poll.missed
+                        && !poll.events.iter().any(|e| {
+                            affects(dir, &e.path) || e.destination.as_deref().map(|d| affects(dir, d)).unwrap_or(false)
+                        });
+                    cached.change_cursor = latest;
+                }
+                valid
+            }
+            None => false,
+        };
+
+        if valid {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            entries.get(dir).map(|cached| cached.files.clone())
+        } else {
+            entries.remove(dir);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+
+    /// 一覧を保存する。modified と change_cursor は一覧を読む前に取得したものを渡す
+    pub fn put(&self, dir: &Path, files: Vec<FileInfo>, modified: Option<SystemTime>, change_cursor: u64) {
+        if !self.enabled() {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_CACHED_DIRS && !entries.contains_key(dir) {
+            let oldest = entries.iter().min_by_key(|(_, c)| c.cached_at).map(|(p, _)| p.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(dir.to_path_buf(), CachedListing {
+            files,
+            dir_modified: modified,
+            cached_at: Instant::now(),
+            change_cursor,
+        });
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries.lock().unwrap().len(),
+            ttl_secs: self.ttl.as_secs(),
+        }
+    }
+}
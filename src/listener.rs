@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+
+use crate::auth::Operation;
+
+/// 待ち受けるアドレス (listener= の 1 行)。どの待ち受けも同じハンドラーと状態を使う
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Listener {
+    pub address: SocketAddr,
+    pub tls: bool,
+    pub allowed_operations: Vec<Operation>, // 空ならトークンで許可された操作をすべて受け付ける
+}
+
+impl Listener {
+    // 形式: 192.168.1.10:8443|tls=true|allow=read,list,search (IPv6 は [::1]:8767)
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split('|');
+        let address = parts.next()?.trim().parse().ok()?;
+
+        let mut listener = Listener {
+            address,
+            tls: false,
+            allowed_operations: Vec::new(),
+        };
+        for part in parts {
+            let (key, val) = part.split_once('=')?;
+            let val = val.trim();
+            match key.trim() {
+                "tls" => listener.tls = val.parse().ok()?,
+                "allow" => listener.allowed_operations = Operation::parse_list(val)?,
+                _ => return None,
+            }
+        }
+        Some(listener)
+    }
+
+    pub fn to_ini_value(&self) -> String {
+        let mut value = self.address.to_string();
+        if self.tls {
+            value.push_str("|tls=true");
+        }
+        if !self.allowed_operations.is_empty() {
+            value.push_str(&format!("|allow={}", Operation::to_ini_value(&self.allowed_operations)));
+        }
+        value
+    }
+
+    /// この待ち受けで operation を受け付けるか
+    pub fn allows(&self, operation: Operation) -> bool {
+        self.allowed_operations.is_empty() || self.allowed_operations.contains(&operation)
+    }
+
+    pub fn scheme(&self) -> &'static str {
+        if self.tls { "https" } else { "http" }
+    }
+}
@@ -0,0 +1,186 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+// ログファイルがこの大きさを超えたら .1 に移して新しく書き始める (log_max_bytes= で変更できる)
+pub const DEFAULT_MAX_BYTES: u64 = 5 * 1024 * 1024;
+
+static MAX_BYTES: AtomicU64 = AtomicU64::new(DEFAULT_MAX_BYTES);
+
+// /api/logs/tail で読み込むファイル末尾の大きさ
+const TAIL_WINDOW_BYTES: u64 = 1024 * 1024;
+
+pub const DEFAULT_TAIL_LINES: usize = 200;
+pub const MAX_TAIL_LINES: usize = 2000;
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+// 起動時に init するまではコンソールへの表示のみ (verify-audit などのコマンドはファイルに書かない)
+static LOG: Mutex<Option<LogFile>> = Mutex::new(None);
+
+// --stdio では標準出力を JSON-RPC に使うため、表示はすべて標準エラー出力に出す
+static CONSOLE_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// 標準出力 (--stdio では標準エラー出力) に表示し、ログファイルにも書き込む
+#[macro_export]
+macro_rules! log {
+    () => { log!("") };
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        $crate::logs::console(&line);
+        $crate::logs::append(&line);
+    }};
+}
+
+/// 標準エラー出力に表示し、ログファイルにも書き込む
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        eprintln!("{}", line);
+        $crate::logs::append(&line);
+    }};
+}
+
+pub fn use_stderr() {
+    CONSOLE_STDERR.store(true, Ordering::Relaxed);
+}
+
+/// コンソールにだけ表示する (ログファイルには書き込まない)
+pub fn console(line: &str) {
+    if CONSOLE_STDERR.load(Ordering::Relaxed) {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+/// ログファイルを開く (windows_subsystem のビルドではコンソールがないため、ここに残した出力で診断する)
+pub fn init(path: PathBuf) {
+    match open(&path) {
+        Ok(file) => {
+            let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+            *LOG.lock().unwrap() = Some(LogFile { path, file, size });
+        }
+        Err(e) => eprintln!("⚠️ ログファイルを開けません: {} ({})", path.display(), e),
+    }
+}
+
+fn open(path: &Path) -> std::io::Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// ログファイルを切り替える大きさを変える (0 なら切り替えない)
+pub fn set_max_bytes(bytes: u64) {
+    MAX_BYTES.store(if bytes == 0 { u64::MAX } else { bytes }, Ordering::Relaxed);
+}
+
+pub fn path() -> Option<PathBuf> {
+    LOG.lock().unwrap().as_ref().map(|log| log.path.clone())
+}
+
+pub fn append(line: &str) {
+    let mut guard = LOG.lock().unwrap();
+    let Some(log) = guard.as_mut() else {
+        return;
+    };
+    if log.size >= MAX_BYTES.load(Ordering::Relaxed) {
+        rotate(log);
+    }
+    let entry = format!("[{}] {}\n", crate::auth::unix_now(), line);
+    // 書き込めなくても処理は続ける (エラーを表示するとログの書き込みが再帰する)
+    if log.file.write_all(entry.as_bytes()).is_ok() {
+        log.size += entry.len() as u64;
+    }
+}
+
+fn rotate(log: &mut LogFile) {
+    let _ = fs::rename(&log.path, rotated_path(&log.path));
+    if let Ok(file) = open(&log.path) {
+        log.file = file;
+        log.size = 0;
+    }
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".1");
+    PathBuf::from(name)
+}
+
+/// ログファイルの末尾の行を返す (古い順)
+pub fn tail(lines: usize) -> std::io::Result<Vec<String>> {
+    let path = path().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Log file is not open"))?;
+    let mut file = File::open(&path)?;
+    let size = file.metadata()?.len();
+    let start = size.saturating_sub(TAIL_WINDOW_BYTES);
+    file.seek(SeekFrom::Start(start))?;
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+
+    let text = String::from_utf8_lossy(&buffer);
+    let mut all: Vec<&str> = text.lines().collect();
+    // 途中から読んだ場合、最初の行は欠けている
+    if start > 0 && !all.is_empty() {
+        all.remove(0);
+    }
+    let skip = all.len().saturating_sub(lines);
+    Ok(all[skip..].iter().map(|line| line.to_string()).collect())
+}
+
+/// ログファイルを既定のアプリケーション (テキストエディター) で開く
+#[cfg(target_os = "windows")]
+pub fn open_in_editor(path: &Path) -> Result<(), String> {
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::um::shellapi::ShellExecuteW;
+    use winapi::um::winuser::SW_SHOWNORMAL;
+
+    fn to_wide(s: &OsStr) -> Vec<u16> {
+        s.encode_wide().chain(std::iter::once(0)).collect()
+    }
+
+    let verb = to_wide(OsStr::new("open"));
+    let file = to_wide(path.as_os_str());
+    let result = unsafe {
+        ShellExecuteW(
+            std::ptr::null_mut(),
+            verb.as_ptr(),
+            file.as_ptr(),
+            std::ptr::null(),
+            std::ptr::null(),
+            SW_SHOWNORMAL,
+        )
+    };
+
+    // 32 以下はエラーコード
+    let code = result as isize;
+    if code > 32 {
+        Ok(())
+    } else {
+        Err(format!("Failed to open the log (ShellExecute error {})", code))
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn open_in_editor(path: &Path) -> Result<(), String> {
+    let opener = if cfg!(target_os = "macos") { "open" } else { "xdg-open" };
+    let status = std::process::Command::new(opener)
+        .arg(path)
+        .status()
+        .map_err(|e| format!("Failed to run {}: {}", opener, e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} failed: {}", opener, status))
+    }
+}
@@ -0,0 +1,80 @@
+//! Windows の長いパス (\\?\) と UNC パスの表記を揃える
+//!
+//! std::fs の関数は MAX_PATH (260 文字) を超えるパスに自動で \\?\ を付けるため、node_modules のような
+//! 深いフォルダもそのまま扱える。ただし canonicalize は常に \\?\C:\... や \\?\UNC\server\share\... を返すため、
+//! そのまま比べると、パスが存在するかどうか (共有に接続済みかどうか) で表記が変わり、同じ場所と判断できない。
+//! 許可ルートなどと比べる前に simplify で \\?\ を外し、std::fs を通らない API には extended か legacy で整えて渡す。
+use std::path::{Path, PathBuf};
+
+// これ以上長いパスは \\?\ を付けないと Windows の API に渡せない (フォルダの作成は MAX_PATH - 12 まで)
+#[cfg(target_os = "windows")]
+const LEGACY_MAX_PATH: usize = 248;
+
+// \\?\ を受け付けない API に渡せる長さ (作業フォルダの上限の MAX_PATH - 2 に合わせる)
+#[cfg(target_os = "windows")]
+const SHORT_MAX_PATH: usize = 258;
+
+/// \\?\C:\... を C:\...、\\?\UNC\server\share\... を \\server\share\... にする (ほかのパスはそのまま)
+pub fn simplify(path: &Path) -> PathBuf {
+    if !cfg!(target_os = "windows") {
+        return path.to_path_buf();
+    }
+    let Some(value) = path.to_str() else {
+        return path.to_path_buf();
+    };
+    if let Some(rest) = value.strip_prefix(r"\\?\UNC\") {
+        return PathBuf::from(format!(r"\\{}", rest));
+    }
+    match value.strip_prefix(r"\\?\") {
+        // \\?\Volume{...}\ のようにドライブ文字のないパスは外せない
+        Some(rest) if is_drive_path(rest) => PathBuf::from(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn is_drive_path(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && (bytes.len() == 2 || bytes[2] == b'\\')
+}
+
+/// 長い絶対パスに \\?\ (UNC なら \\?\UNC\) を付ける。std::fs を通らない API に渡す場合に使う
+/// (\\?\ 付きのパスは "." と ".." を解釈しないため、path は resolve したものを渡す)
+#[cfg(target_os = "windows")]
+pub fn extended(path: &Path) -> PathBuf {
+    let Some(value) = path.to_str() else {
+        return path.to_path_buf();
+    };
+    if value.len() < LEGACY_MAX_PATH || value.starts_with(r"\\?\") || value.starts_with(r"\\.\") || !path.is_absolute() {
+        return path.to_path_buf();
+    }
+    // \\?\ の後ろでは / を区切りとして扱わない
+    let value = value.replace('/', "\\");
+    match value.strip_prefix(r"\\") {
+        Some(rest) => PathBuf::from(format!(r"\\?\UNC\{}", rest)),
+        None => PathBuf::from(format!(r"\\?\{}", value)),
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn extended(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// \\?\ を受け付けない API (ShellExecute やプロセスの作業フォルダ) に渡すパス。長すぎて渡せなければエラー
+#[cfg(target_os = "windows")]
+pub fn legacy(path: &Path) -> Result<PathBuf, String> {
+    use std::os::windows::ffi::OsStrExt;
+
+    let path = simplify(path);
+    // 上限は UTF-16 の文字数
+    let len = path.as_os_str().encode_wide().count();
+    if len > SHORT_MAX_PATH {
+        return Err(format!("Path is too long ({} characters, the limit here is {}): {}", len, SHORT_MAX_PATH, path.display()));
+    }
+    Ok(path)
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn legacy(path: &Path) -> Result<PathBuf, String> {
+    Ok(path.to_path_buf())
+}
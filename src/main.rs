@@ -3,675 +3,22 @@
     windows_subsystem = "windows"
 )]
 
-use serde::{Deserialize, Serialize};
-use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use warp::{Filter, Rejection, Reply};
-use warp::http::Method;
-use walkdir::WalkDir;
-use sha2::{Sha256, Digest};
+#[cfg(target_os = "windows")]
 use systray::Application;
-use base64::{Engine as _, engine::general_purpose};
 
+use file_agent::{audit, autostart, logs, log, log_error, server, shutdown, start_api_server, start_stdio_server, Config};
 #[cfg(target_os = "windows")]
-use native_windows_gui as nwg;
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct Config {
-    token: String,
-    port: u16,
-}
-
-impl Config {
-    fn get_ini_path() -> PathBuf {
-        let exe_path = std::env::current_exe().unwrap_or_else(|_| PathBuf::from("."));
-        let exe_dir = exe_path.parent().unwrap_or_else(|| Path::new("."));
-        exe_dir.join("file_agent.ini")
-    }
-    
-    fn load() -> Self {
-        let ini_path = Self::get_ini_path();
-        
-        if let Ok(content) = fs::read_to_string(&ini_path) {
-            println!("設定ファイル読み込み: {}", ini_path.display());
-            
-            let mut port = 8767;
-            let mut token = "default-token-12345".to_string();
-            
-            for line in content.lines() {
-                let line = line.trim();
-                if line.starts_with("port=") {
-                    if let Ok(p) = line[5..].parse::<u16>() {
-                        port = p;
-                    }
-                } else if line.starts_with("token=") {
-                    token = line[6..].to_string();
-                }
-            }
-            
-            return Config { token, port };
-        }
-        
-        println!("設定ファイルが見つかりません。デフォルト設定を使用します。");
-        let default_config = Self::default();
-        let _ = default_config.save(); // デフォルト設定を保存
-        default_config
-    }
-    
-    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let ini_path = Self::get_ini_path();
-        let content = format!(
-            "[Settings]\nport={}\ntoken={}\n",
-            self.port,
-            self.token
-        );
-        
-        fs::write(&ini_path, content)?;
-        println!("設定ファイルを保存しました: {}", ini_path.display());
-        Ok(())
-    }
-}
-
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            token: "default-token-12345".to_string(),
-            port: 8767,
-        }
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct FileInfo {
-    path: String,
-    name: String,
-    is_file: bool,
-    size: Option<u64>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct ApiResponse<T> {
-    success: bool,
-    data: Option<T>,
-    error: Option<String>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct ReadRequest {
-    path: String,
-    token: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct WriteRequest {
-    path: String,
-    content: String,
-    token: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct WriteBinaryRequest {
-    path: String,
-    content: String, // Base64エンコードされたバイナリデータ
-    token: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct DeleteRequest {
-    path: String,
-    token: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct SearchRequest {
-    directory: String,
-    pattern: String,
-    token: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct CreateRequest {
-    path: String,
-    is_directory: bool,
-    token: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct MoveRequest {
-    source: String,
-    destination: String,
-    token: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct CopyRequest {
-    source: String,
-    destination: String,
-    token: String,
-}
-
-fn verify_token(token: &str, expected_hash: &str) -> bool {
-    let mut hasher = Sha256::new();
-    hasher.update(token.as_bytes());
-    let result = hasher.finalize();
-    let hash = format!("{:x}", result);
-    hash == expected_hash
-}
-
-async fn check_auth(token: &str, expected_hash: &str) -> Result<(), String> {
-    if !verify_token(token, expected_hash) {
-        Err("認証エラー: 無効なトークンです".to_string())
-    } else {
-        Ok(())
-    }
-}
-
-async fn read_file(request: ReadRequest, expected_hash: String) -> Result<impl Reply, Rejection> {
-    if let Err(e) = check_auth(&request.token, &expected_hash).await {
-        return Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some(e),
-        }));
-    }
-    
-    match fs::read_to_string(&request.path) {
-        Ok(content) => Ok(warp::reply::json(&ApiResponse {
-            success: true,
-            data: Some(content),
-            error: None,
-        })),
-        Err(e) => Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some(e.to_string()),
-        })),
-    }
-}
-
-async fn read_binary_file(request: ReadRequest, expected_hash: String) -> Result<impl Reply, Rejection> {
-    if let Err(e) = check_auth(&request.token, &expected_hash).await {
-        return Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some(e),
-        }));
-    }
-    
-    match fs::read(&request.path) {
-        Ok(content) => {
-            let base64_content = general_purpose::STANDARD.encode(&content);
-            Ok(warp::reply::json(&ApiResponse {
-                success: true,
-                data: Some(base64_content),
-                error: None,
-            }))
-        },
-        Err(e) => Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some(e.to_string()),
-        })),
-    }
-}
-
-async fn write_file(request: WriteRequest, expected_hash: String) -> Result<impl Reply, Rejection> {
-    if let Err(e) = check_auth(&request.token, &expected_hash).await {
-        return Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some(e),
-        }));
-    }
-    
-    match fs::write(&request.path, &request.content) {
-        Ok(_) => Ok(warp::reply::json(&ApiResponse {
-            success: true,
-            data: Some("File written successfully".to_string()),
-            error: None,
-        })),
-        Err(e) => Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some(e.to_string()),
-        })),
-    }
-}
-
-async fn write_binary_file(request: WriteBinaryRequest, expected_hash: String) -> Result<impl Reply, Rejection> {
-    if let Err(e) = check_auth(&request.token, &expected_hash).await {
-        return Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some(e),
-        }));
-    }
-    
-    // Base64デコード
-    match general_purpose::STANDARD.decode(&request.content) {
-        Ok(binary_data) => {
-            // バイナリデータをファイルに書き込み
-            match fs::write(&request.path, &binary_data) {
-                Ok(_) => Ok(warp::reply::json(&ApiResponse {
-                    success: true,
-                    data: Some("Binary file written successfully".to_string()),
-                    error: None,
-                })),
-                Err(e) => Ok(warp::reply::json(&ApiResponse::<String> {
-                    success: false,
-                    data: None,
-                    error: Some(format!("File write error: {}", e)),
-                })),
-            }
-        },
-        Err(e) => Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some(format!("Base64 decode error: {}", e)),
-        })),
-    }
-}
-
-async fn delete_file(request: DeleteRequest, expected_hash: String) -> Result<impl Reply, Rejection> {
-    if let Err(e) = check_auth(&request.token, &expected_hash).await {
-        return Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some(e),
-        }));
-    }
-    
-    let path = Path::new(&request.path);
-    let result = if path.is_file() {
-        fs::remove_file(path)
-    } else if path.is_dir() {
-        fs::remove_dir_all(path)
-    } else {
-        return Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some("Path does not exist".to_string()),
-        }));
-    };
-
-    match result {
-        Ok(_) => Ok(warp::reply::json(&ApiResponse {
-            success: true,
-            data: Some("Deleted successfully".to_string()),
-            error: None,
-        })),
-        Err(e) => Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some(e.to_string()),
-        })),
-    }
-}
-
-async fn search_files(request: SearchRequest, expected_hash: String) -> Result<impl Reply, Rejection> {
-    if let Err(e) = check_auth(&request.token, &expected_hash).await {
-        return Ok(warp::reply::json(&ApiResponse::<Vec<FileInfo>> {
-            success: false,
-            data: None,
-            error: Some(e),
-        }));
-    }
-    
-    let mut files = Vec::new();
-    let pattern = request.pattern.to_lowercase();
-
-    for entry in WalkDir::new(&request.directory)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .take(1000)
-    {
-        let path = entry.path();
-        let name = path.file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("")
-            .to_lowercase();
-
-        if name.contains(&pattern) {
-            let metadata = entry.metadata().ok();
-            files.push(FileInfo {
-                path: path.to_string_lossy().to_string(),
-                name: path.file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("")
-                    .to_string(),
-                is_file: path.is_file(),
-                size: metadata.as_ref().map(|m| m.len()),
-            });
-        }
-    }
-
-    Ok(warp::reply::json(&ApiResponse {
-        success: true,
-        data: Some(files),
-        error: None,
-    }))
-}
-
-async fn list_directory(path: String, token: String, expected_hash: String) -> Result<impl Reply, Rejection> {
-    if !verify_token(&token, &expected_hash) {
-        return Ok(warp::reply::json(&ApiResponse::<Vec<FileInfo>> {
-            success: false,
-            data: None,
-            error: Some("認証エラー: 無効なトークンです".to_string()),
-        }));
-    }
-
-    let mut files = Vec::new();
-    
-    match fs::read_dir(&path) {
-        Ok(entries) => {
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    let path = entry.path();
-                    let metadata = entry.metadata().ok();
-                    files.push(FileInfo {
-                        path: path.to_string_lossy().to_string(),
-                        name: path.file_name()
-                            .and_then(|n| n.to_str())
-                            .unwrap_or("")
-                            .to_string(),
-                        is_file: path.is_file(),
-                        size: metadata.as_ref().map(|m| m.len()),
-                    });
-                }
-            }
-            Ok(warp::reply::json(&ApiResponse {
-                success: true,
-                data: Some(files),
-                error: None,
-            }))
-        }
-        Err(e) => Ok(warp::reply::json(&ApiResponse::<Vec<FileInfo>> {
-            success: false,
-            data: None,
-            error: Some(e.to_string()),
-        })),
-    }
-}
-
-async fn create_file_or_directory(request: CreateRequest, expected_hash: String) -> Result<impl Reply, Rejection> {
-    if let Err(e) = check_auth(&request.token, &expected_hash).await {
-        return Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some(e),
-        }));
-    }
-    
-    let path = Path::new(&request.path);
-    
-    let result = if request.is_directory {
-        fs::create_dir_all(path)
-    } else {
-        if let Some(parent) = path.parent() {
-            if !parent.exists() {
-                if let Err(e) = fs::create_dir_all(parent) {
-                    return Ok(warp::reply::json(&ApiResponse::<String> {
-                        success: false,
-                        data: None,
-                        error: Some(format!("Failed to create parent directory: {}", e)),
-                    }));
-                }
-            }
-        }
-        fs::write(path, "")
-    };
-
-    match result {
-        Ok(_) => Ok(warp::reply::json(&ApiResponse {
-            success: true,
-            data: Some(format!("{} created successfully", if request.is_directory { "Directory" } else { "File" })),
-            error: None,
-        })),
-        Err(e) => Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some(e.to_string()),
-        })),
-    }
-}
-
-async fn move_file(request: MoveRequest, expected_hash: String) -> Result<impl Reply, Rejection> {
-    if let Err(e) = check_auth(&request.token, &expected_hash).await {
-        return Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some(e),
-        }));
-    }
-    
-    let source = Path::new(&request.source);
-    let destination = Path::new(&request.destination);
-    
-    if !source.exists() {
-        return Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some("Source file does not exist".to_string()),
-        }));
-    }
-    
-    if let Some(parent) = destination.parent() {
-        if !parent.exists() {
-            if let Err(e) = fs::create_dir_all(parent) {
-                return Ok(warp::reply::json(&ApiResponse::<String> {
-                    success: false,
-                    data: None,
-                    error: Some(format!("Failed to create destination directory: {}", e)),
-                }));
-            }
-        }
-    }
-
-    match fs::rename(source, destination) {
-        Ok(_) => Ok(warp::reply::json(&ApiResponse {
-            success: true,
-            data: Some("File moved successfully".to_string()),
-            error: None,
-        })),
-        Err(e) => Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some(e.to_string()),
-        })),
-    }
-}
-
-async fn copy_file(request: CopyRequest, expected_hash: String) -> Result<impl Reply, Rejection> {
-    if let Err(e) = check_auth(&request.token, &expected_hash).await {
-        return Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some(e),
-        }));
-    }
-    
-    let source = Path::new(&request.source);
-    let destination = Path::new(&request.destination);
-    
-    if !source.exists() {
-        return Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some("Source file does not exist".to_string()),
-        }));
-    }
-    
-    if let Some(parent) = destination.parent() {
-        if !parent.exists() {
-            if let Err(e) = fs::create_dir_all(parent) {
-                return Ok(warp::reply::json(&ApiResponse::<String> {
-                    success: false,
-                    data: None,
-                    error: Some(format!("Failed to create destination directory: {}", e)),
-                }));
-            }
-        }
-    }
-
-    let result = if source.is_dir() {
-        copy_dir_recursive(source, destination)
-    } else {
-        fs::copy(source, destination).map(|_| ())
-    };
-
-    match result {
-        Ok(_) => Ok(warp::reply::json(&ApiResponse {
-            success: true,
-            data: Some("File copied successfully".to_string()),
-            error: None,
-        })),
-        Err(e) => Ok(warp::reply::json(&ApiResponse::<String> {
-            success: false,
-            data: None,
-            error: Some(e.to_string()),
-        })),
-    }
-}
-
-fn copy_dir_recursive(src: &Path, dst: &Path) -> std::io::Result<()> {
-    if !dst.exists() {
-        fs::create_dir_all(dst)?;
-    }
-    
-    for entry in fs::read_dir(src)? {
-        let entry = entry?;
-        let src_path = entry.path();
-        let dst_path = dst.join(entry.file_name());
-        
-        if src_path.is_dir() {
-            copy_dir_recursive(&src_path, &dst_path)?;
-        } else {
-            fs::copy(&src_path, &dst_path)?;
-        }
-    }
-    Ok(())
-}
-
-fn generate_token_hash(token: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(token.as_bytes());
-    let result = hasher.finalize();
-    format!("{:x}", result)
-}
+use file_agent::auth::{self, TokenMeta};
+#[cfg(target_os = "windows")]
+use file_agent::generate_token_hash;
 
-async fn start_api_server(config: Config) {
-    let token_hash = generate_token_hash(&config.token);
-    
-    println!("✅ サーバー起動中...");
-    
-    if let Err(e) = std::net::TcpListener::bind(("127.0.0.1", config.port)) {
-        eprintln!("❌ サーバー起動エラー: {}", e);
-        eprintln!("ポート {} が既に使用されている可能性があります。", config.port);
-        eprintln!("config.json でポート番号を変更するか、以下のコマンドで使用中のプロセスを終了してください:");
-        eprintln!("  netstat -ano | findstr :{}", config.port);
-        eprintln!("  taskkill /PID <プロセスID> /F");
-        return;
-    }
-    
-    println!("✅ サーバー起動成功");
-
-    let cors = warp::cors()
-        .allow_any_origin()
-        .allow_headers(vec!["content-type"])
-        .allow_methods(&[Method::GET, Method::POST, Method::PUT, Method::DELETE]);
-
-    let token_hash_filter = warp::any().map(move || token_hash.clone());
-
-    let read_route = warp::path!("api" / "read")
-        .and(warp::post())
-        .and(warp::body::json())
-        .and(token_hash_filter.clone())
-        .and_then(read_file);
-
-    let read_binary_route = warp::path!("api" / "read_binary")
-        .and(warp::post())
-        .and(warp::body::json())
-        .and(token_hash_filter.clone())
-        .and_then(read_binary_file);
-
-    let write_route = warp::path!("api" / "write")
-        .and(warp::post())
-        .and(warp::body::json())
-        .and(token_hash_filter.clone())
-        .and_then(write_file);
-
-    let write_binary_route = warp::path!("api" / "write_binary")
-        .and(warp::post())
-        .and(warp::body::json())
-        .and(token_hash_filter.clone())
-        .and_then(write_binary_file);
-
-    let delete_route = warp::path!("api" / "delete")
-        .and(warp::post())
-        .and(warp::body::json())
-        .and(token_hash_filter.clone())
-        .and_then(delete_file);
-
-    let search_route = warp::path!("api" / "search")
-        .and(warp::post())
-        .and(warp::body::json())
-        .and(token_hash_filter.clone())
-        .and_then(search_files);
-
-    let list_route = warp::path!("api" / "list")
-        .and(warp::get())
-        .and(warp::query::<std::collections::HashMap<String, String>>())
-        .and(token_hash_filter.clone())
-        .and_then(move |query: std::collections::HashMap<String, String>, expected_hash: String| async move {
-            let path = query.get("path").cloned().unwrap_or_else(|| ".".to_string());
-            let token = query.get("token").cloned().unwrap_or_default();
-            list_directory(path, token, expected_hash).await
-        });
+#[cfg(target_os = "windows")]
+use native_windows_gui as nwg;
 
-    let create_route = warp::path!("api" / "create")
-        .and(warp::post())
-        .and(warp::body::json())
-        .and(token_hash_filter.clone())
-        .and_then(create_file_or_directory);
-
-    let move_route = warp::path!("api" / "move")
-        .and(warp::post())
-        .and(warp::body::json())
-        .and(token_hash_filter.clone())
-        .and_then(move_file);
-
-    let copy_route = warp::path!("api" / "copy")
-        .and(warp::post())
-        .and(warp::body::json())
-        .and(token_hash_filter.clone())
-        .and_then(copy_file);
-
-    let health_route = warp::path!("api" / "health")
-        .map(|| warp::reply::json(&ApiResponse {
-            success: true,
-            data: Some("File Agent is running (token required for operations)".to_string()),
-            error: None,
-        }));
-
-    let routes = read_route
-        .or(read_binary_route)
-        .or(write_route)
-        .or(write_binary_route)
-        .or(delete_route)
-        .or(search_route)
-        .or(list_route)
-        .or(create_route)
-        .or(move_route)
-        .or(copy_route)
-        .or(health_route)
-        .with(cors);
-
-    warp::serve(routes)
-        .run(([127, 0, 0, 1], config.port))
-        .await;
-}
+// 終了するときにサーバーの停止を待つ時間 (処理中のリクエストを打ち切るまでの時間に余裕を加える)
+const STOP_WAIT: std::time::Duration = std::time::Duration::from_secs(shutdown::DRAIN_TIMEOUT.as_secs() + 5);
 
 #[cfg(target_os = "windows")]
 fn show_config_dialog(config: Arc<Mutex<Config>>) {
@@ -712,7 +59,7 @@ fn show_config_dialog(config: Arc<Mutex<Config>>) {
         nwg::Label::builder()
             .size((100, 25))
             .position((10, 60))
-            .text("トークン:")
+            .text("新しいトークン:")
             .parent(&window)
             .build(&mut token_label)
             .unwrap();
@@ -720,7 +67,7 @@ fn show_config_dialog(config: Arc<Mutex<Config>>) {
         nwg::TextInput::builder()
             .size((250, 25))
             .position((120, 60))
-            .text(&config.lock().unwrap().token)
+            .placeholder_text(Some("変更しない場合は空欄"))
             .parent(&window)
             .build(&mut token_input)
             .unwrap();
@@ -754,15 +101,34 @@ fn show_config_dialog(config: Arc<Mutex<Config>>) {
                     if handle == save_handle {
                         if let Ok(port) = port_input.text().parse::<u16>() {
                             let mut cfg = config.lock().unwrap();
+                            let restart = port != cfg.port;
+                            // API でローテーションされたトークンを上書きしないよう、保存済みの設定に反映する
+                            match Config::load() {
+                                Ok(loaded) => *cfg = loaded,
+                                Err(e) => {
+                                    nwg::modal_error_message(&window_handle, "File Agent", &format!("設定ファイルに誤りがあります:\n{}", e));
+                                    return;
+                                }
+                            }
                             cfg.port = port;
-                            cfg.token = token_input.text();
+                            if !token_input.text().is_empty() {
+                                cfg.token_hash = generate_token_hash(&token_input.text());
+                                cfg.token_meta = TokenMeta {
+                                    created: Some(auth::unix_now()),
+                                    ..TokenMeta::default()
+                                };
+                            }
                             if let Err(e) = cfg.save() {
                                 nwg::modal_error_message(&window_handle, "エラー", &format!("設定の保存に失敗しました: {}", e));
-                            } else {
+                            } else if restart {
                                 nwg::modal_info_message(&window_handle, "成功", "設定を保存しました。自動的に再起動します。");
                                 nwg::stop_thread_dispatch();
-                                // 自動的に再起動
+                                // ポートの変更だけは待ち受け直す必要があるため再起動する
                                 restart_application();
+                            } else {
+                                // それ以外の変更は設定ファイルの監視で反映される (接続中のリクエストは切断しない)
+                                nwg::modal_info_message(&window_handle, "成功", "設定を保存しました。すぐに反映されます。");
+                                nwg::stop_thread_dispatch();
                             }
                         } else {
                             nwg::modal_error_message(&window_handle, "エラー", "ポート番号が無効です");
@@ -780,13 +146,53 @@ fn show_config_dialog(config: Arc<Mutex<Config>>) {
     });
 }
 
-#[cfg(not(target_os = "windows"))]
-fn show_config_dialog(_config: Arc<Mutex<Config>>) {
-    println!("設定ダイアログは Windows でのみ利用可能です");
+// トレイの通知 (バルーン) を表示する。systray には通知がないため、隠しウィンドウの通知領域アイコンを一時的に追加する
+#[cfg(target_os = "windows")]
+fn show_balloon(title: &str, message: &str) {
+    use winapi::um::shellapi::{Shell_NotifyIconW, NIF_ICON, NIF_INFO, NIIF_WARNING, NIM_ADD, NIM_DELETE, NOTIFYICONDATAW};
+    use winapi::um::winuser::{LoadIconW, IDI_WARNING};
+
+    // 末尾の NUL を残して UTF-16 で書き込む (入りきらない部分は切り捨てる)
+    fn copy_wide(target: &mut [u16], text: &str) {
+        let len = target.len() - 1;
+        for (slot, unit) in target[..len].iter_mut().zip(text.encode_utf16()) {
+            *slot = unit;
+        }
+    }
+
+    let title = title.to_string();
+    let message = message.to_string();
+    std::thread::spawn(move || {
+        if nwg::init().is_err() {
+            return;
+        }
+        let mut window = Default::default();
+        if nwg::Window::builder().flags(nwg::WindowFlags::WINDOW).title("File Agent").build(&mut window).is_err() {
+            return;
+        }
+        let Some(hwnd) = window.handle.hwnd() else {
+            return;
+        };
+        unsafe {
+            let mut data: NOTIFYICONDATAW = std::mem::zeroed();
+            data.cbSize = std::mem::size_of::<NOTIFYICONDATAW>() as u32;
+            data.hWnd = hwnd;
+            data.uID = 1;
+            data.uFlags = NIF_ICON | NIF_INFO;
+            data.hIcon = LoadIconW(std::ptr::null_mut(), IDI_WARNING);
+            data.dwInfoFlags = NIIF_WARNING;
+            copy_wide(&mut data.szInfoTitle, &title);
+            copy_wide(&mut data.szInfo, &message);
+            Shell_NotifyIconW(NIM_ADD, &mut data);
+            std::thread::sleep(std::time::Duration::from_secs(15));
+            Shell_NotifyIconW(NIM_DELETE, &mut data);
+        }
+    });
 }
 
+#[cfg(any(target_os = "windows", target_os = "macos", all(target_os = "linux", feature = "tray")))]
 fn restart_application() {
-    println!("アプリケーションを再起動します...");
+    log!("アプリケーションを再起動します...");
     
     let exe_path = std::env::current_exe().unwrap();
     let args: Vec<String> = std::env::args().collect();
@@ -812,41 +218,297 @@ fn restart_application() {
     std::process::exit(0);
 }
 
+// file_agent verify-audit [--log <path>] [--public-key <hex>]
+// 監査ログの署名と連鎖をオフラインで検証する。署名の検証には公開鍵だけを使う
+// (省略時は file_agent.ini の receipt_key から導出する)。ログの省略時は既定のログを使う
+fn verify_audit(args: &[String]) -> i32 {
+    let mut log_path = None;
+    let mut public_key = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--log" => log_path = args.next().map(PathBuf::from),
+            "--public-key" => public_key = args.next().cloned(),
+            _ => {
+                eprintln!("使い方: file_agent verify-audit [--log <path>] [--public-key <hex>]");
+                return 2;
+            }
+        }
+    }
+    let log_path = log_path.unwrap_or_else(Config::get_audit_log_path);
+    let public_key = match public_key {
+        Some(public_key) => public_key,
+        None => match Config::load() {
+            Ok(config) if config.receipt_key.is_empty() => {
+                eprintln!("❌ receipt_key が設定されていません (--public-key で公開鍵を指定してください)");
+                return 2;
+            }
+            Ok(config) => audit::receipt_public_key(&config.receipt_key),
+            Err(e) => {
+                eprintln!("❌ 設定ファイルに誤りがあります:\n{}", e);
+                return 2;
+            }
+        },
+    };
+
+    let report = match audit::verify_log(&log_path, &public_key) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("❌ 監査ログを検証できません: {}", e);
+            return 2;
+        }
+    };
+    println!("監査ログ: {}", log_path.display());
+    println!("  エントリ数: {} (署名付き: {})", report.entries, report.signed);
+    if let Some(last) = &report.last_signed {
+        println!("  最新の署名付きエントリ: {} {} {} ({})", last.timestamp, last.action, last.path, last.signature.as_deref().unwrap_or_default());
+    }
+    if report.errors.is_empty() {
+        println!("✅ 署名と連鎖はすべて正常です");
+        0
+    } else {
+        for error in &report.errors {
+            println!("❌ {}", error);
+        }
+        1
+    }
+}
+
+// file_agent regenerate-token
+// メインのトークンを作り直して平文を一度だけ表示する (実行中のエージェントは設定ファイルの監視で反映する)
+fn regenerate_token() -> i32 {
+    let Some(mut config) = load_config() else {
+        return 1;
+    };
+    if config.env_overrides.iter().any(|key| key == "token_hash") {
+        eprintln!("❌ トークンは環境変数 FILE_AGENT_TOKEN で指定されています");
+        return 1;
+    }
+    config.regenerate_token();
+    match config.save() {
+        Ok(_) => {
+            println!("実行中のエージェントには数秒で反映されます");
+            0
+        }
+        Err(e) => {
+            eprintln!("❌ 設定の保存に失敗しました: {}", e);
+            1
+        }
+    }
+}
+
+// 設定を読み込む。誤りがあれば誤りのある行をすべて表示する
+fn load_config() -> Option<Config> {
+    match Config::load() {
+        Ok(config) => Some(config),
+        Err(e) => {
+            log_error!("❌ 設定ファイルに誤りがあります:");
+            for error in &e.errors {
+                log_error!("  {}", error);
+            }
+            None
+        }
+    }
+}
+
+// 設定で指定したログファイルと大きさに切り替える
+fn init_logging(config: &Config) {
+    logs::set_max_bytes(config.log_max_bytes);
+    let path = config.log_path();
+    if logs::path().as_ref() != Some(&path) {
+        logs::init(path);
+    }
+}
+
+// file_agent --stdio (標準出力は JSON-RPC の応答に使うため、表示は標準エラー出力に出す)
+fn run_stdio() -> i32 {
+    logs::use_stderr();
+    logs::init(Config::get_ini_path().with_file_name("file_agent.log"));
+    log!("File Agent starting (stdio)...");
+
+    let Some(mut config) = load_config() else {
+        return 1;
+    };
+    if config.ensure_token() {
+        let _ = config.save();
+    }
+    init_logging(&config);
+    for problem in config.validate() {
+        log!("⚠️ {}", problem);
+    }
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        tokio::spawn(request_shutdown_on_signal());
+        start_stdio_server(config).await;
+    });
+    // 停止した場合は標準入力の読み込みが残っているため、終わるのを待たない
+    rt.shutdown_background();
+    0
+}
+
+// file_agent --daemon (--headless): トレイを使わずに API サーバーだけを動かす (systemd / launchd 向け)。
+// SIGTERM / SIGINT / /api/shutdown で処理中のリクエストを終えてから終了コード 0、サーバーを起動できなければ 1 で終了する
+fn run_daemon() -> i32 {
+    logs::init(Config::get_ini_path().with_file_name("file_agent.log"));
+    log!("File Agent starting (daemon)...");
+
+    let Some(mut config) = load_config() else {
+        return 1;
+    };
+    if config.ensure_token() {
+        let _ = config.save();
+    }
+    init_logging(&config);
+    for problem in config.validate() {
+        log!("⚠️ {}", problem);
+    }
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        tokio::spawn(request_shutdown_on_signal());
+        start_api_server(config).await;
+        if shutdown::is_requested() {
+            0
+        } else {
+            1
+        }
+    })
+}
+
+// 終了を求めるシグナルを受け取ったら、サーバーに停止を要求する
+async fn request_shutdown_on_signal() {
+    let signal = shutdown_signal().await;
+    log!("🛑 {} を受け取ったため終了します", signal);
+    shutdown::request();
+}
+
+// 終了を求めるシグナルを待つ (受け取ったシグナルの名前を返す)
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                _ = terminate.recv() => "SIGTERM",
+                _ = tokio::signal::ctrl_c() => "SIGINT",
+            },
+            Err(e) => {
+                log_error!("⚠️ SIGTERM を受け取れません: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                "SIGINT"
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl+C"
+    }
+}
+
 fn main() {
-    println!("File Agent starting...");
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(|a| a.as_str()) {
+        Some("verify-audit") => std::process::exit(verify_audit(&args[2..])),
+        Some("regenerate-token") => std::process::exit(regenerate_token()),
+        Some("--stdio") => std::process::exit(run_stdio()),
+        Some("--daemon") | Some("--headless") => std::process::exit(run_daemon()),
+        _ => {}
+    }
+
+    logs::init(Config::get_ini_path().with_file_name("file_agent.log"));
+    log!("File Agent starting...");
     
-    let config = Arc::new(Mutex::new(Config::load()));
+    let Some(mut loaded) = load_config() else {
+        std::process::exit(1);
+    };
+    if loaded.ensure_token() {
+        let _ = loaded.save();
+    }
+    init_logging(&loaded);
+    // トレイとログに実際のポートを表示するため、先にポートを決める (待ち受けられない場合はサーバーの起動時に表示する)
+    let _ = server::resolve_port(&mut loaded);
+    let config = Arc::new(Mutex::new(loaded));
     let config_display = config.lock().unwrap().clone();
-    let token_hash = generate_token_hash(&config_display.token);
     
-    println!("設定:");
-    println!("  ポート: {}", config_display.port);
-    println!("  トークン: {}", config_display.token);
-    println!("  トークンハッシュ: {}", token_hash);
-    println!("  API サーバー: http://localhost:{}", config_display.port);
-    println!();
-
-    // APIサーバーを別スレッドで起動
+    log!("設定:");
+    log!("  ポート: {}", config_display.port);
+    log!("  トークンハッシュ: {}", config_display.token_hash);
+    if config_display.socket.is_empty() {
+        for listener in config_display.effective_listeners() {
+            let host = match listener.address.ip() {
+                address if address.is_loopback() => "localhost".to_string(),
+                std::net::IpAddr::V6(address) => format!("[{}]", address),
+                address => address.to_string(),
+            };
+            log!("  API サーバー: {}://{}:{}", listener.scheme(), host, listener.address.port());
+        }
+    } else {
+        log!("  API ソケット: {}", config_display.socket);
+    }
+    log!();
+    let problems = config_display.validate();
+    for problem in &problems {
+        log!("⚠️ {}", problem);
+    }
+
+    // APIサーバーを別スレッドで起動。Ctrl+C や /api/shutdown で停止したらプロセスを終了する
     let config_for_server = config_display.clone();
     std::thread::spawn(move || {
         let rt = tokio::runtime::Runtime::new().unwrap();
-        rt.block_on(start_api_server(config_for_server));
+        rt.block_on(async {
+            tokio::spawn(request_shutdown_on_signal());
+            start_api_server(config_for_server).await;
+            // サーバーを起動できなかった場合も、トレイから終了するまでは動かし続ける
+            shutdown::requested().await;
+        });
+        std::process::exit(0);
     });
 
+    autostart::sync(config_display.autostart);
+    run_tray(config, &problems);
+}
+
+// ログイン時の自動起動を登録 (または解除) し、autostart= を設定ファイルに保存する。登録後の状態を返す
+#[cfg(any(target_os = "windows", target_os = "macos", all(target_os = "linux", feature = "tray")))]
+fn set_autostart(enabled: bool) -> bool {
+    if let Err(e) = autostart::set_enabled(enabled) {
+        log_error!("⚠️ {}", e);
+        return autostart::is_enabled();
+    }
+    match Config::load() {
+        Ok(mut config) => {
+            config.autostart = enabled;
+            if let Err(e) = config.save() {
+                log_error!("⚠️ 設定の保存に失敗しました: {}", e);
+            }
+        }
+        Err(e) => log_error!("⚠️ 設定ファイルに誤りがあるため autostart= を保存できません:\n{}", e),
+    }
+    if enabled {
+        log!("✅ ログイン時に自動起動するよう登録しました");
+    } else {
+        log!("✅ ログイン時の自動起動の登録を解除しました");
+    }
+    enabled
+}
+
+// システムトレイ (Windows)
+#[cfg(target_os = "windows")]
+fn run_tray(config: Arc<Mutex<Config>>, problems: &[String]) {
     // システムトレイアプリケーションを作成
     let mut app = match Application::new() {
         Ok(app) => {
-            println!("✅ システムトレイアプリケーションを作成しました");
+            log!("✅ システムトレイアプリケーションを作成しました");
+            if !problems.is_empty() {
+                show_balloon("File Agent の設定を確認してください", &problems.join("\n"));
+            }
             app
         }
         Err(e) => {
-            eprintln!("❌ システムトレイの作成に失敗しました: {}", e);
-            eprintln!("コンソールモードで実行します。Ctrl+C で終了してください。");
-            
-            // フォールバック: 単純なループで待機
-            loop {
-                std::thread::sleep(std::time::Duration::from_secs(1));
-            }
+            log_error!("❌ システムトレイの作成に失敗しました: {}", e);
+            log_error!("コンソールモードで実行します。Ctrl+C で終了してください。");
+            wait_for_shutdown();
+            return;
         }
     };
 
@@ -859,7 +521,7 @@ fn main() {
         let exe_dir = exe_path.parent().unwrap_or_else(|| std::path::Path::new("."));
         let icon_in_exe_dir = exe_dir.join("icon.ico");
         if icon_in_exe_dir.exists() {
-            println!("アイコンパス: {}", icon_in_exe_dir.display());
+            log!("アイコンパス: {}", icon_in_exe_dir.display());
             icon_in_exe_dir.to_string_lossy().to_string()
         } else {
             "icon.ico".to_string()
@@ -867,51 +529,325 @@ fn main() {
     };
     
     if let Err(e) = app.set_icon_from_file(&icon_path) {
-        println!("⚠️ アイコンの設定に失敗しました: {}", e);
+        log!("⚠️ アイコンの設定に失敗しました: {}", e);
         // デフォルトアイコンを設定してみる
         if let Err(e2) = app.set_icon_from_resource(&"IDI_APPLICATION") {
-            println!("⚠️ デフォルトアイコンの設定も失敗: {}", e2);
+            log!("⚠️ デフォルトアイコンの設定も失敗: {}", e2);
         }
     } else {
-        println!("✅ アイコンを設定しました: {}", icon_path);
+        log!("✅ アイコンを設定しました: {}", icon_path);
     }
 
     // ツールチップを設定
-    let _ = app.set_tooltip("File Agent");
+    let _ = app.set_tooltip(&tray_title(&config.lock().unwrap()));
 
     // メニューアイテムを追加
     let config_clone = config.clone();
     if let Err(e) = app.add_menu_item("設定", move |_| {
-        println!("設定メニューが選択されました");
+        log!("設定メニューが選択されました");
         show_config_dialog(config_clone.clone());
         Ok::<_, systray::Error>(())
     }) {
-        println!("⚠️ 設定メニューの追加に失敗: {}", e);
+        log!("⚠️ 設定メニューの追加に失敗: {}", e);
+    }
+
+    if let Err(e) = app.add_menu_item("ログを表示", |_| {
+        log!("ログ表示メニューが選択されました");
+        match logs::path() {
+            Some(path) => {
+                if let Err(e) = logs::open_in_editor(&path) {
+                    log_error!("⚠️ ログを開けませんでした: {}", e);
+                }
+            }
+            None => log!("⚠️ ログファイルが開かれていません"),
+        }
+        Ok::<_, systray::Error>(())
+    }) {
+        log!("⚠️ ログ表示メニューの追加に失敗: {}", e);
+    }
+
+    // systray のメニューにはチェックを付けられないため、選ぶたびに切り替えて通知で知らせる
+    if let Err(e) = app.add_menu_item("ログイン時に起動 (オン/オフ)", |_| {
+        log!("自動起動メニューが選択されました");
+        let message = if set_autostart(!autostart::is_enabled()) {
+            "ログイン時に自動起動します"
+        } else {
+            "ログイン時に自動起動しません"
+        };
+        show_balloon("File Agent", message);
+        Ok::<_, systray::Error>(())
+    }) {
+        log!("⚠️ 自動起動メニューの追加に失敗: {}", e);
     }
 
     if let Err(e) = app.add_menu_separator() {
-        println!("⚠️ セパレーターの追加に失敗: {}", e);
+        log!("⚠️ セパレーターの追加に失敗: {}", e);
     }
 
     if let Err(e) = app.add_menu_item("再起動", |_| {
-        println!("再起動メニューが選択されました");
+        log!("再起動メニューが選択されました");
         restart_application();
         Ok::<_, systray::Error>(())
     }) {
-        println!("⚠️ 再起動メニューの追加に失敗: {}", e);
+        log!("⚠️ 再起動メニューの追加に失敗: {}", e);
     }
 
     if let Err(e) = app.add_menu_item("終了", |window| {
-        println!("終了メニューが選択されました");
+        log!("終了メニューが選択されました");
         window.quit();
         Ok::<_, systray::Error>(())
     }) {
-        println!("⚠️ 終了メニューの追加に失敗: {}", e);
+        log!("⚠️ 終了メニューの追加に失敗: {}", e);
     }
 
-    println!("🔧 システムトレイで実行中...");
-    println!("   右クリックでメニューが表示されます");
+    log!("🔧 システムトレイで実行中...");
+    log!("   右クリックでメニューが表示されます");
 
     // イベントループを実行
     app.wait_for_message().unwrap();
-}
\ No newline at end of file
+    stop_server();
+}
+
+// Linux のトレイ (StatusNotifierItem / AppIndicator)。デスクトップのセッションがなければトレイなしで動かす
+#[cfg(all(target_os = "linux", feature = "tray"))]
+fn run_tray(config: Arc<Mutex<Config>>, problems: &[String]) {
+    use ksni::menu::{CheckmarkItem, StandardItem};
+
+    struct AgentTray {
+        title: String,
+        problems: Vec<String>,
+        autostart: bool,
+    }
+
+    impl ksni::Tray for AgentTray {
+        fn id(&self) -> String {
+            "file_agent".to_string()
+        }
+
+        fn title(&self) -> String {
+            "File Agent".to_string()
+        }
+
+        fn icon_name(&self) -> String {
+            if self.problems.is_empty() { "folder-remote" } else { "dialog-warning" }.to_string()
+        }
+
+        fn tool_tip(&self) -> ksni::ToolTip {
+            let description = if self.problems.is_empty() {
+                String::new()
+            } else {
+                format!("設定を確認してください\n{}", self.problems.join("\n"))
+            };
+            ksni::ToolTip {
+                title: self.title.clone(),
+                description,
+                ..Default::default()
+            }
+        }
+
+        fn menu(&self) -> Vec<ksni::MenuItem<Self>> {
+            vec![
+                StandardItem {
+                    label: "設定".to_string(),
+                    // 設定ファイルをエディターで開く (保存すると自動的に反映される)
+                    activate: Box::new(|_| {
+                        log!("設定メニューが選択されました");
+                        if let Err(e) = logs::open_in_editor(&Config::get_ini_path()) {
+                            log_error!("⚠️ 設定ファイルを開けませんでした: {}", e);
+                        }
+                    }),
+                    ..Default::default()
+                }
+                .into(),
+                StandardItem {
+                    label: "ログを表示".to_string(),
+                    activate: Box::new(|_| {
+                        log!("ログ表示メニューが選択されました");
+                        match logs::path() {
+                            Some(path) => {
+                                if let Err(e) = logs::open_in_editor(&path) {
+                                    log_error!("⚠️ ログを開けませんでした: {}", e);
+                                }
+                            }
+                            None => log!("⚠️ ログファイルが開かれていません"),
+                        }
+                    }),
+                    ..Default::default()
+                }
+                .into(),
+                CheckmarkItem {
+                    label: "ログイン時に起動".to_string(),
+                    checked: self.autostart,
+                    activate: Box::new(|tray: &mut Self| {
+                        tray.autostart = set_autostart(!tray.autostart);
+                    }),
+                    ..Default::default()
+                }
+                .into(),
+                ksni::MenuItem::Separator,
+                StandardItem {
+                    label: "再起動".to_string(),
+                    activate: Box::new(|_| {
+                        log!("再起動メニューが選択されました");
+                        restart_application();
+                    }),
+                    ..Default::default()
+                }
+                .into(),
+                StandardItem {
+                    label: "終了".to_string(),
+                    activate: Box::new(|_| {
+                        log!("終了メニューが選択されました");
+                        stop_server();
+                        std::process::exit(0);
+                    }),
+                    ..Default::default()
+                }
+                .into(),
+            ]
+        }
+    }
+
+    if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+        log!("デスクトップのセッションがないため、トレイなしで実行します。Ctrl+C で終了してください。");
+        wait_for_shutdown();
+        return;
+    }
+    let service = ksni::TrayService::new(AgentTray {
+        title: tray_title(&config.lock().unwrap()),
+        problems: problems.to_vec(),
+        autostart: autostart::is_enabled(),
+    });
+    log!("🔧 システムトレイで実行中...");
+    if let Err(e) = service.run() {
+        log_error!("❌ システムトレイの作成に失敗しました: {}", e);
+        log_error!("トレイなしで実行します。Ctrl+C で終了してください。");
+        wait_for_shutdown();
+    }
+}
+
+// トレイのないその他の OS (Linux で tray の機能を外してビルドした場合も)
+#[cfg(not(any(target_os = "windows", target_os = "macos", all(target_os = "linux", feature = "tray"))))]
+fn run_tray(_config: Arc<Mutex<Config>>, _problems: &[String]) {
+    log!("トレイなしで実行します。Ctrl+C で終了してください。");
+    wait_for_shutdown();
+}
+
+// トレイのツールチップに表示する名前 (ポートで待ち受ける場合はポート番号を含める)
+#[cfg(any(target_os = "windows", target_os = "macos", all(target_os = "linux", feature = "tray")))]
+fn tray_title(config: &Config) -> String {
+    if config.socket.is_empty() {
+        let ports: Vec<String> = config.effective_listeners().iter().map(|listener| listener.address.port().to_string()).collect();
+        format!("File Agent (ポート {})", ports.join(", "))
+    } else {
+        "File Agent".to_string()
+    }
+}
+
+// トレイを使えないときに、終了のシグナルか /api/shutdown で停止するまで待つ
+fn wait_for_shutdown() {
+    let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    rt.block_on(shutdown::requested());
+    shutdown::wait_for_servers(STOP_WAIT);
+}
+
+// トレイの終了メニュー: サーバーに停止を要求し、処理中のリクエストが終わるまで待つ
+#[cfg(any(target_os = "windows", target_os = "macos", all(target_os = "linux", feature = "tray")))]
+fn stop_server() {
+    shutdown::request();
+    if !shutdown::wait_for_servers(STOP_WAIT) {
+        log_error!("⚠️ サーバーの停止を待たずに終了します");
+    }
+}
+
+// macOS のメニューバー (systray は macOS のメニューバーに対応していないため tray-icon を使う)
+#[cfg(target_os = "macos")]
+fn run_tray(config: Arc<Mutex<Config>>, problems: &[String]) {
+    use tao::event::{Event, StartCause};
+    use tao::event_loop::{ControlFlow, EventLoopBuilder};
+    use tao::platform::macos::{ActivationPolicy, EventLoopExtMacOS};
+    use tray_icon::menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+    use tray_icon::TrayIconBuilder;
+
+    let mut event_loop = EventLoopBuilder::<MenuEvent>::with_user_event().build();
+    // Dock にはアイコンを出さず、メニューバーだけに表示する
+    event_loop.set_activation_policy(ActivationPolicy::Accessory);
+    let proxy = event_loop.create_proxy();
+    MenuEvent::set_event_handler(Some(move |event| {
+        let _ = proxy.send_event(event);
+    }));
+
+    let settings = MenuItem::new("設定", true, None);
+    let show_log = MenuItem::new("ログを表示", true, None);
+    let login_item = CheckMenuItem::new("ログイン時に起動", true, autostart::is_enabled(), None);
+    let restart = MenuItem::new("再起動", true, None);
+    let quit = MenuItem::new("終了", true, None);
+    let menu = Menu::new();
+    if let Err(e) = menu.append_items(&[&settings, &show_log, &login_item, &PredefinedMenuItem::separator(), &restart, &quit]) {
+        log!("⚠️ メニューの追加に失敗: {}", e);
+    }
+
+    // 設定に問題があればタイトルに印を付け、ツールチップに内容を表示する
+    let name = tray_title(&config.lock().unwrap());
+    let (title, tooltip) = if problems.is_empty() {
+        ("File Agent".to_string(), name)
+    } else {
+        ("File Agent ⚠️".to_string(), format!("{}\n設定を確認してください\n{}", name, problems.join("\n")))
+    };
+    let mut menu = Some(menu);
+    let mut tray = None;
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Wait;
+        match event {
+            // メニューバーのアイコンはイベントループが始まってから作る
+            Event::NewEvents(StartCause::Init) => {
+                let builder = TrayIconBuilder::new().with_title(&title).with_tooltip(&tooltip);
+                let builder = match menu.take() {
+                    Some(menu) => builder.with_menu(Box::new(menu)),
+                    None => builder,
+                };
+                match builder.build() {
+                    Ok(icon) => {
+                        log!("✅ メニューバーに追加しました");
+                        log!("🔧 メニューバーで実行中...");
+                        tray = Some(icon);
+                    }
+                    Err(e) => log_error!("❌ メニューバーへの追加に失敗しました: {}", e),
+                }
+            }
+            Event::UserEvent(event) => {
+                if event.id() == settings.id() {
+                    // 設定ファイルをエディターで開く (保存すると自動的に反映される)
+                    log!("設定メニューが選択されました");
+                    if let Err(e) = logs::open_in_editor(&Config::get_ini_path()) {
+                        log_error!("⚠️ 設定ファイルを開けませんでした: {}", e);
+                    }
+                } else if event.id() == show_log.id() {
+                    log!("ログ表示メニューが選択されました");
+                    match logs::path() {
+                        Some(path) => {
+                            if let Err(e) = logs::open_in_editor(&path) {
+                                log_error!("⚠️ ログを開けませんでした: {}", e);
+                            }
+                        }
+                        None => log!("⚠️ ログファイルが開かれていません"),
+                    }
+                } else if event.id() == login_item.id() {
+                    // チェックはクリックで切り替わっている。登録できなければ元に戻す
+                    log!("自動起動メニューが選択されました");
+                    let enabled = set_autostart(login_item.is_checked());
+                    login_item.set_checked(enabled);
+                } else if event.id() == restart.id() {
+                    log!("再起動メニューが選択されました");
+                    restart_application();
+                } else if event.id() == quit.id() {
+                    log!("終了メニューが選択されました");
+                    stop_server();
+                    tray.take();
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            _ => {}
+        }
+    });
+}
@@ -0,0 +1,87 @@
+//! mDNS (Bonjour) でのエージェントの公開
+//!
+//! mdns=true の場合、同じ LAN のアプリが IP アドレスとポートを入力せずに見つけられるよう
+//! `_fileagent._tcp.local.` として待ち受けているポートを公開する。
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::listener::Listener;
+
+const SERVICE_TYPE: &str = "_fileagent._tcp.local.";
+
+// 終了時に公開の取り下げ (goodbye) を送り終えるまで待つ時間
+const UNREGISTER_WAIT: Duration = Duration::from_secs(1);
+
+/// 公開中のサービス。破棄すると公開を取り下げる
+pub(crate) struct Advertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+/// 公開するサービスの情報を作る。mdns=false の場合や、ループバックだけで待ち受けていて LAN から接続できない場合は None
+/// (ループバックだけの場合は validate で警告する)。複数の待ち受けがある場合は、最初のループバック以外のものを公開する
+pub(crate) fn service(config: &Config, listeners: &[Listener]) -> Option<ServiceInfo> {
+    if !config.mdns {
+        return None;
+    }
+    let listener = listeners.iter().find(|listener| !listener.address.ip().is_loopback())?;
+    let (address, port) = (listener.address.ip(), listener.address.port());
+
+    // SRV のホスト名は OS のホスト名と重ならないよう agent_id から作る
+    let id = config.agent_id.split('-').next().unwrap_or_default();
+    let host_name = format!("file-agent-{}.local.", id);
+    let instance = std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| format!("file-agent-{}", id));
+    // アプリが接続方法とペアリングの手順を判断できるよう TXT に載せる
+    let tls = if listener.tls { "true" } else { "false" };
+    let properties = [
+        ("agent_id", config.agent_id.as_str()),
+        ("version", env!("CARGO_PKG_VERSION")),
+        ("tls", tls),
+        ("api", "/api/v1"),
+        ("pair", "/api/v1/clients/pair"),
+    ];
+    // すべてのアドレスで待ち受ける場合は、各ネットワークインターフェースのアドレスを公開する
+    let service = if address.is_unspecified() {
+        ServiceInfo::new(SERVICE_TYPE, &instance, &host_name, (), port, &properties[..]).map(ServiceInfo::enable_addr_auto)
+    } else {
+        ServiceInfo::new(SERVICE_TYPE, &instance, &host_name, address, port, &properties[..])
+    };
+    match service {
+        Ok(service) => Some(service),
+        Err(e) => {
+            log_error!("⚠️ mDNS で公開できません: {}", e);
+            None
+        }
+    }
+}
+
+/// サービスを公開する (待ち受けを始めてから呼ぶ)
+pub(crate) fn advertise(service: ServiceInfo) -> Option<Advertisement> {
+    let fullname = service.get_fullname().to_string();
+    let result = ServiceDaemon::new().and_then(|daemon| daemon.register(service).map(|_| daemon));
+    match result {
+        Ok(daemon) => {
+            log!("📡 mDNS で公開しました: {}", fullname);
+            Some(Advertisement { daemon, fullname })
+        }
+        Err(e) => {
+            log_error!("⚠️ mDNS で公開できません: {}", e);
+            None
+        }
+    }
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        // アプリの一覧から消えるよう、取り下げを送ってから止める
+        if let Ok(status) = self.daemon.unregister(&self.fullname) {
+            let _ = status.recv_timeout(UNREGISTER_WAIT);
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
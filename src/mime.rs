@@ -0,0 +1,217 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use utoipa::ToSchema;
+
+// 拡張子 → MIME タイプ対応表
+const EXTENSION_TYPES: &[(&str, &str)] = &[
+    ("txt", "text/plain"),
+    ("log", "text/plain"),
+    ("ini", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("json", "application/json"),
+    ("xml", "application/xml"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("toml", "application/toml"),
+    ("rs", "text/x-rust"),
+    ("py", "text/x-python"),
+    ("c", "text/x-c"),
+    ("h", "text/x-c"),
+    ("cpp", "text/x-c++"),
+    ("java", "text/x-java"),
+    ("ts", "text/x-typescript"),
+    ("sh", "application/x-sh"),
+    ("bat", "application/x-bat"),
+    ("ps1", "text/plain"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("bmp", "image/bmp"),
+    ("webp", "image/webp"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/x-icon"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("ogg", "audio/ogg"),
+    ("flac", "audio/flac"),
+    ("m4a", "audio/mp4"),
+    ("aac", "audio/aac"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("avi", "video/x-msvideo"),
+    ("mov", "video/quicktime"),
+    ("mkv", "video/x-matroska"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("7z", "application/x-7z-compressed"),
+    ("rar", "application/vnd.rar"),
+    ("doc", "application/msword"),
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+    ("xls", "application/vnd.ms-excel"),
+    ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    ("pptx", "application/vnd.openxmlformats-officedocument.presentationml.presentation"),
+    ("exe", "application/vnd.microsoft.portable-executable"),
+    ("dll", "application/vnd.microsoft.portable-executable"),
+    ("wasm", "application/wasm"),
+];
+
+pub const OCTET_STREAM: &str = "application/octet-stream";
+
+/// 拡張子から MIME タイプを推定する (不明な場合は application/octet-stream)
+pub fn from_extension(path: &Path) -> &'static str {
+    let ext = match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => ext.to_lowercase(),
+        None => return OCTET_STREAM,
+    };
+    EXTENSION_TYPES
+        .iter()
+        .find(|(e, _)| *e == ext)
+        .map(|(_, mime)| *mime)
+        .unwrap_or(OCTET_STREAM)
+}
+
+// 判定に使う先頭バイト数
+pub const SNIFF_LEN: usize = 8192;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Detection {
+    pub mime_type: String,
+    pub is_binary: bool,
+    pub detected_by: String, // "magic" | "content" | "extension"
+}
+
+// (オフセット, シグネチャ, MIME タイプ)
+const MAGIC_NUMBERS: &[(usize, &[u8], &str)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (0, b"BM", "image/bmp"),
+    (0, b"\x00\x00\x01\x00", "image/x-icon"),
+    (0, b"II*\x00", "image/tiff"),
+    (0, b"MM\x00*", "image/tiff"),
+    (8, b"WEBP", "image/webp"),
+    (8, b"WAVE", "audio/wav"),
+    (8, b"AVI ", "video/x-msvideo"),
+    (0, b"ID3", "audio/mpeg"),
+    (0, b"\xff\xfb", "audio/mpeg"),
+    (0, b"\xff\xf3", "audio/mpeg"),
+    (0, b"OggS", "audio/ogg"),
+    (0, b"fLaC", "audio/flac"),
+    (4, b"ftypM4A", "audio/mp4"),
+    (4, b"ftypqt", "video/quicktime"),
+    (4, b"ftyp", "video/mp4"),
+    (0, b"\x1a\x45\xdf\xa3", "video/x-matroska"),
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"PK\x05\x06", "application/zip"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (0, b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (0, b"Rar!\x1a\x07", "application/vnd.rar"),
+    (257, b"ustar", "application/x-tar"),
+    (0, b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1", "application/x-ole-storage"),
+    (0, b"SQLite format 3\x00", "application/vnd.sqlite3"),
+    (0, b"MZ", "application/vnd.microsoft.portable-executable"),
+    (0, b"\x7fELF", "application/x-elf"),
+    (0, b"\x00asm", "application/wasm"),
+];
+
+const WEAK_MAGIC_TYPES: &[&str] = &["image/bmp", "audio/mpeg", "application/vnd.microsoft.portable-executable"];
+
+/// 先頭バイトのマジックナンバーから MIME タイプを判定する
+pub fn sniff(head: &[u8]) -> Option<&'static str> {
+    MAGIC_NUMBERS
+        .iter()
+        .find(|(offset, magic, _)| head.len() >= offset + magic.len() && &head[*offset..offset + magic.len()] == *magic)
+        .map(|(_, _, mime)| *mime)
+}
+
+/// NUL バイトを含む、または UTF-8 として解釈できない内容をバイナリとみなす
+pub fn looks_binary(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        // UTF-16 の BOM 付きテキストは除外
+        return !(head.starts_with(b"\xff\xfe") || head.starts_with(b"\xfe\xff"));
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => false,
+        // 読み込み範囲の末尾で途切れたマルチバイト文字は許容する
+        Err(e) => e.error_len().is_some(),
+    }
+}
+
+fn is_text_type(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || matches!(
+            mime,
+            "application/json" | "application/xml" | "application/yaml" | "application/toml"
+                | "application/x-sh" | "application/x-bat" | "image/svg+xml"
+        )
+}
+
+/// ファイル内容 (先頭部分) と拡張子から MIME タイプを判定する
+pub fn detect(path: &Path, head: &[u8]) -> Detection {
+    let by_extension = from_extension(path);
+    let binary = looks_binary(head);
+
+    // 短いシグネチャ ("BM", "MZ" など) はテキストと衝突しやすいため、バイナリの場合のみ採用
+    let magic = sniff(head).filter(|mime| binary || !WEAK_MAGIC_TYPES.contains(mime));
+
+    if let Some(mime) = magic {
+        // docx/xlsx などの ZIP/OLE コンテナは拡張子の方が具体的
+        let container = matches!(mime, "application/zip" | "application/x-ole-storage");
+        let mime = if container && by_extension != OCTET_STREAM && !is_text_type(by_extension) {
+            by_extension
+        } else {
+            mime
+        };
+        return Detection {
+            mime_type: mime.to_string(),
+            is_binary: true,
+            detected_by: "magic".to_string(),
+        };
+    }
+
+    if binary {
+        return Detection {
+            mime_type: by_extension.to_string(),
+            is_binary: true,
+            detected_by: "extension".to_string(),
+        };
+    }
+
+    if is_text_type(by_extension) {
+        return Detection {
+            mime_type: by_extension.to_string(),
+            is_binary: false,
+            detected_by: "extension".to_string(),
+        };
+    }
+
+    let text = String::from_utf8_lossy(&head[..head.len().min(512)]).trim_start().to_lowercase();
+    let mime = if text.starts_with("<?xml") {
+        "application/xml"
+    } else if text.starts_with("<!doctype html") || text.starts_with("<html") {
+        "text/html"
+    } else if text.starts_with('{') || text.starts_with('[') {
+        "application/json"
+    } else {
+        "text/plain"
+    };
+    Detection {
+        mime_type: mime.to_string(),
+        is_binary: false,
+        detected_by: "content".to_string(),
+    }
+}
@@ -0,0 +1,156 @@
+use crate::handlers::ENDPOINTS;
+use std::sync::Arc;
+use utoipa::openapi::path::Operation;
+use utoipa::openapi::schema::{Object, ObjectBuilder, Type};
+use utoipa::openapi::{ContentBuilder, Ref, ResponseBuilder};
+use utoipa::{Modify, OpenApi};
+use warp::http::{StatusCode, Uri};
+use warp::path::{FullPath, Tail};
+use warp::{Rejection, Reply};
+
+// Swagger UI を表示するパス (末尾の / がないと相対パスのファイルを読み込めない)
+const DOCS_PATH: &str = "/api/docs/";
+
+/// API の OpenAPI 3 ドキュメント (/api/openapi.json で返す)
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "file_agent API",
+        description = "Local file agent. Requests carry the API token in the JSON body (POST) or the token query parameter (GET, HEAD, and PUT /api/file). \
+                       Operations return HTTP 200 with success=false on errors; the tag of each endpoint is the operation the token must allow.",
+    ),
+    paths(
+        crate::handlers::health_check,
+        crate::handlers::version_info,
+        crate::handlers::get_capabilities,
+        crate::handlers::read_file,
+        crate::handlers::read_binary_file,
+        crate::handlers::read_file_chunk,
+        crate::handlers::detect_mime,
+        crate::handlers::hash_file,
+        crate::handlers::write_file,
+        crate::handlers::write_binary_file,
+        crate::handlers::upload_file,
+        crate::handlers::download_file,
+        crate::handlers::delete_file,
+        crate::handlers::list_directory,
+        crate::handlers::list_directory_stream,
+        crate::handlers::search_files,
+        crate::handlers::search_files_stream,
+        crate::handlers::grep_files,
+        crate::handlers::index_search,
+        crate::handlers::stale_report,
+        crate::handlers::dir_size,
+        crate::handlers::create_file_or_directory,
+        crate::handlers::move_file,
+        crate::handlers::copy_file,
+        crate::handlers::list_jobs,
+        crate::handlers::paste_from_clipboard,
+        crate::handlers::get_clipboard,
+        crate::handlers::set_clipboard,
+        crate::handlers::print_document,
+        crate::handlers::run_cleanup,
+        crate::handlers::poll_changes,
+        crate::handlers::list_clients,
+        crate::handlers::pair_client,
+        crate::handlers::remove_client,
+        crate::handlers::get_metrics,
+        crate::handlers::tail_logs,
+        crate::handlers::rotate_token,
+        crate::handlers::shutdown_agent,
+        crate::handlers::vault_status,
+        crate::handlers::vault_unlock,
+        crate::handlers::vault_lock,
+        crate::handlers::vault_rotate,
+        crate::handlers::list_trash,
+        crate::handlers::purge_trash,
+        crate::handlers::list_plugins,
+        crate::handlers::transform_file,
+        crate::handlers::call_plugin,
+        crate::handlers::run_script,
+        crate::handlers::run_exec,
+        crate::handlers::git_status,
+        crate::handlers::git_diff,
+        crate::handlers::git_log,
+        crate::handlers::s3_upload,
+        crate::handlers::s3_download,
+        crate::handlers::s3_list,
+        crate::handlers::get_sync_status,
+        crate::handlers::get_shares,
+    ),
+    // レスポンスの説明で参照する data の型 (ハッシュ付きの読み込み、競合、不正なパス、スキャンでの拒否、バックグラウンドのコピー)
+    components(schemas(crate::handlers::ReadWithHash, crate::handlers::HashConflict, crate::paths::InvalidPath, crate::scan::ContentRejected, crate::jobs::CopyJob)),
+    modifiers(&CommonResponses),
+)]
+pub struct ApiDoc;
+
+// 各エンドポイントに必要な操作のタグと、リクエストの処理前に返すエラー (403 / 411 / 413 / 429) を加える
+struct CommonResponses;
+
+impl Modify for CommonResponses {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.schemas.insert(
+            "ErrorResponse".to_string(),
+            ObjectBuilder::new()
+                .property("success", Object::with_type(Type::Boolean))
+                .property("error", Object::with_type(Type::String))
+                .property("retry_after_secs", Object::with_type(Type::Integer))
+                .required("success")
+                .required("error")
+                .into(),
+        );
+
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            for (method, operation) in [("GET", &mut item.get), ("POST", &mut item.post), ("PUT", &mut item.put)] {
+                if let Some(operation) = operation {
+                    add_common(operation, path, method);
+                }
+            }
+        }
+    }
+}
+
+fn add_common(operation: &mut Operation, path: &str, method: &str) {
+    let tag = ENDPOINTS
+        .iter()
+        .find(|&&(m, p, _)| m == method && p == path)
+        .and_then(|&(_, _, required)| required)
+        .map(|required| required.name())
+        .unwrap_or("agent");
+    operation.tags = Some(vec![tag.to_string()]);
+
+    let mut errors = vec![
+        ("403", "The client address is not in allowed_ips"),
+        ("429", "Rate limit exceeded; retry after retry_after_secs (also sent as Retry-After)"),
+        ("503", "Too many concurrent requests or heavy operations (busy: true); retry after retry_after_secs"),
+        ("504", "A file system operation did not finish within request_timeout_secs"),
+    ];
+    if method == "POST" || method == "PUT" {
+        errors.push(("411", "Content-Length header is missing"));
+        errors.push(("413", "Request body exceeds max_body_bytes"));
+    }
+    for (status, description) in errors {
+        let response = ResponseBuilder::new()
+            .description(description)
+            .content(
+                "application/json",
+                ContentBuilder::new().schema(Some(Ref::from_schema_name("ErrorResponse"))).build(),
+            )
+            .build();
+        operation.responses.responses.insert(status.to_string(), response.into());
+    }
+}
+
+/// 同梱の Swagger UI のファイルを返す (/api/docs は /api/docs/ にリダイレクトする)。config が None なら表示しない
+pub async fn serve_docs(full: FullPath, tail: Tail, config: Option<Arc<utoipa_swagger_ui::Config<'static>>>) -> Result<warp::reply::Response, Rejection> {
+    let config = config.ok_or_else(warp::reject::not_found)?;
+    if full.as_str() == DOCS_PATH.trim_end_matches('/') {
+        return Ok(warp::redirect::found(Uri::from_static(DOCS_PATH)).into_response());
+    }
+    match utoipa_swagger_ui::serve(tail.as_str(), config) {
+        Ok(Some(file)) => Ok(warp::reply::with_header(file.bytes.to_vec(), "content-type", file.content_type).into_response()),
+        Ok(None) => Err(warp::reject::not_found()),
+        Err(e) => Ok(warp::reply::with_status(e.to_string(), StatusCode::INTERNAL_SERVER_ERROR).into_response()),
+    }
+}
@@ -0,0 +1,58 @@
+//! エージェントから外へ出す HTTP リクエスト (Webhook のフック・S3・WebDAV) で共有するクライアント
+//!
+//! proxy= があればそのプロキシを通し (no_proxy= のホストは直接つなぐ)、なければ環境変数
+//! (HTTPS_PROXY・HTTP_PROXY・NO_PROXY) と OS のプロキシ設定に従う。クライアントは起動時に 1 度だけ作る。
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::config::Config;
+
+// 接続を待つ時間 (応答を待つ時間はリクエストごとに決める)
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+/// 設定から共有のクライアントを作る。proxy= が不正ならログに出し、環境変数と OS の設定を使う
+pub fn init(config: &Config) {
+    if CLIENT.get().is_some() {
+        return;
+    }
+    let client = match build(&config.proxy, &config.no_proxy) {
+        Ok(client) => client,
+        Err(e) => {
+            log_error!("⚠️ proxy= の設定が不正です ({}): 環境変数と OS のプロキシ設定を使います", e);
+            default_client()
+        }
+    };
+    if !config.proxy.is_empty() {
+        log!("🌐 外へのリクエストはプロキシ {} を通します", redact(&config.proxy));
+    }
+    let _ = CLIENT.set(client);
+}
+
+/// proxy (空なら環境変数と OS の設定) を使うクライアントを作る
+pub(crate) fn build(proxy: &str, no_proxy: &str) -> Result<reqwest::Client, String> {
+    let mut builder = reqwest::Client::builder().connect_timeout(CONNECT_TIMEOUT);
+    if !proxy.is_empty() {
+        let proxy = reqwest::Proxy::all(proxy).map_err(|e| e.to_string())?.no_proxy(reqwest::NoProxy::from_string(no_proxy));
+        builder = builder.proxy(proxy);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// 共有のクライアント (init の前なら環境変数と OS の設定で作る)
+pub fn client() -> reqwest::Client {
+    CLIENT.get_or_init(default_client).clone()
+}
+
+fn default_client() -> reqwest::Client {
+    reqwest::Client::builder().connect_timeout(CONNECT_TIMEOUT).build().unwrap_or_default()
+}
+
+// ログに出す URL からユーザー名とパスワードを除く
+fn redact(proxy: &str) -> String {
+    match proxy.split_once("://").and_then(|(scheme, rest)| rest.split_once('@').map(|(_, host)| (scheme, host))) {
+        Some((scheme, host)) => format!("{}://***@{}", scheme, host),
+        None => proxy.to_string(),
+    }
+}
@@ -0,0 +1,124 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+// Windows の予約デバイス名 (拡張子付きでも予約扱い)
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// 不正なパスの詳細 (レスポンスの data に入る)
+#[derive(Debug, Serialize, ToSchema)]
+pub struct InvalidPath {
+    pub invalid_path: bool,
+    pub path: String,
+    pub reason: String,
+}
+
+fn invalid(raw: &str, reason: String) -> InvalidPath {
+    InvalidPath {
+        invalid_path: true,
+        path: raw.replace('\0', "\\0"),
+        reason,
+    }
+}
+
+fn is_reserved(name: &str) -> bool {
+    // "NUL.txt" や "CON " も予約名として扱われる
+    let stem = name.split('.').next().unwrap_or(name).trim_end_matches([' ', '.']);
+    RESERVED_NAMES.iter().any(|reserved| stem.eq_ignore_ascii_case(reserved))
+}
+
+/// クライアントから受け取ったパス文字列を検証する。
+/// 空文字・NUL 文字・".." を含むパスと、Windows の予約デバイス名を拒否する
+pub fn validate(raw: &str) -> Result<(), InvalidPath> {
+    if raw.trim().is_empty() {
+        return Err(invalid(raw, "path is empty".to_string()));
+    }
+    if raw.contains('\0') {
+        return Err(invalid(raw, "path contains a null byte".to_string()));
+    }
+
+    if raw.starts_with("\\\\.\\") || raw.starts_with("//./") {
+        return Err(invalid(raw, "device namespace paths are not allowed".to_string()));
+    }
+
+    // \\?\ の後ろは Windows がそのまま使うため、"/" は区切りにならず "." も解釈されない
+    if let Some(rest) = raw.strip_prefix("\\\\?\\") {
+        if rest.contains('/') {
+            return Err(invalid(raw, "'/' is not a separator in \\\\?\\ paths (use '\\')".to_string()));
+        }
+        if rest.split('\\').any(|part| part == ".") {
+            return Err(invalid(raw, "\\\\?\\ paths cannot contain '.'".to_string()));
+        }
+    }
+
+    // Windows 以外でも "\" を区切りとして扱い、どちらの形式のパスも同じ基準で確認する
+    for part in raw.split(['/', '\\']) {
+        if part == ".." {
+            return Err(invalid(raw, "path contains '..'".to_string()));
+        }
+        if is_reserved(part) {
+            return Err(invalid(raw, format!("'{}' is a reserved device name", part)));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(raw: &str) -> String {
+        validate(raw).expect_err(raw).reason
+    }
+
+    #[test]
+    fn ordinary_paths_are_accepted() {
+        for raw in ["/home/user/a.txt", r"C:\Users\me\notes..txt", "D:/work/...", r"\\server\share\file", "relative/CONSOLE.log", "nul-free.txt"] {
+            assert!(validate(raw).is_ok(), "{}", raw);
+        }
+    }
+
+    #[test]
+    fn empty_and_nul_paths_are_rejected() {
+        assert_eq!(reason(""), "path is empty");
+        assert_eq!(reason("   "), "path is empty");
+        assert_eq!(reason("/tmp/a\0b"), "path contains a null byte");
+        // data に入れるパスでは NUL を見える形にする
+        assert_eq!(validate("/tmp/a\0b").unwrap_err().path, "/tmp/a\\0b");
+    }
+
+    #[test]
+    fn parent_components_are_rejected_with_either_separator() {
+        for raw in ["/data/../etc/passwd", r"C:\data\..\Windows", "..", "a/..\\b"] {
+            assert_eq!(reason(raw), "path contains '..'", "{}", raw);
+        }
+    }
+
+    #[test]
+    fn device_namespace_paths_are_rejected() {
+        for raw in [r"\\.\PhysicalDrive0", "//./COM1", r"\\.\pipe\name"] {
+            assert_eq!(reason(raw), "device namespace paths are not allowed", "{}", raw);
+        }
+    }
+
+    #[test]
+    fn long_paths_must_use_backslashes_and_no_dot_components() {
+        assert!(validate(r"\\?\C:\Users\me\a.txt").is_ok());
+        assert!(validate(r"\\?\UNC\server\share\a.txt").is_ok());
+        assert_eq!(reason(r"\\?\C:/Users/me"), "'/' is not a separator in \\\\?\\ paths (use '\\')");
+        assert_eq!(reason(r"\\?\C:\Users\.\me"), "\\\\?\\ paths cannot contain '.'");
+        assert_eq!(reason(r"\\?\C:\Users\..\me"), "path contains '..'");
+    }
+
+    #[test]
+    fn reserved_device_names_are_rejected() {
+        for (raw, name) in [("/tmp/NUL", "NUL"), (r"C:\work\con.txt", "con.txt"), ("D:/lpt9.log.bak", "lpt9.log.bak"), ("/tmp/Com1 ", "Com1 "), ("aux./x", "aux.")] {
+            assert_eq!(reason(raw), format!("'{}' is a reserved device name", name), "{}", raw);
+        }
+        assert!(validate("/tmp/COM10").is_ok());
+        assert!(validate("/tmp/LPT0.txt").is_ok());
+    }
+}
@@ -0,0 +1,387 @@
+//! WASM プラグイン (plugin_dir=)
+//!
+//! plugin_dir= のフォルダにある .wasm を起動時に読み込み、プラグインが宣言したエンドポイント
+//! (POST /api/plugins/<プラグイン>/<エンドポイント>) とファイルの変換 (POST /api/plugins/transform) を加える。
+//! プラグインは呼び出しごとに新しいインスタンスを wasmtime のサンドボックスで実行する。ファイルには
+//! 下の file_agent モジュールの関数を通してだけ、許可ルート (トークンに許可ルートがあればその範囲) の中にアクセスできる。
+//!
+//! プラグインが公開するもの (i64 の戻り値は (位置 << 32) | 長さ):
+//!   memory
+//!   file_agent_alloc(len: i32) -> i32                 ホストが渡すデータの領域を確保する
+//!   file_agent_manifest() -> i64                      {"description": ..., "endpoints": [...], "transforms": [...]}
+//!   file_agent_handle(ptr: i32, len: i32) -> i64      {"endpoint": ..., "body": ...} を受け取り、JSON を返す
+//!   file_agent_transform(name_ptr, name_len, ptr, len) -> i64  ファイルの内容を変換する (transforms がなければ不要)
+//!
+//! プラグインが使える関数 (モジュール名 file_agent):
+//!   log(ptr, len)                                     エージェントのログに出す
+//!   fail(ptr, len)                                    呼び出しを失敗にする (理由は応答の error になる)
+//!   read_file(path_ptr, path_len) -> i64              ファイルの内容 (失敗したら -1)
+//!   write_file(path_ptr, path_len, ptr, len) -> i32   0 なら成功、-1 なら失敗
+//!   list_dir(path_ptr, path_len) -> i64               フォルダ内の名前の JSON 配列 (失敗したら -1)
+//!   last_error() -> i64                               直前に失敗した関数の理由
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use utoipa::ToSchema;
+use wasmtime::{Caller, Engine, Extern, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, Trap};
+
+use crate::sandbox::{self, Sandbox};
+
+// 1 回の呼び出しで使える燃料 (命令数の目安。無限ループのプラグインを止める)
+const FUEL_PER_CALL: u64 = 5_000_000_000;
+
+// プラグインが使えるメモリの上限
+const MAX_MEMORY_BYTES: usize = 256 * 1024 * 1024;
+
+const MODULE: &str = "file_agent";
+
+/// 読み込んだプラグイン (/api/plugins で返す)
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct PluginInfo {
+    pub name: String, // .wasm のファイル名 (拡張子なし)
+    pub description: String,
+    pub endpoints: Vec<String>,  // POST /api/plugins/<name>/<endpoint>
+    pub transforms: Vec<String>, // POST /api/plugins/transform の transform
+}
+
+// file_agent_manifest が返す JSON
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    endpoints: Vec<String>,
+    #[serde(default)]
+    transforms: Vec<String>,
+}
+
+// file_agent_handle に渡す JSON
+#[derive(Debug, Serialize)]
+struct HandleInput<'a> {
+    endpoint: &'a str,
+    body: &'a serde_json::Value,
+}
+
+// 1 回の呼び出しの状態
+struct HostState {
+    plugin: String,
+    sandbox: Option<Sandbox>, // マニフェストを読む間はファイルにアクセスできない
+    limits: StoreLimits,
+    last_error: String,
+    failure: Option<String>, // fail で設定された理由
+}
+
+impl HostState {
+    fn sandbox(&self) -> Result<&Sandbox, String> {
+        self.sandbox.as_ref().ok_or_else(|| "File access is not available while loading the plugin".to_string())
+    }
+}
+
+struct Plugin {
+    info: PluginInfo,
+    module: Module,
+}
+
+/// 読み込んだプラグインと、それを実行するエンジン
+pub struct PluginHost {
+    engine: Engine,
+    linker: Linker<HostState>,
+    plugins: Vec<Plugin>,
+}
+
+impl PluginHost {
+    /// dir にある .wasm をすべて読み込む (None ならプラグインなし)。読み込めないプラグインはログに出して飛ばす
+    pub fn load(dir: Option<&Path>) -> Self {
+        let mut engine_config = wasmtime::Config::new();
+        engine_config.consume_fuel(true);
+        let engine = Engine::new(&engine_config).expect("wasmtime engine with fuel");
+        let linker = host_functions(&engine).expect("plugin host functions");
+        let mut host = PluginHost { engine, linker, plugins: Vec::new() };
+
+        let Some(dir) = dir else {
+            return host;
+        };
+        let mut files: Vec<PathBuf> = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("wasm")))
+                .collect(),
+            Err(e) => {
+                log_error!("⚠️ プラグインのフォルダを読み込めません ({}): {}", dir.display(), e);
+                return host;
+            }
+        };
+        files.sort();
+        for file in files {
+            match host.load_plugin(&file) {
+                Ok(info) => log!(
+                    "🧩 プラグインを読み込みました: {} (エンドポイント: {} / 変換: {})",
+                    info.name,
+                    info.endpoints.len(),
+                    info.transforms.len()
+                ),
+                Err(e) => log_error!("⚠️ プラグインを読み込めません ({}): {}", file.display(), e),
+            }
+        }
+        host
+    }
+
+    fn load_plugin(&mut self, file: &Path) -> Result<PluginInfo, String> {
+        let name = file.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default().to_string();
+        if !valid_name(&name) {
+            return Err("the file name may only contain letters, digits, '-' and '_'".to_string());
+        }
+        if self.find(&name).is_ok() {
+            return Err(format!("a plugin named {} is already loaded", name));
+        }
+        let module = Module::from_file(&self.engine, file).map_err(|e| e.to_string())?;
+        let plugin = Plugin {
+            info: PluginInfo { name: name.clone(), description: String::new(), endpoints: Vec::new(), transforms: Vec::new() },
+            module,
+        };
+        let manifest = self.run(&plugin, None, |store, instance| {
+            let manifest = instance.get_typed_func::<(), i64>(&mut *store, "file_agent_manifest")?;
+            let packed = manifest.call(&mut *store, ())?;
+            take(store, instance, packed)
+        })?;
+        let manifest: Manifest = serde_json::from_slice(&manifest).map_err(|e| format!("invalid manifest: {}", e))?;
+        if let Some(invalid) = manifest.endpoints.iter().chain(&manifest.transforms).find(|name| !valid_name(name)) {
+            return Err(format!("invalid endpoint or transform name: {}", invalid));
+        }
+
+        let info = PluginInfo {
+            name,
+            description: manifest.description,
+            endpoints: manifest.endpoints,
+            transforms: manifest.transforms,
+        };
+        self.plugins.push(Plugin { info: info.clone(), ..plugin });
+        Ok(info)
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.plugins.is_empty()
+    }
+
+    pub fn list(&self) -> Vec<PluginInfo> {
+        self.plugins.iter().map(|plugin| plugin.info.clone()).collect()
+    }
+
+    /// プラグインのエンドポイントを呼び出し、プラグインが返した JSON を返す
+    pub fn call_endpoint(&self, name: &str, endpoint: &str, body: &serde_json::Value, sandbox: Sandbox) -> Result<serde_json::Value, String> {
+        let plugin = self.find(name)?;
+        if !plugin.info.endpoints.iter().any(|e| e == endpoint) {
+            return Err(format!("Plugin {} has no endpoint {}", name, endpoint));
+        }
+        let input = serde_json::to_vec(&HandleInput { endpoint, body }).map_err(|e| e.to_string())?;
+        let output = self.run(plugin, Some(sandbox), |store, instance| {
+            let handle = instance.get_typed_func::<(i32, i32), i64>(&mut *store, "file_agent_handle")?;
+            let (ptr, len) = give(store, instance, &input)?;
+            let packed = handle.call(&mut *store, (ptr, len))?;
+            take(store, instance, packed)
+        })?;
+        serde_json::from_slice(&output).map_err(|e| format!("Plugin {} returned invalid JSON: {}", name, e))
+    }
+
+    /// ファイルの内容をプラグインの変換に通す
+    pub fn transform(&self, name: &str, transform: &str, content: &[u8], sandbox: Sandbox) -> Result<Vec<u8>, String> {
+        let plugin = self.find(name)?;
+        if !plugin.info.transforms.iter().any(|t| t == transform) {
+            return Err(format!("Plugin {} has no transform {}", name, transform));
+        }
+        self.run(plugin, Some(sandbox), |store, instance| {
+            let run = instance.get_typed_func::<(i32, i32, i32, i32), i64>(&mut *store, "file_agent_transform")?;
+            let (name_ptr, name_len) = give(store, instance, transform.as_bytes())?;
+            let (ptr, len) = give(store, instance, content)?;
+            let packed = run.call(&mut *store, (name_ptr, name_len, ptr, len))?;
+            take(store, instance, packed)
+        })
+    }
+
+    fn find(&self, name: &str) -> Result<&Plugin, String> {
+        self.plugins
+            .iter()
+            .find(|plugin| plugin.info.name == name)
+            .ok_or_else(|| format!("Plugin not found: {}", name))
+    }
+
+    // 新しいインスタンスで f を実行する (燃料とメモリを制限する)
+    fn run<T>(
+        &self,
+        plugin: &Plugin,
+        sandbox: Option<Sandbox>,
+        f: impl FnOnce(&mut Store<HostState>, &Instance) -> wasmtime::Result<T>,
+    ) -> Result<T, String> {
+        let name = &plugin.info.name;
+        let state = HostState {
+            plugin: name.clone(),
+            sandbox,
+            limits: StoreLimitsBuilder::new().memory_size(MAX_MEMORY_BYTES).build(),
+            last_error: String::new(),
+            failure: None,
+        };
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(FUEL_PER_CALL).map_err(|e| e.to_string())?;
+
+        let result = self
+            .linker
+            .instantiate(&mut store, &plugin.module)
+            .and_then(|instance| f(&mut store, &instance));
+        if let Some(failure) = store.data_mut().failure.take() {
+            return Err(failure);
+        }
+        result.map_err(|e| match e.downcast_ref::<Trap>() {
+            Some(Trap::OutOfFuel) => format!("Plugin {} ran too long and was stopped", name),
+            _ => format!("Plugin {} failed: {}", name, e),
+        })
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// プラグインから呼べる関数
+fn host_functions(engine: &Engine) -> wasmtime::Result<Linker<HostState>> {
+    let mut linker = Linker::new(engine);
+
+    linker.func_wrap(MODULE, "log", |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+        let message = read_string(&mut caller, ptr, len)?;
+        log!("🧩 [{}] {}", caller.data().plugin, message);
+        Ok(())
+    })?;
+
+    linker.func_wrap(MODULE, "fail", |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+        let message = read_string(&mut caller, ptr, len)?;
+        caller.data_mut().failure = Some(message);
+        Ok(())
+    })?;
+
+    linker.func_wrap(MODULE, "read_file", |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> wasmtime::Result<i64> {
+        let path = read_string(&mut caller, ptr, len)?;
+        let result = caller.data().sandbox().and_then(|sandbox| sandbox.read(&path));
+        answer(&mut caller, result)
+    })?;
+
+    linker.func_wrap(
+        MODULE,
+        "write_file",
+        |mut caller: Caller<'_, HostState>, path_ptr: i32, path_len: i32, ptr: i32, len: i32| -> wasmtime::Result<i32> {
+            let path = read_string(&mut caller, path_ptr, path_len)?;
+            let data = read_bytes(&mut caller, ptr, len)?;
+            let state = caller.data();
+            let result = state.sandbox().and_then(|sandbox| sandbox.write(&format!("plugin {}", state.plugin), &path, &data));
+            match result {
+                Ok(_) => Ok(0),
+                Err(e) => {
+                    caller.data_mut().last_error = e;
+                    Ok(-1)
+                }
+            }
+        },
+    )?;
+
+    linker.func_wrap(MODULE, "list_dir", |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> wasmtime::Result<i64> {
+        let path = read_string(&mut caller, ptr, len)?;
+        let result = caller
+            .data()
+            .sandbox()
+            .and_then(|sandbox| sandbox.list(&path))
+            .and_then(|names| serde_json::to_vec(&names).map_err(|e| e.to_string()));
+        answer(&mut caller, result)
+    })?;
+
+    linker.func_wrap(MODULE, "last_error", |mut caller: Caller<'_, HostState>| -> wasmtime::Result<i64> {
+        let message = caller.data().last_error.clone().into_bytes();
+        copy_to_guest(&mut caller, &message)
+    })?;
+
+    Ok(linker)
+}
+
+// 成功したら内容をプラグインのメモリに置いて位置と長さを、失敗したら理由を残して -1 を返す
+fn answer(caller: &mut Caller<'_, HostState>, result: Result<Vec<u8>, String>) -> wasmtime::Result<i64> {
+    match result {
+        Ok(data) => copy_to_guest(caller, &data),
+        Err(e) => {
+            caller.data_mut().last_error = e;
+            Ok(-1)
+        }
+    }
+}
+
+fn caller_memory(caller: &mut Caller<'_, HostState>) -> wasmtime::Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmtime::Error::msg("the plugin does not export memory"))
+}
+
+fn read_bytes(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> wasmtime::Result<Vec<u8>> {
+    let memory = caller_memory(caller)?;
+    // len はプラグインが決めるので、確保する前にメモリの範囲内で上限以下か確かめる
+    let (ptr, len) = (ptr as u32 as usize, usize::try_from(len)?);
+    check_range(ptr, len, memory.data_size(&*caller), sandbox::MAX_FILE_BYTES as usize)?;
+    let mut data = vec![0; len];
+    memory.read(&*caller, ptr, &mut data)?;
+    Ok(data)
+}
+
+// ptr から len バイトがメモリ (size バイト) の中にあり、max 以下か
+fn check_range(ptr: usize, len: usize, size: usize, max: usize) -> wasmtime::Result<()> {
+    if len > max || !matches!(ptr.checked_add(len), Some(end) if end <= size) {
+        return Err(wasmtime::Error::msg("the plugin passed an invalid memory range"));
+    }
+    Ok(())
+}
+
+fn read_string(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> wasmtime::Result<String> {
+    Ok(String::from_utf8_lossy(&read_bytes(caller, ptr, len)?).to_string())
+}
+
+fn copy_to_guest(caller: &mut Caller<'_, HostState>, data: &[u8]) -> wasmtime::Result<i64> {
+    let alloc = caller
+        .get_export("file_agent_alloc")
+        .and_then(Extern::into_func)
+        .ok_or_else(|| wasmtime::Error::msg("the plugin does not export file_agent_alloc"))?;
+    let len = i32::try_from(data.len())?;
+    let ptr = alloc.typed::<i32, i32>(&*caller)?.call(&mut *caller, len)?;
+    caller_memory(caller)?.write(&mut *caller, ptr as u32 as usize, data)?;
+    Ok(pack(ptr, len))
+}
+
+// ホストからプラグインにデータを渡す (file_agent_alloc で確保した領域に書く)
+fn give(store: &mut Store<HostState>, instance: &Instance, data: &[u8]) -> wasmtime::Result<(i32, i32)> {
+    let alloc = instance.get_typed_func::<i32, i32>(&mut *store, "file_agent_alloc")?;
+    let len = i32::try_from(data.len())?;
+    let ptr = alloc.call(&mut *store, len)?;
+    instance_memory(store, instance)?.write(&mut *store, ptr as u32 as usize, data)?;
+    Ok((ptr, len))
+}
+
+// プラグインが返した位置と長さの内容を読み出す
+fn take(store: &mut Store<HostState>, instance: &Instance, packed: i64) -> wasmtime::Result<Vec<u8>> {
+    let (ptr, len) = unpack(packed);
+    let memory = instance_memory(store, instance)?;
+    check_range(ptr, len, memory.data_size(&*store), MAX_MEMORY_BYTES)?;
+    let mut data = vec![0; len];
+    memory.read(&*store, ptr, &mut data)?;
+    Ok(data)
+}
+
+fn instance_memory(store: &mut Store<HostState>, instance: &Instance) -> wasmtime::Result<Memory> {
+    instance
+        .get_memory(&mut *store, "memory")
+        .ok_or_else(|| wasmtime::Error::msg("the plugin does not export memory"))
+}
+
+fn pack(ptr: i32, len: i32) -> i64 {
+    (((ptr as u32 as u64) << 32) | len as u32 as u64) as i64
+}
+
+fn unpack(packed: i64) -> (usize, usize) {
+    let packed = packed as u64;
+    ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize)
+}
@@ -0,0 +1,288 @@
+use crate::quota::resolve;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// バージョニング有効時に旧版を保存するフォルダ名 (ルート直下)
+pub const VERSIONS_DIR: &str = ".file_agent_versions";
+
+/// ルートディレクトリ単位の機能ポリシー
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RootPolicy {
+    pub path: PathBuf,
+    pub read: bool,
+    pub write: bool,
+    pub search: bool,
+    pub versioning: bool,               // 上書き・削除前に旧版を保存する
+    pub quarantine: Option<PathBuf>,    // 書き込みをこのフォルダに振り向ける
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Read,
+    Write,
+    Search,
+}
+
+impl Action {
+    fn name(self) -> &'static str {
+        match self {
+            Action::Read => "read",
+            Action::Write => "write",
+            Action::Search => "search",
+        }
+    }
+}
+
+impl RootPolicy {
+    // 形式: D:\archive|write=false|search=false|versioning=true|quarantine=D:\quarantine
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split('|');
+        let path = parts.next()?.trim();
+        if path.is_empty() {
+            return None;
+        }
+
+        let mut policy = RootPolicy {
+            path: PathBuf::from(path),
+            read: true,
+            write: true,
+            search: true,
+            versioning: false,
+            quarantine: None,
+        };
+        for part in parts {
+            let (key, val) = part.split_once('=')?;
+            let val = val.trim();
+            match key.trim() {
+                "read" => policy.read = val == "true",
+                "write" => policy.write = val == "true",
+                "search" => policy.search = val == "true",
+                "versioning" => policy.versioning = val == "true",
+                "quarantine" if !val.is_empty() => policy.quarantine = Some(PathBuf::from(val)),
+                _ => return None,
+            }
+        }
+        Some(policy)
+    }
+
+    pub fn to_ini_value(&self) -> String {
+        let mut value = self.path.display().to_string();
+        if !self.read {
+            value.push_str("|read=false");
+        }
+        if !self.write {
+            value.push_str("|write=false");
+        }
+        if !self.search {
+            value.push_str("|search=false");
+        }
+        if self.versioning {
+            value.push_str("|versioning=true");
+        }
+        if let Some(quarantine) = &self.quarantine {
+            value.push_str(&format!("|quarantine={}", quarantine.display()));
+        }
+        value
+    }
+
+    fn allows(&self, action: Action) -> bool {
+        match action {
+            Action::Read => self.read,
+            Action::Write => self.write,
+            Action::Search => self.search,
+        }
+    }
+}
+
+/// path に適用されるポリシー (最も深いルートを優先) と正規化したルート・パスを返す
+fn find<'a>(policies: &'a [RootPolicy], path: &Path) -> Option<(&'a RootPolicy, PathBuf, PathBuf)> {
+    if policies.is_empty() {
+        return None;
+    }
+    let path = resolve(path);
+    policies
+        .iter()
+        .map(|policy| (policy, resolve(&policy.path)))
+        .filter(|(_, root)| path.starts_with(root))
+        .max_by_key(|(_, root)| root.components().count())
+        .map(|(policy, root)| (policy, root, path.clone()))
+}
+
+/// 許可ルートが設定されている場合、path (正規化後) がいずれかの配下にあるか確認する
+pub fn check_allowed(allowed_roots: &[PathBuf], path: &Path) -> Result<(), String> {
+    if allowed_roots.is_empty() || is_allowed(allowed_roots, path) {
+        Ok(())
+    } else {
+        Err(format!("Access denied: {} is outside the allowed roots", path.display()))
+    }
+}
+
+pub fn is_allowed(allowed_roots: &[PathBuf], path: &Path) -> bool {
+    let path = resolve(path);
+    allowed_roots.iter().any(|root| path.starts_with(resolve(root)))
+}
+
+/// path に対する操作がポリシーで許可されているか確認する (ポリシー外のパスは許可)
+pub fn check(policies: &[RootPolicy], path: &Path, action: Action) -> Result<(), String> {
+    match find(policies, path) {
+        Some((policy, _, _)) if !policy.allows(action) => Err(format!(
+            "Policy for {} does not allow {}",
+            policy.path.display(),
+            action.name()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// 書き込みを確認し、実際に書き込むパスを返す (隔離ルートでは隔離フォルダ内のパス)
+pub fn write_target(policies: &[RootPolicy], path: &Path) -> Result<PathBuf, String> {
+    let (policy, root, resolved) = match find(policies, path) {
+        Some(found) => found,
+        None => return Ok(path.to_path_buf()),
+    };
+    if !policy.write {
+        return Err(format!("Policy for {} does not allow write", policy.path.display()));
+    }
+
+    let quarantine = match &policy.quarantine {
+        Some(quarantine) => quarantine,
+        None => return Ok(path.to_path_buf()),
+    };
+    let relative = resolved.strip_prefix(&root).unwrap_or(&resolved);
+    let target = quarantine.join(relative);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create quarantine directory: {}", e))?;
+    }
+    Ok(target)
+}
+
+/// バージョニングが有効なルートでは、上書き・削除される前のファイルを保存する
+pub fn save_version(policies: &[RootPolicy], path: &Path) -> Result<(), String> {
+    let (policy, root, resolved) = match find(policies, path) {
+        Some(found) => found,
+        None => return Ok(()),
+    };
+    if !policy.versioning || !resolved.is_file() {
+        return Ok(());
+    }
+
+    let relative = resolved.strip_prefix(&root).unwrap_or(&resolved);
+    if relative.starts_with(VERSIONS_DIR) {
+        return Ok(());
+    }
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let mut name = relative.as_os_str().to_os_string();
+    name.push(format!(".{}", secs));
+    let target = root.join(VERSIONS_DIR).join(name);
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create version directory: {}", e))?;
+    }
+    fs::copy(&resolved, &target)
+        .map(|_| ())
+        .map_err(|e| format!("Failed to save previous version: {}", e))
+}
+
+/// dir 配下にある、action を許可しないルートを dir 基準のパスで返す (再帰走査での除外用)
+pub fn denied_under(policies: &[RootPolicy], dir: &Path, action: Action) -> Vec<PathBuf> {
+    if policies.is_empty() {
+        return Vec::new();
+    }
+    let resolved_dir = resolve(dir);
+    policies
+        .iter()
+        .filter(|policy| !policy.allows(action))
+        .filter_map(|policy| {
+            let root = resolve(&policy.path);
+            root.strip_prefix(&resolved_dir).ok().map(|relative| dir.join(relative))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("file_agent_policy_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn no_roots_allow_everything() {
+        assert!(check_allowed(&[], Path::new("/etc/passwd")).is_ok());
+    }
+
+    #[test]
+    fn roots_do_not_match_sibling_prefixes() {
+        let dir = test_dir("siblings");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        fs::create_dir_all(dir.join("data2")).unwrap();
+        let roots = vec![dir.join("data")];
+
+        assert!(check_allowed(&roots, &dir.join("data")).is_ok());
+        assert!(check_allowed(&roots, &dir.join("data").join("new").join("a.txt")).is_ok());
+        let error = check_allowed(&roots, &dir.join("data2").join("a.txt")).unwrap_err();
+        assert!(error.starts_with("Access denied:") && error.ends_with("is outside the allowed roots"), "{}", error);
+        assert!(check_allowed(&roots, &dir).is_err());
+    }
+
+    #[test]
+    fn parent_components_of_missing_paths_are_resolved() {
+        let dir = test_dir("parents");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        let roots = vec![dir.join("data")];
+
+        assert!(check_allowed(&roots, &dir.join("data").join("missing").join("..").join("a.txt")).is_ok());
+        assert!(check_allowed(&roots, &dir.join("data").join("missing").join("..").join("..").join("a.txt")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_out_of_a_root_are_refused() {
+        let dir = test_dir("symlinks");
+        fs::create_dir_all(dir.join("data")).unwrap();
+        fs::create_dir_all(dir.join("outside")).unwrap();
+        fs::write(dir.join("outside").join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(dir.join("outside"), dir.join("data").join("link")).unwrap();
+        std::os::unix::fs::symlink(dir.join("outside").join("secret.txt"), dir.join("data").join("secret.txt")).unwrap();
+        let roots = vec![dir.join("data")];
+
+        assert_eq!(resolve(&dir.join("data").join("link").join("secret.txt")), resolve(&dir.join("outside").join("secret.txt")));
+        assert!(check_allowed(&roots, &dir.join("data").join("link").join("secret.txt")).is_err());
+        assert!(check_allowed(&roots, &dir.join("data").join("secret.txt")).is_err());
+        // まだ存在しないファイルも、存在する部分 (シンボリックリンク) を解決してから確認する
+        assert!(check_allowed(&roots, &dir.join("data").join("link").join("new").join("b.txt")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_roots_allow_their_target() {
+        let dir = test_dir("linked_root");
+        fs::create_dir_all(dir.join("real")).unwrap();
+        std::os::unix::fs::symlink(dir.join("real"), dir.join("data")).unwrap();
+
+        assert!(check_allowed(&[dir.join("data")], &dir.join("real").join("a.txt")).is_ok());
+        assert!(check_allowed(&[dir.join("data")], &dir.join("data").join("a.txt")).is_ok());
+    }
+
+    #[test]
+    fn read_only_policies_refuse_writes_below_them() {
+        let dir = test_dir("read_only");
+        fs::create_dir_all(dir.join("archive").join("2024")).unwrap();
+        let policies = vec![RootPolicy::parse(&format!("{}|write=false|search=false", dir.join("archive").display())).unwrap()];
+        let inside = dir.join("archive").join("2024").join("a.txt");
+
+        assert!(check(&policies, &inside, Action::Read).is_ok());
+        assert!(check(&policies, &inside, Action::Search).unwrap_err().ends_with("does not allow search"));
+        assert!(write_target(&policies, &inside).unwrap_err().ends_with("does not allow write"));
+        assert_eq!(write_target(&policies, &dir.join("archive2").join("a.txt")).unwrap(), dir.join("archive2").join("a.txt"));
+    }
+}
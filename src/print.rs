@@ -0,0 +1,63 @@
+use std::path::Path;
+
+/// ファイルをプリンターに送る (printer 未指定時は既定のプリンター)
+#[cfg(target_os = "windows")]
+pub fn print_file(path: &Path, printer: Option<&str>) -> Result<(), String> {
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::um::shellapi::ShellExecuteW;
+    use winapi::um::winuser::SW_HIDE;
+
+    fn to_wide(s: &OsStr) -> Vec<u16> {
+        s.encode_wide().chain(std::iter::once(0)).collect()
+    }
+
+    // 関連付けられたアプリケーションの print / printto 動詞を使用する
+    let (verb, parameters) = match printer {
+        Some(printer) => ("printto", Some(format!("\"{}\"", printer))),
+        None => ("print", None),
+    };
+    // ShellExecute は \\?\ 付きのパスを開けない
+    let path = crate::longpath::legacy(path)?;
+    let verb = to_wide(OsStr::new(verb));
+    let file = to_wide(path.as_os_str());
+    let parameters = parameters.map(|p| to_wide(OsStr::new(&p)));
+
+    let result = unsafe {
+        ShellExecuteW(
+            std::ptr::null_mut(),
+            verb.as_ptr(),
+            file.as_ptr(),
+            parameters.as_ref().map(|p| p.as_ptr()).unwrap_or(std::ptr::null()),
+            std::ptr::null(),
+            SW_HIDE,
+        )
+    };
+
+    // 32 以下はエラーコード
+    let code = result as isize;
+    if code > 32 {
+        Ok(())
+    } else {
+        Err(format!("Failed to print (ShellExecute error {})", code))
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn print_file(path: &Path, printer: Option<&str>) -> Result<(), String> {
+    let mut command = std::process::Command::new("lp");
+    if let Some(printer) = printer {
+        command.arg("-d").arg(printer);
+    }
+    let output = command
+        .arg("--")
+        .arg(path)
+        .output()
+        .map_err(|e| format!("Failed to run lp: {}", e))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("lp failed: {}", String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
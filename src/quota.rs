@@ -0,0 +1,304 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+
+use crate::longpath;
+
+// 容量制限のフォルダの使用量を数え直すまでの時間 (エージェント経由の書き込みは予約で加算するため、
+// エージェント外の変更と削除だけがこの時間まで反映されない)
+const USAGE_TTL: Duration = Duration::from_secs(60);
+
+struct CountedUsage {
+    usage: Usage,
+    counted_at: Instant,
+}
+
+impl CountedUsage {
+    fn fresh(&self) -> bool {
+        self.counted_at.elapsed() < USAGE_TTL
+    }
+}
+
+// 容量制限のフォルダ (正規化したパス) ごとの使用量。確認と予約はこのロックの中で行う
+static USAGE: Mutex<BTreeMap<PathBuf, CountedUsage>> = Mutex::new(BTreeMap::new());
+
+/// ディレクトリ単位の容量制限
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DirQuota {
+    pub path: PathBuf,
+    pub max_bytes: Option<u64>,
+    pub max_files: Option<u64>,
+}
+
+impl DirQuota {
+    // 形式: C:\inbox|max_bytes=1073741824|max_files=1000
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split('|');
+        let path = parts.next()?.trim();
+        if path.is_empty() {
+            return None;
+        }
+
+        let mut quota = DirQuota {
+            path: PathBuf::from(path),
+            max_bytes: None,
+            max_files: None,
+        };
+        for part in parts {
+            let (key, val) = part.split_once('=')?;
+            let val = val.trim().parse::<u64>().ok()?;
+            match key.trim() {
+                "max_bytes" => quota.max_bytes = Some(val),
+                "max_files" => quota.max_files = Some(val),
+                _ => return None,
+            }
+        }
+        Some(quota)
+    }
+
+    pub fn to_ini_value(&self) -> String {
+        let mut value = self.path.display().to_string();
+        if let Some(max_bytes) = self.max_bytes {
+            value.push_str(&format!("|max_bytes={}", max_bytes));
+        }
+        if let Some(max_files) = self.max_files {
+            value.push_str(&format!("|max_files={}", max_files));
+        }
+        value
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Usage {
+    pub bytes: u64,
+    pub files: u64,
+}
+
+/// パス配下のファイル合計サイズとファイル数
+pub fn usage_of(path: &Path) -> Usage {
+    let mut usage = Usage::default();
+    for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_file() {
+            usage.files += 1;
+            usage.bytes += entry.metadata().map(|m| m.len()).unwrap_or(0);
+        }
+    }
+    usage
+}
+
+/// 存在しないパスでも、存在する部分を正規化して絶対パスに解決する。
+/// 存在しない部分の "." / ".." は字句的に処理する (Windows と同じ解釈)。
+/// \\?\ は付けずに返す (存在するかどうかで表記が変わらないように)
+pub fn resolve(path: &Path) -> PathBuf {
+    let path = longpath::simplify(path);
+    if let Ok(canonical) = path.canonicalize() {
+        return longpath::simplify(&canonical);
+    }
+    let absolute = if path.is_relative() {
+        std::env::current_dir().map(|dir| dir.join(&path)).unwrap_or_else(|_| path.clone())
+    } else {
+        path
+    };
+
+    let mut resolved = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => {
+                resolved.push(name);
+                if let Ok(canonical) = resolved.canonicalize() {
+                    resolved = longpath::simplify(&canonical);
+                }
+            }
+            // ドライブ・ルートは正規化せずにそのまま使う
+            other => resolved.push(other),
+        }
+    }
+    resolved
+}
+
+/// target への書き込み (added 分の増加) が容量制限を超えないか確認し、超えなければ使用量に予約する。
+/// source が同じ制限ディレクトリ内にある場合 (移動) は増加なしとみなす。
+/// 使用量は USAGE_TTL の間覚えておき、書き込みのたびにフォルダ全体を走査しない
+pub fn check(quotas: &[DirQuota], target: &Path, source: Option<&Path>, added: Usage) -> Result<(), String> {
+    if quotas.is_empty() {
+        return Ok(());
+    }
+
+    let target = resolve(target);
+    let source = source.map(resolve);
+    let limited: Vec<(&DirQuota, PathBuf)> = quotas
+        .iter()
+        .map(|quota| (quota, resolve(&quota.path)))
+        .filter(|(_, root)| target.starts_with(root) && !source.as_ref().is_some_and(|source| source.starts_with(root)))
+        .collect();
+    if limited.is_empty() {
+        return Ok(());
+    }
+    // 上書きされる既存ファイルの分は差し引く
+    let replaced = if target.is_file() { usage_of(&target) } else { Usage::default() };
+
+    // 数え直すフォルダはロックの外で走査する (その間の書き込みを待たせないように)
+    let stale: Vec<PathBuf> = {
+        let counted = USAGE.lock().unwrap();
+        limited
+            .iter()
+            .filter(|(_, root)| !counted.get(root).is_some_and(CountedUsage::fresh))
+            .map(|(_, root)| root.clone())
+            .collect()
+    };
+    let recounted: Vec<(PathBuf, Usage)> = stale
+        .into_iter()
+        .map(|root| {
+            let usage = usage_of(&root);
+            (root, usage)
+        })
+        .collect();
+
+    let mut counted = USAGE.lock().unwrap();
+    for (root, usage) in recounted {
+        // 走査中に他の書き込みが数え直して予約していれば、そちらを使う
+        if !counted.get(&root).is_some_and(CountedUsage::fresh) {
+            counted.insert(root, CountedUsage { usage, counted_at: Instant::now() });
+        }
+    }
+
+    let mut reserved = Vec::new();
+    for (quota, root) in &limited {
+        let current = counted.get(root).map(|c| c.usage).unwrap_or_default();
+        let after = Usage {
+            bytes: (current.bytes + added.bytes).saturating_sub(replaced.bytes),
+            files: (current.files + added.files).saturating_sub(replaced.files),
+        };
+        if let Some(max_bytes) = quota.max_bytes {
+            if after.bytes > max_bytes {
+                return Err(format!(
+                    "Quota exceeded for {}: {} bytes would be used (limit {} bytes)",
+                    quota.path.display(), after.bytes, max_bytes
+                ));
+            }
+        }
+        if let Some(max_files) = quota.max_files {
+            if after.files > max_files {
+                return Err(format!(
+                    "Quota exceeded for {}: {} files would be stored (limit {} files)",
+                    quota.path.display(), after.files, max_files
+                ));
+            }
+        }
+        reserved.push((root, after));
+    }
+    for (root, after) in reserved {
+        if let Some(entry) = counted.get_mut(root) {
+            entry.usage = after;
+        }
+    }
+    Ok(())
+}
+
+/// target のボリュームに needed バイトを書き込む空きがあるか確認する (途中で容量不足になって
+/// 書きかけのファイルが残らないよう、書き込む前に確認する)。空き容量を取得できない場合は確認しない
+pub fn check_free_space(target: &Path, needed: u64) -> Result<(), String> {
+    if needed == 0 {
+        return Ok(());
+    }
+    // まだ存在しないファイルやフォルダは、存在する一番近い親のボリュームで確認する
+    let resolved = resolve(target);
+    let Some(existing) = resolved.ancestors().find(|path| path.exists()) else {
+        return Ok(());
+    };
+    // fs2 は Windows の API にパスをそのまま渡すため、長いパスには \\?\ を付ける
+    match fs2::available_space(longpath::extended(existing)) {
+        Ok(available) if available < needed => Err(format!(
+            "Not enough space on the destination volume: {} bytes needed, {} bytes available",
+            needed, available
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("file_agent_quota_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn quota(dir: &Path, limits: &str) -> Vec<DirQuota> {
+        vec![DirQuota::parse(&format!("{}{}", dir.display(), limits)).unwrap()]
+    }
+
+    fn bytes(bytes: u64) -> Usage {
+        Usage { bytes, files: 1 }
+    }
+
+    #[test]
+    fn parses_the_ini_value() {
+        let quota = DirQuota::parse("/inbox|max_bytes=1024|max_files=10").unwrap();
+        assert_eq!((quota.max_bytes, quota.max_files), (Some(1024), Some(10)));
+        assert_eq!(quota.to_ini_value(), "/inbox|max_bytes=1024|max_files=10");
+        assert!(DirQuota::parse("/inbox|max_bytes=lots").is_none());
+        assert!(DirQuota::parse("/inbox|max_size=1").is_none());
+    }
+
+    #[test]
+    fn byte_limit_counts_existing_files_and_reservations() {
+        let dir = test_dir("bytes");
+        fs::write(dir.join("existing.txt"), "1234").unwrap();
+        let quotas = quota(&dir, "|max_bytes=10");
+
+        assert!(check(&quotas, &dir.join("a.txt"), None, bytes(5)).is_ok());
+        // 書き込みがまだ終わっていなくても、予約した 5 バイトを数える
+        let error = check(&quotas, &dir.join("b.txt"), None, bytes(5)).unwrap_err();
+        assert!(error.contains("14 bytes would be used (limit 10 bytes)"), "{}", error);
+        assert!(check(&quotas, &dir.join("c.txt"), None, bytes(1)).is_ok());
+    }
+
+    #[test]
+    fn overwriting_counts_only_the_difference() {
+        let dir = test_dir("overwrite");
+        fs::write(dir.join("existing.txt"), "1234567890").unwrap();
+        let quotas = quota(&dir, "|max_bytes=10|max_files=1");
+
+        assert!(check(&quotas, &dir.join("existing.txt"), None, bytes(11)).unwrap_err().contains("11 bytes would be used"));
+        assert!(check(&quotas, &dir.join("existing.txt"), None, bytes(8)).is_ok());
+    }
+
+    #[test]
+    fn file_limit_counts_files_below_the_folder() {
+        let dir = test_dir("files");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub").join("a.txt"), "").unwrap();
+        let quotas = quota(&dir, "|max_files=2");
+
+        assert!(check(&quotas, &dir.join("sub").join("b.txt"), None, bytes(0)).is_ok());
+        let error = check(&quotas, &dir.join("c.txt"), None, bytes(0)).unwrap_err();
+        assert!(error.contains("3 files would be stored (limit 2 files)"), "{}", error);
+        // フォルダのコピーは中のファイルをまとめて数える
+        let copy = test_dir("files_copy");
+        assert!(check(&quota(&copy, "|max_files=2"), &copy.join("folder"), None, Usage { bytes: 0, files: 3 }).is_err());
+    }
+
+    #[test]
+    fn moves_within_the_folder_and_paths_outside_are_not_limited() {
+        let dir = test_dir("moves");
+        fs::create_dir_all(dir.join("inbox")).unwrap();
+        fs::write(dir.join("inbox").join("a.txt"), "1234").unwrap();
+        let quotas = quota(&dir.join("inbox"), "|max_bytes=4|max_files=1");
+
+        assert!(check(&quotas, &dir.join("inbox").join("b.txt"), Some(&dir.join("inbox").join("a.txt")), bytes(4)).is_ok());
+        assert!(check(&quotas, &dir.join("inbox2").join("b.txt"), None, bytes(100)).is_ok());
+        assert!(check(&quotas, &dir.join("inbox").join("b.txt"), Some(&dir.join("other.txt")), bytes(4)).is_err());
+    }
+}
@@ -0,0 +1,70 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+
+// 保持するクライアント数がこれを超えたら、満タンに戻ったバケットを捨てる
+const MAX_TRACKED_CLIENTS: usize = 1024;
+
+/// 制限を超えたリクエストの拒否理由 (recover で 429 のレスポンスにする)
+#[derive(Debug)]
+pub struct TooManyRequests {
+    pub retry_after_secs: u64,
+}
+
+impl warp::reject::Reject for TooManyRequests {}
+
+#[derive(Debug, Serialize)]
+pub struct TooManyRequestsResponse {
+    pub success: bool,
+    pub data: Option<()>,
+    pub error: Option<String>,
+    pub retry_after_secs: u64,
+}
+
+/// クライアントのアドレスごとのトークンバケット。毎秒 per_second 回、最大 burst 回まで連続で受け付ける
+pub struct IpRateLimiter {
+    limits: RwLock<(f64, f64)>, // (per_second, burst)
+    buckets: Mutex<HashMap<IpAddr, (f64, Instant)>>, // アドレス -> (残りの回数, 最終更新時刻)
+}
+
+impl IpRateLimiter {
+    pub fn new(per_second: u32, burst: u32) -> Self {
+        Self {
+            limits: RwLock::new((per_second as f64, burst.max(1) as f64)),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 設定ファイルの変更を反映する (各クライアントの残りの回数は新しい burst までに切り詰める)
+    pub fn set_limits(&self, per_second: u32, burst: u32) {
+        *self.limits.write().unwrap() = (per_second as f64, burst.max(1) as f64);
+    }
+
+    /// 1 回分を消費する。使い切っていれば次に受け付けられるまでの秒数を返す
+    pub fn check(&self, ip: IpAddr) -> Result<(), TooManyRequests> {
+        let (per_second, burst) = *self.limits.read().unwrap();
+        if per_second <= 0.0 {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(&ip) {
+            buckets.retain(|_, (tokens, updated)| *tokens + now.duration_since(*updated).as_secs_f64() * per_second < burst);
+        }
+
+        let (tokens, updated) = buckets.entry(ip).or_insert((burst, now));
+        *tokens = (*tokens + now.duration_since(*updated).as_secs_f64() * per_second).min(burst);
+        *updated = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(TooManyRequests {
+                retry_after_secs: ((1.0 - *tokens) / per_second).ceil().max(1.0) as u64,
+            })
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::auth::Auth;
+use crate::config::Config;
+use crate::logs;
+use crate::ratelimit::IpRateLimiter;
+use crate::{timeout, writequota};
+
+// 設定ファイルの更新時刻を確認する間隔
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// 現在の設定。リクエストごとにその時点の設定を取り出す (再読み込みすると差し替わる)
+pub(crate) type LiveConfig = Arc<RwLock<Arc<Config>>>;
+
+/// 設定ファイルが変わったら読み込み直し、再起動なしで反映できる設定を反映する
+pub(crate) async fn watch(live: LiveConfig, auth: Arc<Auth>, rate_limiter: Arc<IpRateLimiter>) {
+    let path = Config::get_ini_path();
+    let mut modified = modified_time(&path);
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let current = modified_time(&path);
+        if current == modified {
+            continue;
+        }
+        modified = current;
+
+        let config = match tokio::task::spawn_blocking(Config::load).await {
+            Ok(Ok(config)) => config,
+            Ok(Err(e)) => {
+                log_error!("❌ 設定ファイルに誤りがあるため、変更を反映しません:");
+                for error in &e.errors {
+                    log_error!("  {}", error);
+                }
+                continue;
+            }
+            Err(_) => continue,
+        };
+        // 読み込み時の保存 (平文のトークンの移行など) で更新時刻が変わっても、もう一度読み込まない
+        modified = modified_time(&path);
+        apply(&live, &auth, &rate_limiter, config);
+    }
+}
+
+fn apply(live: &LiveConfig, auth: &Auth, rate_limiter: &IpRateLimiter, config: Config) {
+    let restart = live.read().unwrap().restart_required(&config);
+    auth.reload(config.token_hash.clone(), &config.token_meta, &config.token_tiers, &config.allowed_operations, &config.allowed_roots);
+    rate_limiter.set_limits(config.rate_limit_per_second, config.rate_limit_burst);
+    logs::set_max_bytes(config.log_max_bytes);
+    timeout::set_limit(config.request_timeout_secs);
+    writequota::set_limits(config.max_write_bytes, config.daily_write_bytes);
+    let log_path = config.log_path();
+    if logs::path().is_some_and(|path| path != log_path) {
+        logs::init(log_path);
+    }
+    *live.write().unwrap() = Arc::new(config);
+
+    log!("🔄 設定ファイルの変更を反映しました");
+    for problem in live.read().unwrap().validate() {
+        log!("⚠️ {}", problem);
+    }
+    if !restart.is_empty() {
+        log!("⚠️ 次の設定は再起動後に反映されます: {}", restart.join(", "));
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
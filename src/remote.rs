@@ -0,0 +1,197 @@
+//! ファイルを置くリモート (S3 のバケット / WebDAV のサーバー) の共通の型
+//!
+//! 同期 (sync=) は Remote を通して、どちらのリモートにも同じ操作 (一覧・送信・受信・削除) を行う。
+use std::path::Path;
+
+use crate::config::Config;
+use crate::s3::{self, Bucket};
+use crate::webdav::WebDav;
+use crate::{outbound, tempfiles};
+
+/// 送る内容
+pub enum Upload {
+    File(tokio::fs::File, u64), // 読み込みながら送る (大きさは Content-Length に使う)
+    Bytes(Vec<u8>),             // 保管庫のファイルを復号した内容など
+}
+
+impl Upload {
+    pub(crate) fn into_body(self) -> (reqwest::Body, u64) {
+        match self {
+            Upload::File(file, size) => (reqwest::Body::wrap_stream(tokio_util::io::ReaderStream::new(file)), size),
+            Upload::Bytes(data) => {
+                let size = data.len() as u64;
+                (reqwest::Body::from(data), size)
+            }
+        }
+    }
+}
+
+/// ダウンロード中のファイル
+pub struct Download {
+    pub size: u64,
+    received: u64,
+    service: &'static str, // エラーに付ける名前 (S3 / WebDAV)
+    response: reqwest::Response,
+}
+
+impl Download {
+    pub(crate) fn new(response: reqwest::Response, service: &'static str) -> Result<Self, String> {
+        let size = response.content_length().ok_or_else(|| format!("{} did not report the file size", service))?;
+        Ok(Download { size, received: 0, service, response })
+    }
+
+    /// 本文をすべて読み込む (保管庫に書き込む場合やスキャンする場合)
+    pub async fn bytes(mut self) -> Result<Vec<u8>, String> {
+        let mut data = Vec::with_capacity(self.size.min(64 * 1024 * 1024) as usize);
+        while let Some(chunk) = self.next_chunk().await? {
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+
+    /// 同じフォルダの一時ファイルに書き込んでから target を置き換える (途中で切れても元のファイルを壊さない)
+    pub async fn save(mut self, target: &Path) -> Result<(), String> {
+        use tokio::io::AsyncWriteExt;
+
+        let temp = tempfiles::create(target, "download");
+        let result = async {
+            let mut file = tokio::fs::File::create(&temp).await.map_err(|e| e.to_string())?;
+            while let Some(chunk) = self.next_chunk().await? {
+                file.write_all(&chunk).await.map_err(|e| e.to_string())?;
+            }
+            file.flush().await.map_err(|e| e.to_string())?;
+            drop(file);
+            tokio::fs::rename(&temp, target).await.map_err(|e| e.to_string())
+        }
+        .await;
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&temp).await;
+        }
+        tempfiles::release(&temp);
+        result
+    }
+
+    // 報告された大きさを超えて送られてきたら止める (クォータの確認は size で行うため)
+    async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, String> {
+        let Some(chunk) = self.response.chunk().await.map_err(|e| format!("{} download failed: {}", self.service, e))? else {
+            return Ok(None);
+        };
+        self.received += chunk.len() as u64;
+        if self.received > self.size {
+            return Err(format!("{} sent more than the reported {} bytes", self.service, self.size));
+        }
+        Ok(Some(chunk.to_vec()))
+    }
+}
+
+/// 読み込み済みの内容を、Download::save と同じく一時ファイル経由で target に書き込む (スキャンした場合)
+pub async fn save_bytes(target: &Path, data: &[u8]) -> Result<(), String> {
+    let temp = tempfiles::create(target, "download");
+    let result = async {
+        tokio::fs::write(&temp, data).await.map_err(|e| e.to_string())?;
+        tokio::fs::rename(&temp, target).await.map_err(|e| e.to_string())
+    }
+    .await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&temp).await;
+    }
+    tempfiles::release(&temp);
+    result
+}
+
+/// リモートのファイル
+#[derive(Debug, Clone)]
+pub struct RemoteFile {
+    pub path: String, // フォルダからの相対パス (/ 区切り)
+    pub size: u64,
+    pub modified: u64, // UNIX 秒 (分からなければ 0)
+    pub version: String, // 内容が変わると変わる値 (ETag、なければ大きさと更新日時)
+}
+
+/// 同期先のフォルダ
+pub enum Remote {
+    S3 { bucket: Bucket, prefix: String },       // s3:<prefix>
+    WebDav { server: WebDav, folder: String },   // webdav:<folder>
+}
+
+impl Remote {
+    /// sync= の remote= (s3:backups/work か webdav:backups/work) を設定のリモートと組み合わせる
+    pub fn open(value: &str, config: &Config) -> Result<Self, String> {
+        let (kind, folder) = value.split_once(':').ok_or_else(|| format!("Invalid remote: {}", value))?;
+        let folder = folder.trim_matches('/').to_string();
+        match kind {
+            "s3" => {
+                let bucket = Bucket::from_config(config).ok_or("S3 is not configured (set s3_endpoint= and s3_bucket=)")??;
+                let prefix = if folder.is_empty() { folder } else { format!("{}/", folder) };
+                Ok(Remote::S3 { bucket, prefix })
+            }
+            "webdav" => {
+                let server = WebDav::from_config(config).ok_or("WebDAV is not configured (set webdav_url=)")??;
+                Ok(Remote::WebDav { server, folder })
+            }
+            _ => Err(format!("Invalid remote: {}", value)),
+        }
+    }
+
+    /// 同期先の場所 (s3://bucket/prefix や https://server/folder)
+    pub fn location(&self) -> String {
+        match self {
+            Remote::S3 { bucket, prefix } => bucket.location(prefix),
+            Remote::WebDav { server, folder } => server.url(folder),
+        }
+    }
+
+    /// フォルダの下のすべてのファイル
+    pub async fn list(&self) -> Result<Vec<RemoteFile>, String> {
+        match self {
+            Remote::S3 { bucket, prefix } => Ok(bucket
+                .list_all(prefix)
+                .await?
+                .into_iter()
+                // 末尾が / のキーは、ほかのツールが作るフォルダの目印
+                .filter(|object| !object.key.ends_with('/'))
+                .filter_map(|object| {
+                    let path = object.key.strip_prefix(prefix.as_str())?.to_string();
+                    let modified = s3::parse_modified(&object.modified).unwrap_or(0);
+                    let version = if object.etag.is_empty() { format!("{}-{}", object.size, modified) } else { object.etag };
+                    Some(RemoteFile { path, size: object.size, modified, version })
+                })
+                .collect()),
+            Remote::WebDav { server, folder } => server.list_all(folder).await,
+        }
+    }
+
+    pub async fn upload(&self, path: &str, content: Upload) -> Result<(), String> {
+        match self {
+            Remote::S3 { bucket, prefix } => bucket.upload(&format!("{}{}", prefix, path), content).await.map(|_| ()),
+            Remote::WebDav { server, folder } => server.upload(&join(folder, path), content).await,
+        }
+    }
+
+    pub async fn download(&self, path: &str) -> Result<Download, String> {
+        match self {
+            Remote::S3 { bucket, prefix } => bucket.download(&format!("{}{}", prefix, path)).await,
+            Remote::WebDav { server, folder } => server.download(&join(folder, path)).await,
+        }
+    }
+
+    pub async fn delete(&self, path: &str) -> Result<(), String> {
+        match self {
+            Remote::S3 { bucket, prefix } => bucket.delete(&format!("{}{}", prefix, path)).await,
+            Remote::WebDav { server, folder } => server.delete(&join(folder, path)).await,
+        }
+    }
+}
+
+fn join(folder: &str, path: &str) -> String {
+    if folder.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}/{}", folder, path)
+    }
+}
+
+/// S3 と WebDAV で使う HTTP のクライアント (proxy= を反映した共有のクライアント)
+pub(crate) fn client() -> reqwest::Client {
+    outbound::client()
+}
@@ -0,0 +1,328 @@
+use crate::handlers::{endpoint_matches, ENDPOINTS};
+use crate::shutdown;
+use futures_util::{future, stream, SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, Mutex};
+use warp::filters::BoxedFilter;
+use warp::http::{Method, Request, StatusCode};
+use warp::hyper::service::Service;
+use warp::hyper::Body;
+use warp::ws::{Message, WebSocket};
+
+// JSON-RPC 2.0 のエラーコード (-32000 番台はこのエージェント独自のもの)
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const REQUEST_FAILED: i64 = -32000; // HTTP のエラー (413、429 など) で処理されなかった
+const NOT_AUTHENTICATED: i64 = -32001;
+
+// 変更の通知で /api/changes/poll を待つ秒数
+const SUBSCRIBE_WAIT_SECS: u64 = 30;
+
+/// RPC から API を呼び出したときの接続元 (リクエストの拡張として渡し、認証やレート制限で使う)
+#[derive(Debug, Clone)]
+pub struct Peer(pub Option<SocketAddr>);
+
+/// RPC のメソッドを処理する v1 のルート (パスは /api/v1/ より後の部分)
+pub type ApiRoutes = BoxedFilter<(warp::reply::Response,)>;
+
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    #[serde(default)]
+    id: Option<Value>, // なければ通知 (応答を返さない)
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError { code, message: message.into(), data: None }
+    }
+}
+
+// 接続ごとの状態 (auth で受け取ったトークンと、変更の通知のタスク)
+struct Session {
+    token: Option<String>,
+    subscription: Option<tokio::task::JoinHandle<()>>,
+}
+
+/// WebSocket の接続を処理する。最初に auth でトークンを送り、以降のメソッドはそのトークンで呼び出す
+pub async fn serve(socket: WebSocket, peer: Peer, routes: ApiRoutes) {
+    let (mut sink, stream) = socket.split();
+    let (sender, mut outgoing) = mpsc::unbounded_channel::<String>();
+    let writer = tokio::spawn(async move {
+        while let Some(text) = outgoing.recv().await {
+            if sink.send(Message::text(text)).await.is_err() {
+                break;
+            }
+        }
+        // エージェントの停止で終わった場合は、クライアントに接続を閉じることを伝える
+        if shutdown::is_requested() {
+            let _ = sink.close().await;
+        }
+    });
+
+    let messages = stream
+        .take_while(|message| future::ready(matches!(message, Ok(message) if !message.is_close())))
+        .filter_map(|message| future::ready(message.ok().and_then(|message| message.to_str().ok().map(str::to_string))));
+    run(messages, sender, peer, routes, None).await;
+    let _ = writer.await;
+}
+
+/// 標準入力の 1 行を 1 つのリクエストとして処理し、応答と通知を 1 行ずつ標準出力に書く (入力が終わったら戻る)
+pub async fn serve_stdio(routes: ApiRoutes, trusted: bool) {
+    let (sender, mut outgoing) = mpsc::unbounded_channel::<String>();
+    let writer = tokio::spawn(async move {
+        let mut stdout = tokio::io::stdout();
+        while let Some(text) = outgoing.recv().await {
+            let line = format!("{}\n", text);
+            if stdout.write_all(line.as_bytes()).await.is_err() || stdout.flush().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let lines = stream::unfold(BufReader::new(tokio::io::stdin()).lines(), |mut lines| async move {
+        match lines.next_line().await {
+            Ok(Some(line)) => Some((line, lines)),
+            _ => None,
+        }
+    })
+    .filter(|line| future::ready(!line.trim().is_empty()));
+    // 起動したプロセスを信頼する場合は auth なしで呼び出せる (トークンは確認されない)
+    let token = trusted.then(String::new);
+    run(lines, sender, Peer(None), routes, token).await;
+    let _ = writer.await;
+}
+
+// メッセージを処理する。応答と通知は sender に送る (処理中のリクエストが終わると sender がすべて閉じる)
+async fn run(messages: impl Stream<Item = String>, sender: mpsc::UnboundedSender<String>, peer: Peer, routes: ApiRoutes, token: Option<String>) {
+    let session = Arc::new(Mutex::new(Session { token, subscription: None }));
+    // エージェントの停止を要求されたら新しいメッセージを読まない (処理中のリクエストには応答する)
+    let messages = messages.take_until(shutdown::requested());
+    futures_util::pin_mut!(messages);
+    while let Some(text) = messages.next().await {
+        let request = match serde_json::from_str::<RpcRequest>(&text) {
+            Ok(request) => request,
+            Err(e) => {
+                let kind = if serde_json::from_str::<Value>(&text).is_ok() { INVALID_REQUEST } else { PARSE_ERROR };
+                send(&sender, reply(Value::Null, Err(RpcError::new(kind, e.to_string()))));
+                continue;
+            }
+        };
+
+        // auth は順番どおりに処理し、ほかのメソッドは並行して処理する (応答は id で対応づける)
+        if request.method == "auth" {
+            let result = authenticate(&routes, &peer, &session, &request.params).await;
+            respond(&sender, request.id, result);
+            continue;
+        }
+        let (routes, peer, session, sender) = (routes.clone(), peer.clone(), session.clone(), sender.clone());
+        tokio::spawn(async move {
+            let result = handle(&routes, &peer, &session, &sender, &request.method, request.params).await;
+            respond(&sender, request.id, result);
+        });
+    }
+
+    let subscription = session.lock().await.subscription.take();
+    if let Some(subscription) = subscription {
+        subscription.abort();
+    }
+}
+
+// トークンを /api/capabilities で確認する (失敗は認証の失敗として記録され、続けばロックされる)
+async fn authenticate(routes: &ApiRoutes, peer: &Peer, session: &Mutex<Session>, params: &Value) -> Result<Value, RpcError> {
+    let token = params.get("token").and_then(Value::as_str).unwrap_or_default().to_string();
+    let capabilities = call(routes, peer, &token, "capabilities", Value::Null).await?;
+    if capabilities.get("success").and_then(Value::as_bool) != Some(true) {
+        let message = capabilities.get("error").and_then(Value::as_str).unwrap_or("Authentication failed");
+        return Err(RpcError::new(NOT_AUTHENTICATED, message));
+    }
+    session.lock().await.token = Some(token);
+    Ok(capabilities)
+}
+
+async fn handle(
+    routes: &ApiRoutes,
+    peer: &Peer,
+    session: &Arc<Mutex<Session>>,
+    sender: &mpsc::UnboundedSender<String>,
+    method: &str,
+    params: Value,
+) -> Result<Value, RpcError> {
+    let token = session
+        .lock()
+        .await
+        .token
+        .clone()
+        .ok_or_else(|| RpcError::new(NOT_AUTHENTICATED, "Call auth with a token first"))?;
+
+    match method {
+        "subscribe" => {
+            // cursor 未指定の場合は現在位置から通知する
+            let cursor = params.get("cursor").and_then(Value::as_u64);
+            let first = call(routes, peer, &token, "changes/poll", json!({ "cursor": cursor, "wait": 0 })).await?;
+            if first.get("success").and_then(Value::as_bool) != Some(true) {
+                return Ok(first);
+            }
+            let cursor = cursor.or_else(|| first.pointer("/data/cursor").and_then(Value::as_u64)).unwrap_or(0);
+            let task = tokio::spawn(push_changes(routes.clone(), peer.clone(), token, cursor, sender.clone()));
+            if let Some(previous) = session.lock().await.subscription.replace(task) {
+                previous.abort();
+            }
+            Ok(json!({ "success": true, "data": { "cursor": cursor }, "error": null }))
+        }
+        "unsubscribe" => {
+            let subscribed = match session.lock().await.subscription.take() {
+                Some(subscription) => {
+                    subscription.abort();
+                    true
+                }
+                None => false,
+            };
+            Ok(json!({ "success": true, "data": { "subscribed": subscribed }, "error": null }))
+        }
+        _ => call(routes, peer, &token, method, params).await,
+    }
+}
+
+// /api/changes/poll を繰り返し、変更があれば changes の通知として送る
+async fn push_changes(routes: ApiRoutes, peer: Peer, token: String, mut cursor: u64, sender: mpsc::UnboundedSender<String>) {
+    loop {
+        let poll = match call(&routes, &peer, &token, "changes/poll", json!({ "cursor": cursor, "wait": SUBSCRIBE_WAIT_SECS })).await {
+            Ok(poll) if poll.get("success").and_then(Value::as_bool) == Some(true) => poll,
+            // トークンが無効になった場合などは通知をやめる
+            _ => return,
+        };
+        // エージェントの停止で待機が打ち切られた場合は通知をやめる
+        if shutdown::is_requested() {
+            return;
+        }
+        let Some(data) = poll.get("data") else {
+            return;
+        };
+        cursor = data.get("cursor").and_then(Value::as_u64).unwrap_or(cursor);
+        let has_events = data.get("events").and_then(Value::as_array).map(|events| !events.is_empty()).unwrap_or(false);
+        let missed = data.get("missed").and_then(Value::as_bool).unwrap_or(false);
+        if has_events || missed {
+            let notification = json!({ "jsonrpc": "2.0", "method": "changes", "params": data });
+            if sender.send(notification.to_string()).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// API のエンドポイントを呼び出し、JSON の応答を返す (メソッド名は /api/ より後のパス)
+async fn call(routes: &ApiRoutes, peer: &Peer, token: &str, method: &str, params: Value) -> Result<Value, RpcError> {
+    // ファイルの内容をそのまま送受信する /api/file は JSON のメッセージでは呼べない
+    let http_method = ENDPOINTS
+        .iter()
+        .find(|&&(_, path, _)| path != "/api/file" && endpoint_matches(path, &format!("/api/{}", method)))
+        .map(|&(http_method, _, _)| http_method)
+        .ok_or_else(|| RpcError::new(METHOD_NOT_FOUND, format!("Method not found: {}", method)))?;
+
+    let mut params = match params {
+        Value::Object(params) => params,
+        Value::Null => serde_json::Map::new(),
+        _ => return Err(RpcError::new(INVALID_REQUEST, "params must be an object")),
+    };
+    params.insert("token".to_string(), Value::String(token.to_string()));
+
+    let request = if http_method == "GET" {
+        let query: Vec<(String, String)> = params
+            .into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| match value {
+                Value::String(value) => (key, value),
+                value => (key, value.to_string()),
+            })
+            .collect();
+        let query = serde_urlencoded::to_string(query).map_err(|e| RpcError::new(INVALID_REQUEST, e.to_string()))?;
+        Request::builder()
+            .method(Method::GET)
+            .uri(format!("/{}?{}", method, query))
+            .extension(peer.clone())
+            .body(Body::empty())
+    } else {
+        let body = Value::Object(params).to_string();
+        Request::builder()
+            .method(Method::POST)
+            .uri(format!("/{}", method))
+            .header("content-type", "application/json")
+            .header("content-length", body.len())
+            .extension(peer.clone())
+            .body(Body::from(body))
+    }
+    .map_err(|e| RpcError::new(INVALID_REQUEST, e.to_string()))?;
+
+    let response = warp::service(routes.clone())
+        .call(request)
+        .await
+        .map_err(|e| RpcError::new(REQUEST_FAILED, e.to_string()))?;
+    let status = response.status();
+    let bytes = warp::hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| RpcError::new(REQUEST_FAILED, e.to_string()))?;
+
+    // ストリーミング検索は 1 行に 1 つの JSON を返すため配列にする
+    let body = serde_json::from_slice::<Value>(&bytes).unwrap_or_else(|_| {
+        String::from_utf8_lossy(&bytes)
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+            .collect()
+    });
+
+    if status == StatusCode::OK {
+        Ok(body)
+    } else {
+        let message = body
+            .get("error")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .unwrap_or_else(|| status.to_string());
+        Err(RpcError { code: REQUEST_FAILED, message, data: Some(json!({ "status": status.as_u16(), "body": body })) })
+    }
+}
+
+fn reply(id: Value, result: Result<Value, RpcError>) -> String {
+    let response = match result {
+        Ok(result) => RpcResponse { jsonrpc: "2.0", id, result: Some(result), error: None },
+        Err(error) => RpcResponse { jsonrpc: "2.0", id, result: None, error: Some(error) },
+    };
+    serde_json::to_string(&response).unwrap_or_default()
+}
+
+// id のない通知には応答しない
+fn respond(sender: &mpsc::UnboundedSender<String>, id: Option<Value>, result: Result<Value, RpcError>) {
+    if let Some(id) = id {
+        send(sender, reply(id, result));
+    }
+}
+
+fn send(sender: &mpsc::UnboundedSender<String>, text: String) {
+    let _ = sender.send(text);
+}
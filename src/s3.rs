@@ -0,0 +1,311 @@
+//! S3 互換のバケットとのファイルのやり取り (s3_endpoint= / s3_bucket=)
+//!
+//! AWS S3・MinIO・Cloudflare R2 など、署名 V4 に対応したサービスを REST API で直接呼び出す (SDK は使わない)。
+//! 本文はハッシュせずに送る (UNSIGNED-PAYLOAD) ので、大きなファイルも読み込みながら送れる。
+//! s3_prefix= を設定すると、キーはすべてその下に置き、応答のキーからは取り除く。
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+use crate::config::Config;
+use crate::remote::{self, Download, Upload};
+use crate::signing::{hmac_sha256, hmac_sha256_hex};
+use crate::{sha256_hex, xml};
+
+// 1 回の PUT で送れる大きさ (これより大きいファイルはマルチパートが必要)
+pub const MAX_OBJECT_BYTES: u64 = 5 * 1024 * 1024 * 1024;
+
+// キーの長さの上限 (UTF-8 のバイト数)
+const MAX_KEY_BYTES: usize = 1024;
+
+// 一覧で 1 回に返す件数 (S3 の上限と同じ)
+const LIST_PAGE_SIZE: usize = 1000;
+
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// 設定したバケット
+#[derive(Debug, Clone)]
+pub struct Bucket {
+    endpoint: reqwest::Url,
+    region: String,
+    name: String,
+    access_key: String,
+    secret_key: String,
+    path_style: bool,
+    prefix: String,
+}
+
+/// 一覧の結果
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct S3Listing {
+    pub prefix: String,
+    pub objects: Vec<S3Object>,
+    pub prefixes: Vec<String>, // prefix の下の「フォルダ」 (/ で区切った次の段)
+    pub next_continuation: Option<String>, // 続きがあるときに continuation に渡す値
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct S3Object {
+    pub key: String,
+    pub size: u64,
+    pub modified: String, // ISO 8601 (S3 の LastModified のまま)
+    pub etag: String,
+}
+
+impl Bucket {
+    /// 設定からバケットを作る (s3_endpoint= と s3_bucket= がなければ None)
+    pub fn from_config(config: &Config) -> Option<Result<Self, String>> {
+        if config.s3_endpoint.is_empty() || config.s3_bucket.is_empty() {
+            return None;
+        }
+        Some(Self::new(config))
+    }
+
+    fn new(config: &Config) -> Result<Self, String> {
+        let endpoint = reqwest::Url::parse(&config.s3_endpoint).map_err(|_| format!("Invalid s3_endpoint: {}", config.s3_endpoint))?;
+        if !matches!(endpoint.scheme(), "http" | "https") || endpoint.host_str().is_none() {
+            return Err(format!("Invalid s3_endpoint: {}", config.s3_endpoint));
+        }
+        if config.s3_access_key.is_empty() || config.s3_secret_key.is_empty() {
+            return Err("s3_access_key and s3_secret_key are required".to_string());
+        }
+        let prefix = config.s3_prefix.trim_matches('/');
+        Ok(Bucket {
+            endpoint,
+            region: config.s3_region.clone(),
+            name: config.s3_bucket.clone(),
+            access_key: config.s3_access_key.clone(),
+            secret_key: config.s3_secret_key.clone(),
+            path_style: config.s3_path_style,
+            prefix: if prefix.is_empty() { String::new() } else { format!("{}/", prefix) },
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// key の場所 (s3://<bucket>/<s3_prefix><key>。表示や同期の状態の確認に使う)
+    pub fn location(&self, key: &str) -> String {
+        format!("s3://{}/{}{}", self.name, self.prefix, key)
+    }
+
+    /// content を key に送る。ETag を返す
+    pub async fn upload(&self, key: &str, content: Upload) -> Result<String, String> {
+        let (body, size) = content.into_body();
+        self.put(key, body, size).await
+    }
+
+    async fn put(&self, key: &str, body: reqwest::Body, size: u64) -> Result<String, String> {
+        if size > MAX_OBJECT_BYTES {
+            return Err(format!("File too large for S3: {} bytes (limit {} bytes)", size, MAX_OBJECT_BYTES));
+        }
+        let response = self
+            .request(reqwest::Method::PUT, Some(key), &[])
+            .header(reqwest::header::CONTENT_LENGTH, size)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| format!("S3 request failed: {}", e))?;
+        let response = check_status(response).await?;
+        Ok(header_text(&response, "etag"))
+    }
+
+    /// key のダウンロードを始める (本文は Download から読む)
+    pub async fn download(&self, key: &str) -> Result<Download, String> {
+        let response = self
+            .request(reqwest::Method::GET, Some(key), &[])
+            .send()
+            .await
+            .map_err(|e| format!("S3 request failed: {}", e))?;
+        let response = check_status(response).await?;
+        Download::new(response, "S3")
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), String> {
+        let response = self
+            .request(reqwest::Method::DELETE, Some(key), &[])
+            .send()
+            .await
+            .map_err(|e| format!("S3 request failed: {}", e))?;
+        check_status(response).await.map(|_| ())
+    }
+
+    /// prefix の下のオブジェクトと、その次の段の「フォルダ」の一覧
+    pub async fn list(&self, prefix: &str, continuation: Option<&str>) -> Result<S3Listing, String> {
+        self.list_page(prefix, true, continuation).await
+    }
+
+    /// prefix の下のすべてのオブジェクト (「フォルダ」の中も含む)
+    pub async fn list_all(&self, prefix: &str) -> Result<Vec<S3Object>, String> {
+        let mut objects = Vec::new();
+        let mut continuation = None;
+        loop {
+            let page = self.list_page(prefix, false, continuation.as_deref()).await?;
+            objects.extend(page.objects);
+            match page.next_continuation {
+                Some(next) => continuation = Some(next),
+                None => return Ok(objects),
+            }
+        }
+    }
+
+    // delimited なら / で区切った次の段までを返す
+    async fn list_page(&self, prefix: &str, delimited: bool, continuation: Option<&str>) -> Result<S3Listing, String> {
+        let full_prefix = format!("{}{}", self.prefix, prefix);
+        let mut query = vec![
+            ("list-type", "2".to_string()),
+            ("max-keys", LIST_PAGE_SIZE.to_string()),
+            ("prefix", full_prefix),
+        ];
+        if delimited {
+            query.push(("delimiter", "/".to_string()));
+        }
+        if let Some(token) = continuation.filter(|token| !token.is_empty()) {
+            query.push(("continuation-token", token.to_string()));
+        }
+        let response = self
+            .request(reqwest::Method::GET, None, &query)
+            .send()
+            .await
+            .map_err(|e| format!("S3 request failed: {}", e))?;
+        let body = check_status(response).await?.text().await.map_err(|e| format!("S3 request failed: {}", e))?;
+
+        let strip = |key: String| key.strip_prefix(&self.prefix).map(str::to_string).unwrap_or(key);
+        let objects = xml::elements(&body, "Contents")
+            .into_iter()
+            .filter_map(|item| {
+                Some(S3Object {
+                    key: strip(xml::element(item, "Key")?),
+                    size: xml::element(item, "Size").and_then(|size| size.parse().ok()).unwrap_or(0),
+                    modified: xml::element(item, "LastModified").unwrap_or_default(),
+                    etag: xml::element(item, "ETag").unwrap_or_default().trim_matches('"').to_string(),
+                })
+            })
+            .collect();
+        let prefixes = xml::elements(&body, "CommonPrefixes").into_iter().filter_map(|item| xml::element(item, "Prefix")).map(strip).collect();
+        let truncated = xml::element(&body, "IsTruncated").is_some_and(|value| value == "true");
+        Ok(S3Listing {
+            prefix: prefix.to_string(),
+            objects,
+            prefixes,
+            next_continuation: if truncated { xml::element(&body, "NextContinuationToken") } else { None },
+        })
+    }
+
+    // 署名した要求を作る (key が None ならバケットへの要求)
+    fn request(&self, method: reqwest::Method, key: Option<&str>, query: &[(&str, String)]) -> reqwest::RequestBuilder {
+        let host = match self.endpoint.port() {
+            Some(port) => format!("{}:{}", self.endpoint.host_str().unwrap_or_default(), port),
+            None => self.endpoint.host_str().unwrap_or_default().to_string(),
+        };
+        let (host, mut path) = if self.path_style {
+            (host, format!("/{}", uri_encode(&self.name, false)))
+        } else {
+            (format!("{}.{}", self.name, host), String::new())
+        };
+        if let Some(key) = key {
+            path.push('/');
+            path.push_str(&uri_encode(&format!("{}{}", self.prefix, key), true));
+        }
+        if path.is_empty() {
+            path.push('/');
+        }
+
+        let mut pairs: Vec<(String, String)> = query.iter().map(|(name, value)| (uri_encode(name, false), uri_encode(value, false))).collect();
+        pairs.sort();
+        let query = pairs.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join("&");
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let (date, timestamp) = amz_date(now);
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method, path, query, host, UNSIGNED_PAYLOAD, timestamp, signed_headers, UNSIGNED_PAYLOAD
+        );
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", timestamp, scope, sha256_hex(canonical.as_bytes()));
+        let mut key = hmac_sha256(format!("AWS4{}", self.secret_key).as_bytes(), date.as_bytes());
+        for part in [self.region.as_str(), "s3", "aws4_request"] {
+            key = hmac_sha256(&key, part.as_bytes());
+        }
+        let signature = hmac_sha256_hex(&key, string_to_sign.as_bytes());
+
+        let mut url = format!("{}://{}{}", self.endpoint.scheme(), host, path);
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&query);
+        }
+        remote::client()
+            .request(method, url)
+            .header("x-amz-content-sha256", UNSIGNED_PAYLOAD)
+            .header("x-amz-date", timestamp)
+            .header(
+                reqwest::header::AUTHORIZATION,
+                format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}", self.access_key, scope, signed_headers, signature),
+            )
+    }
+}
+
+/// クライアントが指定したキー (s3_prefix= より後の部分) を確認する
+pub fn validate_key(key: &str) -> Result<(), String> {
+    if key.is_empty() || key.starts_with('/') || key.ends_with('/') {
+        return Err(format!("Invalid S3 key: {}", key));
+    }
+    if key.len() > MAX_KEY_BYTES {
+        return Err(format!("S3 key too long: {} bytes (limit {} bytes)", key.len(), MAX_KEY_BYTES));
+    }
+    if key.split('/').any(|part| part == "." || part == "..") || key.chars().any(char::is_control) {
+        return Err(format!("Invalid S3 key: {}", key));
+    }
+    Ok(())
+}
+
+/// S3 の LastModified (2024-05-01T09:12:44.000Z) を UNIX 秒にする
+pub fn parse_modified(value: &str) -> Option<u64> {
+    let number = |range: std::ops::Range<usize>| value.get(range).and_then(|part| part.parse::<i64>().ok());
+    let days = crate::days_from_civil(number(0..4)?, number(5..7)? as u32, number(8..10)? as u32);
+    let secs = days * 86_400 + number(11..13)? * 3600 + number(14..16)? * 60 + number(17..19)?;
+    u64::try_from(secs).ok()
+}
+
+// 2xx 以外はエラーの本文の Code と Message を返す
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, String> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    match (xml::element(&body, "Code"), xml::element(&body, "Message")) {
+        (Some(code), Some(message)) => Err(format!("S3 error {}: {}: {}", status.as_u16(), code, message)),
+        (Some(code), None) => Err(format!("S3 error {}: {}", status.as_u16(), code)),
+        _ => Err(format!("S3 error {}", status.as_u16())),
+    }
+}
+
+fn header_text(response: &reqwest::Response, name: &str) -> String {
+    let value = response.headers().get(name).and_then(|value| value.to_str().ok()).unwrap_or_default();
+    value.trim_matches('"').to_string()
+}
+
+// 署名の日付 (YYYYMMDD) と日時 (YYYYMMDDTHHMMSSZ)
+fn amz_date(secs: u64) -> (String, String) {
+    let (year, month, day) = crate::civil_from_days((secs / 86_400) as i64);
+    let time = secs % 86_400;
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let timestamp = format!("{}T{:02}{:02}{:02}Z", date, time / 3600, time / 60 % 60, time % 60);
+    (date, timestamp)
+}
+
+// 署名 V4 の URI エンコード (英数字と -_.~ 以外をすべて %XX にする。keep_slash ならキーの / は残す。WebDAV のパスにも使う)
+pub(crate) fn uri_encode(value: &str, keep_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if keep_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
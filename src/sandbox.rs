@@ -0,0 +1,199 @@
+//! プラグインとスクリプトのファイル API
+//!
+//! WASM プラグイン (plugins) と rhai スクリプト (script) は、ファイルにこの Sandbox を通してだけアクセスする。
+//! 範囲は許可ルート (トークンに許可ルートがあればその範囲) の中で保管庫の外に限り、
+//! 操作ごとにトークンの allow= と、通常の API と同じポリシー・クォータ・フックを確認する。
+//! 書き込みとコピーの内容は、プラグインからでもスクリプトからでもここでウイルススキャンに通す。
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::audit::AuditLog;
+use crate::changes::ChangeLog;
+use crate::config::Config;
+use crate::handlers::{check_access, write_target};
+use crate::{copy, hooks, paths, policy, quota, scan, writequota};
+
+// read で渡すファイルの大きさの上限 (プラグインのメモリやスクリプトの文字列に収まるように)
+pub const MAX_FILE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// ファイルにアクセスできる範囲 (呼び出したトークンの許可ルートと操作)
+#[derive(Clone)]
+pub struct Sandbox {
+    pub config: Arc<Config>, // トークンの許可ルートを反映した設定
+    pub tier: String,        // 書き込みの日ごとの上限を数えるティア
+    pub read: bool,          // トークンに read が許可されている
+    pub write: bool,         // トークンに write が許可されている
+    pub moves: bool,         // トークンに move が許可されている
+    pub copies: bool,        // トークンに copy が許可されている
+    pub changes: Arc<ChangeLog>,
+    pub audit: Arc<AuditLog>,
+}
+
+impl Sandbox {
+    // アクセスは、許可ルートの中で保管庫の外に限る
+    fn check(&self, raw: &str) -> Result<(), String> {
+        paths::validate(raw).map_err(|e| format!("Invalid path {}: {}", e.path, e.reason))?;
+        if self.config.allowed_roots.is_empty() {
+            return Err("Plugins and scripts can only access files under allowed_root= folders".to_string());
+        }
+        if policy::is_allowed(&self.config.vault_roots, Path::new(raw)) {
+            return Err(format!("Plugins and scripts cannot access vault folders: {}", raw));
+        }
+        Ok(())
+    }
+
+    // 書き込む内容を API の書き込みと同じスキャナーで確認する (検出したら書き込まない)
+    fn scan(&self, target: &Path, data: &[u8]) -> Result<(), String> {
+        if !self.config.scanner.enabled() {
+            return Ok(());
+        }
+        scan::check(&self.config.scanner, &self.audit, target, data).map_err(|e| e.message())
+    }
+
+    fn require(allowed: bool, operation: &str) -> Result<(), String> {
+        if allowed {
+            Ok(())
+        } else {
+            Err(format!("Operation '{}' is not allowed for this token", operation))
+        }
+    }
+
+    pub fn read(&self, raw: &str) -> Result<Vec<u8>, String> {
+        Self::require(self.read, "read")?;
+        self.check(raw)?;
+        let path = Path::new(raw);
+        check_access(&self.config, path, policy::Action::Read)?;
+        let metadata = fs::metadata(path).map_err(|e| format!("{}: {}", raw, e))?;
+        if !metadata.is_file() {
+            return Err(format!("Not a file: {}", raw));
+        }
+        if metadata.len() > MAX_FILE_BYTES {
+            return Err(format!("File too large: {} bytes (limit {} bytes)", metadata.len(), MAX_FILE_BYTES));
+        }
+        fs::read(path).map_err(|e| format!("{}: {}", raw, e))
+    }
+
+    /// 書き込んだ実際のパスを返す (隔離ルートでは隔離フォルダ内)。origin は監査ログに残す呼び出し元
+    pub fn write(&self, origin: &str, raw: &str, data: &[u8]) -> Result<PathBuf, String> {
+        Self::require(self.write, "write")?;
+        self.check(raw)?;
+        let length = data.len() as u64;
+        writequota::check_file(length)?;
+        let target = write_target(&self.config, Path::new(raw))?;
+        quota::check(&self.config.quotas, &target, None, quota::Usage { bytes: length, files: 1 })?;
+        quota::check_free_space(&target, length)?;
+        self.scan(&target, data)?;
+        hooks::before(&self.config.hooks, "write", &target, None)?;
+        policy::save_version(&self.config.policies, &target)?;
+        writequota::reserve(&self.tier, length)?;
+        if let Err(e) = fs::write(&target, data) {
+            writequota::refund(&self.tier, length);
+            return Err(format!("{}: {}", target.display(), e));
+        }
+        self.changes.record("write", &target.to_string_lossy(), None);
+        hooks::after(&self.config.hooks, "write", &target, None);
+        self.audit.record("write", &target.to_string_lossy(), origin);
+        Ok(target)
+    }
+
+    pub fn list(&self, raw: &str) -> Result<Vec<String>, String> {
+        Self::require(self.read, "read")?;
+        self.check(raw)?;
+        let path = Path::new(raw);
+        check_access(&self.config, path, policy::Action::Read)?;
+        let mut names: Vec<String> = fs::read_dir(path)
+            .map_err(|e| format!("{}: {}", raw, e))?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    /// ファイルかフォルダがあれば true (範囲外のパスはエラー)
+    pub fn exists(&self, raw: &str) -> Result<bool, String> {
+        Self::require(self.read, "read")?;
+        self.check(raw)?;
+        let path = Path::new(raw);
+        check_access(&self.config, path, policy::Action::Read)?;
+        Ok(path.exists())
+    }
+
+    pub fn is_dir(&self, raw: &str) -> Result<bool, String> {
+        Ok(self.exists(raw)? && Path::new(raw).is_dir())
+    }
+
+    pub fn create_dir(&self, origin: &str, raw: &str) -> Result<PathBuf, String> {
+        Self::require(self.write, "write")?;
+        self.check(raw)?;
+        let target = write_target(&self.config, Path::new(raw))?;
+        if target.is_dir() {
+            return Ok(target);
+        }
+        quota::check(&self.config.quotas, &target, None, quota::Usage { bytes: 0, files: 1 })?;
+        fs::create_dir_all(&target).map_err(|e| format!("{}: {}", target.display(), e))?;
+        self.changes.record("create", &target.to_string_lossy(), None);
+        self.audit.record("create", &target.to_string_lossy(), origin);
+        Ok(target)
+    }
+
+    /// ファイルかフォルダを移動 (名前の変更) し、移動先の実際のパスを返す
+    pub fn rename(&self, origin: &str, from: &str, to: &str) -> Result<PathBuf, String> {
+        Self::require(self.moves, "move")?;
+        self.check(from)?;
+        self.check(to)?;
+        let source = Path::new(from);
+        check_access(&self.config, source, policy::Action::Write)?;
+        let destination = write_target(&self.config, Path::new(to))?;
+        if !source.exists() {
+            return Err(format!("Source does not exist: {}", from));
+        }
+        if destination.exists() {
+            return Err(format!("Destination already exists: {}", destination.display()));
+        }
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create destination directory: {}", e))?;
+        }
+        quota::check(&self.config.quotas, &destination, Some(source), quota::usage_of(source))?;
+        hooks::before(&self.config.hooks, "move", source, Some(&destination))?;
+        fs::rename(source, &destination).map_err(|e| format!("{} -> {}: {}", from, destination.display(), e))?;
+        self.changes.record("move", from, Some(&destination.to_string_lossy()));
+        hooks::after(&self.config.hooks, "move", source, Some(&destination));
+        self.audit.record("move", &destination.to_string_lossy(), &format!("{} from {}", origin, from));
+        Ok(destination)
+    }
+
+    /// ファイルをコピーし、コピー先の実際のパスを返す (フォルダはコピーしない)
+    pub fn copy(&self, origin: &str, from: &str, to: &str) -> Result<PathBuf, String> {
+        Self::require(self.copies, "copy")?;
+        self.check(from)?;
+        self.check(to)?;
+        let source = Path::new(from);
+        check_access(&self.config, source, policy::Action::Read)?;
+        if !source.is_file() {
+            return Err(format!("Not a file: {}", from));
+        }
+        let destination = write_target(&self.config, Path::new(to))?;
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create destination directory: {}", e))?;
+        }
+        let added = quota::usage_of(source);
+        quota::check(&self.config.quotas, &destination, None, added)?;
+        quota::check_free_space(&destination, added.bytes)?;
+        writequota::check_file(added.bytes)?;
+        if self.config.scanner.enabled() {
+            let data = fs::read(source).map_err(|e| format!("{}: {}", from, e))?;
+            self.scan(&destination, &data)?;
+        }
+        policy::save_version(&self.config.policies, &destination)?;
+        writequota::reserve(&self.tier, added.bytes)?;
+        if let Err(e) = copy::file(source, &destination) {
+            writequota::refund(&self.tier, added.bytes);
+            return Err(format!("{} -> {}: {}", from, destination.display(), e));
+        }
+        self.changes.record("copy", from, Some(&destination.to_string_lossy()));
+        self.audit.record("copy", &destination.to_string_lossy(), &format!("{} from {}", origin, from));
+        Ok(destination)
+    }
+}
@@ -0,0 +1,180 @@
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use utoipa::ToSchema;
+
+use crate::audit::AuditLog;
+
+// clamd の応答を待つ最長時間
+const CLAMD_TIMEOUT: Duration = Duration::from_secs(60);
+
+// INSTREAM で一度に送る大きさ
+const CLAMD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// 書き込む内容を確認するウイルススキャナー
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub enum Scanner {
+    #[default]
+    None,
+    Clamd(String),   // clamd のアドレス (host:port、Windows 以外ではソケットのパスも可)
+    Command(String), // 内容を標準入力に渡すコマンド。終了コード 0 は問題なし、1 は検出
+}
+
+/// スキャナーが検出した内容の書き込みを拒否した理由
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ContentRejected {
+    pub content_rejected: bool,
+    pub path: String,
+    pub signature: String,
+}
+
+pub enum ScanError {
+    Rejected(String), // 検出したシグネチャ名
+    Failed(String),   // スキャナーを実行できなかった
+}
+
+impl ScanError {
+    /// クライアントや同期の結果に返すメッセージ
+    pub fn message(&self) -> String {
+        match self {
+            ScanError::Rejected(signature) => format!("Content rejected by virus scan: {}", signature),
+            ScanError::Failed(e) => format!("Virus scan failed: {}", e),
+        }
+    }
+}
+
+/// path に書き込む内容をスキャンし、拒否した場合はログと監査ログに残す (ブロッキング)
+pub fn check(scanner: &Scanner, audit: &AuditLog, path: &Path, data: &[u8]) -> Result<(), ScanError> {
+    record(audit, path, scanner.scan(data))
+}
+
+/// コピーする source (フォルダなら中のファイルすべて) をスキャンする。監査ログには target 側のパスを残す
+pub fn check_tree(scanner: &Scanner, audit: &AuditLog, source: &Path, target: &Path) -> Result<(), ScanError> {
+    for entry in walkdir::WalkDir::new(source) {
+        let entry = entry.map_err(|e| ScanError::Failed(e.to_string()))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let data = std::fs::read(entry.path()).map_err(|e| ScanError::Failed(format!("{}: {}", entry.path().display(), e)))?;
+        let relative = entry.path().strip_prefix(source).unwrap_or(Path::new(""));
+        check(scanner, audit, &target.join(relative), &data)?;
+    }
+    Ok(())
+}
+
+/// スキャンの結果をログと監査ログに残す (別スレッドでスキャンした場合)
+pub fn record(audit: &AuditLog, path: &Path, result: Result<(), ScanError>) -> Result<(), ScanError> {
+    match &result {
+        Ok(()) => {}
+        Err(ScanError::Rejected(signature)) => {
+            let path = path.to_string_lossy();
+            log!("🦠 スキャナーが検出したため書き込みを拒否しました: {} ({})", path, signature);
+            audit.record("content_rejected", &path, signature);
+        }
+        Err(ScanError::Failed(e)) => log_error!("⚠️ ウイルススキャンに失敗しました: {}", e),
+    }
+    result
+}
+
+impl Scanner {
+    pub fn enabled(&self) -> bool {
+        !matches!(self, Scanner::None)
+    }
+
+    /// 内容をスキャンする。スキャナーが使えない場合も書き込みは拒否する
+    pub fn scan(&self, data: &[u8]) -> Result<(), ScanError> {
+        match self {
+            Scanner::None => Ok(()),
+            Scanner::Clamd(address) => scan_clamd(address, data),
+            Scanner::Command(command) => scan_command(command, data),
+        }
+    }
+}
+
+fn scan_clamd(address: &str, data: &[u8]) -> Result<(), ScanError> {
+    let failed = |e: std::io::Error| ScanError::Failed(format!("clamd ({}): {}", address, e));
+
+    #[cfg(unix)]
+    if address.starts_with('/') {
+        let mut stream = std::os::unix::net::UnixStream::connect(address).map_err(failed)?;
+        stream.set_read_timeout(Some(CLAMD_TIMEOUT)).map_err(failed)?;
+        return clamd_instream(&mut stream, data).map_err(failed).and_then(clamd_result);
+    }
+
+    let mut stream = std::net::TcpStream::connect(address).map_err(failed)?;
+    stream.set_read_timeout(Some(CLAMD_TIMEOUT)).map_err(failed)?;
+    clamd_instream(&mut stream, data).map_err(failed).and_then(clamd_result)
+}
+
+// INSTREAM: 長さ (4 バイト、ビッグエンディアン) 付きのチャンクを送り、長さ 0 で終える
+fn clamd_instream(stream: &mut (impl Read + Write), data: &[u8]) -> std::io::Result<String> {
+    stream.write_all(b"zINSTREAM\0")?;
+    for chunk in data.chunks(CLAMD_CHUNK_SIZE) {
+        stream.write_all(&(chunk.len() as u32).to_be_bytes())?;
+        stream.write_all(chunk)?;
+    }
+    stream.write_all(&0u32.to_be_bytes())?;
+    stream.flush()?;
+
+    // z で始まるコマンドの応答は \0 で終わる
+    let mut reply = Vec::new();
+    let mut buffer = [0u8; 256];
+    while !reply.contains(&0) {
+        let n = stream.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        reply.extend_from_slice(&buffer[..n]);
+    }
+    let end = reply.iter().position(|&b| b == 0).unwrap_or(reply.len());
+    Ok(String::from_utf8_lossy(&reply[..end]).trim().to_string())
+}
+
+// 応答は "stream: OK"、"stream: <シグネチャ> FOUND"、"<理由> ERROR" のいずれか
+fn clamd_result(reply: String) -> Result<(), ScanError> {
+    let result = reply.strip_prefix("stream:").unwrap_or(&reply).trim();
+    if result == "OK" {
+        Ok(())
+    } else if let Some(signature) = result.strip_suffix("FOUND") {
+        Err(ScanError::Rejected(signature.trim().to_string()))
+    } else {
+        Err(ScanError::Failed(format!("clamd: {}", reply)))
+    }
+}
+
+fn scan_command(command_line: &str, data: &[u8]) -> Result<(), ScanError> {
+    let mut parts = command_line.split_whitespace();
+    let program = parts.next().ok_or_else(|| ScanError::Failed("scan command is empty".to_string()))?;
+    let mut child = Command::new(program)
+        .args(parts)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| ScanError::Failed(format!("Failed to run {}: {}", program, e)))?;
+
+    // 出力を読みながら書き込まないと、パイプが詰まって止まることがある
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = data.to_vec();
+    let writer = std::thread::spawn(move || {
+        let _ = stdin.write_all(&input);
+    });
+    let output = child
+        .wait_with_output()
+        .map_err(|e| ScanError::Failed(format!("Failed to run {}: {}", program, e)))?;
+    let _ = writer.join();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let message = stdout.lines().map(str::trim).rfind(|line| !line.is_empty()).unwrap_or_default().to_string();
+    match output.status.code() {
+        Some(0) => Ok(()),
+        Some(1) => Err(ScanError::Rejected(if message.is_empty() { "detected".to_string() } else { message })),
+        _ => Err(ScanError::Failed(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))),
+    }
+}
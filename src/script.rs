@@ -0,0 +1,168 @@
+//! サーバー側のスクリプト (POST /api/script, allow_script=true のときだけ)
+//!
+//! rhai のスクリプトを 1 回のリクエストで実行し、複数のファイルの操作 (規則による名前の変更、条件付きの移動など) を
+//! まとめて行う。スクリプトから使えるファイルの関数は下のものだけで、どれも sandbox::Sandbox を通して
+//! 許可ルートの中に限られる (import・eval は使えない)。リクエストの params はスクリプトの変数 params になる。
+//!
+//!   read(path) -> String            UTF-8 のテキストファイルを読む (read)
+//!   write(path, text) -> String     書き込んで実際のパスを返す (write)
+//!   list(path) -> Array             フォルダ内の名前 (read)
+//!   exists(path) / is_dir(path)     (read)
+//!   mkdir(path) -> String           (write)
+//!   rename(from, to) -> String      移動・名前の変更 (move)
+//!   copy(from, to) -> String        ファイルのコピー (copy)
+//!   file_name(path) / parent(path) / extension(path) / join_path(dir, name)   パスの操作 (ファイルにはアクセスしない)
+//!
+//! print / debug の出力は応答の output に入る。
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Scope};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+use crate::sandbox::{Sandbox, MAX_FILE_BYTES};
+
+// 1 回の実行で評価できる操作の数 (無限ループを止める)
+const MAX_OPERATIONS: u64 = 50_000_000;
+
+// 1 回の実行にかけられる時間
+const MAX_RUN_TIME: Duration = Duration::from_secs(60);
+
+// 配列・マップの要素数の上限
+const MAX_ITEMS: usize = 100_000;
+
+// output に残す行数の上限
+const MAX_OUTPUT_LINES: usize = 1000;
+
+// 監査ログに残す呼び出し元
+const ORIGIN: &str = "script";
+
+type Fallible<T> = Result<T, Box<EvalAltResult>>;
+
+/// スクリプトの実行結果
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ScriptOutput {
+    pub result: serde_json::Value, // スクリプトの最後の式の値
+    pub output: Vec<String>,       // print / debug の出力
+    pub changed: Vec<String>,      // 書き込み・作成・移動・コピーしたパス (実際のパス)
+}
+
+/// source を実行する。ファイルへのアクセスは sandbox の範囲に限る
+pub fn run(source: &str, params: &serde_json::Value, sandbox: Sandbox) -> Result<ScriptOutput, String> {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let changed = Rc::new(RefCell::new(Vec::new()));
+    let engine = engine(sandbox, &output, &changed);
+
+    let mut scope = Scope::new();
+    let params = rhai::serde::to_dynamic(params).map_err(|e| format!("Invalid params: {}", e))?;
+    scope.push_dynamic("params", params);
+
+    let result = engine.eval_with_scope::<Dynamic>(&mut scope, source).map_err(|e| match *e {
+        EvalAltResult::ErrorTerminated(..) => format!("Script ran longer than {} seconds and was stopped", MAX_RUN_TIME.as_secs()),
+        EvalAltResult::ErrorTooManyOperations(..) => "Script exceeded the operation limit and was stopped".to_string(),
+        e => format!("Script failed: {}", e),
+    })?;
+    let result = rhai::serde::from_dynamic(&result).map_err(|e| format!("Script returned a value that cannot be converted to JSON: {}", e))?;
+
+    Ok(ScriptOutput {
+        result,
+        output: output.take(),
+        changed: changed.take(),
+    })
+}
+
+fn engine(sandbox: Sandbox, output: &Rc<RefCell<Vec<String>>>, changed: &Rc<RefCell<Vec<String>>>) -> Engine {
+    let mut engine = Engine::new();
+
+    // スクリプトからほかのファイルを読み込ませない
+    engine.set_module_resolver(DummyModuleResolver::new());
+    engine.disable_symbol("eval");
+
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(64);
+    engine.set_max_expr_depths(64, 64);
+    engine.set_max_string_size(MAX_FILE_BYTES as usize);
+    engine.set_max_array_size(MAX_ITEMS);
+    engine.set_max_map_size(MAX_ITEMS);
+    let deadline = Instant::now() + MAX_RUN_TIME;
+    engine.on_progress(move |_| (Instant::now() > deadline).then(|| Dynamic::from("timeout")));
+
+    let print_output = output.clone();
+    engine.on_print(move |text| push_line(&print_output, text));
+    let debug_output = output.clone();
+    engine.on_debug(move |text, _, _| push_line(&debug_output, text));
+
+    let files = sandbox.clone();
+    engine.register_fn("read", move |path: &str| -> Fallible<String> {
+        let data = files.read(path)?;
+        String::from_utf8(data).map_err(|_| format!("Not a UTF-8 text file: {}", path).into())
+    });
+
+    let files = sandbox.clone();
+    let written = changed.clone();
+    engine.register_fn("write", move |path: &str, text: &str| -> Fallible<String> {
+        let target = files.write(ORIGIN, path, text.as_bytes())?;
+        Ok(record(&written, &target))
+    });
+
+    let files = sandbox.clone();
+    engine.register_fn("list", move |path: &str| -> Fallible<Array> {
+        Ok(files.list(path)?.into_iter().map(Dynamic::from).collect())
+    });
+
+    let files = sandbox.clone();
+    engine.register_fn("exists", move |path: &str| -> Fallible<bool> { Ok(files.exists(path)?) });
+
+    let files = sandbox.clone();
+    engine.register_fn("is_dir", move |path: &str| -> Fallible<bool> { Ok(files.is_dir(path)?) });
+
+    let files = sandbox.clone();
+    let created = changed.clone();
+    engine.register_fn("mkdir", move |path: &str| -> Fallible<String> {
+        let target = files.create_dir(ORIGIN, path)?;
+        Ok(record(&created, &target))
+    });
+
+    let files = sandbox.clone();
+    let moved = changed.clone();
+    engine.register_fn("rename", move |from: &str, to: &str| -> Fallible<String> {
+        let target = files.rename(ORIGIN, from, to)?;
+        Ok(record(&moved, &target))
+    });
+
+    let files = sandbox;
+    let copied = changed.clone();
+    engine.register_fn("copy", move |from: &str, to: &str| -> Fallible<String> {
+        let target = files.copy(ORIGIN, from, to)?;
+        Ok(record(&copied, &target))
+    });
+
+    engine.register_fn("file_name", |path: &str| -> String {
+        Path::new(path).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default()
+    });
+    engine.register_fn("parent", |path: &str| -> String {
+        Path::new(path).parent().map(|parent| parent.to_string_lossy().to_string()).unwrap_or_default()
+    });
+    engine.register_fn("extension", |path: &str| -> String {
+        Path::new(path).extension().map(|ext| ext.to_string_lossy().to_string()).unwrap_or_default()
+    });
+    engine.register_fn("join_path", |dir: &str, name: &str| -> String { Path::new(dir).join(name).to_string_lossy().to_string() });
+
+    engine
+}
+
+fn push_line(output: &Rc<RefCell<Vec<String>>>, text: &str) {
+    let mut output = output.borrow_mut();
+    if output.len() < MAX_OUTPUT_LINES {
+        output.push(text.to_string());
+    }
+}
+
+fn record(changed: &Rc<RefCell<Vec<String>>>, target: &Path) -> String {
+    let target = target.to_string_lossy().to_string();
+    changed.borrow_mut().push(target.clone());
+    target
+}
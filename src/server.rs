@@ -0,0 +1,1315 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use futures_util::{FutureExt, Stream, StreamExt, TryStreamExt};
+use warp::{Filter, Rejection, Reply};
+use warp::hyper::body::Buf;
+use warp::filters::BoxedFilter;
+use warp::http::Method;
+use tokio::sync::OwnedSemaphorePermit;
+
+use crate::apiversion::{self, ApiVersion};
+use crate::audit::AuditLog;
+use crate::auth::{Auth, ClientAuth, Lockout, Operation};
+use crate::changes::ChangeLog;
+use crate::clients::ClientRegistry;
+use crate::config::Config;
+use crate::dirsize::DirSizeCache;
+use crate::hashcache::HashCache;
+use crate::handlers::*;
+use crate::jobs::JobStore;
+use crate::listcache::ListCache;
+use crate::plugins::PluginHost;
+use crate::ratelimit::IpRateLimiter;
+use crate::reload::{self, LiveConfig};
+use crate::trash::Trash;
+use crate::vault::Vault;
+use crate::{cleanup, compress, concurrency, index, ipfilter, jobs, logs, mdns, openapi, outbound, policy, ratelimit, rpc, shares, shutdown, socket, sync, timeout, tls, tempfiles, trash, webui, writequota};
+/// 待ち受け (listener= の allow=) で許可していない操作のリクエストの拒否理由
+#[derive(Debug)]
+struct ListenerForbidden {
+    operation: Operation,
+}
+
+impl warp::reject::Reject for ListenerForbidden {}
+
+/// 本文が上限を超えたリクエストの拒否理由 (length が None なら Content-Length がない)
+#[derive(Debug)]
+struct BodyTooLarge {
+    length: Option<u64>,
+    limit: u64,
+}
+
+impl warp::reject::Reject for BodyTooLarge {}
+
+// Content-Length で本文の大きさを確認してから読み込む (Content-Length のないリクエストは大きさが分からないため拒否する)
+fn body_limit(live: &LiveConfig, endpoint: &'static str) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let live = live.clone();
+    warp::header::optional::<u64>("content-length")
+        .and_then(move |length: Option<u64>| {
+            let limit = live.read().unwrap().body_limit(endpoint);
+            async move {
+                match length {
+                    Some(length) if length <= limit => Ok(()),
+                    length => Err(warp::reject::custom(BodyTooLarge { length, limit })),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+// PUT /api/file の本文をチャンクごとの Bytes の列にする
+fn upload_body(body: impl Stream<Item = Result<impl Buf, warp::Error>> + Send + 'static) -> UploadBody {
+    body.map_ok(|mut chunk| chunk.copy_to_bytes(chunk.remaining())).boxed()
+}
+
+// レート制限の拒否を 429 と JSON のエラーにする (それ以外の拒否は warp の既定の処理に任せる)
+async fn handle_rejection(rejection: Rejection) -> Result<warp::reply::Response, Rejection> {
+    if let Some(too_large) = rejection.find::<BodyTooLarge>() {
+        let (status, error) = match too_large.length {
+            Some(length) => (
+                warp::http::StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Request body too large: {} bytes (limit {} bytes)", length, too_large.limit),
+            ),
+            None => (
+                warp::http::StatusCode::LENGTH_REQUIRED,
+                "Content-Length header is required".to_string(),
+            ),
+        };
+        let body = warp::reply::json(&ApiResponse::<()> {
+            success: false,
+            data: None,
+            error: Some(error),
+        });
+        return Ok(warp::reply::with_status(body, status).into_response());
+    }
+
+    if let Some(unsupported) = rejection.find::<apiversion::Unsupported>() {
+        let body = warp::reply::json(&ApiResponse::<()> {
+            success: false,
+            data: None,
+            error: Some(format!(
+                "Unsupported API version: {} (supported: {})",
+                unsupported.version,
+                apiversion::supported_names().join(", ")
+            )),
+        });
+        return Ok(warp::reply::with_status(body, warp::http::StatusCode::NOT_FOUND).into_response());
+    }
+
+    if let Some(forbidden) = rejection.find::<ipfilter::Forbidden>() {
+        let body = warp::reply::json(&ApiResponse::<()> {
+            success: false,
+            data: None,
+            error: Some(format!("Access denied: {} is not in the allowed addresses", forbidden.client)),
+        });
+        return Ok(warp::reply::with_status(body, warp::http::StatusCode::FORBIDDEN).into_response());
+    }
+
+    if let Some(forbidden) = rejection.find::<ListenerForbidden>() {
+        let body = warp::reply::json(&ApiResponse::<()> {
+            success: false,
+            data: None,
+            error: Some(format!("Operation '{}' is not allowed on this listener", forbidden.operation.name())),
+        });
+        return Ok(warp::reply::with_status(body, warp::http::StatusCode::FORBIDDEN).into_response());
+    }
+
+    if let Some(timed_out) = rejection.find::<timeout::TimedOut>() {
+        let body = warp::reply::json(&ApiResponse::<()> {
+            success: false,
+            data: None,
+            error: Some(format!("Operation timed out after {} seconds", timed_out.secs)),
+        });
+        return Ok(warp::reply::with_status(body, warp::http::StatusCode::GATEWAY_TIMEOUT).into_response());
+    }
+
+    if let Some(busy) = rejection.find::<concurrency::Busy>() {
+        let body = warp::reply::json(&concurrency::BusyResponse::from(busy));
+        let reply = warp::reply::with_status(body, warp::http::StatusCode::SERVICE_UNAVAILABLE);
+        return Ok(warp::reply::with_header(reply, "retry-after", busy.retry_after_secs.to_string()).into_response());
+    }
+
+    match rejection.find::<ratelimit::TooManyRequests>() {
+        Some(limited) => {
+            let body = warp::reply::json(&ratelimit::TooManyRequestsResponse {
+                success: false,
+                data: None,
+                error: Some(format!("Too many requests; retry after {} seconds", limited.retry_after_secs)),
+                retry_after_secs: limited.retry_after_secs,
+            });
+            let reply = warp::reply::with_status(body, warp::http::StatusCode::TOO_MANY_REQUESTS);
+            Ok(warp::reply::with_header(reply, "retry-after", limited.retry_after_secs.to_string()).into_response())
+        }
+        None => Err(rejection),
+    }
+}
+
+fn new_auth(config: &Config) -> Auth {
+    let lockout = Lockout::new(
+        config.auth_lockout_failures,
+        std::time::Duration::from_secs(config.auth_lockout_window_secs),
+        std::time::Duration::from_secs(config.auth_lockout_secs),
+    );
+    Auth::new(config.token_hash.clone(), &config.token_meta, &config.token_tiers, &config.allowed_operations, &config.allowed_roots, lockout)
+}
+
+/// 設定どおりに API サーバーを起動する (TCP / TLS / ソケット)。サーバーが停止するまで戻らない
+pub async fn start_api_server(mut config: Config) {
+    let mut auth = new_auth(&config);
+    
+    log!("✅ サーバー起動中...");
+
+    // ソケットで待ち受ける場合は TCP も TLS も使わない
+    let socket_mode = !config.socket.is_empty();
+    if let Err(e) = resolve_port(&mut config) {
+        log_error!("❌ サーバー起動エラー: {}", e);
+        port_in_use_help(&config);
+        return;
+    }
+    let listeners = if socket_mode { Vec::new() } else { config.effective_listeners() };
+
+    // ループバック以外で待ち受ける場合、トークンが平文で LAN に流れないよう TLS を必須にする (allow_insecure_lan=true なら警告だけ)
+    let insecure: Vec<_> = listeners.iter().filter(|listener| !listener.tls && !listener.address.ip().is_loopback()).collect();
+    if config.allow_insecure_lan {
+        for listener in &insecure {
+            log_error!("⚠️ {} では TLS なしで待ち受けます。トークンとファイルの内容が平文でネットワークに流れます (allow_insecure_lan=true)", listener.address);
+        }
+    } else if let Some(listener) = insecure.first() {
+        if config.listeners.is_empty() {
+            log_error!("❌ {} で待ち受けるには TLS が必要です (tls_cert= と tls_key=、または tls_self_signed=true を設定してください)", config.bind_address);
+        } else {
+            log_error!("❌ {} で待ち受けるには TLS が必要です (listener= に tls=true を付けてください)", listener.address);
+        }
+        return;
+    }
+    let tls = if listeners.iter().any(|listener| listener.tls) {
+        match config.tls_paths() {
+            Some(paths) => Some(paths),
+            None => {
+                log_error!("❌ TLS で待ち受けるには tls_cert= と tls_key=、または tls_self_signed=true を設定してください");
+                return;
+            }
+        }
+    } else {
+        None
+    };
+    if let Some((cert, key)) = &tls {
+        if config.tls_self_signed {
+            // 証明書には LAN で待ち受けるアドレスを含める
+            let address = listeners
+                .iter()
+                .filter(|listener| listener.tls)
+                .map(|listener| listener.address.ip())
+                .find(|ip| !ip.is_loopback())
+                .unwrap_or(config.bind_address);
+            match tls::ensure_self_signed(cert, key, address) {
+                Ok(true) => log!("🔐 自己署名の証明書を生成しました: {}", cert.display()),
+                Ok(false) => {}
+                Err(e) => {
+                    log_error!("❌ {}", e);
+                    return;
+                }
+            }
+        }
+        if let Err(e) = tls::check_files(cert, key) {
+            log_error!("❌ TLS の証明書を読み込めません: {}", e);
+            return;
+        }
+    } else if !config.tls_client_ca.is_empty() {
+        log_error!("❌ クライアント証明書の認証 (tls_client_ca=) には TLS が必要です");
+        return;
+    }
+
+    // 相互 TLS: CA が署名したクライアント証明書のない接続はハンドシェイクで拒否される
+    let client_ca = (!config.tls_client_ca.is_empty()).then(|| PathBuf::from(&config.tls_client_ca));
+    if let Some(ca) = &client_ca {
+        if let Err(e) = tls::check_pem(ca) {
+            log_error!("❌ クライアント証明書の CA を読み込めません: {}", e);
+            return;
+        }
+        log!("🔐 クライアント証明書を必須にします (CA: {})", ca.display());
+        if config.tls_client_cert_only {
+            // トークンを確認しないため、TLS でない待ち受けがあると証明書なしで使えてしまう
+            if listeners.iter().any(|listener| !listener.tls) {
+                log_error!("❌ tls_client_cert_only=true では、すべての待ち受けで TLS が必要です");
+                return;
+            }
+            log!("🔐 クライアント証明書だけで認証します (トークンは確認しません)");
+            auth.trust_transport();
+        }
+    } else if config.tls_client_cert_only {
+        log_error!("❌ tls_client_cert_only=true には tls_client_ca= の設定が必要です");
+        return;
+    }
+
+    let incoming = if socket_mode {
+        match socket::incoming(&config.socket) {
+            Ok(incoming) => Some(incoming),
+            Err(e) => {
+                log_error!("❌ ソケットで待ち受けられません ({}): {}", config.socket, e);
+                return;
+            }
+        }
+    } else {
+        None
+    };
+    if socket_mode && !config.socket_require_token {
+        log!("⚠️ ソケットの接続はトークンなしで受け付けます (socket_require_token=false)");
+        auth.trust_transport();
+    }
+    let auth = Arc::new(auth);
+
+    let mdns_service = mdns::service(&config, &listeners);
+    let show_listeners = !config.listeners.is_empty();
+
+    // 停止を要求されたら新しい接続の受け付けをやめ、処理中のリクエストが終わってから止まる
+    if let Some(incoming) = incoming {
+        log!("🔌 ソケットで待ち受けます: {}", config.socket);
+        let (routes, _) = build_routes(config, auth, true, &[Vec::new()]);
+        let routes = routes.into_iter().next().expect("one route per listener");
+        log!("✅ サーバー起動成功");
+        serve_until_shutdown(warp::serve(routes).serve_incoming_with_graceful_shutdown(incoming, shutdown::requested())).await;
+        return;
+    }
+
+    // 待ち受けごとにルートを作る (ハンドラーと状態はすべての待ち受けで共有する)
+    let operations: Vec<Vec<Operation>> = listeners.iter().map(|listener| listener.allowed_operations.clone()).collect();
+    let (routes, _) = build_routes(config, auth, true, &operations);
+    let mut servers = Vec::new();
+    for (listener, routes) in listeners.iter().zip(routes) {
+        let server = match &tls {
+            Some((cert, key)) if listener.tls => {
+                let server = warp::serve(routes).tls().cert_path(cert).key_path(key);
+                match &client_ca {
+                    Some(ca) => server
+                        .client_auth_required_path(ca)
+                        .try_bind_with_graceful_shutdown(listener.address, shutdown::requested())
+                        .map(|(_, server)| server.boxed()),
+                    None => server.try_bind_with_graceful_shutdown(listener.address, shutdown::requested()).map(|(_, server)| server.boxed()),
+                }
+            }
+            _ => warp::serve(routes).try_bind_with_graceful_shutdown(listener.address, shutdown::requested()).map(|(_, server)| server.boxed()),
+        };
+        match server {
+            Ok(server) => servers.push(server),
+            Err(e) => {
+                log_error!("❌ サーバー起動エラー: {}", e);
+                log_error!("{} で待ち受けられませんでした。", listener.address);
+                return;
+            }
+        }
+        if show_listeners {
+            if listener.allowed_operations.is_empty() {
+                log!("🌐 待ち受けます: {}://{}", listener.scheme(), listener.address);
+            } else {
+                log!("🌐 待ち受けます: {}://{} (操作: {})", listener.scheme(), listener.address, Operation::to_ini_value(&listener.allowed_operations));
+            }
+        }
+    }
+
+    log!("✅ サーバー起動成功");
+    // 停止するまで公開する (停止すると公開を取り下げる)
+    let _advertisement = mdns_service.and_then(mdns::advertise);
+    serve_until_shutdown(futures_util::future::join_all(servers).map(drop)).await;
+}
+
+/// 待ち受けるポートを決める。port が使用中で port_fallback= が 1 以上なら続くポートを順に試し、
+/// 最初に空いていたポートを config.port にして設定ファイルにも保存する (ソケットや listener= で待ち受ける場合は何もしない)
+pub fn resolve_port(config: &mut Config) -> std::io::Result<()> {
+    if !config.socket.is_empty() || !config.listeners.is_empty() {
+        return Ok(());
+    }
+    let last = config.port.saturating_add(config.port_fallback);
+    let mut first_error = None;
+    for port in config.port..=last {
+        // 確認に使ったソケットはすぐに閉じる (同じポートで warp が待ち受ける)
+        match std::net::TcpListener::bind((config.bind_address, port)) {
+            Ok(listener) => drop(listener),
+            Err(e) => {
+                first_error.get_or_insert(e);
+                continue;
+            }
+        }
+        if port != config.port {
+            log!("⚠️ ポート {} は使用中のため、ポート {} で待ち受けます", config.port, port);
+            config.port = port;
+            save_port(port);
+        }
+        return Ok(());
+    }
+    Err(first_error.expect("at least one port is tried"))
+}
+
+// 選んだポートを設定ファイルに保存する (次回からクライアントが同じポートに接続できるように)
+fn save_port(port: u16) {
+    match Config::load() {
+        Ok(mut saved) => {
+            saved.port = port;
+            if let Err(e) = saved.save() {
+                log_error!("⚠️ ポートを設定ファイルに保存できませんでした: {}", e);
+            }
+        }
+        Err(e) => log_error!("⚠️ 設定ファイルに誤りがあるため、ポートを保存できません:\n{}", e),
+    }
+}
+
+fn port_in_use_help(config: &Config) {
+    if config.port_fallback == 0 {
+        log_error!("ポート {} が既に使用されている可能性があります。", config.port);
+        log_error!("port_fallback= を設定すると空いているポートを探します。file_agent.ini でポート番号を変更するか、以下のコマンドで使用中のプロセスを終了してください:");
+    } else {
+        log_error!("ポート {}〜{} はすべて使用中です。", config.port, config.port.saturating_add(config.port_fallback));
+        log_error!("file_agent.ini でポート番号を変更するか、以下のコマンドで使用中のプロセスを終了してください:");
+    }
+    log_error!("  netstat -ano | findstr :{}", config.port);
+    log_error!("  taskkill /PID <プロセスID> /F");
+}
+
+// サーバーを動かし、停止の要求から shutdown::DRAIN_TIMEOUT 経っても終わらないリクエストは打ち切る
+async fn serve_until_shutdown(server: impl std::future::Future<Output = ()>) {
+    let _running = shutdown::Running::start();
+    let drain_timeout = async {
+        shutdown::requested().await;
+        log!("🛑 停止します (処理中のリクエストの完了を待っています)");
+        tokio::time::sleep(shutdown::DRAIN_TIMEOUT).await;
+    };
+    tokio::select! {
+        _ = server => log!("✅ サーバーを停止しました"),
+        _ = drain_timeout => log_error!("⚠️ {} 秒以内に終わらなかったリクエストを打ち切って停止しました", shutdown::DRAIN_TIMEOUT.as_secs()),
+    }
+}
+
+/// --stdio: ポートを開かず、標準入出力で JSON-RPC を処理する (起動したプロセスだけが使える)
+pub async fn start_stdio_server(config: Config) {
+    let mut auth = new_auth(&config);
+    if !config.stdio_require_token {
+        log!("🔐 標準入出力の RPC はトークンなしで受け付けます (stdio_require_token=true で確認します)");
+        auth.trust_transport();
+    }
+    let trusted = !config.stdio_require_token;
+    let (_, rpc_routes) = build_routes(config, Arc::new(auth), true, &[]);
+    rpc::serve_stdio(rpc_routes, trusted).await;
+}
+
+/// API のルートをひとつのフィルターとして返す (他の warp サーバーに組み込む場合に使う)
+///
+/// 返すフィルターは /api (/api/v1)・/api/docs・/ui を処理し、拒否はすべて応答に変換する。
+/// TLS とソケットの設定は使わないため、待ち受けは呼び出し側で行う。Tokio のランタイム内で呼ぶこと
+/// (インデックスやクリーンアップなどのバックグラウンドの処理を起動する)
+pub fn routes(config: Config) -> BoxedFilter<(warp::reply::Response,)> {
+    let auth = Arc::new(new_auth(&config));
+    let (routes, _) = build_routes(config, auth, false, &[Vec::new()]);
+    routes.into_iter().next().expect("one route per listener")
+}
+
+// 待ち受けごとの API のルート (listeners の各要素はその待ち受けで許可する操作、空ならすべて) と、
+// RPC (標準入出力) のメソッドを処理するルートを作る。watch が true なら file_agent.ini の変更を監視して反映する
+fn build_routes(config: Config, auth: Arc<Auth>, watch: bool, listeners: &[Vec<Operation>]) -> (Vec<BoxedFilter<(warp::reply::Response,)>>, rpc::ApiRoutes) {
+    // フック・S3・WebDAV が使う HTTP のクライアント (proxy= は起動時の値を使う)
+    outbound::init(&config);
+
+    // 許可したオリジン以外のブラウザからのリクエストは拒否する (Origin のないリクエストは対象外)
+    let cors = warp::cors()
+        .allow_headers(vec!["content-type", "x-client-name", "if-none-match"])
+        .allow_methods(&[Method::GET, Method::HEAD, Method::POST, Method::PUT, Method::DELETE])
+        .expose_headers(vec!["etag", "last-modified", "content-length"]);
+    let cors = if config.cors_origins.iter().any(|origin| origin == "any") {
+        log!("⚠️ すべてのオリジンからのブラウザのアクセスを許可しています (cors_origin=any)");
+        cors.allow_any_origin()
+    } else {
+        cors.allow_origins(config.cors_origins.iter().map(String::as_str))
+    };
+
+    let changes = Arc::new(ChangeLog::new());
+    let changes_for_filter = changes.clone();
+    let changes_filter = warp::any().map(move || changes_for_filter.clone());
+
+    // 起動時にだけ使う設定 (待ち受けやバックグラウンドの処理) はこの時点の値を使う
+    let config = Arc::new(config);
+    let live: LiveConfig = Arc::new(RwLock::new(config.clone()));
+    let live_for_filter = live.clone();
+    let config_filter = warp::any().map(move || live_for_filter.read().unwrap().clone());
+
+    let audit = Arc::new(AuditLog::new(Config::get_audit_log_path(), &config.receipt_key));
+    let audit_for_filter = audit.clone();
+    let audit_filter = warp::any().map(move || audit_for_filter.clone());
+
+    // 接続元のアドレス (WebSocket の RPC から呼び出した場合は WebSocket の接続元)
+    let client_addr = warp::addr::remote()
+        .and(warp::ext::optional::<rpc::Peer>())
+        .map(|addr: Option<std::net::SocketAddr>, peer: Option<rpc::Peer>| addr.or(peer.and_then(|peer| peer.0)));
+
+    // 認証失敗を送信元のアドレスごとに数えるため、リクエストごとに作る
+    let audit_for_auth = audit.clone();
+    let auth_for_reload = auth.clone();
+    let auth_filter = client_addr.map(move |addr: Option<std::net::SocketAddr>| {
+        ClientAuth::new(auth.clone(), audit_for_auth.clone(), addr.map(|addr| addr.ip()))
+    });
+
+    let vault = Arc::new(Vault::new(config.vault_roots.clone(), &config.vault_key, config.vault_keys.clone()));
+    let vault_filter = warp::any().map(move || vault.clone());
+
+    let list_cache = Arc::new(ListCache::new(std::time::Duration::from_secs(config.list_cache_ttl_secs)));
+    let list_cache_filter = warp::any().map(move || list_cache.clone());
+
+    let hashes = Arc::new(HashCache::load(Config::get_hash_cache_path(), &changes));
+    let hashes_filter = warp::any().map(move || hashes.clone());
+
+    let dir_sizes = Arc::new(DirSizeCache::new(std::time::Duration::from_secs(config.dir_size_cache_ttl_secs)));
+    let dir_sizes_filter = warp::any().map(move || dir_sizes.clone());
+
+    // プラグインは起動時にだけ読み込む (plugin_dir= の変更やプラグインの追加は再起動で反映する)
+    let plugins = Arc::new(PluginHost::load(config.plugin_path().as_deref()));
+    let plugins_filter = warp::any().map(move || plugins.clone());
+
+    let clients = Arc::new(ClientRegistry::load(Config::get_clients_path()));
+    let clients_filter = warp::any().map(move || clients.clone());
+
+    let trash = Arc::new(Trash::load(
+        Config::get_trash_path(),
+        std::time::Duration::from_secs(config.soft_delete_retention_hours * 3600),
+    ));
+    tokio::spawn(trash::run_purger(trash.clone()));
+    let trash_filter = warp::any().map(move || trash.clone());
+
+    // 重い処理 (走査・ハッシュ・コピージョブ) の数はハンドラーとジョブで制限する (再開するジョブも数える)
+    let busy_wait = std::time::Duration::from_secs(config.busy_wait_secs);
+    concurrency::set_heavy_limit(config.max_heavy_operations, busy_wait);
+    timeout::set_limit(config.request_timeout_secs);
+    writequota::set_limits(config.max_write_bytes, config.daily_write_bytes);
+
+    // 再起動前に終わらなかったコピージョブは、現在の設定で許可されていれば再開する
+    let jobs = Arc::new(JobStore::load(Config::get_jobs_dir()));
+    let config_for_jobs = config.clone();
+    jobs::resume_all(jobs.clone(), changes.clone(), audit.clone(), move |job| {
+        check_access(&config_for_jobs, Path::new(&job.source), policy::Action::Read)?;
+        write_target(&config_for_jobs, Path::new(&job.destination)).map(|_| ())
+    });
+    let jobs_filter = warp::any().map(move || jobs.clone());
+
+    // クライアントのアドレスごとのレート制限 (超えたリクエストは recover で 429 にする)
+    let rate_limiter = Arc::new(IpRateLimiter::new(config.rate_limit_per_second, config.rate_limit_burst));
+    if watch {
+        tokio::spawn(reload::watch(live.clone(), auth_for_reload, rate_limiter.clone()));
+    }
+    // 同時に処理するリクエストの数を制限する (WebSocket の RPC はメッセージごとに数える)
+    let request_limiter = Arc::new(concurrency::Limiter::new(concurrency::Kind::Requests, config.max_concurrent_requests, busy_wait));
+    let request_slot = warp::any().and_then(move || {
+        let limiter = request_limiter.clone();
+        async move { limiter.acquire().await.map_err(warp::reject::custom) }
+    });
+
+    // 許可リストにないアドレスからのリクエストはハンドラーに渡す前に拒否する
+    let live_for_ips = live.clone();
+    let ip_filter = client_addr
+        .and_then(move |addr: Option<std::net::SocketAddr>| {
+            let result = match addr {
+                Some(addr) => ipfilter::check(&live_for_ips.read().unwrap().allowed_ips, addr.ip()).map_err(warp::reject::custom),
+                None => Ok(()),
+            };
+            async move { result }
+        })
+        .untuple_one();
+
+    let rate_limit = client_addr
+        .and_then(move |addr: Option<std::net::SocketAddr>| {
+            let rate_limiter = rate_limiter.clone();
+            async move {
+                match addr {
+                    Some(addr) => rate_limiter.check(addr.ip()).map_err(warp::reject::custom),
+                    None => Ok(()),
+                }
+            }
+        })
+        .untuple_one();
+
+    // X-Client-Name ヘッダー付きのリクエストでペアリング済みクライアントの last_seen を更新する
+    let client_seen = warp::header::optional::<String>("x-client-name")
+        .and(clients_filter.clone())
+        .map(|name: Option<String>, clients: Arc<ClientRegistry>| {
+            if let Some(name) = name {
+                clients.seen(&name);
+            }
+        })
+        .untuple_one();
+
+    let search_index = Arc::new(RwLock::new(None));
+    if !config.index_dirs.is_empty() {
+        index::spawn_indexer(
+            config.index_dirs.clone(),
+            config.index_max_file_size,
+            config.walk_excludes.clone(),
+            std::time::Duration::from_secs(config.index_interval_minutes * 60),
+            Config::get_index_path(),
+            search_index.clone(),
+        );
+    }
+    let index_filter = warp::any().map(move || search_index.clone());
+
+    tempfiles::init(Config::get_temp_manifest_path());
+    tokio::spawn(tempfiles::run_scheduler(std::time::Duration::from_secs(config.temp_max_age_hours * 3600)));
+
+    if !config.shares.is_empty() {
+        let configured = config.shares.clone();
+        tokio::task::spawn_blocking(move || shares::connect_all(&configured));
+    }
+
+    let syncs = sync::start(&config.sync_tasks, live.clone(), audit.clone(), changes.clone());
+    let syncs_filter = warp::any().map(move || syncs.clone());
+
+    tokio::spawn(cleanup::run_scheduler(
+        config.cleanup_rules.clone(),
+        config.walk_excludes.clone(),
+        std::time::Duration::from_secs(config.cleanup_interval_minutes * 60),
+        audit,
+        changes.clone(),
+    ));
+
+    let read_route = warp::path!("read")
+        .and(warp::post())
+        .and(body_limit(&live, "read"))
+        .and(warp::body::json())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and(vault_filter.clone())
+        .and(changes_filter.clone())
+        .and(hashes_filter.clone())
+        .and_then(read_file);
+
+    let read_binary_route = warp::path!("read_binary")
+        .and(warp::post())
+        .and(body_limit(&live, "read_binary"))
+        .and(warp::body::json())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and(vault_filter.clone())
+        .and_then(read_binary_file);
+
+    let read_chunk_route = warp::path!("read_chunk")
+        .and(warp::post())
+        .and(body_limit(&live, "read_chunk"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and(vault_filter.clone())
+        .and_then(read_file_chunk);
+
+    let write_route = warp::path!("write")
+        .and(warp::post())
+        .and(body_limit(&live, "write"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(changes_filter.clone())
+        .and(config_filter.clone())
+        .and(audit_filter.clone())
+        .and(vault_filter.clone())
+        .and_then(write_file);
+
+    let write_binary_route = warp::path!("write_binary")
+        .and(warp::post())
+        .and(body_limit(&live, "write_binary"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(changes_filter.clone())
+        .and(config_filter.clone())
+        .and(audit_filter.clone())
+        .and(vault_filter.clone())
+        .and_then(write_binary_file);
+
+    let download_route = warp::path!("file")
+        .and(warp::get().map(|| false).or(warp::head().map(|| true)).unify())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and(vault_filter.clone())
+        .and_then(|head: bool, query: std::collections::HashMap<String, String>, if_none_match: Option<String>, auth: ClientAuth, config: Arc<Config>, vault: Arc<Vault>| async move {
+            let path = query.get("path").cloned().unwrap_or_default();
+            let token = query.get("token").cloned().unwrap_or_default();
+            download_file(path, token, head, if_none_match, auth, config, vault).await
+        });
+
+    let upload_route = warp::path!("file")
+        .and(warp::put())
+        .and(body_limit(&live, "file"))
+        .and(warp::header::exact_ignore_case("content-type", "application/octet-stream"))
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::header::<u64>("content-length"))
+        .and(warp::body::stream().map(upload_body))
+        .and(auth_filter.clone())
+        .and(changes_filter.clone())
+        .and(config_filter.clone())
+        .and(audit_filter.clone())
+        .and(vault_filter.clone())
+        .and_then(|query: std::collections::HashMap<String, String>, length: u64, body: UploadBody, auth: ClientAuth, changes: Arc<ChangeLog>, config: Arc<Config>, audit: Arc<AuditLog>, vault: Arc<Vault>| async move {
+            let path = query.get("path").cloned().unwrap_or_default();
+            let token = query.get("token").cloned().unwrap_or_default();
+            upload_file(path, token, length, body, auth, changes, config, audit, vault).await
+        });
+
+    let delete_route = warp::path!("delete")
+        .and(warp::post())
+        .and(body_limit(&live, "delete"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(changes_filter.clone())
+        .and(config_filter.clone())
+        .and(audit_filter.clone())
+        .and(trash_filter.clone())
+        .and_then(delete_file);
+
+    let search_route = warp::path!("search")
+        .and(warp::post())
+        .and(body_limit(&live, "search"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and_then(search_files);
+
+    let list_route = warp::path!("list")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and(changes_filter.clone())
+        .and(list_cache_filter.clone())
+        .and_then(move |query: std::collections::HashMap<String, String>, auth: ClientAuth, config: Arc<Config>, changes: Arc<ChangeLog>, cache: Arc<ListCache>| async move {
+            let path = query.get("path").cloned().unwrap_or_else(|| ".".to_string());
+            let token = query.get("token").cloned().unwrap_or_default();
+            let show_hidden = query.get("show_hidden").map(|v| v == "true" || v == "1").unwrap_or(true);
+            list_directory(path, token, show_hidden, auth, config, changes, cache).await
+        });
+
+    let list_stream_route = warp::path!("list" / "stream")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and_then(move |query: std::collections::HashMap<String, String>, auth: ClientAuth, config: Arc<Config>| async move {
+            let path = query.get("path").cloned().unwrap_or_else(|| ".".to_string());
+            let token = query.get("token").cloned().unwrap_or_default();
+            let show_hidden = query.get("show_hidden").map(|v| v == "true" || v == "1").unwrap_or(true);
+            list_directory_stream(path, token, show_hidden, auth, config).await
+        });
+
+    let search_stream_route = warp::path!("search" / "stream")
+        .and(warp::post())
+        .and(body_limit(&live, "search_stream"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and_then(search_files_stream);
+
+    let grep_route = warp::path!("grep")
+        .and(warp::post())
+        .and(body_limit(&live, "grep"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and_then(grep_files);
+
+    let index_search_route = warp::path!("index" / "search")
+        .and(warp::post())
+        .and(body_limit(&live, "index_search"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and(index_filter.clone())
+        .and_then(index_search);
+
+    let stale_route = warp::path!("stale")
+        .and(warp::post())
+        .and(body_limit(&live, "stale"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and_then(stale_report);
+
+    let du_route = warp::path!("du")
+        .and(warp::post())
+        .and(body_limit(&live, "du"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and(changes_filter.clone())
+        .and(dir_sizes_filter.clone())
+        .and_then(dir_size);
+
+    let mime_route = warp::path!("mime")
+        .and(warp::post())
+        .and(body_limit(&live, "mime"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and_then(detect_mime);
+
+    let hash_route = warp::path!("hash")
+        .and(warp::post())
+        .and(body_limit(&live, "hash"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and(vault_filter.clone())
+        .and(changes_filter.clone())
+        .and(hashes_filter.clone())
+        .and_then(hash_file);
+
+    let create_route = warp::path!("create")
+        .and(warp::post())
+        .and(body_limit(&live, "create"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(changes_filter.clone())
+        .and(config_filter.clone())
+        .and(audit_filter.clone())
+        .and(vault_filter.clone())
+        .and_then(create_file_or_directory);
+
+    let move_route = warp::path!("move")
+        .and(warp::post())
+        .and(body_limit(&live, "move"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(changes_filter.clone())
+        .and(config_filter.clone())
+        .and(audit_filter.clone())
+        .and(vault_filter.clone())
+        .and_then(move_file);
+
+    let copy_route = warp::path!("copy")
+        .and(warp::post())
+        .and(body_limit(&live, "copy"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(changes_filter.clone())
+        .and(config_filter.clone())
+        .and(audit_filter.clone())
+        .and(vault_filter.clone())
+        .and(jobs_filter.clone())
+        .and_then(copy_file);
+
+    let paste_route = warp::path!("paste_from_clipboard")
+        .and(warp::post())
+        .and(body_limit(&live, "paste_from_clipboard"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(changes_filter.clone())
+        .and(config_filter.clone())
+        .and(audit_filter.clone())
+        .and(vault_filter.clone())
+        .and_then(paste_from_clipboard);
+
+    let clipboard_get_route = warp::path!("clipboard" / "get")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth_filter.clone())
+        .and(audit_filter.clone())
+        .and_then(|query: std::collections::HashMap<String, String>, auth: ClientAuth, audit: Arc<AuditLog>| async move {
+            let token = query.get("token").cloned().unwrap_or_default();
+            get_clipboard(token, auth, audit).await
+        });
+
+    let clipboard_set_route = warp::path!("clipboard" / "set")
+        .and(warp::post())
+        .and(body_limit(&live, "clipboard_set"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(audit_filter.clone())
+        .and_then(set_clipboard);
+
+    let changes_poll_route = warp::path!("changes" / "poll")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth_filter.clone())
+        .and(changes_filter.clone())
+        .and_then(|query: std::collections::HashMap<String, String>, auth: ClientAuth, changes: Arc<ChangeLog>| async move {
+            let cursor = query.get("cursor").and_then(|c| c.parse::<u64>().ok());
+            let wait = query.get("wait").and_then(|w| w.parse::<u64>().ok()).unwrap_or(30);
+            let token = query.get("token").cloned().unwrap_or_default();
+            poll_changes(cursor, wait, token, auth, changes).await
+        });
+
+    let cleanup_route = warp::path!("cleanup")
+        .and(warp::post())
+        .and(body_limit(&live, "cleanup"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(changes_filter.clone())
+        .and(config_filter.clone())
+        .and(audit_filter.clone())
+        .and_then(run_cleanup);
+
+    let print_route = warp::path!("print")
+        .and(warp::post())
+        .and(body_limit(&live, "print"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and_then(print_document);
+
+    let clients_list_route = warp::path!("clients")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth_filter.clone())
+        .and(clients_filter.clone())
+        .and_then(|query: std::collections::HashMap<String, String>, auth: ClientAuth, clients: Arc<ClientRegistry>| async move {
+            let token = query.get("token").cloned().unwrap_or_default();
+            list_clients(token, auth, clients).await
+        });
+
+    let clients_pair_route = warp::path!("clients" / "pair")
+        .and(warp::post())
+        .and(body_limit(&live, "clients_pair"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and(clients_filter.clone())
+        .and_then(pair_client);
+
+    let clients_remove_route = warp::path!("clients" / "remove")
+        .and(warp::post())
+        .and(body_limit(&live, "clients_remove"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(clients_filter.clone())
+        .and_then(remove_client);
+
+    let metrics_route = warp::path!("metrics")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth_filter.clone())
+        .and(list_cache_filter.clone())
+        .and(hashes_filter.clone())
+        .and(dir_sizes_filter.clone())
+        .and_then(|query: std::collections::HashMap<String, String>, auth: ClientAuth, cache: Arc<ListCache>, hashes: Arc<HashCache>, dir_sizes: Arc<DirSizeCache>| async move {
+            let token = query.get("token").cloned().unwrap_or_default();
+            get_metrics(token, auth, cache, hashes, dir_sizes).await
+        });
+
+    let logs_tail_route = warp::path!("logs" / "tail")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth_filter.clone())
+        .and_then(|query: std::collections::HashMap<String, String>, auth: ClientAuth| async move {
+            let lines = query.get("lines").and_then(|n| n.parse::<usize>().ok()).unwrap_or(logs::DEFAULT_TAIL_LINES);
+            let token = query.get("token").cloned().unwrap_or_default();
+            tail_logs(token, lines, auth).await
+        });
+
+    let capabilities_route = warp::path!("capabilities")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and(audit_filter.clone())
+        .and(trash_filter.clone())
+        .and(vault_filter.clone())
+        .and(plugins_filter.clone())
+        .and_then(|query: std::collections::HashMap<String, String>, auth: ClientAuth, config: Arc<Config>, audit: Arc<AuditLog>, trash: Arc<Trash>, vault: Arc<Vault>, plugins: Arc<PluginHost>| async move {
+            let token = query.get("token").cloned().unwrap_or_default();
+            get_capabilities(token, auth, config, audit, trash, vault, plugins).await
+        });
+
+    let vault_status_route = warp::path!("vault" / "status")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth_filter.clone())
+        .and(vault_filter.clone())
+        .and_then(|query: std::collections::HashMap<String, String>, auth: ClientAuth, vault: Arc<Vault>| async move {
+            let token = query.get("token").cloned().unwrap_or_default();
+            vault_status(token, auth, vault).await
+        });
+
+    let vault_unlock_route = warp::path!("vault" / "unlock")
+        .and(warp::post())
+        .and(body_limit(&live, "vault_unlock"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(audit_filter.clone())
+        .and(vault_filter.clone())
+        .and_then(vault_unlock);
+
+    let vault_lock_route = warp::path!("vault" / "lock")
+        .and(warp::post())
+        .and(body_limit(&live, "vault_lock"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(audit_filter.clone())
+        .and(vault_filter.clone())
+        .and_then(vault_lock);
+
+    let vault_rotate_route = warp::path!("vault" / "rotate")
+        .and(warp::post())
+        .and(body_limit(&live, "vault_rotate"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(audit_filter.clone())
+        .and(vault_filter.clone())
+        .and_then(vault_rotate);
+
+    let jobs_list_route = warp::path!("jobs")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and(jobs_filter.clone())
+        .and_then(|query: std::collections::HashMap<String, String>, auth: ClientAuth, config: Arc<Config>, jobs: Arc<JobStore>| async move {
+            let token = query.get("token").cloned().unwrap_or_default();
+            list_jobs(token, auth, config, jobs).await
+        });
+
+    let trash_list_route = warp::path!("trash")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and(trash_filter.clone())
+        .and_then(|query: std::collections::HashMap<String, String>, auth: ClientAuth, config: Arc<Config>, trash: Arc<Trash>| async move {
+            let token = query.get("token").cloned().unwrap_or_default();
+            list_trash(token, auth, config, trash).await
+        });
+
+    let trash_purge_route = warp::path!("trash" / "purge")
+        .and(warp::post())
+        .and(body_limit(&live, "trash_purge"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and(audit_filter.clone())
+        .and(trash_filter.clone())
+        .and_then(purge_trash);
+
+    let plugins_list_route = warp::path!("plugins")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth_filter.clone())
+        .and(plugins_filter.clone())
+        .and_then(|query: std::collections::HashMap<String, String>, auth: ClientAuth, plugins: Arc<PluginHost>| async move {
+            let token = query.get("token").cloned().unwrap_or_default();
+            list_plugins(token, auth, plugins).await
+        });
+
+    let plugins_transform_route = warp::path!("plugins" / "transform")
+        .and(warp::post())
+        .and(body_limit(&live, "plugins_transform"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(changes_filter.clone())
+        .and(config_filter.clone())
+        .and(audit_filter.clone())
+        .and(plugins_filter.clone())
+        .and_then(transform_file);
+
+    let plugins_call_route = warp::path!("plugins" / String / String)
+        .and(warp::post())
+        .and(body_limit(&live, "plugins"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(changes_filter.clone())
+        .and(config_filter.clone())
+        .and(audit_filter.clone())
+        .and(plugins_filter.clone())
+        .and_then(call_plugin);
+
+    let script_route = warp::path!("script")
+        .and(warp::post())
+        .and(body_limit(&live, "script"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(changes_filter.clone())
+        .and(config_filter.clone())
+        .and(audit_filter.clone())
+        .and_then(run_script);
+
+    let exec_route = warp::path!("exec")
+        .and(warp::post())
+        .and(body_limit(&live, "exec"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and(audit_filter.clone())
+        .and_then(run_exec);
+
+    let git_status_route = warp::path!("git" / "status")
+        .and(warp::post())
+        .and(body_limit(&live, "git_status"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and_then(git_status);
+
+    let git_diff_route = warp::path!("git" / "diff")
+        .and(warp::post())
+        .and(body_limit(&live, "git_diff"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and_then(git_diff);
+
+    let git_log_route = warp::path!("git" / "log")
+        .and(warp::post())
+        .and(body_limit(&live, "git_log"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and_then(git_log);
+
+    let s3_upload_route = warp::path!("s3" / "upload")
+        .and(warp::post())
+        .and(body_limit(&live, "s3_upload"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and(audit_filter.clone())
+        .and(vault_filter.clone())
+        .and_then(s3_upload);
+
+    let s3_download_route = warp::path!("s3" / "download")
+        .and(warp::post())
+        .and(body_limit(&live, "s3_download"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(changes_filter.clone())
+        .and(config_filter.clone())
+        .and(audit_filter.clone())
+        .and(vault_filter.clone())
+        .and_then(s3_download);
+
+    let s3_list_route = warp::path!("s3" / "list")
+        .and(warp::post())
+        .and(body_limit(&live, "s3_list"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and_then(s3_list);
+
+    let sync_status_route = warp::path!("sync" / "status")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth_filter.clone())
+        .and(syncs_filter)
+        .and_then(|query: std::collections::HashMap<String, String>, auth: ClientAuth, syncs: Arc<sync::SyncManager>| async move {
+            let token = query.get("token").cloned().unwrap_or_default();
+            get_sync_status(token, auth, syncs).await
+        });
+
+    let shares_route = warp::path!("shares")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and_then(|query: std::collections::HashMap<String, String>, auth: ClientAuth, config: Arc<Config>| async move {
+            let token = query.get("token").cloned().unwrap_or_default();
+            get_shares(token, auth, config).await
+        });
+
+    let tokens_rotate_route = warp::path!("tokens" / "rotate")
+        .and(warp::post())
+        .and(body_limit(&live, "tokens_rotate"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and_then(rotate_token);
+
+    let shutdown_route = warp::path!("shutdown")
+        .and(warp::post())
+        .and(body_limit(&live, "shutdown"))
+        .and(warp::body::json())
+        .and(auth_filter.clone())
+        .and(audit_filter.clone())
+        .and_then(shutdown_agent);
+
+    // OpenAPI のドキュメントは起動時に一度だけ生成する
+    let openapi_doc = Arc::new(<openapi::ApiDoc as utoipa::OpenApi>::openapi());
+    let openapi_route = warp::path!("openapi.json")
+        .and(warp::get())
+        .map(move || warp::reply::json(&*openapi_doc));
+
+    // api_docs=true の場合のみ Swagger UI を表示する
+    let docs_config = config.api_docs.then(|| Arc::new(utoipa_swagger_ui::Config::from("/api/openapi.json")));
+    // Swagger UI はバージョンに関係なく /api/docs/ で表示する (相対パスでファイルを読み込むため)
+    let docs_route = warp::path!("api" / "docs" / ..)
+        .and(warp::get())
+        .and(warp::path::full())
+        .and(warp::path::tail())
+        .and(warp::any().map(move || docs_config.clone()))
+        .and_then(openapi::serve_docs);
+
+    let web_ui = config.web_ui;
+    let ui_route = warp::path("ui")
+        .and(warp::get())
+        .and(warp::path::full())
+        .and(warp::path::tail())
+        .and(warp::any().map(move || web_ui))
+        .and_then(webui::serve);
+
+    let version_route = warp::path!("version")
+        .and(warp::get())
+        .map(|| warp::reply::json(&ApiResponse {
+            success: true,
+            data: Some(version_info()),
+            error: None,
+        }));
+
+    let started = std::time::Instant::now();
+    let health_route = warp::path!("health")
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth_filter.clone())
+        .and(config_filter.clone())
+        .and_then(move |query: std::collections::HashMap<String, String>, auth: ClientAuth, config: Arc<Config>| async move {
+            health_check(query.get("token").cloned(), auth, config, started).await
+        });
+
+    let v1_routes = read_route
+        .or(read_binary_route)
+        .or(read_chunk_route)
+        .or(write_route)
+        .or(write_binary_route)
+        .or(upload_route)
+        .or(download_route)
+        .or(delete_route)
+        .or(search_route)
+        .or(search_stream_route)
+        .or(grep_route)
+        .or(index_search_route)
+        .or(list_route)
+        .or(list_stream_route)
+        .or(du_route)
+        .or(mime_route)
+        .or(hash_route)
+        .or(stale_route)
+        .or(create_route)
+        .or(move_route)
+        .or(copy_route)
+        .or(paste_route)
+        .or(clipboard_get_route)
+        .or(clipboard_set_route)
+        .or(changes_poll_route)
+        .or(cleanup_route)
+        .or(print_route)
+        .or(clients_list_route)
+        .or(clients_pair_route)
+        .or(clients_remove_route)
+        .or(metrics_route)
+        .or(logs_tail_route)
+        .or(vault_status_route)
+        .or(vault_unlock_route)
+        .or(vault_lock_route)
+        .or(vault_rotate_route)
+        .or(jobs_list_route)
+        .or(trash_list_route)
+        .or(trash_purge_route)
+        .or(plugins_list_route)
+        .or(plugins_transform_route)
+        .or(plugins_call_route)
+        .or(script_route)
+        .or(exec_route)
+        .or(git_status_route)
+        .or(git_diff_route)
+        .or(git_log_route)
+        .or(s3_upload_route)
+        .or(s3_download_route)
+        .or(s3_list_route)
+        .or(sync_status_route)
+        .or(shares_route)
+        .or(tokens_rotate_route)
+        .or(shutdown_route)
+        .or(capabilities_route)
+        .or(version_route)
+        .or(openapi_route)
+        .or(health_route);
+
+    // /api/v1/... が現在の API。バージョンなしの /api/... は既存のクライアントのため v1 として扱う。
+    // 互換性のない変更 (ステータスコードやスキーマの変更) は v2 のルートとして加え、v1 の応答は変えない
+    // WebSocket の RPC のメソッドは v1 のルートで処理する (メッセージごとにレート制限を数える)
+    let rpc_routes: rpc::ApiRoutes = rate_limit.clone()
+        .and(request_slot.clone())
+        .and(v1_routes.clone().map(Reply::into_response))
+        .map(|_slot: Option<OwnedSemaphorePermit>, response: warp::reply::Response| response)
+        .recover(handle_rejection)
+        .map(Reply::into_response)
+        .boxed();
+    let ws_max_message = config.body_limit("ws") as usize;
+
+    // Accept-Encoding に応じて JSON の応答を圧縮する (設定は再読み込みで変更できる)
+    let live_for_compression = live.clone();
+    let compression = warp::header::headers_cloned().map(move |headers: warp::http::HeaderMap| {
+        let config = live_for_compression.read().unwrap();
+        config.compression.then(|| compress::Negotiated {
+            encoding: headers
+                .get(warp::http::header::ACCEPT_ENCODING)
+                .and_then(|value| value.to_str().ok())
+                .and_then(compress::Encoding::negotiate),
+            min_bytes: config.compression_min_bytes,
+        })
+    });
+
+    // 待ち受けで許可していない操作は、HTTP でも WebSocket の RPC でもハンドラーに渡す前に拒否する
+    let routes = listeners
+        .iter()
+        .map(|operations| {
+            let guard = listener_guard(operations.clone());
+            let rpc_routes_for_ws: rpc::ApiRoutes = guard.clone()
+                .and(rpc_routes.clone())
+                .recover(handle_rejection)
+                .map(Reply::into_response)
+                .boxed();
+            let ws_route = warp::path!("ws")
+                .and(warp::ws())
+                .and(client_addr)
+                .map(move |ws: warp::ws::Ws, addr: Option<std::net::SocketAddr>| {
+                    let routes = rpc_routes_for_ws.clone();
+                    ws.max_message_size(ws_max_message)
+                        .on_upgrade(move |socket| rpc::serve(socket, rpc::Peer(addr), routes))
+                });
+            let v1_routes = v1_routes.clone().or(ws_route);
+
+            let api_routes = warp::path("api").and(guard).and(
+                ApiVersion::V1.path().and(v1_routes.clone())
+                    .or(v1_routes)
+                    .or(apiversion::unsupported()),
+            );
+
+            let routes = ip_filter.clone().and(rate_limit.clone()).and(client_seen.clone()).and(request_slot.clone())
+                .and(api_routes.or(docs_route.clone()).or(ui_route).map(Reply::into_response))
+                // 応答を返すまで枠を使う (ダウンロードやストリームの本文の送信中は数えない)
+                .map(|_slot: Option<OwnedSemaphorePermit>, response: warp::reply::Response| response)
+                .recover(handle_rejection)
+                .with(cors.clone())
+                .map(Reply::into_response);
+            compression.clone().and(routes).then(compress::response).boxed()
+        })
+        .collect();
+
+    (routes, rpc_routes)
+}
+
+// 待ち受けで許可していない操作のリクエストを拒否する (operations が空ならすべて通す)。
+// パスは /api/v1/read・/api/read・RPC の /read のいずれでもよい
+fn listener_guard(operations: Vec<Operation>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let operations = Arc::new(operations);
+    warp::method()
+        .and(warp::path::full())
+        .and_then(move |method: Method, path: warp::path::FullPath| {
+            let result = match endpoint_operation(&method, path.as_str()) {
+                Some(operation) if !operations.is_empty() && !operations.contains(&operation) => {
+                    Err(warp::reject::custom(ListenerForbidden { operation }))
+                }
+                _ => Ok(()),
+            };
+            async move { result }
+        })
+        .untuple_one()
+}
+
+// リクエストに必要な操作 (ENDPOINTS にないパスやトークンだけで使えるエンドポイントは None)
+fn endpoint_operation(method: &Method, path: &str) -> Option<Operation> {
+    let path = path.strip_prefix("/api").unwrap_or(path);
+    let path = path.strip_prefix("/v1").filter(|rest| rest.starts_with('/')).unwrap_or(path);
+    let path = format!("/api{}", path);
+    ENDPOINTS
+        .iter()
+        .find(|&&(endpoint_method, endpoint_path, _)| endpoint_method == method.as_str() && endpoint_matches(endpoint_path, &path))
+        .and_then(|&(_, _, operation)| operation)
+}
@@ -0,0 +1,342 @@
+//! ネットワーク共有 (\\server\share) への接続 (share=)
+//!
+//! NAS などの共有フォルダに、設定した資格情報で接続する (Windows の WNetAddConnection2)。
+//! 起動時に接続し、その後も共有の下のパスを使う前に接続を確認するため、設定を読み込み直して
+//! 加えた共有や、起動時に NAS が止まっていた共有にも次のアクセスで接続する。
+//! Windows 以外では共有に接続せず、一覧にはマウント済みの CIFS / SMB の共有を返す。
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+use crate::longpath;
+
+// 接続に失敗した共有を再び試すまでの間隔 (応答のない NAS で毎回待たないように)
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 資格情報を設定した共有
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Share {
+    pub path: String, // \\server\share
+    pub user: String, // 空なら現在のユーザーの資格情報で接続する
+    pub password: String,
+    pub drive: String, // 空でなければこのドライブ文字 (Z: など) に割り当てる
+}
+
+impl Share {
+    // 形式: \\nas\docs|user=NAS\me|password=secret|drive=Z:  (/ 区切りの //nas/docs も受け付ける)
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split('|');
+        let path = parts.next()?.trim().replace('/', "\\");
+        let mut names = path.strip_prefix("\\\\")?.split('\\');
+        let (server, name) = (names.next()?, names.next()?);
+        if server.is_empty() || server == "?" || server == "." || name.is_empty() || names.next().is_some() {
+            return None;
+        }
+
+        let mut share = Share {
+            path: format!("\\\\{}\\{}", server, name),
+            user: String::new(),
+            password: String::new(),
+            drive: String::new(),
+        };
+        for part in parts {
+            let (key, val) = part.split_once('=')?;
+            let val = val.trim();
+            match key.trim() {
+                "user" => share.user = val.to_string(),
+                "password" => share.password = val.to_string(),
+                "drive" if val.is_empty() => share.drive.clear(),
+                "drive" => {
+                    let mut chars = val.chars();
+                    match (chars.next(), chars.next(), chars.next()) {
+                        (Some(letter), Some(':'), None) if letter.is_ascii_alphabetic() => share.drive = format!("{}:", letter.to_ascii_uppercase()),
+                        _ => return None,
+                    }
+                }
+                _ => return None,
+            }
+        }
+        Some(share)
+    }
+
+    pub fn to_ini_value(&self) -> String {
+        let mut value = self.path.clone();
+        if !self.user.is_empty() {
+            value.push_str(&format!("|user={}", self.user));
+        }
+        if !self.password.is_empty() {
+            value.push_str(&format!("|password={}", self.password));
+        }
+        if !self.drive.is_empty() {
+            value.push_str(&format!("|drive={}", self.drive));
+        }
+        value
+    }
+
+    // path がこの共有の下 (ドライブ文字を割り当てていればそのドライブの下を含む) か
+    fn covers(&self, path: &Path) -> bool {
+        let path = longpath::simplify(path);
+        let path = path.to_string_lossy();
+        if let Some(unc) = unc(&path) {
+            return is_under(&unc, &self.path);
+        }
+        !self.drive.is_empty() && is_under(&path.replace('/', "\\"), &self.drive)
+    }
+}
+
+/// 接続中の共有と、share= で設定した共有
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ShareInfo {
+    pub remote: String, // \\server\share (Windows 以外ではマウント元の //server/share)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local: Option<String>, // 割り当てたドライブ文字かマウント先
+    pub configured: bool, // share= で設定した共有
+    pub connected: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // 最後の接続の失敗
+}
+
+// 共有ごとの接続の状態 (キーは小文字にした \\server\share)
+struct Connection {
+    connected: bool,
+    error: Option<String>,
+    attempted: Instant,
+}
+
+static CONNECTIONS: Mutex<BTreeMap<String, Connection>> = Mutex::new(BTreeMap::new());
+
+/// path が設定した共有の下なら、まだ接続していなければ接続する (check_access の前に呼ぶ)
+pub fn ensure(shares: &[Share], path: &Path) -> Result<(), String> {
+    match shares.iter().find(|share| share.covers(path)) {
+        Some(share) => connect(share),
+        None => Ok(()),
+    }
+}
+
+/// 設定したすべての共有に接続する (起動時に呼ぶ)
+pub fn connect_all(shares: &[Share]) {
+    for share in shares {
+        // 失敗は connect でログに残す
+        let _ = connect(share);
+    }
+}
+
+fn connect(share: &Share) -> Result<(), String> {
+    let key = share.path.to_lowercase();
+    if let Some(connection) = CONNECTIONS.lock().unwrap().get(&key) {
+        if connection.connected {
+            return Ok(());
+        }
+        if connection.attempted.elapsed() < RETRY_INTERVAL {
+            return Err(connection.error.clone().unwrap_or_default());
+        }
+    }
+
+    let result = add_connection(share).map_err(|e| format!("Could not connect to {}: {}", share.path, e));
+    match &result {
+        Ok(()) => log!("🔗 ネットワーク共有に接続しました: {}", share.path),
+        Err(e) => log_error!("❌ ネットワーク共有に接続できませんでした: {}", e),
+    }
+    CONNECTIONS.lock().unwrap().insert(
+        key,
+        Connection {
+            connected: result.is_ok(),
+            error: result.as_ref().err().cloned(),
+            attempted: Instant::now(),
+        },
+    );
+    result
+}
+
+/// 接続中の共有の一覧 (設定した共有はまだ接続していなくても含める)
+pub fn list(shares: &[Share]) -> Result<Vec<ShareInfo>, String> {
+    let mut infos: Vec<ShareInfo> = mapped()?
+        .into_iter()
+        .map(|(remote, local)| ShareInfo {
+            configured: shares.iter().any(|share| share.path.eq_ignore_ascii_case(&remote)),
+            remote,
+            local,
+            connected: true,
+            error: None,
+        })
+        .collect();
+
+    let connections = CONNECTIONS.lock().unwrap();
+    for share in shares {
+        if infos.iter().any(|info| info.remote.eq_ignore_ascii_case(&share.path)) {
+            continue;
+        }
+        let connection = connections.get(&share.path.to_lowercase());
+        infos.push(ShareInfo {
+            remote: share.path.clone(),
+            local: Some(share.drive.clone()).filter(|drive| !drive.is_empty()),
+            configured: true,
+            connected: connection.is_some_and(|connection| connection.connected),
+            error: connection.and_then(|connection| connection.error.clone()),
+        });
+    }
+    Ok(infos)
+}
+
+/// 共有が roots のいずれかと重なるか (許可ルートのあるトークンに返す共有を絞る)
+pub fn overlaps(roots: &[std::path::PathBuf], info: &ShareInfo) -> bool {
+    let remote = unc(&info.remote).unwrap_or_else(|| info.remote.replace('/', "\\"));
+    let local = info.local.as_deref().map(|local| local.replace('/', "\\"));
+    roots.iter().any(|root| {
+        let root = root.to_string_lossy();
+        let root = unc(&root).unwrap_or_else(|| root.replace('/', "\\"));
+        [Some(&remote), local.as_ref()].into_iter().flatten().any(|share| is_under(&root, share) || is_under(share, &root))
+    })
+}
+
+// UNC のパス (\\server\share\...、//server/share/...、\\?\UNC\server\share\...) を \ 区切りの \\server\share\... にする
+fn unc(path: &str) -> Option<String> {
+    let path = path.replace('/', "\\");
+    if let Some(rest) = path.strip_prefix("\\\\?\\UNC\\") {
+        return Some(format!("\\\\{}", rest));
+    }
+    if path.starts_with("\\\\?\\") || path.starts_with("\\\\.\\") {
+        return None;
+    }
+    path.starts_with("\\\\").then_some(path)
+}
+
+// path が root と同じか root の下か (大文字と小文字は区別しない)
+fn is_under(path: &str, root: &str) -> bool {
+    let root = root.trim_end_matches('\\');
+    path.len() >= root.len()
+        && path.is_char_boundary(root.len())
+        && path[..root.len()].eq_ignore_ascii_case(root)
+        && (path.len() == root.len() || path[root.len()..].starts_with('\\'))
+}
+
+#[cfg(target_os = "windows")]
+fn wide(value: &str) -> Vec<u16> {
+    value.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+#[cfg(target_os = "windows")]
+unsafe fn from_wide(value: *const u16) -> Option<String> {
+    if value.is_null() {
+        return None;
+    }
+    let mut len = 0;
+    while *value.add(len) != 0 {
+        len += 1;
+    }
+    Some(String::from_utf16_lossy(std::slice::from_raw_parts(value, len)))
+}
+
+#[cfg(target_os = "windows")]
+fn add_connection(share: &Share) -> Result<(), String> {
+    use winapi::shared::winerror::{ERROR_ALREADY_ASSIGNED, ERROR_SESSION_CREDENTIAL_CONFLICT, NO_ERROR};
+    use winapi::um::winnetwk::{WNetAddConnection2W, NETRESOURCEW, RESOURCETYPE_DISK};
+
+    let mut remote = wide(&share.path);
+    let mut drive = wide(&share.drive);
+    let user = wide(&share.user);
+    let password = wide(&share.password);
+
+    let code = unsafe {
+        let mut resource: NETRESOURCEW = std::mem::zeroed();
+        resource.dwType = RESOURCETYPE_DISK;
+        resource.lpRemoteName = remote.as_mut_ptr();
+        if !share.drive.is_empty() {
+            resource.lpLocalName = drive.as_mut_ptr();
+        }
+        // user= がなければ、ユーザー名とパスワードに NULL を渡して現在のユーザーの資格情報を使う
+        let (user, password) = if share.user.is_empty() { (std::ptr::null(), std::ptr::null()) } else { (user.as_ptr(), password.as_ptr()) };
+        // フラグなし (ログオンし直したときに復元しない)
+        WNetAddConnection2W(&mut resource, password, user, 0)
+    };
+    match code {
+        NO_ERROR => Ok(()),
+        // 同じサーバーに別の資格情報で接続済み (エクスプローラーで開いた場合など)。その接続で読み書きできる
+        ERROR_SESSION_CREDENTIAL_CONFLICT => {
+            log!("ℹ️ {} のサーバーには別の資格情報で接続済みのため、その接続を使います", share.path);
+            Ok(())
+        }
+        // 前回の起動で割り当てたドライブはログオフするまで残る
+        ERROR_ALREADY_ASSIGNED if drive_remote(&share.drive).is_some_and(|remote| remote.eq_ignore_ascii_case(&share.path)) => Ok(()),
+        ERROR_ALREADY_ASSIGNED => Err(format!("drive {} is already in use", share.drive)),
+        code => Err(std::io::Error::from_raw_os_error(code as i32).to_string()),
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn add_connection(_share: &Share) -> Result<(), String> {
+    Err("connecting to network shares is only supported on Windows (mount the share instead)".to_string())
+}
+
+// ドライブ文字に割り当てられた共有
+#[cfg(target_os = "windows")]
+fn drive_remote(drive: &str) -> Option<String> {
+    use winapi::shared::winerror::NO_ERROR;
+    use winapi::um::winnetwk::WNetGetConnectionW;
+
+    let drive = wide(drive);
+    let mut buffer = vec![0u16; 1024];
+    let mut len = buffer.len() as u32;
+    unsafe {
+        if WNetGetConnectionW(drive.as_ptr(), buffer.as_mut_ptr(), &mut len) != NO_ERROR {
+            return None;
+        }
+        from_wide(buffer.as_ptr())
+    }
+}
+
+// 接続中のディスクの共有 (共有と、割り当てたドライブ文字)
+#[cfg(target_os = "windows")]
+fn mapped() -> Result<Vec<(String, Option<String>)>, String> {
+    use winapi::shared::winerror::{ERROR_MORE_DATA, ERROR_NO_MORE_ITEMS, NO_ERROR};
+    use winapi::um::winnetwk::{WNetCloseEnum, WNetEnumResourceW, WNetOpenEnumW, NETRESOURCEW, RESOURCETYPE_DISK, RESOURCE_CONNECTED};
+
+    let mut handle = std::ptr::null_mut();
+    let code = unsafe { WNetOpenEnumW(RESOURCE_CONNECTED, RESOURCETYPE_DISK, 0, std::ptr::null_mut(), &mut handle) };
+    if code != NO_ERROR {
+        return Err(std::io::Error::from_raw_os_error(code as i32).to_string());
+    }
+
+    // NETRESOURCEW はポインタを含むため、8 バイト単位で確保する
+    let mut buffer = vec![0u64; 2048];
+    let mut shares = Vec::new();
+    let result = loop {
+        let mut count = u32::MAX;
+        let mut size = (buffer.len() * 8) as u32;
+        let code = unsafe { WNetEnumResourceW(handle, &mut count, buffer.as_mut_ptr() as *mut _, &mut size) };
+        match code {
+            NO_ERROR => unsafe {
+                for resource in std::slice::from_raw_parts(buffer.as_ptr() as *const NETRESOURCEW, count as usize) {
+                    if let Some(remote) = from_wide(resource.lpRemoteName) {
+                        shares.push((remote, from_wide(resource.lpLocalName).filter(|local| !local.is_empty())));
+                    }
+                }
+            },
+            ERROR_NO_MORE_ITEMS => break Ok(shares),
+            ERROR_MORE_DATA => buffer.resize(size as usize / 8 + 1, 0),
+            code => break Err(std::io::Error::from_raw_os_error(code as i32).to_string()),
+        }
+    };
+    unsafe { WNetCloseEnum(handle) };
+    result
+}
+
+// マウント済みの CIFS / SMB の共有 (マウント元とマウント先)。/proc/mounts がなければ空
+#[cfg(not(target_os = "windows"))]
+fn mapped() -> Result<Vec<(String, Option<String>)>, String> {
+    let Ok(mounts) = std::fs::read_to_string("/proc/mounts") else {
+        return Ok(Vec::new());
+    };
+    Ok(mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (source, target, kind) = (fields.next()?, fields.next()?, fields.next()?);
+            // /proc/mounts では空白が \040 になる
+            matches!(kind, "cifs" | "smb3" | "smbfs").then(|| (source.replace("\\040", " "), Some(target.replace("\\040", " "))))
+        })
+        .collect())
+}
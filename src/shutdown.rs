@@ -0,0 +1,63 @@
+//! エージェントの停止 (トレイの終了・Ctrl+C・SIGTERM・/api/shutdown)
+//!
+//! 停止を要求すると、サーバーは新しい接続の受け付けをやめ、処理中のリクエスト (アップロードなど) が
+//! 終わってから止まる。WebSocket の接続は処理中のメソッドに応答してから閉じる。
+use std::sync::{Condvar, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// 停止を要求してから処理中のリクエストを待つ時間 (過ぎたら打ち切る)
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+// 停止を要求されたか (true になったら戻らない)
+static REQUESTED: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::channel(false).0);
+
+// 動作中のサーバーの数 (停止を待つために数える)
+static RUNNING: Mutex<usize> = Mutex::new(0);
+static STOPPED: Condvar = Condvar::new();
+
+/// 停止を要求する (何度呼んでもよい)
+pub fn request() {
+    REQUESTED.send_replace(true);
+}
+
+pub fn is_requested() -> bool {
+    *REQUESTED.borrow()
+}
+
+/// 停止が要求されるまで待つ
+pub async fn requested() {
+    let mut receiver = REQUESTED.subscribe();
+    // 送信側は static で破棄されないため、エラーにはならない
+    let _ = receiver.wait_for(|requested| *requested).await;
+}
+
+/// 動作中のサーバーがすべて止まるまで、最大 timeout 待つ (止まった場合は true)
+pub fn wait_for_servers(timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    let mut running = RUNNING.lock().unwrap();
+    while *running > 0 {
+        let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+            return false;
+        };
+        running = STOPPED.wait_timeout(running, remaining).unwrap().0;
+    }
+    true
+}
+
+/// サーバーが動作している間保持する (破棄すると止まったことになる)
+pub(crate) struct Running(());
+
+impl Running {
+    pub(crate) fn start() -> Self {
+        *RUNNING.lock().unwrap() += 1;
+        Running(())
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        *RUNNING.lock().unwrap() -= 1;
+        STOPPED.notify_all();
+    }
+}
@@ -0,0 +1,90 @@
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+const BLOCK_SIZE: usize = 64;
+
+/// HMAC-SHA256 (RFC 2104) を 16 進文字列で返す
+pub fn hmac_sha256_hex(key: &[u8], message: &[u8]) -> String {
+    to_hex(&hmac_sha256(key, message))
+}
+
+/// HMAC-SHA256 (RFC 2104)
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    // ブロック長より長い鍵はハッシュしてから使う
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    inner.update(message);
+    let mut outer = Sha256::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+/// 比較時間が一致位置に依存しないよう、全バイトを比較する
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// ファイル全体を読み込まずに SHA256 を求める
+/// 大きなファイルはメモリマップして少しずつハッシュする (ページキャッシュを使うため RSS が増えない)
+pub fn file_sha256_hex(path: &Path) -> io::Result<String> {
+    let file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    if let Some(map) = map_large(&file)? {
+        for chunk in map.chunks(MMAP_CHUNK_SIZE) {
+            hasher.update(chunk);
+        }
+        return Ok(format!("{:x}", hasher.finalize()));
+    }
+
+    let mut file = file;
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// これ以上の大きさのファイルはメモリマップで読む
+pub(crate) const MMAP_THRESHOLD: u64 = 16 * 1024 * 1024;
+const MMAP_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// MMAP_THRESHOLD 以上のファイルを読み取り専用でメモリマップする (小さいファイルは None)
+pub(crate) fn map_large(file: &fs::File) -> io::Result<Option<memmap2::Mmap>> {
+    if file.metadata()?.len() < MMAP_THRESHOLD {
+        return Ok(None);
+    }
+    // SAFETY: 読み取り専用でマップし、ハッシュや範囲のコピーの間だけ保持する
+    // (マップ中に他のプロセスがファイルを切り詰めた場合は保証されない)
+    let map = unsafe { memmap2::Mmap::map(file)? };
+    #[cfg(unix)]
+    let _ = map.advise(memmap2::Advice::Sequential);
+    Ok(Some(map))
+}
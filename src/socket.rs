@@ -0,0 +1,79 @@
+use futures_util::stream::{self, Stream};
+use std::io;
+
+// 接続を受け付けられなかった場合はログに出して次を待つ (エラーを返すと warp のサーバーが止まる)
+fn log_accept_error(e: &io::Error) {
+    log_error!("⚠️ ソケットの接続を受け付けられませんでした: {}", e);
+}
+
+/// Unix ドメインソケットで接続を受け付ける。ソケットファイルは所有者だけが読み書きできる
+#[cfg(unix)]
+pub fn incoming(path: &str) -> io::Result<impl Stream<Item = io::Result<tokio::net::UnixStream>>> {
+    use std::fs;
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+    use std::path::Path;
+
+    // 前回の起動で残ったソケットファイルを削除する (ソケット以外のファイルは消さない)
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path)));
+        }
+        fs::remove_file(path)?;
+    }
+    // 所有者だけが入れるフォルダで作って 0600 にしてから移す (作った直後の既定の権限で接続されないように)
+    let parent = Path::new(path).parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let private = parent.join(format!(".file_agent.{}", std::process::id()));
+    fs::DirBuilder::new().mode(0o700).create(&private)?;
+    let staged = private.join("s");
+    let bound = tokio::net::UnixListener::bind(&staged).and_then(|listener| {
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&staged);
+    let _ = fs::remove_dir(&private);
+    let listener = bound?;
+
+    Ok(stream::unfold(listener, |listener| async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => return Some((Ok(stream), listener)),
+                Err(e) => log_accept_error(&e),
+            }
+        }
+    }))
+}
+
+/// 名前付きパイプ (\\.\pipe\名前) で接続を受け付ける。リモートからの接続は拒否する
+#[cfg(windows)]
+pub fn incoming(name: &str) -> io::Result<impl Stream<Item = io::Result<tokio::net::windows::named_pipe::NamedPipeServer>>> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    // 同じ名前のパイプを別のプロセスが作っていれば失敗する
+    let first = ServerOptions::new()
+        .first_pipe_instance(true)
+        .reject_remote_clients(true)
+        .create(name)?;
+    let name = name.to_string();
+
+    Ok(stream::unfold((first, name), |(mut server, name)| async move {
+        loop {
+            // 接続されたインスタンスを返す前に、次の接続を待つインスタンスを作る
+            let connected = server.connect().await;
+            let next = match ServerOptions::new().reject_remote_clients(true).create(&name) {
+                Ok(next) => next,
+                Err(e) => {
+                    log_accept_error(&e);
+                    return None;
+                }
+            };
+            match connected {
+                Ok(()) => return Some((Ok(server), (next, name))),
+                Err(e) => {
+                    log_accept_error(&e);
+                    server = next;
+                }
+            }
+        }
+    }))
+}
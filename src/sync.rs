@@ -0,0 +1,669 @@
+//! ローカルのフォルダとリモート (S3 / WebDAV) の定期的な同期 (sync=)
+//!
+//! 前回の同期の後のローカルの大きさ・更新日時とリモートのバージョン (ETag) を状態ファイル
+//! (file_agent_sync/<name>.json) に残し、次の同期ではそれと比べてどちらで変わったかを判断する。
+//! push はローカルを、pull はリモートを正としてもう一方を合わせ、both は変わった方をもう一方に送る。
+//! 両方で変わったファイルは conflict= で決める。削除は delete=true のときだけ、前回同期したファイルに限って反映する。
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+use crate::audit::AuditLog;
+use crate::changes::ChangeLog;
+use crate::cleanup::wildcard_match;
+use crate::config::Config;
+use crate::reload::LiveConfig;
+use crate::remote::{self, Remote, RemoteFile, Upload};
+use crate::{handlers, hooks, policy, quota, scan, shares, tempfiles, writequota};
+use crate::walk::{self, WalkOptions};
+
+// interval_minutes= を省略したときの同期の間隔
+const DEFAULT_INTERVAL_MINUTES: u64 = 15;
+
+// 1 回の同期の結果に残すエラーの数
+const MAX_ERRORS: usize = 20;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncDirection {
+    Push, // ローカル → リモート
+    Pull, // リモート → ローカル
+    Both, // 変わった方からもう一方へ
+}
+
+impl SyncDirection {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "push" => Some(SyncDirection::Push),
+            "pull" => Some(SyncDirection::Pull),
+            "both" => Some(SyncDirection::Both),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SyncDirection::Push => "push",
+            SyncDirection::Pull => "pull",
+            SyncDirection::Both => "both",
+        }
+    }
+}
+
+/// both で両方が変わったファイル (と、初回の同期で両方にあって大きさが違うファイル) の扱い
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    Newer,    // 更新日時が新しい方
+    Local,    // ローカル
+    Remote,   // リモート
+    KeepBoth, // リモートを .conflict-<日時> を付けた名前で受け取り、ローカルを送る
+}
+
+impl ConflictPolicy {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "newer" => Some(ConflictPolicy::Newer),
+            "local" => Some(ConflictPolicy::Local),
+            "remote" => Some(ConflictPolicy::Remote),
+            "keep_both" => Some(ConflictPolicy::KeepBoth),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ConflictPolicy::Newer => "newer",
+            ConflictPolicy::Local => "local",
+            ConflictPolicy::Remote => "remote",
+            ConflictPolicy::KeepBoth => "keep_both",
+        }
+    }
+}
+
+/// 同期するフォルダ
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SyncTask {
+    pub name: String,
+    pub local: PathBuf,
+    pub remote: String, // s3:<prefix> か webdav:<folder>
+    pub direction: SyncDirection,
+    pub conflict: ConflictPolicy,
+    pub delete: bool, // 前回同期したファイルを一方で削除したら、もう一方でも削除する
+    pub interval_minutes: u64,
+}
+
+impl SyncTask {
+    // 形式: work|local=D:\Work|remote=s3:backups/work|direction=both|conflict=newer|delete=true|interval_minutes=15
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split('|');
+        let name = parts.next()?.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return None;
+        }
+
+        let mut task = SyncTask {
+            name: name.to_string(),
+            local: PathBuf::new(),
+            remote: String::new(),
+            direction: SyncDirection::Both,
+            conflict: ConflictPolicy::Newer,
+            delete: false,
+            interval_minutes: DEFAULT_INTERVAL_MINUTES,
+        };
+        for part in parts {
+            let (key, val) = part.split_once('=')?;
+            let val = val.trim();
+            match key.trim() {
+                "local" if !val.is_empty() => task.local = PathBuf::from(val),
+                "remote" if val.starts_with("s3:") || val.starts_with("webdav:") => task.remote = val.to_string(),
+                "direction" => task.direction = SyncDirection::parse(val)?,
+                "conflict" => task.conflict = ConflictPolicy::parse(val)?,
+                "delete" => task.delete = val == "true",
+                "interval_minutes" => task.interval_minutes = val.parse::<u64>().ok()?.max(1),
+                _ => return None,
+            }
+        }
+        if task.local.as_os_str().is_empty() || task.remote.is_empty() {
+            return None;
+        }
+        Some(task)
+    }
+
+    pub fn to_ini_value(&self) -> String {
+        let mut value = format!("{}|local={}|remote={}", self.name, self.local.display(), self.remote);
+        if self.direction != SyncDirection::Both {
+            value.push_str(&format!("|direction={}", self.direction.name()));
+        }
+        if self.conflict != ConflictPolicy::Newer {
+            value.push_str(&format!("|conflict={}", self.conflict.name()));
+        }
+        if self.delete {
+            value.push_str("|delete=true");
+        }
+        if self.interval_minutes != DEFAULT_INTERVAL_MINUTES {
+            value.push_str(&format!("|interval_minutes={}", self.interval_minutes));
+        }
+        value
+    }
+}
+
+/// 1 回の同期の結果
+#[derive(Debug, Serialize, Deserialize, Clone, Default, ToSchema)]
+pub struct SyncResult {
+    pub uploaded: usize,
+    pub downloaded: usize,
+    pub deleted_local: usize,
+    pub deleted_remote: usize,
+    pub conflicts: usize,
+    pub errors: Vec<String>, // 最初の 20 件
+}
+
+impl SyncResult {
+    fn error(&mut self, message: String) {
+        if self.errors.len() < MAX_ERRORS {
+            self.errors.push(message);
+        }
+    }
+
+    fn count(&mut self, action: Action) {
+        match action {
+            Action::Upload => self.uploaded += 1,
+            Action::Download => self.downloaded += 1,
+            Action::KeepBoth => {
+                self.uploaded += 1;
+                self.downloaded += 1;
+            }
+            Action::DeleteLocal => self.deleted_local += 1,
+            Action::DeleteRemote => self.deleted_remote += 1,
+            Action::Keep | Action::Adopt | Action::Forget => {}
+        }
+    }
+
+    fn changed(&self) -> bool {
+        self.uploaded + self.downloaded + self.deleted_local + self.deleted_remote > 0
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "uploaded {}, downloaded {}, deleted locally {}, deleted remotely {}, conflicts {}, errors {}",
+            self.uploaded,
+            self.downloaded,
+            self.deleted_local,
+            self.deleted_remote,
+            self.conflicts,
+            self.errors.len()
+        )
+    }
+}
+
+/// 同期の状態 (GET /api/sync/status)
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct SyncStatus {
+    pub name: String,
+    pub local: String,
+    pub remote: String,
+    pub direction: SyncDirection,
+    pub conflict: ConflictPolicy,
+    pub delete: bool,
+    pub interval_minutes: u64,
+    pub running: bool,
+    pub last_started: Option<u64>,
+    pub last_finished: Option<u64>,
+    pub next_run: Option<u64>,
+    pub last_result: Option<SyncResult>,
+}
+
+/// 起動時の設定の同期ごとの状態
+pub struct SyncManager {
+    statuses: Mutex<Vec<SyncStatus>>,
+}
+
+impl SyncManager {
+    fn new(tasks: &[SyncTask]) -> Self {
+        let statuses = tasks
+            .iter()
+            .map(|task| SyncStatus {
+                name: task.name.clone(),
+                local: task.local.display().to_string(),
+                remote: task.remote.clone(),
+                direction: task.direction,
+                conflict: task.conflict,
+                delete: task.delete,
+                interval_minutes: task.interval_minutes,
+                running: false,
+                last_started: None,
+                last_finished: None,
+                next_run: None,
+                last_result: None,
+            })
+            .collect();
+        Self { statuses: Mutex::new(statuses) }
+    }
+
+    pub fn statuses(&self) -> Vec<SyncStatus> {
+        self.statuses.lock().unwrap().clone()
+    }
+
+    fn update(&self, name: &str, change: impl FnOnce(&mut SyncStatus)) {
+        if let Some(status) = self.statuses.lock().unwrap().iter_mut().find(|status| status.name == name) {
+            change(status);
+        }
+    }
+}
+
+// 前回の同期の後のファイルの状態
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct FileState {
+    local_size: u64,
+    local_modified: u64,
+    remote_version: String,
+}
+
+// 状態ファイルの内容。local か remote が変わったら前回の状態は使わない (別の場所のファイルを削除しないように)
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct SyncState {
+    local: String,
+    remote: String,
+    files: BTreeMap<String, FileState>,
+}
+
+struct LocalFile {
+    size: u64,
+    modified: u64,
+}
+
+// ファイルごとの処理
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Keep,         // どちらも変わっていない
+    Adopt,        // 初回で両方にあり大きさが同じ (送らずに同期済みとする)
+    Upload,
+    Download,
+    KeepBoth,     // リモートを別の名前で受け取ってからローカルを送る
+    DeleteLocal,
+    DeleteRemote,
+    Forget,       // 反映しない (状態から外す)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn modified_secs(metadata: &std::fs::Metadata) -> u64 {
+    metadata.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// 同期ごとに interval_minutes の間隔で同期を始める (起動時にもすぐ同期する)。
+/// 同期する名前と間隔は起動時の設定のものを使い、リモートの設定は同期のたびに現在の設定から読む
+pub(crate) fn start(tasks: &[SyncTask], live: LiveConfig, audit: Arc<AuditLog>, changes: Arc<ChangeLog>) -> Arc<SyncManager> {
+    // 名前が重複する同期は最初のものだけを使う (状態ファイルを共有しないように)
+    let mut unique: Vec<SyncTask> = Vec::new();
+    for task in tasks {
+        if !unique.iter().any(|other| other.name == task.name) {
+            unique.push(task.clone());
+        }
+    }
+    let manager = Arc::new(SyncManager::new(&unique));
+    for task in unique {
+        let live = live.clone();
+        let manager = manager.clone();
+        let audit = audit.clone();
+        let changes = changes.clone();
+        tokio::spawn(async move {
+            let interval = Duration::from_secs(task.interval_minutes * 60);
+            let mut ticker = tokio::time::interval(interval);
+            // 同期に間隔より長くかかっても、続けて同期しない
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                manager.update(&task.name, |status| {
+                    status.running = true;
+                    status.last_started = Some(now_secs());
+                });
+                let config = live.read().unwrap().clone();
+                let result = run(&task, &config, &audit, &changes).await;
+                manager.update(&task.name, |status| {
+                    status.running = false;
+                    status.last_finished = Some(now_secs());
+                    status.next_run = Some(now_secs() + interval.as_secs());
+                    status.last_result = Some(result);
+                });
+            }
+        });
+    }
+    manager
+}
+
+/// 1 回同期する。何かを送受信・削除したか、エラーがあれば監査ログに記録する
+pub async fn run(task: &SyncTask, config: &Config, audit: &AuditLog, changes: &ChangeLog) -> SyncResult {
+    let mut result = SyncResult::default();
+    if let Err(e) = sync(task, config, audit, changes, &mut result).await {
+        result.error(e);
+    }
+    if result.changed() || !result.errors.is_empty() {
+        audit.record("sync", &task.local.display().to_string(), &format!("{} {}: {}", task.name, task.remote, result.summary()));
+    }
+    match result.errors.first() {
+        Some(error) => log_error!("⚠️ 同期 {} でエラーがありました ({} 件): {}", task.name, result.errors.len(), error),
+        None if result.changed() => log!("🔄 同期 {}: {}", task.name, result.summary()),
+        None => {}
+    }
+    result
+}
+
+async fn sync(task: &SyncTask, config: &Config, audit: &AuditLog, changes: &ChangeLog, result: &mut SyncResult) -> Result<(), String> {
+    if policy::is_allowed(&config.vault_roots, &task.local) {
+        return Err(format!("Sync folder is inside a vault: {}", task.local.display()));
+    }
+    let remote = Remote::open(&task.remote, config)?;
+
+    let root = task.local.clone();
+    let excludes = config.walk_excludes.clone();
+    let scan_excludes = excludes.clone();
+    let configured = config.shares.clone();
+    let local = tokio::task::spawn_blocking(move || {
+        // share= の共有の下のフォルダは、接続するまで見つからない
+        shares::ensure(&configured, &root)?;
+        scan_local(&root, scan_excludes)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+    let mut remote_files = BTreeMap::new();
+    for file in remote.list().await? {
+        // ローカルで飛ばす名前はリモートでも飛ばす (リモートにだけあるファイルとして受信・削除しないように)
+        if is_excluded(&excludes, &file.path) {
+            continue;
+        }
+        if is_safe_path(&file.path) {
+            remote_files.insert(file.path.clone(), file);
+        } else {
+            result.error(format!("Skipped remote file with an unsafe name: {}", file.path));
+        }
+    }
+
+    let state_path = Config::get_sync_dir().join(format!("{}.json", task.name));
+    let location = remote.location();
+    let local_name = task.local.display().to_string();
+    let mut state: SyncState = tokio::fs::read(&state_path)
+        .await
+        .ok()
+        .and_then(|content| serde_json::from_slice(&content).ok())
+        .filter(|state: &SyncState| state.local == local_name && state.remote == location)
+        .unwrap_or_default();
+    state.local = local_name;
+    state.remote = location;
+
+    let paths: BTreeSet<String> = local.keys().chain(remote_files.keys()).chain(state.files.keys()).cloned().collect();
+    let mut uploaded = Vec::new();
+    for path in paths {
+        let previous = state.files.remove(&path);
+        let (local_file, remote_file) = (local.get(&path), remote_files.get(&path));
+        let (action, conflict) = decide(task, local_file, remote_file, previous.as_ref());
+        if conflict {
+            result.conflicts += 1;
+        }
+        let outcome = match action {
+            Action::Keep => Ok(previous.clone()),
+            Action::Forget => Ok(None),
+            Action::Adopt => Ok(local_file.zip(remote_file).map(|(local_file, remote_file)| FileState {
+                local_size: local_file.size,
+                local_modified: local_file.modified,
+                remote_version: remote_file.version.clone(),
+            })),
+            _ => transfer(action, &task.local, &path, &remote, remote_file, config, audit, changes).await,
+        };
+        match outcome {
+            Ok(entry) => {
+                result.count(action);
+                if matches!(action, Action::Upload | Action::KeepBoth) {
+                    uploaded.push(path.clone());
+                }
+                if let Some(entry) = entry {
+                    state.files.insert(path, entry);
+                }
+            }
+            Err(e) => {
+                result.error(format!("{}: {}", path, e));
+                // 次の同期でもう一度判断する
+                if let Some(previous) = previous {
+                    state.files.insert(path, previous);
+                }
+            }
+        }
+    }
+
+    if !uploaded.is_empty() {
+        let versions: BTreeMap<String, String> = match remote.list().await {
+            Ok(files) => files.into_iter().map(|file| (file.path, file.version)).collect(),
+            Err(e) => {
+                result.error(e);
+                BTreeMap::new()
+            }
+        };
+        // バージョンが分からないものは状態から外す (次の同期では大きさが同じなら同期済みとする)
+        for path in uploaded {
+            match versions.get(&path) {
+                Some(version) => {
+                    if let Some(entry) = state.files.get_mut(&path) {
+                        entry.remote_version = version.clone();
+                    }
+                }
+                None => {
+                    state.files.remove(&path);
+                }
+            }
+        }
+    }
+
+    let content = serde_json::to_vec(&state).map_err(|e| e.to_string())?;
+    tokio::fs::create_dir_all(Config::get_sync_dir()).await.map_err(|e| e.to_string())?;
+    tokio::fs::write(&state_path, content).await.map_err(|e| format!("Failed to save sync state: {}", e))
+}
+
+// 前回の状態と比べて処理を決める (2 つ目は衝突したか)
+fn decide(task: &SyncTask, local: Option<&LocalFile>, remote: Option<&RemoteFile>, previous: Option<&FileState>) -> (Action, bool) {
+    let local_changed = match (local, previous) {
+        (Some(local), Some(previous)) => local.size != previous.local_size || local.modified != previous.local_modified,
+        (None, None) => false,
+        _ => true,
+    };
+    let remote_changed = match (remote, previous) {
+        (Some(remote), Some(previous)) => remote.version != previous.remote_version,
+        (None, None) => false,
+        _ => true,
+    };
+    let synced = previous.is_some();
+    match (local, remote) {
+        (None, None) => (Action::Forget, false),
+        (Some(local), Some(remote)) => {
+            if !synced && local.size == remote.size {
+                return (Action::Adopt, false);
+            }
+            if !local_changed && !remote_changed {
+                return (Action::Keep, false);
+            }
+            match task.direction {
+                SyncDirection::Push => (Action::Upload, false),
+                SyncDirection::Pull => (Action::Download, false),
+                SyncDirection::Both if !synced || (local_changed && remote_changed) => (resolve(task.conflict, local, remote), true),
+                SyncDirection::Both if local_changed => (Action::Upload, false),
+                SyncDirection::Both => (Action::Download, false),
+            }
+        }
+        // リモートにない (前回同期していて、ローカルが変わっていなければリモートで削除された)
+        (Some(_), None) => match task.direction {
+            SyncDirection::Pull | SyncDirection::Both if synced && !local_changed && task.delete => (Action::DeleteLocal, false),
+            SyncDirection::Pull => (Action::Forget, false),
+            SyncDirection::Push | SyncDirection::Both => (Action::Upload, false),
+        },
+        (None, Some(_)) => match task.direction {
+            SyncDirection::Push | SyncDirection::Both if synced && !remote_changed && task.delete => (Action::DeleteRemote, false),
+            SyncDirection::Push => (Action::Forget, false),
+            SyncDirection::Pull | SyncDirection::Both => (Action::Download, false),
+        },
+    }
+}
+
+fn resolve(policy: ConflictPolicy, local: &LocalFile, remote: &RemoteFile) -> Action {
+    match policy {
+        ConflictPolicy::Newer if remote.modified > local.modified => Action::Download,
+        ConflictPolicy::Newer | ConflictPolicy::Local => Action::Upload,
+        ConflictPolicy::Remote => Action::Download,
+        ConflictPolicy::KeepBoth => Action::KeepBoth,
+    }
+}
+
+// ローカルのファイル (root からの / 区切りの相対パス)。書き込み中の一時ファイルは除く
+fn scan_local(root: &Path, excludes: Vec<String>) -> Result<BTreeMap<String, LocalFile>, String> {
+    if !root.is_dir() {
+        return Err(format!("Local folder not found: {}", root.display()));
+    }
+    let options = WalkOptions { show_hidden: true, exclude_names: excludes, ..Default::default() };
+    let mut files = BTreeMap::new();
+    for entry in walk::entries(root, &options) {
+        let Some(metadata) = entry.metadata.filter(|metadata| metadata.is_file()) else {
+            continue;
+        };
+        if tempfiles::is_recorded(&entry.path) {
+            continue;
+        }
+        let Ok(relative) = entry.path.strip_prefix(root) else {
+            continue;
+        };
+        let path = relative.components().map(|part| part.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+        files.insert(path, LocalFile { size: metadata.len(), modified: modified_secs(&metadata) });
+    }
+    Ok(files)
+}
+
+// / 区切りの相対パスのいずれかの部分が walk_exclude= に一致するか (scan_local で飛ばすフォルダの下も含む)
+fn is_excluded(excludes: &[String], path: &str) -> bool {
+    path.split('/').any(|part| excludes.iter().any(|pattern| wildcard_match(pattern, part)))
+}
+
+// 送受信・削除する。同期した後の状態を返す (削除したら None)
+#[allow(clippy::too_many_arguments)]
+async fn transfer(action: Action, root: &Path, path: &str, remote: &Remote, remote_file: Option<&RemoteFile>, config: &Config, audit: &AuditLog, changes: &ChangeLog) -> Result<Option<FileState>, String> {
+    let target = local_path(root, path);
+    match action {
+        Action::Upload | Action::KeepBoth => {
+            if action == Action::KeepBoth {
+                let copy = local_path(root, &conflict_name(path, now_secs()));
+                download(remote, path, &copy, config, audit).await?;
+                changes.record("write", &copy.to_string_lossy(), None);
+            }
+            let (size, modified) = upload(remote, path, &target).await?;
+            // リモートのバージョンは送り終えた後の一覧で分かる
+            Ok(Some(FileState { local_size: size, local_modified: modified, remote_version: String::new() }))
+        }
+        Action::Download => {
+            let version = remote_file.map(|file| file.version.clone()).unwrap_or_default();
+            let (size, modified) = download(remote, path, &target, config, audit).await?;
+            changes.record("write", &target.to_string_lossy(), None);
+            Ok(Some(FileState { local_size: size, local_modified: modified, remote_version: version }))
+        }
+        Action::DeleteLocal => {
+            tokio::fs::remove_file(&target).await.map_err(|e| e.to_string())?;
+            changes.record("delete", &target.to_string_lossy(), None);
+            Ok(None)
+        }
+        Action::DeleteRemote => remote.delete(path).await.map(|_| None),
+        // 送受信しない (呼び出し側で扱う)
+        Action::Keep | Action::Adopt | Action::Forget => Ok(None),
+    }
+}
+
+async fn upload(remote: &Remote, path: &str, source: &Path) -> Result<(u64, u64), String> {
+    let file = tokio::fs::File::open(source).await.map_err(|e| e.to_string())?;
+    let metadata = file.metadata().await.map_err(|e| e.to_string())?;
+    remote.upload(path, Upload::File(file, metadata.len())).await?;
+    Ok((metadata.len(), modified_secs(&metadata)))
+}
+
+// API の書き込み (s3_download) と同じ確認を通して受け取り、受け取ったファイルの大きさと更新日時を返す
+async fn download(remote: &Remote, path: &str, target: &Path, config: &Config, audit: &AuditLog) -> Result<(u64, u64), String> {
+    let download = remote.download(path).await?;
+    let size = download.size;
+    writequota::check_file(size)?;
+
+    let checked = {
+        let (config, target) = (config.clone(), target.to_path_buf());
+        tokio::task::spawn_blocking(move || {
+            // 隔離ポリシーで別の場所に書き込むと、次の同期でローカルから消えたものとしてリモートを削除してしまう
+            if handlers::write_target(&config, &target)? != target {
+                return Err(format!("Sync does not download into a quarantined folder: {}", target.display()));
+            }
+            quota::check(&config.quotas, &target, None, quota::Usage { bytes: size, files: 1 })?;
+            quota::check_free_space(&target, size)
+        })
+    };
+    checked.await.map_err(|e| e.to_string())??;
+
+    // スキャンする場合は、先にすべて受け取る
+    let (download, data) = if config.scanner.enabled() {
+        let data = download.bytes().await?;
+        let scanner = config.scanner.clone();
+        let (scanned, data) = tokio::task::spawn_blocking(move || (scanner.scan(&data), data))
+            .await
+            .map_err(|e| e.to_string())?;
+        scan::record(audit, target, scanned).map_err(|e| e.message())?;
+        (None, Some(data))
+    } else {
+        (Some(download), None)
+    };
+
+    let saved = {
+        let (config, target) = (config.clone(), target.to_path_buf());
+        tokio::task::spawn_blocking(move || {
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            hooks::before(&config.hooks, "write", &target, None)?;
+            policy::save_version(&config.policies, &target)
+        })
+    };
+    saved.await.map_err(|e| e.to_string())??;
+
+    match download {
+        Some(download) => download.save(target).await?,
+        None => remote::save_bytes(target, &data.unwrap_or_default()).await?,
+    }
+    let metadata = tokio::fs::metadata(target).await.map_err(|e| e.to_string())?;
+    let (hooks, target) = (config.hooks.clone(), target.to_path_buf());
+    let _ = tokio::task::spawn_blocking(move || hooks::after(&hooks, "write", &target, None)).await;
+    Ok((metadata.len(), modified_secs(&metadata)))
+}
+
+fn local_path(root: &Path, path: &str) -> PathBuf {
+    path.split('/').fold(root.to_path_buf(), |target, part| target.join(part))
+}
+
+// リモートの名前がローカルのフォルダの外や別のドライブを指さないか
+fn is_safe_path(path: &str) -> bool {
+    !path.is_empty()
+        && !path.contains(['\\', ':'])
+        && !path.chars().any(char::is_control)
+        && path.split('/').all(|part| !part.is_empty() && part != "." && part != "..")
+}
+
+// a/report.docx → a/report.conflict-20240501-091244.docx
+fn conflict_name(path: &str, now: u64) -> String {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (format!("{}/", dir), name),
+        None => (String::new(), path),
+    };
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+        _ => (name, String::new()),
+    };
+    let (year, month, day) = crate::civil_from_days((now / 86_400) as i64);
+    let time = now % 86_400;
+    format!(
+        "{}{}.conflict-{:04}{:02}{:02}-{:02}{:02}{:02}{}",
+        dir, stem, year, month, day, time / 3600, time / 60 % 60, time % 60, extension
+    )
+}
@@ -0,0 +1,144 @@
+//! 書き込み中の一時ファイルの記録と片付け
+//!
+//! アップロードや Base64 の書き込み、保管庫の鍵の切り替えで作る一時ファイルをマニフェスト
+//! (file_agent_temp.json) に記録し、置き換えか削除が終わったら記録から外す。
+//! 転送の途中でエージェントが終了すると一時ファイルが残るため、起動時に記録に残っているものを削除し、
+//! その後も 1 時間ごとに temp_max_age_hours より古いものを削除する。
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// 古い一時ファイルを確認する間隔
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct TempEntry {
+    created: u64, // 作成した時刻 (UNIX 時刻、秒)
+}
+
+struct Manifest {
+    path: PathBuf,
+    entries: BTreeMap<PathBuf, TempEntry>,
+}
+
+impl Manifest {
+    fn save(&self) {
+        let result = serde_json::to_vec(&self.entries)
+            .map_err(|e| e.to_string())
+            .and_then(|content| fs::write(&self.path, content).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            log_error!("⚠️ 一時ファイルの記録の保存に失敗しました: {}", e);
+        }
+    }
+}
+
+// init するまでは記録しない (クライアントとして使う場合など)
+static MANIFEST: Mutex<Option<Manifest>> = Mutex::new(None);
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// マニフェストを読み込み、前回の終了時に残った一時ファイルを削除する (起動時に呼ぶ)
+pub fn init(path: PathBuf) {
+    let entries: BTreeMap<PathBuf, TempEntry> = fs::read(&path)
+        .ok()
+        .and_then(|content| serde_json::from_slice(&content).ok())
+        .unwrap_or_default();
+    let mut manifest = Manifest { path, entries };
+    // 起動した時点で書き込み中のものはないため、記録に残っているものはすべて不要
+    let removed = remove_where(&mut manifest, |_| true);
+    if removed > 0 {
+        log!("🧹 前回の終了時に残った一時ファイルを {} 件削除しました", removed);
+    }
+    *MANIFEST.lock().unwrap() = Some(manifest);
+}
+
+// 条件に合う記録の一時ファイルを削除して記録から外す (削除したファイル数を返す)
+fn remove_where(manifest: &mut Manifest, stale: impl Fn(&TempEntry) -> bool) -> usize {
+    let before = manifest.entries.len();
+    let mut removed = 0;
+    manifest.entries.retain(|path, entry| {
+        if !stale(entry) {
+            return true;
+        }
+        match fs::remove_file(path) {
+            Ok(()) => {
+                removed += 1;
+                false
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+            // 使用中などで削除できないものは次の確認で再び試す
+            Err(e) => {
+                log_error!("⚠️ 一時ファイルを削除できませんでした: {}: {}", path.display(), e);
+                true
+            }
+        }
+    });
+    if manifest.entries.len() != before {
+        manifest.save();
+    }
+    removed
+}
+
+/// target と同じフォルダに置く一時ファイルのパスを決めて記録する (使い終わったら release する)
+pub fn create(target: &Path, suffix: &str) -> PathBuf {
+    let name = target.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let temp = target.with_file_name(format!(".{}.{}.{}", name, &crate::random_hex()[..8], suffix));
+    if let Some(manifest) = MANIFEST.lock().unwrap().as_mut() {
+        // 記録は相対パスにしない (作業ディレクトリが変わっても削除できるように)
+        let recorded = std::path::absolute(&temp).unwrap_or_else(|_| temp.clone());
+        manifest.entries.insert(recorded, TempEntry { created: now_secs() });
+        manifest.save();
+    }
+    temp
+}
+
+/// 一時ファイルを記録から外す (置き換えたか、削除した後に呼ぶ)
+pub fn release(temp: &Path) {
+    if let Some(manifest) = MANIFEST.lock().unwrap().as_mut() {
+        let recorded = std::path::absolute(temp).unwrap_or_else(|_| temp.to_path_buf());
+        if manifest.entries.remove(&recorded).is_some() {
+            manifest.save();
+        }
+    }
+}
+
+/// path が書き込み中の一時ファイルとして記録されているか (同期で送らないように)
+pub fn is_recorded(path: &Path) -> bool {
+    match MANIFEST.lock().unwrap().as_ref() {
+        Some(manifest) => {
+            let recorded = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+            manifest.entries.contains_key(&recorded)
+        }
+        None => false,
+    }
+}
+
+/// max_age より古い一時ファイルを削除する
+pub fn cleanup(max_age: Duration) -> usize {
+    let cutoff = now_secs().saturating_sub(max_age.as_secs());
+    match MANIFEST.lock().unwrap().as_mut() {
+        Some(manifest) => remove_where(manifest, |entry| entry.created < cutoff),
+        None => 0,
+    }
+}
+
+/// 1 時間ごとに max_age より古い一時ファイルを削除する (max_age が 0 なら起動時の削除のみ)
+pub async fn run_scheduler(max_age: Duration) {
+    if max_age.is_zero() {
+        return;
+    }
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + CLEANUP_INTERVAL, CLEANUP_INTERVAL);
+    loop {
+        ticker.tick().await;
+        match tokio::task::spawn_blocking(move || cleanup(max_age)).await {
+            Ok(removed) if removed > 0 => log!("🧹 古い一時ファイルを {} 件削除しました", removed),
+            Ok(_) => {}
+            Err(e) => log_error!("❌ 一時ファイルの削除に失敗しました: {}", e),
+        }
+    }
+}
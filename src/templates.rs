@@ -0,0 +1,120 @@
+//! テンプレートからのファイルの作成 (template_dir=)
+//!
+//! /api/create の template に template_dir= のフォルダにあるファイルの名前を指定すると、
+//! その内容の {{name}} (作成するファイルの拡張子を除いた名前)・{{date}} (UTC の YYYY-MM-DD) と、
+//! リクエストの variables のキーを置き換えて新しいファイルの内容にする。知らないキーはそのまま残す。
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// テンプレートのファイルの大きさの上限
+const MAX_TEMPLATE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// dir にある template を読み込み、変数を置き換えた内容を返す
+pub fn render(dir: &Path, template: &str, target: &Path, variables: &HashMap<String, String>) -> Result<String, String> {
+    // テンプレートはフォルダの直下のファイルに限る (../ などでフォルダの外を読ませない)
+    if template.is_empty() || template.starts_with('.') || Path::new(template).file_name().is_none_or(|name| name != template) {
+        return Err(format!("Invalid template name: {}", template));
+    }
+    let path = dir.join(template);
+    let metadata = fs::metadata(&path).map_err(|_| format!("Template not found: {}", template))?;
+    if !metadata.is_file() {
+        return Err(format!("Template not found: {}", template));
+    }
+    if metadata.len() > MAX_TEMPLATE_BYTES {
+        return Err(format!("Template too large: {} bytes (limit {} bytes)", metadata.len(), MAX_TEMPLATE_BYTES));
+    }
+    let content = fs::read_to_string(&path).map_err(|e| format!("Failed to read template {}: {}", template, e))?;
+
+    let mut values = HashMap::new();
+    values.insert("name".to_string(), target.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default());
+    values.insert("date".to_string(), today());
+    // リクエストの variables は組み込みの変数より優先する (ローカルの日付を渡すなど)
+    values.extend(variables.iter().map(|(key, value)| (key.clone(), value.clone())));
+    Ok(substitute(&content, &values))
+}
+
+// {{key}} ({{ key }} も可) を置き換える
+fn substitute(content: &str, values: &HashMap<String, String>) -> String {
+    let mut output = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find("}}") {
+            Some(end) => match values.get(after[..end].trim()) {
+                Some(value) => {
+                    output.push_str(value);
+                    rest = &after[end + 2..];
+                }
+                None => {
+                    output.push_str("{{");
+                    rest = after;
+                }
+            },
+            None => {
+                output.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+// 今日の日付 (UTC, YYYY-MM-DD)
+fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) / 86_400;
+    let (year, month, day) = crate::civil_from_days(days as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // テンプレートのフォルダ (外側に読ませたくないファイルも置く)
+    fn template_dir(name: &str) -> std::path::PathBuf {
+        let base = std::env::temp_dir().join(format!("file_agent_templates_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let dir = base.join("templates");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(base.join("secret.txt"), "secret").unwrap();
+        dir
+    }
+
+    #[test]
+    fn name_and_date_are_substituted() {
+        let dir = template_dir("builtin");
+        fs::write(dir.join("note.md"), "# {{name}}\n{{ date }}\n{{unknown}}").unwrap();
+        let content = render(&dir, "note.md", Path::new("/notes/meeting.md"), &HashMap::new()).unwrap();
+        assert_eq!(content, format!("# meeting\n{}\n{{{{unknown}}}}", today()));
+    }
+
+    #[test]
+    fn variables_override_the_builtin_values() {
+        let dir = template_dir("variables");
+        fs::write(dir.join("note.md"), "{{date}} {{author}} {{").unwrap();
+        let variables = HashMap::from([("date".to_string(), "2024-01-02".to_string()), ("author".to_string(), "me".to_string())]);
+        assert_eq!(render(&dir, "note.md", Path::new("a.md"), &variables).unwrap(), "2024-01-02 me {{");
+    }
+
+    #[test]
+    fn today_is_a_date() {
+        let date = today();
+        assert_eq!(date.len(), 10);
+        assert!(date.chars().enumerate().all(|(i, c)| if i == 4 || i == 7 { c == '-' } else { c.is_ascii_digit() }));
+    }
+
+    #[test]
+    fn names_outside_the_template_dir_are_rejected() {
+        let dir = template_dir("outside");
+        let secret = dir.parent().unwrap().join("secret.txt");
+        for name in ["../secret.txt", "sub/../../secret.txt", "", ".hidden", &secret.display().to_string()] {
+            let error = render(&dir, name, Path::new("a.txt"), &HashMap::new()).unwrap_err();
+            assert!(error.starts_with("Invalid template name"), "{}: {}", name, error);
+        }
+        assert_eq!(render(&dir, "missing.txt", Path::new("a.txt"), &HashMap::new()).unwrap_err(), "Template not found: missing.txt");
+    }
+}
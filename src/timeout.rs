@@ -0,0 +1,34 @@
+//! リクエストの処理時間の上限 (request_timeout_secs=)
+//!
+//! 応答のないネットワークドライブなどでファイルシステムの処理が戻らない場合も、上限を過ぎたら
+//! クライアントに 504 を返す。専用スレッドの処理は止められないため、終わるまで裏で続く。
+use std::sync::RwLock;
+use std::time::Duration;
+
+// 処理時間の上限 (None なら上限なし)
+static LIMIT: RwLock<Option<Duration>> = RwLock::new(None);
+
+/// 上限を設定する (0 なら上限なし。設定ファイルの再読み込みでも呼ぶ)
+pub fn set_limit(secs: u64) {
+    *LIMIT.write().unwrap() = (secs > 0).then(|| Duration::from_secs(secs));
+}
+
+pub fn limit() -> Option<Duration> {
+    *LIMIT.read().unwrap()
+}
+
+/// 上限を過ぎたリクエストの拒否理由 (recover で 504 のレスポンスにする)
+#[derive(Debug)]
+pub struct TimedOut {
+    pub secs: u64,
+}
+
+impl warp::reject::Reject for TimedOut {}
+
+/// future を上限まで待つ (上限なしなら終わるまで待つ)
+pub async fn run<T>(future: impl std::future::Future<Output = T>) -> Result<T, TimedOut> {
+    match limit() {
+        Some(limit) => tokio::time::timeout(limit, future).await.map_err(|_| TimedOut { secs: limit.as_secs() }),
+        None => Ok(future.await),
+    }
+}
@@ -0,0 +1,43 @@
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// 証明書と秘密鍵がなければ自己署名の証明書を生成して保存する。生成した場合は true
+pub fn ensure_self_signed(cert_path: &Path, key_path: &Path, bind_address: IpAddr) -> Result<bool, String> {
+    if cert_path.exists() && key_path.exists() {
+        return Ok(false);
+    }
+
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+    if !bind_address.is_unspecified() && !bind_address.is_loopback() {
+        names.push(bind_address.to_string());
+    }
+    if let Ok(host) = std::env::var("COMPUTERNAME").or_else(|_| std::env::var("HOSTNAME")) {
+        if !host.is_empty() {
+            names.push(host);
+        }
+    }
+
+    let certified = rcgen::generate_simple_self_signed(names)
+        .map_err(|e| format!("Failed to generate self-signed certificate: {}", e))?;
+    fs::write(cert_path, certified.cert.pem())
+        .map_err(|e| format!("Failed to write {}: {}", cert_path.display(), e))?;
+    fs::write(key_path, certified.key_pair.serialize_pem())
+        .map_err(|e| format!("Failed to write {}: {}", key_path.display(), e))?;
+    Ok(true)
+}
+
+/// 証明書と秘密鍵が読み込めるか確認する (warp は読み込めないと起動時に panic するため)
+pub fn check_files(cert_path: &Path, key_path: &Path) -> Result<(), String> {
+    check_pem(cert_path)?;
+    check_pem(key_path)
+}
+
+/// PEM 形式のファイルが読み込めるか確認する
+pub fn check_pem(path: &Path) -> Result<(), String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if !content.contains("-----BEGIN") {
+        return Err(format!("{}: not a PEM file", path.display()));
+    }
+    Ok(())
+}
@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+// 削除したフォルダを保管するフォルダ名 (削除したフォルダの親に作る)
+pub const HOLDING_DIR: &str = ".file_agent_trash";
+
+// 期限切れの保管フォルダを削除する間隔
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// 削除され、保管中のフォルダ
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct HeldEntry {
+    pub id: String,
+    pub original_path: String,
+    pub held_path: String,
+    pub deleted_at: u64,
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, ToSchema)]
+pub struct PurgeReport {
+    pub purged: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+/// フォルダ削除の保管領域。保管期間 (retention) が 0 なら削除は即時・永続
+pub struct Trash {
+    path: PathBuf, // 保管中の一覧 (JSON)
+    retention: Duration,
+    entries: Mutex<Vec<HeldEntry>>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl Trash {
+    pub fn load(path: PathBuf, retention: Duration) -> Self {
+        let entries = fs::read(&path)
+            .ok()
+            .and_then(|content| serde_json::from_slice(&content).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            retention,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.retention.is_zero()
+    }
+
+    pub fn retention_hours(&self) -> u64 {
+        self.retention.as_secs() / 3600
+    }
+
+    fn save(&self, entries: &[HeldEntry]) {
+        let result = serde_json::to_vec_pretty(entries)
+            .map_err(|e| e.to_string())
+            .and_then(|content| fs::write(&self.path, content).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            log_error!("⚠️ 保管中フォルダの一覧の保存に失敗しました: {}", e);
+        }
+    }
+
+    /// フォルダを同じ親の保管フォルダへ移動する (同じボリューム内なので名前の変更だけで済む)
+    pub fn hold(&self, dir: &Path) -> Result<HeldEntry, String> {
+        let parent = dir.parent().ok_or("Cannot delete a root directory")?;
+        let name = dir.file_name().ok_or("Cannot delete a root directory")?.to_string_lossy().to_string();
+        let holding = parent.join(HOLDING_DIR);
+        fs::create_dir_all(&holding).map_err(|e| format!("Failed to create holding area: {}", e))?;
+
+        let now = now_secs();
+        let mut id = format!("{}-{}", now, name);
+        let mut n = 1;
+        while holding.join(&id).exists() {
+            n += 1;
+            id = format!("{}-{}-{}", now, n, name);
+        }
+        let held_path = holding.join(&id);
+        fs::rename(dir, &held_path).map_err(|e| format!("Failed to move directory to holding area: {}", e))?;
+
+        let entry = HeldEntry {
+            id,
+            original_path: dir.to_string_lossy().to_string(),
+            held_path: held_path.to_string_lossy().to_string(),
+            deleted_at: now,
+            expires_at: now + self.retention.as_secs(),
+        };
+        let mut entries = self.entries.lock().unwrap();
+        entries.push(entry.clone());
+        self.save(&entries);
+        Ok(entry)
+    }
+
+    pub fn list(&self) -> Vec<HeldEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    /// 保管中のフォルダを完全に削除する。filter が true を返すものだけが対象
+    pub fn purge(&self, filter: impl Fn(&HeldEntry) -> bool) -> PurgeReport {
+        let mut report = PurgeReport::default();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|entry| {
+            if !filter(entry) {
+                return true;
+            }
+            let held = Path::new(&entry.held_path);
+            match fs::remove_dir_all(held) {
+                Ok(_) => {
+                    // 空になった保管フォルダも片付ける
+                    if let Some(holding) = held.parent() {
+                        let _ = fs::remove_dir(holding);
+                    }
+                    report.purged.push(entry.original_path.clone());
+                    false
+                }
+                // 手動で戻された・削除されたものは一覧からのみ除く
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+                Err(e) => {
+                    report.errors.push(format!("{}: {}", entry.held_path, e));
+                    true
+                }
+            }
+        });
+        self.save(&entries);
+        report
+    }
+
+    pub fn purge_expired(&self) -> PurgeReport {
+        let now = now_secs();
+        self.purge(|entry| entry.expires_at <= now)
+    }
+}
+
+/// 保管期間を過ぎたフォルダを定期的に削除する
+pub async fn run_purger(trash: Arc<Trash>) {
+    if !trash.enabled() {
+        return;
+    }
+    let mut ticker = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let trash = trash.clone();
+        let result = tokio::task::spawn_blocking(move || trash.purge_expired()).await;
+        if let Ok(report) = result {
+            if !report.purged.is_empty() {
+                log!("🧹 保管期間を過ぎたフォルダを削除しました: {} 件", report.purged.len());
+            }
+            for error in &report.errors {
+                log_error!("⚠️ 保管中フォルダの削除に失敗しました: {}", error);
+            }
+        }
+    }
+}
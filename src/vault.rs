@@ -0,0 +1,452 @@
+use crate::signing::{from_hex, to_hex};
+use crate::{copy, policy, tempfiles};
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use utoipa::ToSchema;
+use walkdir::WalkDir;
+
+// 暗号化したファイルの先頭に付けるマジック (続けて 12 バイトのノンス、暗号文と認証タグ)
+const MAGIC: &[u8] = b"FAVAULT1";
+const NONCE_LEN: usize = 12;
+// AES-GCM の認証タグの長さ
+const TAG_LEN: usize = 16;
+
+// パスフレーズから鍵を導出する PBKDF2-HMAC-SHA256 の反復回数
+const PBKDF2_ROUNDS: u32 = 600_000;
+const SALT_LEN: usize = 16;
+
+type KeyBytes = [u8; 32];
+
+fn vault_error(message: &str) -> io::Error {
+    io::Error::other(message.to_string())
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> KeyBytes {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+    key
+}
+
+fn seal(key: &KeyBytes, data: &[u8]) -> io::Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let sealed = cipher.encrypt(&nonce, data).map_err(|_| vault_error("Failed to encrypt file"))?;
+    let mut content = Vec::with_capacity(NONCE_LEN + sealed.len());
+    content.extend_from_slice(&nonce);
+    content.extend_from_slice(&sealed);
+    Ok(content)
+}
+
+fn open(key: &KeyBytes, content: &[u8]) -> Option<Vec<u8>> {
+    if content.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, sealed) = content.split_at(NONCE_LEN);
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), sealed)
+        .ok()
+}
+
+// パスフレーズから導出した鍵で、ファイルを暗号化する鍵をラップ・アンラップする
+fn wrap_key(kek: &KeyBytes, key: &KeyBytes) -> Result<String, String> {
+    seal(kek, key).map(|wrapped| to_hex(&wrapped)).map_err(|e| e.to_string())
+}
+
+fn unwrap_key(kek: &KeyBytes, wrapped: &str) -> Option<KeyBytes> {
+    let key = open(kek, &from_hex(wrapped)?)?;
+    key.try_into().ok()
+}
+
+/// file_agent.ini に保存する保管庫の鍵の情報。
+/// ファイルを暗号化する鍵はパスフレーズから導出した鍵でラップして保存し、パスフレーズ自体は保存しない
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct VaultKeyInfo {
+    pub salt: String,                 // vault_salt=
+    pub wrapped_key: String,          // vault_wrapped_key=
+    pub previous_wrapped_key: String, // vault_previous_wrapped_key= (鍵のローテーション中のみ)
+}
+
+impl VaultKeyInfo {
+    pub fn initialized(&self) -> bool {
+        !self.wrapped_key.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, ToSchema)]
+pub struct RotationStatus {
+    pub running: bool,
+    pub files_total: usize,
+    pub files_done: usize,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct VaultStatus {
+    pub roots: Vec<String>,
+    pub initialized: bool, // パスフレーズが設定済みか
+    pub locked: bool,
+    pub rotation_pending: bool, // 古い鍵で暗号化されたファイルが残っている
+    pub rotation: Option<RotationStatus>,
+}
+
+struct Keys {
+    current: KeyBytes,
+    previous: Option<KeyBytes>, // ローテーション中は古い鍵でも復号する
+}
+
+struct VaultState {
+    keys: Option<Keys>,
+    info: VaultKeyInfo,
+}
+
+/// 保管庫 (vault) ルート。配下に書き込むファイルを AES-256-GCM で暗号化し、読み込み時に復号する。
+/// パスフレーズで解錠するまでは暗号化されたファイルの読み書きができない
+pub struct Vault {
+    roots: Vec<PathBuf>,
+    state: Mutex<VaultState>,
+    rotation: Mutex<Option<RotationStatus>>,
+}
+
+impl Vault {
+    /// legacy_key は旧形式の vault_key= の値 (設定されていれば起動時から解錠済み)
+    pub fn new(roots: Vec<PathBuf>, legacy_key: &str, info: VaultKeyInfo) -> Self {
+        let keys = if !info.initialized() && !legacy_key.is_empty() {
+            Some(Keys {
+                current: Sha256::digest(legacy_key.as_bytes()).into(),
+                previous: None,
+            })
+        } else {
+            None
+        };
+        Self {
+            roots,
+            state: Mutex::new(VaultState { keys, info }),
+            rotation: Mutex::new(None),
+        }
+    }
+
+    pub fn is_vault_path(&self, path: &Path) -> bool {
+        !self.roots.is_empty() && policy::is_allowed(&self.roots, path)
+    }
+
+    pub fn status(&self) -> VaultStatus {
+        let state = self.state.lock().unwrap();
+        VaultStatus {
+            roots: self.roots.iter().map(|root| root.display().to_string()).collect(),
+            initialized: state.info.initialized(),
+            locked: state.keys.is_none(),
+            rotation_pending: !state.info.previous_wrapped_key.is_empty(),
+            rotation: self.rotation.lock().unwrap().clone(),
+        }
+    }
+
+    /// パスフレーズで解錠する。未設定なら、このパスフレーズを設定して保存すべき鍵の情報を返す
+    pub fn unlock(&self, passphrase: &str) -> Result<Option<VaultKeyInfo>, String> {
+        if passphrase.is_empty() {
+            return Err("Passphrase must not be empty".to_string());
+        }
+        let info = self.state.lock().unwrap().info.clone();
+
+        if !info.initialized() {
+            // 旧形式の vault_key= で暗号化済みのファイルを読めるよう、その鍵を引き継ぐ
+            let salt = random_bytes::<SALT_LEN>();
+            let kek = derive_key(passphrase, &salt);
+            let mut state = self.state.lock().unwrap();
+            let current = state.keys.as_ref().map(|keys| keys.current).unwrap_or_else(random_bytes::<32>);
+            state.info = VaultKeyInfo {
+                salt: to_hex(&salt),
+                wrapped_key: wrap_key(&kek, &current)?,
+                previous_wrapped_key: String::new(),
+            };
+            state.keys = Some(Keys { current, previous: None });
+            return Ok(Some(state.info.clone()));
+        }
+
+        let keys = unlock_keys(passphrase, &info)?;
+        self.state.lock().unwrap().keys = Some(keys);
+        Ok(None)
+    }
+
+    pub fn lock(&self) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        if !state.info.initialized() {
+            return Err("Set a passphrase with /api/vault/unlock before locking the vault".to_string());
+        }
+        state.keys = None;
+        Ok(())
+    }
+
+    /// ファイルを暗号化する鍵を新しくする (new_passphrase を指定するとパスフレーズも変える)。
+    /// 保存すべき鍵の情報を返す。既存のファイルの再暗号化は run_rotation で行う
+    pub fn start_rotation(&self, passphrase: &str, new_passphrase: Option<&str>) -> Result<VaultKeyInfo, String> {
+        let info = self.state.lock().unwrap().info.clone();
+        if !info.initialized() {
+            return Err("Set a passphrase with /api/vault/unlock before rotating the key".to_string());
+        }
+        if !info.previous_wrapped_key.is_empty() {
+            return Err("A key rotation is already in progress".to_string());
+        }
+        let old = unlock_keys(passphrase, &info)?.current;
+
+        let (salt, kek) = match new_passphrase {
+            Some(new_passphrase) if !new_passphrase.is_empty() => {
+                let salt = random_bytes::<SALT_LEN>().to_vec();
+                let kek = derive_key(new_passphrase, &salt);
+                (salt, kek)
+            }
+            Some(_) => return Err("New passphrase must not be empty".to_string()),
+            None => {
+                let salt = from_hex(&info.salt).unwrap_or_default();
+                let kek = derive_key(passphrase, &salt);
+                (salt, kek)
+            }
+        };
+        let current = random_bytes::<32>();
+        let new_info = VaultKeyInfo {
+            salt: to_hex(&salt),
+            wrapped_key: wrap_key(&kek, &current)?,
+            previous_wrapped_key: wrap_key(&kek, &old)?,
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state.info = new_info.clone();
+        state.keys = Some(Keys {
+            current,
+            previous: Some(old),
+        });
+        Ok(new_info)
+    }
+
+    /// 古い鍵で暗号化されたファイルを新しい鍵で暗号化し直す。
+    /// すべて終われば古い鍵を破棄し、保存すべき鍵の情報を返す
+    pub fn run_rotation(&self) -> Option<VaultKeyInfo> {
+        let (current, previous) = {
+            let state = self.state.lock().unwrap();
+            match &state.keys {
+                Some(Keys {
+                    current,
+                    previous: Some(previous),
+                }) => (*current, *previous),
+                _ => return None,
+            }
+        };
+        {
+            let mut rotation = self.rotation.lock().unwrap();
+            if rotation.as_ref().map(|r| r.running).unwrap_or(false) {
+                return None;
+            }
+            *rotation = Some(RotationStatus {
+                running: true,
+                ..Default::default()
+            });
+        }
+
+        let files: Vec<PathBuf> = self
+            .roots
+            .iter()
+            .flat_map(|root| WalkDir::new(root).into_iter().filter_map(|e| e.ok()))
+            .filter(|entry| entry.file_type().is_file() && is_encrypted_file(entry.path()))
+            .map(|entry| entry.into_path())
+            .collect();
+        self.update_rotation(|r| r.files_total = files.len());
+
+        for path in &files {
+            if let Err(e) = reencrypt(path, &current, &previous) {
+                self.update_rotation(|r| r.errors.push(format!("{}: {}", path.display(), e)));
+            }
+            self.update_rotation(|r| r.files_done += 1);
+        }
+
+        let failed = {
+            let mut rotation = self.rotation.lock().unwrap();
+            let rotation = rotation.as_mut()?;
+            rotation.running = false;
+            !rotation.errors.is_empty()
+        };
+        if failed {
+            log_error!("⚠️ 保管庫の再暗号化に失敗したファイルがあります (古い鍵を残します)");
+            return None;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(keys) = state.keys.as_mut() {
+            keys.previous = None;
+        }
+        state.info.previous_wrapped_key.clear();
+        log!("🔑 保管庫の鍵をローテーションしました: {} ファイル", files.len());
+        Some(state.info.clone())
+    }
+
+    fn update_rotation(&self, f: impl FnOnce(&mut RotationStatus)) {
+        if let Some(rotation) = self.rotation.lock().unwrap().as_mut() {
+            f(rotation);
+        }
+    }
+
+    fn encrypt(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let key = match &self.state.lock().unwrap().keys {
+            Some(keys) => keys.current,
+            None => return Err(vault_error("Vault is locked")),
+        };
+        let mut content = MAGIC.to_vec();
+        content.extend_from_slice(&seal(&key, data)?);
+        Ok(content)
+    }
+
+    // 暗号化されていない内容はそのまま返す (vault 設定前からあるファイルなど)
+    fn decrypt(&self, content: Vec<u8>) -> io::Result<Vec<u8>> {
+        if !is_encrypted(&content) {
+            return Ok(content);
+        }
+        let (current, previous) = match &self.state.lock().unwrap().keys {
+            Some(keys) => (keys.current, keys.previous),
+            None => return Err(vault_error("Vault is locked")),
+        };
+        let sealed = &content[MAGIC.len()..];
+        open(&current, sealed)
+            .or_else(|| previous.and_then(|previous| open(&previous, sealed)))
+            .ok_or_else(|| vault_error("Failed to decrypt file: wrong vault key or corrupted file"))
+    }
+
+    /// path に書き込む。vault ルート配下なら暗号化する
+    pub fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        if self.is_vault_path(path) {
+            fs::write(path, self.encrypt(data)?)
+        } else {
+            fs::write(path, data)
+        }
+    }
+
+    /// path を読み込む。暗号化されていれば復号する (vault の外へコピーされたファイルも含む)
+    pub fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.decrypt(fs::read(path)?)
+    }
+
+    /// 読み込んだときの内容の大きさ (暗号化されていれば復号後の大きさ)。先頭のマジックだけを読む
+    pub fn content_len(&self, path: &Path, file_len: u64) -> u64 {
+        if is_encrypted_file(path) {
+            file_len.saturating_sub((MAGIC.len() + NONCE_LEN + TAG_LEN) as u64)
+        } else {
+            file_len
+        }
+    }
+
+    /// ファイルが暗号化されているか (先頭のマジックだけを読む)
+    pub fn is_encrypted_file(&self, path: &Path) -> bool {
+        is_encrypted_file(path)
+    }
+
+    // from を to へ置くときに暗号化の状態を変えるか (vault ルートへ入れる平文のファイルは暗号化し、
+    // vault ルートから外へ出す暗号化されたファイルは復号する)
+    fn converts(&self, from: &Path, to: &Path) -> bool {
+        if self.is_vault_path(to) {
+            !is_encrypted_file(from)
+        } else {
+            self.is_vault_path(from) && is_encrypted_file(from)
+        }
+    }
+
+    /// ファイルをコピーする。vault ルートへのコピーは暗号化し、vault ルートから外へのコピーは復号する。
+    /// コピーしたバイト数を返す
+    pub fn copy_file(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        if self.converts(from, to) {
+            let data = self.read(from)?;
+            self.write(to, &data)?;
+            Ok(data.len() as u64)
+        } else {
+            copy::file(from, to)
+        }
+    }
+
+    /// フォルダを再帰的にコピーする。vault ルートが関わらなければ copy::dir_recursive で並行してコピーし、
+    /// 関わるならファイルごとに copy_file で暗号化・復号する
+    pub fn copy_dir(&self, from: &Path, to: &Path, parallelism: usize) -> io::Result<()> {
+        if !self.is_vault_path(from) && !self.is_vault_path(to) {
+            return copy::dir_recursive(from, to, parallelism);
+        }
+        for entry in WalkDir::new(from).sort_by_file_name() {
+            let entry = entry?;
+            let relative = entry.path().strip_prefix(from).map_err(io::Error::other)?;
+            let target = to.join(relative);
+            if entry.file_type().is_dir() {
+                fs::create_dir_all(&target)?;
+            } else {
+                self.copy_file(entry.path(), &target)
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", entry.path().display(), e)))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// from を to に移動する。vault ルートの内外をまたぐ場合は、コピーして暗号化・復号してから from を消す
+    /// (暗号化・復号できなければ from を残したままエラーを返す)
+    pub fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        if self.is_vault_path(from) == self.is_vault_path(to) {
+            return fs::rename(from, to);
+        }
+        if from.is_dir() {
+            self.copy_dir(from, to, 1)?;
+            fs::remove_dir_all(from)
+        } else {
+            self.copy_file(from, to)?;
+            fs::remove_file(from)
+        }
+    }
+}
+
+fn unlock_keys(passphrase: &str, info: &VaultKeyInfo) -> Result<Keys, String> {
+    let salt = from_hex(&info.salt).ok_or("Vault salt in the configuration is invalid")?;
+    let kek = derive_key(passphrase, &salt);
+    let current = unwrap_key(&kek, &info.wrapped_key).ok_or("Wrong vault passphrase")?;
+    let previous = if info.previous_wrapped_key.is_empty() {
+        None
+    } else {
+        Some(unwrap_key(&kek, &info.previous_wrapped_key).ok_or("Previous vault key in the configuration is invalid")?)
+    };
+    Ok(Keys { current, previous })
+}
+
+// 新しい鍵で復号できるファイルは済みとして飛ばす。書き込みは一時ファイルから置き換える
+fn reencrypt(path: &Path, current: &KeyBytes, previous: &KeyBytes) -> Result<(), String> {
+    let content = fs::read(path).map_err(|e| e.to_string())?;
+    let sealed = &content[MAGIC.len()..];
+    if open(current, sealed).is_some() {
+        return Ok(());
+    }
+    let data = open(previous, sealed).ok_or("cannot be decrypted with the previous key")?;
+    let mut rotated = MAGIC.to_vec();
+    rotated.extend_from_slice(&seal(current, &data).map_err(|e| e.to_string())?);
+
+    let temp = tempfiles::create(path, "rotating");
+    let result = fs::write(&temp, rotated).and_then(|_| fs::rename(&temp, path)).map_err(|e| {
+        let _ = fs::remove_file(&temp);
+        e.to_string()
+    });
+    tempfiles::release(&temp);
+    result
+}
+
+fn is_encrypted(content: &[u8]) -> bool {
+    content.len() >= MAGIC.len() + NONCE_LEN && content.starts_with(MAGIC)
+}
+
+fn is_encrypted_file(path: &Path) -> bool {
+    let mut head = [0u8; MAGIC.len()];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut head))
+        .map(|_| head == MAGIC)
+        .unwrap_or(false)
+}
@@ -0,0 +1,115 @@
+use crate::cleanup::wildcard_match;
+use crate::is_hidden;
+use crate::policy;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+// walk_exclude= を設定しない場合に除外する名前 (アクセスできずエラーになるシステム領域と、削除したフォルダの保管領域)
+pub const DEFAULT_EXCLUDES: &[&str] = &[
+    "$RECYCLE.BIN",
+    crate::trash::HOLDING_DIR,
+    "System Volume Information",
+    "pagefile.sys",
+    "hiberfil.sys",
+    "swapfile.sys",
+];
+
+/// 名前が除外パターン (`*` と `?` のワイルドカード、大文字小文字を区別しない) に一致するか
+pub fn is_excluded_name(patterns: &[String], path: &Path) -> bool {
+    match path.file_name() {
+        Some(name) => {
+            let name = name.to_string_lossy();
+            patterns.iter().any(|pattern| wildcard_match(pattern, &name))
+        }
+        None => false,
+    }
+}
+
+/// 再帰走査のオプション
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    pub show_hidden: bool,
+    pub respect_gitignore: bool, // .gitignore / .ignore に一致するパスを除外
+    pub follow_symlinks: bool,   // リンク先のディレクトリも走査する (循環は検出して打ち切る)
+    pub allowed_roots: Vec<PathBuf>, // 空でなければ、リンク先がこの範囲外のリンクは辿らない
+    pub excluded: Vec<PathBuf>,  // これらのパス配下は走査しない
+    pub exclude_names: Vec<String>, // 名前がこれらに一致するエントリ (フォルダなら配下も) を飛ばす
+    pub resume_after: Option<PathBuf>, // 走査順でこのパス以前のエントリを飛ばす (ページング用)
+}
+
+pub struct WalkEntry {
+    pub path: PathBuf,
+    pub metadata: Option<fs::Metadata>, // シンボリックリンクを辿らない
+}
+
+// リンクを辿る場合でも WalkEntry にはリンク自体のメタデータを渡す
+fn entry_metadata(path: &Path, followed: Option<fs::Metadata>, follow_symlinks: bool) -> Option<fs::Metadata> {
+    if follow_symlinks {
+        fs::symlink_metadata(path).ok()
+    } else {
+        followed
+    }
+}
+
+/// root 配下をファイル名順 (深さ優先) で走査する (root 自身も depth 0 として含む)。
+/// 走査順は Path の比較順と一致するため、resume_after から同じ順序で再開できる。
+/// follow_symlinks 時、祖先ディレクトリへ戻るリンクはエラーとして返されるため走査対象から外れる
+pub fn entries(root: &Path, options: &WalkOptions) -> Box<dyn Iterator<Item = WalkEntry> + Send> {
+    let show_hidden = options.show_hidden;
+    let follow = options.follow_symlinks;
+    let excluded = options.excluded.clone();
+    let exclude_names = options.exclude_names.clone();
+    let allowed_roots = options.allowed_roots.clone();
+    let resume_after = options.resume_after.clone();
+    let prune_resume = resume_after.clone();
+    let keep = move |path: &Path, depth: usize, is_symlink: bool, metadata: Option<&fs::Metadata>| {
+        if excluded.iter().any(|d| path.starts_with(d)) {
+            return false;
+        }
+        if depth > 0 && is_excluded_name(&exclude_names, path) {
+            return false;
+        }
+        // 再開位置より前にあるディレクトリは (再開位置の祖先を除き) 中に入らない
+        if let Some(after) = &prune_resume {
+            if path < after.as_path() && !after.starts_with(path) {
+                return false;
+            }
+        }
+        if is_symlink && follow && !allowed_roots.is_empty() && !policy::is_allowed(&allowed_roots, path) {
+            return false;
+        }
+        show_hidden || depth == 0 || !is_hidden(path, metadata)
+    };
+
+    if options.respect_gitignore {
+        let walker = ignore::WalkBuilder::new(root)
+            .standard_filters(false)
+            .git_ignore(true)
+            .git_global(true)
+            .git_exclude(true)
+            .ignore(true)
+            .parents(true)
+            .require_git(false)
+            .follow_links(follow)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .filter_entry(move |e| keep(e.path(), e.depth(), e.path_is_symlink(), e.metadata().ok().as_ref()))
+            .build();
+        let entries = walker.filter_map(|e| e.ok()).map(move |e| WalkEntry {
+            metadata: entry_metadata(e.path(), e.metadata().ok(), follow),
+            path: e.into_path(),
+        });
+        return Box::new(entries.filter(move |e| resume_after.as_ref().map(|after| e.path > *after).unwrap_or(true)));
+    }
+
+    let walker = WalkDir::new(root)
+        .follow_links(follow)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(move |e| keep(e.path(), e.depth(), e.path_is_symlink(), e.metadata().ok().as_ref()));
+    let entries = walker.filter_map(|e| e.ok()).map(move |e| WalkEntry {
+        metadata: entry_metadata(e.path(), e.metadata().ok(), follow),
+        path: e.into_path(),
+    });
+    Box::new(entries.filter(move |e| resume_after.as_ref().map(|after| e.path > *after).unwrap_or(true)))
+}
@@ -0,0 +1,223 @@
+//! WebDAV のサーバーとのファイルのやり取り (webdav_url=)
+//!
+//! Nextcloud・ownCloud・Synology など、PROPFIND に対応したサーバーを同期 (sync=) の相手にする。
+//! 一覧は Depth: 1 の PROPFIND でフォルダを 1 段ずつ辿る (Depth: infinity は無効にしているサーバーが多いため)。
+//! ファイルを送る前に、ないフォルダは MKCOL で作る。
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use crate::config::Config;
+use crate::remote::{self, Download, RemoteFile, Upload};
+use crate::s3::uri_encode;
+use crate::xml;
+
+// PROPFIND で取得するプロパティ
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?><D:propfind xmlns:D="DAV:"><D:prop><D:resourcetype/><D:getcontentlength/><D:getlastmodified/><D:getetag/></D:prop></D:propfind>"#;
+
+/// 設定した WebDAV のサーバー
+#[derive(Debug)]
+pub struct WebDav {
+    base: reqwest::Url, // 末尾は /
+    user: String,
+    password: String,
+    created: Mutex<HashSet<String>>, // MKCOL で作った (または既にあった) フォルダ
+}
+
+impl WebDav {
+    /// 設定からサーバーを作る (webdav_url= がなければ None)
+    pub fn from_config(config: &Config) -> Option<Result<Self, String>> {
+        if config.webdav_url.is_empty() {
+            return None;
+        }
+        Some(Self::new(config))
+    }
+
+    fn new(config: &Config) -> Result<Self, String> {
+        let mut base = reqwest::Url::parse(&config.webdav_url).map_err(|_| format!("Invalid webdav_url: {}", config.webdav_url))?;
+        if !matches!(base.scheme(), "http" | "https") || base.host_str().is_none() {
+            return Err(format!("Invalid webdav_url: {}", config.webdav_url));
+        }
+        if !base.path().ends_with('/') {
+            let path = format!("{}/", base.path());
+            base.set_path(&path);
+        }
+        Ok(WebDav {
+            base,
+            user: config.webdav_user.clone(),
+            password: config.webdav_password.clone(),
+            created: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// path (webdav_url= からの相対パス、/ 区切り) の URL
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, uri_encode(path, true))
+    }
+
+    /// folder の下のすべてのファイル (path は folder からの相対パス)。folder がなければ空
+    pub async fn list_all(&self, folder: &str) -> Result<Vec<RemoteFile>, String> {
+        let mut files = Vec::new();
+        let mut pending = vec![folder.to_string()];
+        while let Some(current) = pending.pop() {
+            let target = if current.is_empty() { String::new() } else { format!("{}/", current) };
+            let response = self
+                .request(reqwest::Method::from_bytes(b"PROPFIND").unwrap(), &target)
+                .header("Depth", "1")
+                .header(reqwest::header::CONTENT_TYPE, "application/xml")
+                .body(PROPFIND_BODY)
+                .send()
+                .await
+                .map_err(|e| format!("WebDAV request failed: {}", e))?;
+            if response.status() == reqwest::StatusCode::NOT_FOUND && current == folder {
+                return Ok(files);
+            }
+            let body = check_status(response).await?.text().await.map_err(|e| format!("WebDAV request failed: {}", e))?;
+
+            for item in xml::elements(&body, "response") {
+                let Some(path) = xml::element(item, "href").and_then(|href| self.relative_path(&href)) else {
+                    continue;
+                };
+                // 応答には問い合わせたフォルダ自身も含まれる。下の段のものだけを扱う (辿り続けないように)
+                let inside = if current.is_empty() { !path.is_empty() } else { path.starts_with(&format!("{}/", current)) };
+                if !inside {
+                    continue;
+                }
+                if xml::has_element(item, "collection") {
+                    pending.push(path);
+                    continue;
+                }
+                let size = xml::element(item, "getcontentlength").and_then(|size| size.trim().parse().ok()).unwrap_or(0);
+                let modified = xml::element(item, "getlastmodified")
+                    .and_then(|value| httpdate::parse_http_date(value.trim()).ok())
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                let etag = xml::element(item, "getetag").unwrap_or_default();
+                let etag = etag.trim().trim_start_matches("W/").trim_matches('"');
+                let version = if etag.is_empty() { format!("{}-{}", size, modified) } else { etag.to_string() };
+                let relative = if folder.is_empty() { path } else { path[folder.len() + 1..].to_string() };
+                files.push(RemoteFile { path: relative, size, modified, version });
+            }
+        }
+        Ok(files)
+    }
+
+    /// content を path に送る (ないフォルダは作る)
+    pub async fn upload(&self, path: &str, content: Upload) -> Result<(), String> {
+        if let Some((parent, _)) = path.rsplit_once('/') {
+            self.create_folders(parent).await?;
+        }
+        let (body, size) = content.into_body();
+        let response = self
+            .request(reqwest::Method::PUT, path)
+            .header(reqwest::header::CONTENT_LENGTH, size)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| format!("WebDAV request failed: {}", e))?;
+        check_status(response).await.map(|_| ())
+    }
+
+    /// path のダウンロードを始める (本文は Download から読む)
+    pub async fn download(&self, path: &str) -> Result<Download, String> {
+        let response = self
+            .request(reqwest::Method::GET, path)
+            .send()
+            .await
+            .map_err(|e| format!("WebDAV request failed: {}", e))?;
+        let response = check_status(response).await?;
+        Download::new(response, "WebDAV")
+    }
+
+    /// path を削除する (既になければ何もしない)
+    pub async fn delete(&self, path: &str) -> Result<(), String> {
+        let response = self
+            .request(reqwest::Method::DELETE, path)
+            .send()
+            .await
+            .map_err(|e| format!("WebDAV request failed: {}", e))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        check_status(response).await.map(|_| ())
+    }
+
+    // folder とその親を上から順に作る (405 は既にある)
+    async fn create_folders(&self, folder: &str) -> Result<(), String> {
+        let mut current = String::new();
+        for part in folder.split('/') {
+            if !current.is_empty() {
+                current.push('/');
+            }
+            current.push_str(part);
+            if self.created.lock().unwrap().contains(&current) {
+                continue;
+            }
+            let response = self
+                .request(reqwest::Method::from_bytes(b"MKCOL").unwrap(), &format!("{}/", current))
+                .send()
+                .await
+                .map_err(|e| format!("WebDAV request failed: {}", e))?;
+            if response.status() != reqwest::StatusCode::METHOD_NOT_ALLOWED {
+                check_status(response).await?;
+            }
+            self.created.lock().unwrap().insert(current.clone());
+        }
+        Ok(())
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = remote::client().request(method, self.url(path));
+        if self.user.is_empty() {
+            request
+        } else {
+            request.basic_auth(&self.user, Some(&self.password))
+        }
+    }
+
+    // 応答の href (パスか URL、%XX でエンコードされている) を webdav_url= からの相対パスにする (末尾の / は除く)
+    fn relative_path(&self, href: &str) -> Option<String> {
+        let path = match reqwest::Url::parse(href) {
+            Ok(url) => url.path().to_string(),
+            Err(_) => href.to_string(),
+        };
+        let path = percent_decode(&path);
+        let base = percent_decode(self.base.path());
+        // webdav_url= のフォルダ自身は末尾の / を付けずに返すサーバーもある
+        if format!("{}/", path) == base {
+            return Some(String::new());
+        }
+        Some(path.strip_prefix(&base)?.trim_end_matches('/').to_string())
+    }
+}
+
+// 2xx 以外は状態コードをエラーにする
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, String> {
+    let status = response.status();
+    if status.is_success() {
+        Ok(response)
+    } else {
+        Err(format!("WebDAV error {}", status.as_u16()))
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok()).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
@@ -0,0 +1,26 @@
+use crate::mime;
+use include_dir::{include_dir, Dir};
+use std::path::Path;
+use warp::http::Uri;
+use warp::path::{FullPath, Tail};
+use warp::{Rejection, Reply};
+
+// ブラウザー用のファイル管理画面 (ui/ 以下をビルド時に埋め込む)
+static ASSETS: Dir<'static> = include_dir!("$CARGO_MANIFEST_DIR/ui");
+
+// 画面を表示するパス (末尾の / がないと相対パスのファイルを読み込めない)
+const UI_PATH: &str = "/ui/";
+
+/// /ui/ 以下の埋め込みファイルを返す (web_ui=false の場合は 404)
+pub async fn serve(full: FullPath, tail: Tail, enabled: bool) -> Result<warp::reply::Response, Rejection> {
+    if !enabled {
+        return Err(warp::reject::not_found());
+    }
+    if full.as_str() == UI_PATH.trim_end_matches('/') {
+        return Ok(warp::redirect::found(Uri::from_static(UI_PATH)).into_response());
+    }
+    let name = if tail.as_str().is_empty() { "index.html" } else { tail.as_str() };
+    let file = ASSETS.get_file(name).ok_or_else(warp::reject::not_found)?;
+    let reply = warp::reply::with_header(file.contents(), "content-type", mime::from_extension(Path::new(name)));
+    Ok(warp::reply::with_header(reply, "cache-control", "no-cache").into_response())
+}
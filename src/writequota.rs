@@ -0,0 +1,99 @@
+//! 書き込みの上限 (max_write_bytes= と daily_write_bytes=)
+//!
+//! 1 回に書き込めるファイルの大きさと、トークン (ティア) ごとに 1 日 (UTC) に書き込める合計を制限し、
+//! 暴走したクライアントがディスクを使い切らないようにする。1 日の合計はメモリに数えるため、再起動で 0 に戻る。
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Limits {
+    max_write_bytes: u64,   // 0 なら上限なし
+    daily_write_bytes: u64, // 0 なら上限なし
+}
+
+static LIMITS: RwLock<Limits> = RwLock::new(Limits { max_write_bytes: 0, daily_write_bytes: 0 });
+
+// ティア名 -> (UTC の日付, その日に書き込んだバイト数)
+static USAGE: Mutex<Option<HashMap<String, (u64, u64)>>> = Mutex::new(None);
+
+fn today() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) / SECS_PER_DAY
+}
+
+/// 上限を設定する (0 なら上限なし。設定ファイルの再読み込みでも呼ぶ)
+pub fn set_limits(max_write_bytes: u64, daily_write_bytes: u64) {
+    *LIMITS.write().unwrap() = Limits { max_write_bytes, daily_write_bytes };
+}
+
+/// 1 回に書き込めるファイルの大きさの上限 (None なら上限なし)
+pub fn max_write_bytes() -> Option<u64> {
+    let max = LIMITS.read().unwrap().max_write_bytes;
+    (max > 0).then_some(max)
+}
+
+/// トークンごとに 1 日に書き込める合計の上限 (None なら上限なし)
+pub fn daily_write_bytes() -> Option<u64> {
+    let max = LIMITS.read().unwrap().daily_write_bytes;
+    (max > 0).then_some(max)
+}
+
+/// ティアが今日 (UTC) 書き込んだバイト数
+pub fn written_today(tier: &str) -> u64 {
+    let day = today();
+    USAGE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|usage| usage.get(tier))
+        .filter(|(used_day, _)| *used_day == day)
+        .map_or(0, |(_, bytes)| *bytes)
+}
+
+/// 1 つのファイルとして書き込む大きさが max_write_bytes 以内か確認する
+pub fn check_file(bytes: u64) -> Result<(), String> {
+    match max_write_bytes() {
+        Some(max) if bytes > max => Err(format!(
+            "Quota exceeded: writing {} bytes is over the maximum file size ({} bytes)",
+            bytes, max
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// 書き込む bytes を今日の合計に加える。daily_write_bytes を超える場合は加えずにエラーを返す
+pub fn reserve(tier: &str, bytes: u64) -> Result<(), String> {
+    let daily = daily_write_bytes();
+    let day = today();
+    let mut usage = USAGE.lock().unwrap();
+    let entry = usage.get_or_insert_with(HashMap::new).entry(tier.to_string()).or_insert((day, 0));
+    if entry.0 != day {
+        *entry = (day, 0);
+    }
+    if let Some(daily) = daily {
+        let after = entry.1.saturating_add(bytes);
+        if after > daily {
+            return Err(format!(
+                "Quota exceeded for tier '{}': {} bytes would be written today (daily limit {} bytes, {} bytes left)",
+                tier,
+                after,
+                daily,
+                daily.saturating_sub(entry.1)
+            ));
+        }
+    }
+    entry.1 = entry.1.saturating_add(bytes);
+    Ok(())
+}
+
+/// reserve した分を戻す (書き込みに失敗した場合)
+pub fn refund(tier: &str, bytes: u64) {
+    let day = today();
+    if let Some(entry) = USAGE.lock().unwrap().as_mut().and_then(|usage| usage.get_mut(tier)) {
+        if entry.0 == day {
+            entry.1 = entry.1.saturating_sub(bytes);
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! S3 と WebDAV の応答の XML を読む最小限の関数
+//!
+//! どちらの応答も属性に値を持たない単純な形なので、要素の中身を名前で取り出せば足りる。
+//! 名前空間の接頭辞 (WebDAV の D: など) は区別しない。
+/// <name>...</name> (<D:name> なども) の中身をすべて返す。空要素 (<name/>) は空文字列
+pub fn elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some((content, after)) = next_element(rest, name) {
+        found.push(content);
+        rest = after;
+    }
+    found
+}
+
+/// 最初の <name> の中身 (実体参照を戻したもの)
+pub fn element(xml: &str, name: &str) -> Option<String> {
+    next_element(xml, name).map(|(content, _)| unescape(content))
+}
+
+/// <name> があるか (WebDAV の <D:collection/> など)
+pub fn has_element(xml: &str, name: &str) -> bool {
+    next_element(xml, name).is_some()
+}
+
+// 次の <name> の中身と、その閉じタグより後の部分
+fn next_element<'a>(xml: &'a str, name: &str) -> Option<(&'a str, &'a str)> {
+    let mut rest = xml;
+    loop {
+        let tag = &rest[rest.find('<')? + 1..];
+        let end = tag.find('>')?;
+        let inner = &tag[..end];
+        let after = &tag[end + 1..];
+        let full_name = inner.split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or_default();
+        let local_name = full_name.rsplit(':').next().unwrap_or_default();
+        if !full_name.is_empty() && local_name == name {
+            if inner.ends_with('/') {
+                return Some(("", after));
+            }
+            let close = format!("</{}>", full_name);
+            let close_at = after.find(&close)?;
+            return Some((&after[..close_at], &after[close_at + close.len()..]));
+        }
+        rest = after;
+    }
+}
+
+pub fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#13;", "\r")
+        .replace("&#10;", "\n")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,178 @@
+//! file_agent::routes() にリクエストを送るテスト (トークンのティア・許可ルートとポリシー・パスの検証)
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use warp::filters::BoxedFilter;
+
+const ADMIN_TOKEN: &str = "admin-token-0123456789abcdef";
+const READER_TOKEN: &str = "reader-token-0123456789abcdef";
+const SHARED_TOKEN: &str = "shared-token-0123456789abcdef";
+
+type Routes = BoxedFilter<(warp::reply::Response,)>;
+
+// テストごとの空のフォルダ (許可ルートにする)
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("file_agent_routes_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// root を許可ルートにし、読み取り専用のティアと root/shared だけのティアを加えた設定のルート
+fn routes(root: &Path, extra: &str) -> Routes {
+    let content = format!(
+        "[tokens]\ntoken={}\ntier=reader|token={}|allow=read\ntier=shared|token={}|root={}\n[roots]\nallowed_root={}\n{}",
+        ADMIN_TOKEN,
+        READER_TOKEN,
+        SHARED_TOKEN,
+        root.join("shared").display(),
+        root.display(),
+        extra
+    );
+    file_agent::routes(file_agent::Config::from_ini(&content).expect("valid settings"))
+}
+
+async fn post(routes: &Routes, endpoint: &str, body: Value) -> Value {
+    let response = warp::test::request().method("POST").path(endpoint).json(&body).reply(routes).await;
+    serde_json::from_slice(response.body()).expect("JSON response")
+}
+
+async fn read(routes: &Routes, token: &str, path: &Path) -> Value {
+    post(routes, "/api/read", json!({ "path": path.display().to_string(), "token": token })).await
+}
+
+async fn write(routes: &Routes, token: &str, path: &Path, content: &str) -> Value {
+    post(routes, "/api/write", json!({ "path": path.display().to_string(), "content": content, "token": token })).await
+}
+
+// /api/list で返る名前 (名前順)
+#[cfg(unix)]
+async fn list_names(routes: &Routes, dir: &Path, query: &str) -> Vec<String> {
+    let path = format!("/api/list?path={}&token={}{}", dir.display(), ADMIN_TOKEN, query);
+    let response = warp::test::request().method("GET").path(&path).reply(routes).await;
+    let response: Value = serde_json::from_slice(response.body()).expect("JSON response");
+    let mut names: Vec<String> = response["data"].as_array().expect("entries").iter().map(|entry| entry["name"].as_str().unwrap_or_default().to_string()).collect();
+    names.sort();
+    names
+}
+
+fn error(response: &Value) -> &str {
+    assert_eq!(response["success"], false, "expected an error: {}", response);
+    response["error"].as_str().unwrap_or_default()
+}
+
+#[tokio::test]
+async fn invalid_token_is_rejected() {
+    let root = test_dir("invalid_token");
+    std::fs::write(root.join("a.txt"), "hello").unwrap();
+    let routes = routes(&root, "");
+
+    assert!(error(&read(&routes, "wrong-token-0123456789abcdef", &root.join("a.txt")).await).contains("無効なトークン"));
+    assert!(error(&read(&routes, "", &root.join("a.txt")).await).contains("無効なトークン"));
+}
+
+#[tokio::test]
+async fn tier_operations_are_limited() {
+    let root = test_dir("tier_operations");
+    std::fs::write(root.join("a.txt"), "hello").unwrap();
+    let routes = routes(&root, "");
+
+    let response = read(&routes, READER_TOKEN, &root.join("a.txt")).await;
+    assert_eq!(response["success"], true, "{}", response);
+    assert_eq!(response["data"], "hello");
+
+    let response = write(&routes, READER_TOKEN, &root.join("a.txt"), "changed").await;
+    assert!(error(&response).contains("Operation 'write' is not allowed for tier 'reader'"));
+    assert_eq!(std::fs::read_to_string(root.join("a.txt")).unwrap(), "hello");
+
+    let response = write(&routes, ADMIN_TOKEN, &root.join("a.txt"), "changed").await;
+    assert_eq!(response["success"], true, "{}", response);
+    assert_eq!(std::fs::read_to_string(root.join("a.txt")).unwrap(), "changed");
+}
+
+#[tokio::test]
+async fn tier_roots_narrow_the_allowed_roots() {
+    let root = test_dir("tier_roots");
+    std::fs::create_dir_all(root.join("shared")).unwrap();
+    std::fs::write(root.join("shared").join("a.txt"), "shared").unwrap();
+    std::fs::write(root.join("private.txt"), "private").unwrap();
+    let routes = routes(&root, "");
+
+    let response = read(&routes, SHARED_TOKEN, &root.join("shared").join("a.txt")).await;
+    assert_eq!(response["data"], "shared", "{}", response);
+    assert!(error(&read(&routes, SHARED_TOKEN, &root.join("private.txt")).await).contains("outside the allowed roots"));
+}
+
+#[tokio::test]
+async fn paths_outside_the_allowed_roots_are_refused() {
+    let root = test_dir("outside_root");
+    let outside = test_dir("outside_root_other");
+    std::fs::write(outside.join("secret.txt"), "secret").unwrap();
+    let routes = routes(&root, "");
+
+    assert!(error(&read(&routes, ADMIN_TOKEN, &outside.join("secret.txt")).await).contains("outside the allowed roots"));
+    assert!(error(&write(&routes, ADMIN_TOKEN, &outside.join("new.txt"), "x").await).contains("outside the allowed roots"));
+    assert!(!outside.join("new.txt").exists());
+
+    // .. で許可ルートの外に出るパスは、場所を確かめる前に不正なパスとして断る
+    let escaped = root.join("..").join(outside.file_name().unwrap()).join("secret.txt");
+    let response = read(&routes, ADMIN_TOKEN, &escaped).await;
+    assert!(error(&response).contains("'..'"));
+    assert_eq!(response["data"]["invalid_path"], true);
+}
+
+#[tokio::test]
+async fn read_only_policy_refuses_writes() {
+    let root = test_dir("read_only_policy");
+    std::fs::create_dir_all(root.join("locked")).unwrap();
+    std::fs::write(root.join("locked").join("a.txt"), "hello").unwrap();
+    let routes = routes(&root, &format!("policy={}|write=false", root.join("locked").display()));
+
+    let response = read(&routes, ADMIN_TOKEN, &root.join("locked").join("a.txt")).await;
+    assert_eq!(response["data"], "hello", "{}", response);
+    assert!(error(&write(&routes, ADMIN_TOKEN, &root.join("locked").join("a.txt"), "changed").await).contains("does not allow write"));
+    assert_eq!(std::fs::read_to_string(root.join("locked").join("a.txt")).unwrap(), "hello");
+
+    let response = write(&routes, ADMIN_TOKEN, &root.join("open.txt"), "written").await;
+    assert_eq!(response["success"], true, "{}", response);
+}
+
+// 隠しファイルは show_hidden=false を指定しなければ返す (Unix のドットファイル)
+#[cfg(unix)]
+#[tokio::test]
+async fn list_includes_hidden_entries_by_default() {
+    let root = test_dir("list_hidden");
+    std::fs::write(root.join(".hidden"), "").unwrap();
+    std::fs::write(root.join("visible.txt"), "").unwrap();
+    let routes = routes(&root, "");
+
+    assert_eq!(list_names(&routes, &root, "").await, [".hidden", "visible.txt"]);
+    assert_eq!(list_names(&routes, &root, "&show_hidden=false").await, ["visible.txt"]);
+}
+
+#[tokio::test]
+async fn device_and_malformed_long_paths_are_invalid() {
+    let root = test_dir("long_paths");
+    let routes = routes(&root, "");
+
+    for path in [r"\\.\PhysicalDrive0", "//./COM1", r"\\?\C:/Windows/win.ini", r"\\?\C:\Users\.\file.txt", r"C:\Users\NUL.txt"] {
+        let response = post(&routes, "/api/read", json!({ "path": path, "token": ADMIN_TOKEN })).await;
+        assert_eq!(response["data"]["invalid_path"], true, "{}: {}", path, response);
+    }
+}
+
+// \\?\ の表記は許可ルートと比べる前に外す
+#[cfg(windows)]
+#[tokio::test]
+async fn long_path_prefixes_are_normalized() {
+    let root = test_dir("long_path_prefixes");
+    std::fs::write(root.join("a.txt"), "hello").unwrap();
+    let routes = routes(&root, "");
+
+    let extended = PathBuf::from(format!(r"\\?\{}", root.join("a.txt").display()));
+    let response = read(&routes, ADMIN_TOKEN, &extended).await;
+    assert_eq!(response["data"], "hello", "{}", response);
+
+    let windows = std::env::var("SystemRoot").unwrap_or_else(|_| r"C:\Windows".to_string());
+    let outside = PathBuf::from(format!(r"\\?\{}\win.ini", windows));
+    assert!(error(&read(&routes, ADMIN_TOKEN, &outside).await).contains("outside the allowed roots"));
+}
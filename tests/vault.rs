@@ -0,0 +1,132 @@
+//! vault ルートへのコピー・移動・テンプレートからの作成 (暗号化) と vault ルートからの移動 (復号) のテスト
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use warp::filters::BoxedFilter;
+
+const TOKEN: &str = "vault-token-0123456789abcdef";
+
+type Routes = BoxedFilter<(warp::reply::Response,)>;
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("file_agent_vault_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(vault_dir(&dir)).unwrap();
+    dir
+}
+
+fn vault_dir(root: &Path) -> PathBuf {
+    root.join("vault")
+}
+
+// root を許可ルート、root/vault を vault ルートにした設定 (vault_key= で起動時から解錠済み)
+fn routes(root: &Path) -> Routes {
+    let content = format!(
+        "token={}\nallowed_root={}\nvault={}\nvault_key=test-vault-key\ntemplate_dir={}\n",
+        TOKEN,
+        root.display(),
+        vault_dir(root).display(),
+        root.join("templates").display()
+    );
+    file_agent::routes(file_agent::Config::from_ini(&content).expect("valid settings"))
+}
+
+async fn post(routes: &Routes, endpoint: &str, body: Value) -> Value {
+    let response = warp::test::request().method("POST").path(endpoint).json(&body).reply(routes).await;
+    let response: Value = serde_json::from_slice(response.body()).expect("JSON response");
+    assert_eq!(response["success"], true, "{}: {}", endpoint, response);
+    response
+}
+
+async fn transfer(routes: &Routes, endpoint: &str, source: &Path, destination: &Path) {
+    let body = json!({ "source": source.display().to_string(), "destination": destination.display().to_string(), "token": TOKEN });
+    post(routes, endpoint, body).await;
+}
+
+async fn read(routes: &Routes, path: &Path) -> Value {
+    post(routes, "/api/read", json!({ "path": path.display().to_string(), "token": TOKEN })).await["data"].clone()
+}
+
+fn is_encrypted(path: &Path) -> bool {
+    std::fs::read(path).unwrap().starts_with(b"FAVAULT1")
+}
+
+#[tokio::test]
+async fn copy_into_vault_encrypts() {
+    let root = test_dir("copy_in");
+    std::fs::write(root.join("a.txt"), "secret").unwrap();
+    let routes = routes(&root);
+
+    let target = vault_dir(&root).join("a.txt");
+    transfer(&routes, "/api/copy", &root.join("a.txt"), &target).await;
+    assert!(is_encrypted(&target));
+    assert_eq!(read(&routes, &target).await, "secret");
+    assert_eq!(std::fs::read_to_string(root.join("a.txt")).unwrap(), "secret");
+}
+
+#[tokio::test]
+async fn folder_copy_into_vault_encrypts_every_file() {
+    let root = test_dir("copy_folder_in");
+    std::fs::create_dir_all(root.join("docs").join("sub")).unwrap();
+    std::fs::write(root.join("docs").join("a.txt"), "a").unwrap();
+    std::fs::write(root.join("docs").join("sub").join("b.txt"), "b").unwrap();
+    let routes = routes(&root);
+
+    let target = vault_dir(&root).join("docs");
+    transfer(&routes, "/api/copy", &root.join("docs"), &target).await;
+    assert!(is_encrypted(&target.join("a.txt")));
+    assert!(is_encrypted(&target.join("sub").join("b.txt")));
+    assert_eq!(read(&routes, &target.join("sub").join("b.txt")).await, "b");
+}
+
+#[tokio::test]
+async fn move_into_vault_encrypts() {
+    let root = test_dir("move_in");
+    std::fs::write(root.join("a.txt"), "secret").unwrap();
+    let routes = routes(&root);
+
+    let target = vault_dir(&root).join("a.txt");
+    transfer(&routes, "/api/move", &root.join("a.txt"), &target).await;
+    assert!(is_encrypted(&target));
+    assert!(!root.join("a.txt").exists());
+    assert_eq!(read(&routes, &target).await, "secret");
+}
+
+#[tokio::test]
+async fn move_out_of_vault_decrypts() {
+    let root = test_dir("move_out");
+    let routes = routes(&root);
+    let source = vault_dir(&root).join("a.txt");
+    post(&routes, "/api/write", json!({ "path": source.display().to_string(), "content": "secret", "token": TOKEN })).await;
+    assert!(is_encrypted(&source));
+
+    transfer(&routes, "/api/move", &source, &root.join("a.txt")).await;
+    assert_eq!(std::fs::read_to_string(root.join("a.txt")).unwrap(), "secret");
+    assert!(!source.exists());
+}
+
+#[tokio::test]
+async fn move_inside_vault_keeps_the_encryption() {
+    let root = test_dir("move_inside");
+    let routes = routes(&root);
+    let source = vault_dir(&root).join("a.txt");
+    post(&routes, "/api/write", json!({ "path": source.display().to_string(), "content": "secret", "token": TOKEN })).await;
+
+    let target = vault_dir(&root).join("b.txt");
+    transfer(&routes, "/api/move", &source, &target).await;
+    assert!(is_encrypted(&target));
+    assert_eq!(read(&routes, &target).await, "secret");
+}
+
+#[tokio::test]
+async fn template_created_in_vault_is_encrypted() {
+    let root = test_dir("template");
+    std::fs::create_dir_all(root.join("templates")).unwrap();
+    std::fs::write(root.join("templates").join("note.md"), "# {{name}} by {{author}}").unwrap();
+    let routes = routes(&root);
+
+    let target = vault_dir(&root).join("minutes.md");
+    let body = json!({ "path": target.display().to_string(), "is_directory": false, "template": "note.md", "variables": { "author": "me" }, "token": TOKEN });
+    post(&routes, "/api/create", body).await;
+    assert!(is_encrypted(&target));
+    assert_eq!(read(&routes, &target).await, "# minutes by me");
+}
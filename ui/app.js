@@ -0,0 +1,260 @@
+// File Agent の組み込みファイルブラウザー (同じオリジンの /api/v1 を呼び出す)
+const API = '/api/v1';
+
+const state = {
+    token: sessionStorage.getItem('file_agent_token') || '',
+    path: '',
+};
+
+const $ = (id) => document.getElementById(id);
+
+async function apiGet(endpoint, params = {}) {
+    const query = new URLSearchParams({ ...params, token: state.token });
+    const response = await fetch(`${API}/${endpoint}?${query}`);
+    return response.json();
+}
+
+async function apiPost(endpoint, body) {
+    const response = await fetch(`${API}/${endpoint}`, {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({ ...body, token: state.token }),
+    });
+    return response.json();
+}
+
+// 失敗した応答は例外にする (error がない場合は応答全体を表示)
+function unwrap(result) {
+    if (!result.success) {
+        throw new Error(result.error || JSON.stringify(result));
+    }
+    return result.data;
+}
+
+function setStatus(message, isError = false) {
+    const status = $('status');
+    status.textContent = message;
+    status.classList.toggle('error', isError);
+}
+
+function separator(path) {
+    return path.includes('\\') ? '\\' : '/';
+}
+
+function joinPath(dir, name) {
+    const sep = separator(dir);
+    return dir.endsWith(sep) ? dir + name : dir + sep + name;
+}
+
+function parentPath(path) {
+    const sep = separator(path);
+    const trimmed = path.length > 1 && path.endsWith(sep) ? path.slice(0, -1) : path;
+    const index = trimmed.lastIndexOf(sep);
+    if (index < 0) {
+        return path;
+    }
+    // C:\ や / のようなルートは末尾の区切り文字を残す
+    return index === 0 || trimmed[index - 1] === ':' ? trimmed.slice(0, index + 1) : trimmed.slice(0, index);
+}
+
+function formatSize(size) {
+    if (size == null) {
+        return '';
+    }
+    const units = ['B', 'KB', 'MB', 'GB', 'TB'];
+    let value = size;
+    let unit = 0;
+    while (value >= 1024 && unit < units.length - 1) {
+        value /= 1024;
+        unit++;
+    }
+    return `${unit === 0 ? value : value.toFixed(1)} ${units[unit]}`;
+}
+
+function formatModified(seconds) {
+    return seconds == null ? '' : new Date(seconds * 1000).toLocaleString();
+}
+
+async function login(token) {
+    state.token = token;
+    const capabilities = unwrap(await apiGet('capabilities'));
+    sessionStorage.setItem('file_agent_token', token);
+
+    // トークンのルートが制限されていなければエージェントの許可ルートを使う
+    let roots = capabilities.token.allowed_roots;
+    if (roots.length === 0) {
+        const health = unwrap(await apiGet('health'));
+        roots = health.details ? health.details.roots.map((root) => root.path) : [];
+    }
+
+    const select = $('rootSelect');
+    select.innerHTML = '';
+    for (const root of roots) {
+        select.append(new Option(root, root));
+    }
+    select.hidden = roots.length === 0;
+
+    $('loginView').hidden = true;
+    $('browserView').hidden = false;
+    if (roots.length > 0) {
+        await navigate(roots[0]);
+    } else {
+        setStatus('フォルダのパスを入力してください');
+    }
+}
+
+function logout() {
+    sessionStorage.removeItem('file_agent_token');
+    state.token = '';
+    state.path = '';
+    $('fileList').innerHTML = '';
+    $('browserView').hidden = true;
+    $('loginView').hidden = false;
+}
+
+async function navigate(path) {
+    setStatus('読み込み中...');
+    try {
+        const files = unwrap(await apiGet('list', { path }));
+        state.path = path;
+        $('pathInput').value = path;
+        renderFiles(files);
+        setStatus(`${files.length} 個のアイテム`);
+    } catch (e) {
+        setStatus(e.message, true);
+    }
+}
+
+function renderFiles(files) {
+    files.sort((a, b) => (a.is_file - b.is_file) || a.name.localeCompare(b.name));
+    const list = $('fileList');
+    list.innerHTML = '';
+    for (const file of files) {
+        const row = document.createElement('tr');
+
+        const name = document.createElement('td');
+        name.className = 'name';
+        name.textContent = `${file.is_file ? '📄' : '📁'} ${file.name}`;
+        name.addEventListener('click', () => file.is_file ? download(file) : navigate(file.path));
+
+        const size = document.createElement('td');
+        size.className = 'size';
+        size.textContent = file.is_file ? formatSize(file.size) : '';
+
+        const modified = document.createElement('td');
+        modified.className = 'modified';
+        modified.textContent = formatModified(file.modified);
+
+        const actions = document.createElement('td');
+        actions.className = 'actions';
+        if (file.is_file) {
+            actions.append(actionButton('ダウンロード', () => download(file)));
+        }
+        actions.append(actionButton('名前の変更', () => rename(file)));
+        actions.append(actionButton('削除', () => remove(file)));
+
+        row.append(name, size, modified, actions);
+        list.append(row);
+    }
+}
+
+function actionButton(label, onClick) {
+    const button = document.createElement('button');
+    button.textContent = label;
+    button.addEventListener('click', onClick);
+    return button;
+}
+
+async function download(file) {
+    setStatus(`${file.name} をダウンロード中...`);
+    try {
+        const content = unwrap(await apiPost('read_binary', { path: file.path }));
+        const bytes = Uint8Array.from(atob(content), (c) => c.charCodeAt(0));
+        const url = URL.createObjectURL(new Blob([bytes], { type: file.mime_type }));
+        const link = document.createElement('a');
+        link.href = url;
+        link.download = file.name;
+        link.click();
+        URL.revokeObjectURL(url);
+        setStatus(`${file.name} をダウンロードしました`);
+    } catch (e) {
+        setStatus(e.message, true);
+    }
+}
+
+async function rename(file) {
+    const name = prompt('新しい名前', file.name);
+    if (!name || name === file.name) {
+        return;
+    }
+    try {
+        unwrap(await apiPost('move', { source: file.path, destination: joinPath(parentPath(file.path), name) }));
+        await navigate(state.path);
+    } catch (e) {
+        setStatus(e.message, true);
+    }
+}
+
+async function remove(file) {
+    if (!confirm(`${file.name} を削除しますか?`)) {
+        return;
+    }
+    try {
+        unwrap(await apiPost('delete', { path: file.path, recursive: true }));
+        await navigate(state.path);
+    } catch (e) {
+        setStatus(e.message, true);
+    }
+}
+
+function readAsBase64(file) {
+    return new Promise((resolve, reject) => {
+        const reader = new FileReader();
+        // data:...;base64, の後ろだけを送る
+        reader.onload = () => resolve(reader.result.slice(reader.result.indexOf(',') + 1));
+        reader.onerror = () => reject(reader.error);
+        reader.readAsDataURL(file);
+    });
+}
+
+async function upload(files) {
+    if (!state.path) {
+        setStatus('アップロード先のフォルダを開いてください', true);
+        return;
+    }
+    try {
+        for (const file of files) {
+            setStatus(`${file.name} をアップロード中...`);
+            const content = await readAsBase64(file);
+            unwrap(await apiPost('write_binary', { path: joinPath(state.path, file.name), content }));
+        }
+        await navigate(state.path);
+    } catch (e) {
+        setStatus(e.message, true);
+    }
+}
+
+$('loginForm').addEventListener('submit', async (event) => {
+    event.preventDefault();
+    $('loginError').textContent = '';
+    try {
+        await login($('tokenInput').value);
+    } catch (e) {
+        state.token = '';
+        $('loginError').textContent = e.message;
+    }
+});
+$('logoutBtn').addEventListener('click', logout);
+$('rootSelect').addEventListener('change', (event) => navigate(event.target.value));
+$('upBtn').addEventListener('click', () => state.path && navigate(parentPath(state.path)));
+$('goBtn').addEventListener('click', () => navigate($('pathInput').value));
+$('pathInput').addEventListener('keydown', (event) => event.key === 'Enter' && navigate($('pathInput').value));
+$('refreshBtn').addEventListener('click', () => state.path && navigate(state.path));
+$('uploadInput').addEventListener('change', async (event) => {
+    await upload(Array.from(event.target.files));
+    event.target.value = '';
+});
+
+if (state.token) {
+    login(state.token).catch(logout);
+}